@@ -1,10 +1,9 @@
 use crate::vec::*;
-use rand::prelude::*;
 use rayon::prelude::*;
 use std::fs;
 use std::path::PathBuf;
 
-use crate::config::UserConfig;
+use crate::config::{RenderParams, UserConfig};
 use crate::geom::*;
 use iced::{
     button, scrollable, Align, Application, Button, Column, Command, Container, Element,
@@ -13,7 +12,8 @@ use iced::{
 use nfd::Response;
 use tempfile::NamedTempFile;
 
-use crate::{camera, style};
+use crate::sampler::{self, SamplerKind};
+use crate::{style, texture};
 use names::{Generator, Name};
 use tinyfiledialogs::{MessageBoxIcon, YesNo};
 
@@ -28,6 +28,8 @@ pub struct AppModel {
     temp_image_path: PathBuf,
     config: Option<UserConfig>,
     config_path: Option<PathBuf>,
+    /// Sampler given with `--sampler`, used in place of the configured one.
+    sampler: Option<SamplerKind>,
     state: AppState,
 
     rand_adj: String,
@@ -40,19 +42,14 @@ pub struct AppModel {
     scroll_state: scrollable::State,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 enum AppState {
+    #[default]
     Ready,
     Rendering,
     Done,
 }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self::Ready
-    }
-}
-
 #[derive(Debug, Clone)]
 pub enum Error {
     TraceError,
@@ -72,7 +69,31 @@ impl Application for AppModel {
     type Message = Message;
 
     fn new() -> (Self, Command<Message>) {
-        (Self::default(), Command::none())
+        let mut app = Self::default();
+        // prayer [--sampler random|stratified|sobol] [config]
+        let mut path = None;
+        let mut args = std::env::args_os().skip(1);
+        while let Some(arg) = args.next() {
+            if arg != "--sampler" {
+                path = Some(PathBuf::from(arg));
+                continue;
+            }
+            let name = match args.next() {
+                Some(name) => name,
+                None => {
+                    eprintln!("--sampler needs a value: random, stratified or sobol");
+                    break;
+                }
+            };
+            match name.to_string_lossy().parse() {
+                Ok(kind) => app.sampler = Some(kind),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        if let Some(path) = path {
+            app.load_config(path);
+        }
+        (app, Command::none())
     }
 
     fn title(&self) -> String {
@@ -83,32 +104,12 @@ impl Application for AppModel {
         let mut command = Command::none();
         match message {
             Message::ChooseConfig => {
-                let response = nfd::open_file_dialog(Some("toml"), None).unwrap_or_else(|e| {
-                    panic!(e);
+                let response = nfd::open_file_dialog(Some("toml,obj"), None).unwrap_or_else(|e| {
+                    panic!("{}", e);
                 });
 
-                match response {
-                    Response::Okay(path) => {
-                        let path = PathBuf::from(path);
-                        self.config_path = Some(path);
-                        if let Some(path) = self.config_path.as_ref() {
-                            let result = UserConfig::from_file(path);
-                            match result {
-                                Ok(config) => {
-                                    self.config = Some(config);
-                                }
-                                Err(e) => {
-                                    self.config = None;
-                                    tinyfiledialogs::message_box_ok(
-                                        "Configuration",
-                                        format!("Error in configuration file:\n {}", e).as_str(),
-                                        MessageBoxIcon::Info,
-                                    );
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
+                if let Response::Okay(path) = response {
+                    self.load_config(PathBuf::from(path));
                 }
             }
             Message::Trace => {
@@ -127,7 +128,11 @@ impl Application for AppModel {
                     &self.result,
                     config.params.resolution.x,
                     config.params.resolution.y,
-                    image::RGB(8),
+                    if config.scene.background.is_transparent() {
+                        image::RGBA(8)
+                    } else {
+                        image::RGB(8)
+                    },
                 )
                 .unwrap();
                 self.image = Some(iced::image::Handle::from_path(&self.temp_image_path));
@@ -140,7 +145,7 @@ impl Application for AppModel {
 
                 self.state = AppState::Done;
             }
-            Message::Done(Err(e)) => {
+            Message::Done(Err(_)) => {
                 tinyfiledialogs::message_box_ok(
                     "Configuration",
                     "Can't start tracing without a config file!",
@@ -149,22 +154,19 @@ impl Application for AppModel {
             }
             Message::SaveImage => {
                 let response = nfd::open_save_dialog(Some("png"), None).unwrap_or_else(|e| {
-                    panic!(e);
+                    panic!("{}", e);
                 });
 
-                match response {
-                    Response::Okay(path) => {
-                        let _result = fs::copy(&self.temp_image_path, PathBuf::from(path))
-                            .unwrap_or_else(|e| {
-                                tinyfiledialogs::message_box_ok(
-                                    "Error",
-                                    format!("Image could not be saved: {}", e).as_str(),
-                                    MessageBoxIcon::Error,
-                                );
-                                0
-                            });
-                    }
-                    _ => {}
+                if let Response::Okay(path) = response {
+                    let _result = fs::copy(&self.temp_image_path, PathBuf::from(path))
+                        .unwrap_or_else(|e| {
+                            tinyfiledialogs::message_box_ok(
+                                "Error",
+                                format!("Image could not be saved: {}", e).as_str(),
+                                MessageBoxIcon::Error,
+                            );
+                            0
+                        });
                 }
             }
             Message::Quit => {
@@ -174,9 +176,8 @@ impl Application for AppModel {
                     MessageBoxIcon::Question,
                     YesNo::No,
                 );
-                match choice {
-                    YesNo::Yes => std::process::exit(0),
-                    _ => {}
+                if choice == YesNo::Yes {
+                    std::process::exit(0);
                 }
             }
         }
@@ -184,7 +185,7 @@ impl Application for AppModel {
         command
     }
 
-    fn view(&mut self) -> Element<Message> {
+    fn view(&mut self) -> Element<'_, Message> {
         let mut main_view = Column::new();
         let mut scrollable = Scrollable::new(&mut self.scroll_state);
         let config_button =
@@ -199,7 +200,7 @@ impl Application for AppModel {
 
         let mut path_label = Text::new("");
         if let Some(path) = self.config_path.as_ref() {
-            if let Some(_) = self.config {
+            if self.config.is_some() {
                 path_label = Text::new(path.to_str().unwrap());
             }
         }
@@ -243,49 +244,284 @@ impl Application for AppModel {
     }
 }
 
+impl AppModel {
+    /// Loads either a scene configuration or a bare .obj mesh,
+    /// which is rendered with default parameters.
+    fn load_config(&mut self, path: PathBuf) {
+        self.config_path = Some(path.clone());
+        let result = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("obj") => UserConfig::from_obj(&path),
+            _ => UserConfig::from_file(&path),
+        };
+        match result {
+            Ok(mut config) => {
+                if let Some(kind) = self.sampler {
+                    config.params.sampler = kind;
+                }
+                self.config = Some(config);
+            }
+            Err(e) => {
+                self.config = None;
+                tinyfiledialogs::message_box_ok(
+                    "Configuration",
+                    format!("Error in configuration file:\n {}", e).as_str(),
+                    MessageBoxIcon::Info,
+                );
+            }
+        }
+    }
+}
+
 async fn trace_main(config: UserConfig) -> Result<Vec<u8>, Error> {
-    let UserConfig { params, scene } = config;
+    let UserConfig { params, mut scene } = config;
+    texture::set_cache_budget(params.texture_cache_mb);
+
+    let path = match &params.camera_path {
+        Some(path) => path,
+        None => return render(&params, &scene),
+    };
+    // Each frame is saved as it is done, and the last one is shown
+    let mut buffer = Vec::new();
+    for frame in 0..path.frames {
+        scene.animate(path.progress(frame));
+        buffer = render(&params.at_frame(path, frame), &scene)?;
+        let file = format!("{}_{:04}.png", path.output, frame);
+        image::save_buffer(
+            &file,
+            &buffer,
+            params.resolution.x,
+            params.resolution.y,
+            if scene.background.is_transparent() {
+                image::RGBA(8)
+            } else {
+                image::RGB(8)
+            },
+        )
+        .map_err(|e| {
+            eprintln!("Frame {} could not be saved: {}", file, e);
+            Error::TraceError
+        })?;
+    }
+    Ok(buffer)
+}
 
+/// Renders a single image of `scene`, as 8-bit pixels.
+fn render(params: &RenderParams, scene: &Scene) -> Result<Vec<u8>, Error> {
     let w = params.resolution.x;
     let h = params.resolution.y;
-    let camera = camera::Camera::looking_at(
-        glm::vec3(0.0, 2.0, -5.0),
-        glm::vec3(0.0, 0.0, 0.0),
-        glm::vec3(0.0, 1.0, 0.0),
-        80.0,
-        w as f32 / h as f32,
-    );
-
-    let buffer: Vec<u8> = (0..w * h)
-        .into_par_iter()
-        .flat_map(|i| {
-            let x = i % w;
-            let y = i / w;
-            let color = (0..params.samples)
+    // Stereo images hold a view for each eye
+    let (view_w, view_h) = match &params.stereo {
+        Some(stereo) => stereo.eye_size(w, h),
+        None => (w, h),
+    };
+    let camera = params.camera(view_w as f32 / view_h as f32);
+    let spread = camera.pixel_spread(view_h);
+    let eyes = params.stereo.map(|stereo| (stereo, stereo.eyes(&camera)));
+    // The camera seeing the point `x`, `y` of the image, with where that
+    // point lies in its view
+    let view = |x: f32, y: f32| match &eyes {
+        Some((stereo, eyes)) => {
+            let (eye, x, y) = stereo.view(x, y);
+            (&eyes[eye], x, y)
+        }
+        None => (&camera, x, y),
+    };
+
+    let groups = if params.light_passes.is_some() {
+        scene.light_groups().len()
+    } else {
+        0
+    };
+    let (colors, passes): (Vec<Vec4>, Vec<Vec<Vec3>>) = match &params.toon {
+        // Toon shading takes one sample through each pixel center, as the
+        // outlines are found between pixels
+        Some(toon) => {
+            let samples: Vec<_> = (0..w * h)
                 .into_par_iter()
-                .map(|_| {
-                    let mut rng = rand::thread_rng();
-                    let rand: f32 = rng.gen();
-                    let u = (x as f32 + rand) / w as f32;
-                    let rand: f32 = rng.gen();
-                    let v = (y as f32 + rand) / h as f32;
-                    let ray = camera.ray_at(u, v);
-                    trace(&ray, &scene, params.max_light_bounces)
+                .map(|i| {
+                    let u = ((i % w) as f32 + 0.5) / w as f32;
+                    let v = ((i / w) as f32 + 0.5) / h as f32;
+                    let (camera, u, v) = view(u, v);
+                    match camera.ray_at(u, v, glm::zero(), 0.0) {
+                        Some(ray) => toon.shade(&ray, scene),
+                        None => ToonSample::empty(),
+                    }
                 })
-                .sum::<Vec3>()
-                / params.samples as f32;
-            let color = glm::vec3(1.0, 1.0, 1.0) - glm::exp(&(-color * params.exposure));
-            vec![
-                (color.x.max(0.0).min(1.0).powf(1.0 / params.gamma) * 255.99) as u8,
-                (color.y.max(0.0).min(1.0).powf(1.0 / params.gamma) * 255.99) as u8,
-                (color.z.max(0.0).min(1.0).powf(1.0 / params.gamma) * 255.99) as u8,
-            ]
+                .collect();
+            (toon.outline(&samples, w, h, (view_w, view_h)), Vec::new())
+        }
+        None => (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let x = i % w;
+                let y = i / w;
+                // Starts sample `s` of the pixel, which picks where it goes
+                // through the pixel, the lens and the shutter interval
+                let sample_ray = |s: usize| {
+                    sampler::start_sample(params.sampler, i, s as u32, params.samples as u32);
+                    let jitter = sampler::next_2d();
+                    let u = (x as f32 + jitter.x) / w as f32;
+                    let v = (y as f32 + jitter.y) / h as f32;
+                    let lens = sampler::next_2d();
+                    let time = sampler::next_1d();
+                    let (camera, u, v) = view(u, v);
+                    let ray = camera.ray_at(u, v, lens, time)?;
+                    Some(ray.with_spread(spread))
+                };
+                // Samples the camera sees nothing through add nothing
+                let trace_sample = |ray: Option<&_>, passes: &mut [Vec3]| match ray {
+                    Some(ray) => trace_passes(ray, scene, params.max_light_bounces, passes),
+                    None => glm::zero(),
+                };
+                if groups > 0 {
+                    let mut passes = vec![glm::zero(); groups];
+                    let color = (0..params.samples)
+                        .map(|s| trace_sample(sample_ray(s).as_ref(), &mut passes))
+                        .sum::<Vec4>();
+                    let n = params.samples as f32;
+                    let passes = passes.into_iter().map(|pass| pass / n).collect();
+                    return (color / n, passes);
+                }
+                // In packet mode samples of the same pixel are traced in groups,
+                // with any remainder traced one by one. Each sample of a group
+                // is started again before its path is followed, drawing the
+                // numbers of its camera ray anew, so that its bounces go on
+                // from there as they would alone
+                let packets = if params.packets {
+                    params.samples / LANES
+                } else {
+                    0
+                };
+                let packed = (0..packets)
+                    .into_par_iter()
+                    .map(|packet| {
+                        let s = packet * LANES;
+                        let rays = [
+                            sample_ray(s),
+                            sample_ray(s + 1),
+                            sample_ray(s + 2),
+                            sample_ray(s + 3),
+                        ];
+                        match rays {
+                            [Some(a), Some(b), Some(c), Some(d)] => {
+                                let packet = RayPacket::new([a, b, c, d]);
+                                let restart = |lane| {
+                                    sample_ray(s + lane);
+                                };
+                                trace_packet(&packet, scene, params.max_light_bounces, restart)
+                                    .iter()
+                                    .sum::<Vec4>()
+                            }
+                            rays => rays
+                                .iter()
+                                .map(|ray| trace_sample(ray.as_ref(), &mut []))
+                                .sum(),
+                        }
+                    })
+                    .sum::<Vec4>();
+                let color = (packets * LANES..params.samples)
+                    .into_par_iter()
+                    .map(|s| trace_sample(sample_ray(s).as_ref(), &mut []))
+                    .sum::<Vec4>();
+                ((packed + color) / params.samples as f32, Vec::new())
+            })
+            .unzip(),
+    };
+
+    if let Some(prefix) = &params.light_passes {
+        save_light_passes(prefix, scene.light_groups(), &passes, w, h).map_err(|e| {
+            eprintln!("Light passes could not be saved: {}", e);
+            Error::TraceError
+        })?;
+    }
+
+    // Lens effects go on the finished image, as chromatic aberration moves
+    // colors between pixels
+    let colors = match &params.lens_effects {
+        Some(effects) => (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let x = ((i % w) as f32 + 0.5) / w as f32;
+                let y = ((i / w) as f32 + 0.5) / h as f32;
+                // Each eye's view goes through a lens of its own
+                match &eyes {
+                    Some((stereo, eyes)) => {
+                        let (eye, x, y) = stereo.view(x, y);
+                        effects.apply(&eyes[eye], x, y, |x, y| {
+                            let (x, y) = stereo.image_point(eye, x, y);
+                            sample_image(&colors, w, h, x, y)
+                        })
+                    }
+                    None => effects.apply(&camera, x, y, |x, y| sample_image(&colors, w, h, x, y)),
+                }
+            })
+            .collect(),
+        None => colors,
+    };
+
+    // Transparent backgrounds leave colors premultiplied by their coverage,
+    // which is undone for the image's straight alpha
+    let transparent = scene.background.is_transparent();
+    let buffer: Vec<u8> = colors
+        .into_par_iter()
+        .flat_map(|color| {
+            let alpha = color.w.clamp(0.0, 1.0);
+            let color = if alpha > 0.0 {
+                glm::vec4_to_vec3(&color) / alpha
+            } else {
+                glm::zero()
+            };
+            let color = glm::vec3(1.0, 1.0, 1.0) - glm::exp(&(-color * params.exposure_scale()));
+            let mut pixel = vec![
+                (color.x.clamp(0.0, 1.0).powf(1.0 / params.gamma) * 255.99) as u8,
+                (color.y.clamp(0.0, 1.0).powf(1.0 / params.gamma) * 255.99) as u8,
+                (color.z.clamp(0.0, 1.0).powf(1.0 / params.gamma) * 255.99) as u8,
+            ];
+            if transparent {
+                pixel.push((alpha * 255.99) as u8);
+            }
+            pixel
         })
         .collect::<Vec<_>>();
 
     Ok(buffer)
 }
 
+/// Color of the point `x`, `y` of an image `w` by `h` pixels, interpolated
+/// between the nearest pixels.
+fn sample_image(colors: &[Vec4], w: u32, h: u32, x: f32, y: f32) -> Vec4 {
+    let x = (x * w as f32 - 0.5).max(0.0).min((w - 1) as f32);
+    let y = (y * h as f32 - 0.5).max(0.0).min((h - 1) as f32);
+    let (x0, y0) = (x as u32, y as u32);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let pixel = |x: u32, y: u32| colors[(y * w + x) as usize];
+    let top = glm::lerp(&pixel(x0, y0), &pixel(x1, y0), fx);
+    let bottom = glm::lerp(&pixel(x0, y1), &pixel(x1, y1), fx);
+    glm::lerp(&top, &bottom, fy)
+}
+
+/// Writes one linear HDR image per light group from the groups' colors at
+/// each pixel.
+fn save_light_passes(
+    prefix: &str,
+    groups: &[String],
+    passes: &[Vec<Vec3>],
+    w: u32,
+    h: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (i, group) in groups.iter().enumerate() {
+        let pixels: Vec<_> = passes
+            .iter()
+            .map(|pixel| image::Rgb([pixel[i].x, pixel[i].y, pixel[i].z]))
+            .collect();
+        let file = fs::File::create(format!("{}_{}.hdr", prefix, group))?;
+        let writer = std::io::BufWriter::new(file);
+        image::hdr::HDREncoder::new(writer).encode(&pixels, w as usize, h as usize)?;
+    }
+    Ok(())
+}
+
 fn button<'a, Message>(state: &'a mut button::State, label: &str) -> Button<'a, Message> {
     Button::new(
         state,
@@ -1,42 +1,714 @@
 use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
 
 use crate::ray::Ray;
-use crate::Vec3;
+use crate::{Vec2, Vec3, Vec4};
 
+use std::convert::TryFrom;
 use std::f32::consts::PI;
 
-pub struct Camera {
+/// How directions in front of the camera map to the image.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Projection {
+    /// Rays spread out from a point, covering the field of view.
+    #[default]
+    Perspective,
+    /// Rays run parallel, covering a view `height` units high, or wide
+    /// when the field of view is horizontal, so that sizes do not shrink
+    /// with distance.
+    Orthographic { height: f32 },
+    /// Rays spread out over up to all directions, with `fov` degrees
+    /// across the image, as with a fisheye lens or for dome projection.
+    Fisheye {
+        fov: f32,
+        #[serde(default)]
+        mapping: FisheyeMapping,
+    },
+    /// Rays in all directions in latitude-longitude layout, the view
+    /// direction in the middle, for 360° panoramas and environment maps.
+    /// Images twice as wide as they are high keep directions evenly spaced.
+    Equirectangular,
+}
+
+/// How far from the center of a fisheye image directions land, by their
+/// angle to the view direction.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FisheyeMapping {
+    /// In proportion to the angle.
+    #[default]
+    Equidistant,
+    /// Keeping areas in proportion to solid angle, as many real fisheye
+    /// lenses do.
+    Equisolid,
+}
+
+/// Side of the view that a field of view, or the height of orthographic
+/// views, is measured across. The other side follows from the aspect ratio.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FovAxis {
+    #[default]
+    Vertical,
+    Horizontal,
+}
+
+impl FovAxis {
+    /// Degrees across the height of a perspective view `aspect` times as
+    /// wide as it is high with `fov` degrees across this side.
+    pub fn vertical_fov(self, fov: f32, aspect: f32) -> f32 {
+        match self {
+            FovAxis::Vertical => fov,
+            FovAxis::Horizontal => {
+                let half_w = (fov.to_radians() / 2.0).tan();
+                2.0 * (half_w / aspect).atan().to_degrees()
+            }
+        }
+    }
+
+    /// Degrees across the height of a fisheye view `aspect` times as wide
+    /// as it is high with `fov` degrees across this side.
+    pub fn vertical_fisheye_fov(self, fov: f32, mapping: FisheyeMapping, aspect: f32) -> f32 {
+        match (self, mapping) {
+            (FovAxis::Vertical, _) => fov,
+            (FovAxis::Horizontal, FisheyeMapping::Equidistant) => fov / aspect,
+            (FovAxis::Horizontal, FisheyeMapping::Equisolid) => {
+                let s = (fov.to_radians() / 4.0).min(PI / 2.0).sin() / aspect;
+                4.0 * s.asin().to_degrees()
+            }
+        }
+    }
+
+    /// Height of an orthographic view `aspect` times as wide as it is high
+    /// that is `size` across this side.
+    pub fn vertical_size(self, size: f32, aspect: f32) -> f32 {
+        match self {
+            FovAxis::Vertical => size,
+            FovAxis::Horizontal => size / aspect,
+        }
+    }
+}
+
+/// Renders a view for each eye into the two halves of the image, for VR
+/// headsets and 3D displays.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Stereo {
+    #[serde(default)]
+    pub layout: StereoLayout,
+    /// Distance between the eyes.
+    #[serde(default = "default_eye_separation")]
+    pub eye_separation: f32,
+    /// Distance at which the views of the eyes line up, so that things
+    /// there appear at the depth of the screen. The focus distance when
+    /// not given.
+    pub convergence: Option<f32>,
+}
+
+/// Where each eye's view goes, the left eye's coming first.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum StereoLayout {
+    #[default]
+    SideBySide,
+    TopBottom,
+}
+
+/// An average adult's, in meters.
+fn default_eye_separation() -> f32 {
+    0.064
+}
+
+impl Stereo {
+    /// Size in pixels of each eye's view in an image `width` by `height`.
+    pub fn eye_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.layout {
+            StereoLayout::SideBySide => (width / 2, height),
+            StereoLayout::TopBottom => (width, height / 2),
+        }
+    }
+
+    /// The eye seeing the point `x`, `y` of the image, 0 for the left and 1
+    /// for the right, with where that point lies in its view.
+    pub fn view(&self, x: f32, y: f32) -> (usize, f32, f32) {
+        match self.layout {
+            StereoLayout::SideBySide if x < 0.5 => (0, 2.0 * x, y),
+            StereoLayout::SideBySide => (1, 2.0 * x - 1.0, y),
+            StereoLayout::TopBottom if y < 0.5 => (0, x, 2.0 * y),
+            StereoLayout::TopBottom => (1, x, 2.0 * y - 1.0),
+        }
+    }
+
+    /// The point of the image where the point `x`, `y` of the given eye's
+    /// view lies, undoing `view`.
+    pub fn image_point(&self, eye: usize, x: f32, y: f32) -> (f32, f32) {
+        let eye = eye as f32;
+        match self.layout {
+            StereoLayout::SideBySide => ((x + eye) / 2.0, y),
+            StereoLayout::TopBottom => (x, (y + eye) / 2.0),
+        }
+    }
+
+    /// Views of the left and right eye, on either side of `camera`.
+    pub fn eyes(&self, camera: &Camera) -> [Camera; 2] {
+        let convergence = self.convergence.unwrap_or(camera.focus_distance);
+        let offset = self.eye_separation / 2.0;
+        [
+            camera.eye(-offset, convergence),
+            camera.eye(offset, convergence),
+        ]
+    }
+}
+
+/// The film back of a production camera, which frames the view by its gate
+/// rather than by the image, for output matching plates that are
+/// overscanned or shot through anamorphic lenses.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct FilmBack {
+    /// Width and height of the gate, in millimeters. The field of view
+    /// spans its height, and it fills the width of the image.
+    pub sensor_size: Vec2,
+    /// Width over height of the pixels as displayed, such as 2 for frames
+    /// squeezed by an anamorphic lens.
+    #[serde(default = "default_pixel_aspect")]
+    pub pixel_aspect: f32,
+    /// Percentage of the gate's size by which the image extends past it on
+    /// each side.
+    #[serde(default)]
+    pub overscan: f32,
+}
+
+fn default_pixel_aspect() -> f32 {
+    1.0
+}
+
+/// Brown–Conrady lens distortion with the coefficients of OpenCV's camera
+/// calibration, for renders that line up with footage from the calibrated
+/// camera. `k1` to `k3` bend lines towards or away from the center, while
+/// `p1` and `p2` account for a lens not quite parallel to the sensor.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct Distortion {
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+    pub p1: f32,
+    pub p2: f32,
+}
+
+impl Distortion {
+    /// Where the lens moves point `p` of the image plane at unit distance.
+    fn distort(&self, p: Vec2) -> Vec2 {
+        let r2 = p.dot(&p);
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let (x, y) = (p.x, p.y);
+        glm::vec2(
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+
+    /// The point that the lens moves to `p`, found by fixed-point iteration,
+    /// which converges for the distortion of real lenses.
+    fn undistort(&self, p: Vec2) -> Vec2 {
+        let mut q = p;
+        for _ in 0..20 {
+            q += p - self.distort(q);
+        }
+        q
+    }
+}
+
+/// Flaws of real lenses applied to the finished image, for a more
+/// photographic look.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct LensEffects {
+    /// Darkens perspective views towards the edges by the fourth power of
+    /// the cosine of the angle off the view axis, as light reaching the
+    /// sensor at a slant is spread over more of it.
+    pub vignetting: bool,
+    /// Fraction by which red is magnified and blue shrunk about the center
+    /// of the view, fringing edges with color towards the sides.
+    pub chromatic_aberration: f32,
+}
+
+impl LensEffects {
+    /// Color of the point `x`, `y` of the view of `camera`, where `sample`
+    /// gives the color rendered at a point of the view.
+    pub fn apply(
+        &self,
+        camera: &Camera,
+        x: f32,
+        y: f32,
+        sample: impl Fn(f32, f32) -> Vec4,
+    ) -> Vec4 {
+        let scaled = |scale: f32| {
+            let x = 0.5 + (x - 0.5) / scale;
+            let y = 0.5 + (y - 0.5) / scale;
+            sample(x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))
+        };
+        let mut color = sample(x, y);
+        let ca = self.chromatic_aberration;
+        if ca != 0.0 {
+            color.x = scaled(1.0 + ca).x;
+            color.z = scaled(1.0 - ca).z;
+        }
+        if self.vignetting {
+            let falloff = camera.falloff(x, y);
+            color.x *= falloff;
+            color.y *= falloff;
+            color.z *= falloff;
+        }
+        color
+    }
+}
+
+/// Where the camera is and which way it faces.
+#[derive(Clone, Copy)]
+struct Frame {
     position: Vec3,
-    bl_corner: Vec3,
-    horizontal: Vec3,
-    vertical: Vec3,
+    /// Unit vectors along the image's width and height, and backwards
+    /// from the view.
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn looking_at(position: Vec3, at: Vec3, up: Vec3) -> Self {
+        let w = glm::normalize(&(position - at));
+        let u: Vec3 = glm::normalize(&w.cross(&up));
+        let v = w.cross(&u);
+        Frame { position, u, v, w }
+    }
+
+    /// The frame `t` of the way from this one to `other`.
+    fn lerp(&self, other: &Frame, t: f32) -> Frame {
+        let w = glm::normalize(&glm::lerp(&self.w, &other.w, t));
+        let u = glm::lerp(&self.u, &other.u, t);
+        let u = glm::normalize(&(u - w * glm::dot(&u, &w)));
+        Frame {
+            position: glm::lerp(&self.position, &other.position, t),
+            u,
+            v: w.cross(&u),
+            w,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Camera {
+    frame: Frame,
+    /// Frame at the end of the shutter interval, for cameras that move.
+    end: Option<Frame>,
+    /// Half the size of the image, at unit distance for perspective views.
+    half_w: f32,
+    half_h: f32,
+    projection: Projection,
+    /// Offset of perspective views across the image, at unit distance.
+    shift: Vec2,
+    lens_radius: f32,
+    focus_distance: f32,
+    /// Degrees the plane of focus is turned around the image's horizontal
+    /// and vertical axes.
+    tilt: Vec2,
+    distortion: Option<Distortion>,
 }
 
 impl Camera {
     pub fn looking_at(position: Vec3, at: Vec3, up: Vec3, fov: f32, aspect: f32) -> Self {
         let theta = fov * PI / 180.0;
         let half_h = f32::tan(theta / 2.0);
-        let half_w = aspect * half_h;
+        Camera::new(position, at, up, half_h, aspect, Projection::Perspective)
+    }
 
-        let w = glm::normalize(&(position - at));
-        let u: Vec3 = glm::normalize(&w.cross(&up));
-        let v = w.cross(&u);
+    /// Camera seeing a view `height` units high around the line from
+    /// `position` to `at`, with everything in focus.
+    pub fn orthographic(position: Vec3, at: Vec3, up: Vec3, height: f32, aspect: f32) -> Self {
+        let projection = Projection::Orthographic { height };
+        Camera::new(position, at, up, height / 2.0, aspect, projection)
+    }
+
+    /// Camera seeing `fov` degrees, up to 360, across the height of the
+    /// image, around the direction from `position` to `at`.
+    pub fn fisheye(
+        position: Vec3,
+        at: Vec3,
+        up: Vec3,
+        fov: f32,
+        mapping: FisheyeMapping,
+        aspect: f32,
+    ) -> Self {
+        let projection = Projection::Fisheye { fov, mapping };
+        Camera::new(position, at, up, 1.0, aspect, projection)
+    }
+
+    /// Camera seeing all around `position`, with the direction to `at` in
+    /// the middle of the image.
+    pub fn equirectangular(position: Vec3, at: Vec3, up: Vec3) -> Self {
+        Camera::new(position, at, up, 1.0, 2.0, Projection::Equirectangular)
+    }
 
-        let bl_corner = position - half_w * u - half_h * v - w;
-        let horizontal = 2.0 * half_w * u;
-        let vertical = 2.0 * half_h * v;
+    fn new(
+        position: Vec3,
+        at: Vec3,
+        up: Vec3,
+        half_h: f32,
+        aspect: f32,
+        projection: Projection,
+    ) -> Self {
         Camera {
-            position,
-            bl_corner,
-            horizontal,
-            vertical,
+            frame: Frame::looking_at(position, at, up),
+            end: None,
+            half_w: aspect * half_h,
+            half_h,
+            projection,
+            shift: glm::zero(),
+            lens_radius: 0.0,
+            focus_distance: 1.0,
+            tilt: glm::zero(),
+            distortion: None,
         }
     }
 
-    pub fn ray_at(&self, x: f32, y: f32) -> Ray {
-        Ray::new(
-            self.position,
-            self.bl_corner + x * self.horizontal + y * self.vertical - self.position,
-        )
+    /// Frames the view by a film gate `gate_aspect` times as wide as it is
+    /// high, keeping the field of view across the gate's height while
+    /// fitting its width to the image, then widens it by `overscan`
+    /// percent on each side. Equirectangular views always see everything.
+    pub fn with_film_gate(mut self, gate_aspect: f32, overscan: f32) -> Self {
+        if let Projection::Equirectangular = self.projection {
+            return self;
+        }
+        let aspect = self.half_w / self.half_h;
+        self.half_w = self.half_h * gate_aspect * (1.0 + overscan / 100.0);
+        self.half_h = self.half_w / aspect;
+        self
+    }
+
+    /// Sees through a thin lens of radius `aperture` rather than a pinhole,
+    /// which keeps what is `focus_distance` away sharp and blurs what is
+    /// nearer or farther. Only perspective views are blurred.
+    pub fn with_lens(mut self, aperture: f32, focus_distance: f32) -> Self {
+        self.lens_radius = aperture;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// Shifts the lens across the image, by fractions of its width and
+    /// height to the right and up, which moves the view without turning
+    /// it, as for keeping verticals parallel when looking up at buildings.
+    /// Applies to perspective and orthographic views.
+    pub fn with_shift(mut self, shift: Vec2) -> Self {
+        self.shift.x += shift.x * 2.0 * self.half_w;
+        self.shift.y -= shift.y * 2.0 * self.half_h;
+        self
+    }
+
+    /// Tilts the lens `tilt` degrees up and `swing` degrees to the right,
+    /// turning the plane of focus with it, so that a receding surface can
+    /// be in focus throughout, or only a sliver of the scene as in fake
+    /// miniatures. Only blurs through an aperture.
+    pub fn with_tilt(mut self, tilt: f32, swing: f32) -> Self {
+        self.tilt = glm::vec2(tilt, swing);
+        self
+    }
+
+    /// Distorts perspective views as `distortion` describes.
+    pub fn with_distortion(mut self, distortion: Distortion) -> Self {
+        self.distortion = Some(distortion);
+        self
+    }
+
+    /// Moves the camera over the shutter interval, to end up at `position`
+    /// looking at `at`, which blurs the whole image.
+    pub fn with_motion(mut self, position: Vec3, at: Vec3, up: Vec3) -> Self {
+        self.end = Some(Frame::looking_at(position, at, up));
+        self
+    }
+
+    fn frame_at(&self, time: f32) -> Frame {
+        match &self.end {
+            Some(end) => self.frame.lerp(end, time.clamp(0.0, 1.0)),
+            None => self.frame,
+        }
+    }
+
+    /// The view of an eye `offset` to the right. Perspective views are
+    /// shifted across to line up with this one's at `convergence`, keeping
+    /// the image planes parallel; others are only moved.
+    fn eye(&self, offset: f32, convergence: f32) -> Camera {
+        let mut eye = self.clone();
+        eye.frame.position += self.frame.u * offset;
+        if let Some(end) = &mut eye.end {
+            end.position += end.u * offset;
+        }
+        if let Projection::Perspective = self.projection {
+            eye.shift.x -= offset / convergence;
+        }
+        eye
+    }
+
+    /// Width of a pixel at unit distance along its ray, for an image the
+    /// given number of pixels high. Zero for orthographic views, whose
+    /// pixels are as wide everywhere.
+    pub fn pixel_spread(&self, height: u32) -> f32 {
+        match self.projection {
+            Projection::Perspective => 2.0 * self.half_h / height as f32,
+            Projection::Orthographic { .. } => 0.0,
+            Projection::Fisheye { fov, .. } => fov.to_radians() / height as f32,
+            Projection::Equirectangular => PI / height as f32,
+        }
+    }
+
+    /// Fraction of the light reaching the center of the image that reaches
+    /// the point `x`, `y`, with cos^4 falloff through the center of the
+    /// lens. Views other than perspective ones are lit evenly.
+    fn falloff(&self, x: f32, y: f32) -> f32 {
+        if let Projection::Perspective = self.projection {
+            let (px, py) = ((2.0 * x - 1.0) * self.half_w, (2.0 * y - 1.0) * self.half_h);
+            let p = match &self.distortion {
+                Some(distortion) => distortion.undistort(glm::vec2(px, py)),
+                None => glm::vec2(px, py),
+            } + self.shift;
+            // The cosine is one over the length of (px, py, 1)
+            let cos2 = 1.0 / (1.0 + glm::dot(&p, &p));
+            cos2 * cos2
+        } else {
+            1.0
+        }
+    }
+
+    /// Ray through the point `x`, `y` of the image at `time` in the shutter
+    /// interval, leaving from the point of the lens picked by `lens`. Zero
+    /// is its center. None where the image shows nothing, such as outside
+    /// the circle of a fisheye view.
+    pub fn ray_at(&self, x: f32, y: f32, lens: Vec2, time: f32) -> Option<Ray> {
+        let Frame { position, u, v, w } = self.frame_at(time);
+        let (px, py) = ((2.0 * x - 1.0) * self.half_w, (2.0 * y - 1.0) * self.half_h);
+        let ray = match self.projection {
+            Projection::Perspective => {
+                // Each point of the image shows what the lens bends onto it
+                let p = match &self.distortion {
+                    Some(distortion) => distortion.undistort(glm::vec2(px, py)),
+                    None => glm::vec2(px, py),
+                };
+                let (px, py) = (p.x + self.shift.x, p.y + self.shift.y);
+                let r = self.lens_radius * lens.x.sqrt();
+                let phi = 2.0 * PI * lens.y;
+                let origin = position + u * (r * phi.cos()) + v * (r * phi.sin());
+                // Where the ray through the center of the lens meets the
+                // plane of focus, turned by the tilt
+                let dir = u * px + v * py - w;
+                let normal = glm::rotate_vec3(&w, self.tilt.x.to_radians(), &u);
+                let normal = glm::rotate_vec3(&normal, self.tilt.y.to_radians(), &v);
+                let t = self.focus_distance * glm::dot(&normal, &w) / glm::dot(&normal, &-dir);
+                let t = if t > 0.0 { t } else { self.focus_distance };
+                Ray::new(origin, position + dir * t - origin)
+            }
+            Projection::Orthographic { .. } => {
+                let (px, py) = (px + self.shift.x, py + self.shift.y);
+                Ray::new(position + u * px + v * py, -w)
+            }
+            Projection::Fisheye { fov, mapping } => {
+                // The top and bottom edges are one away from the center
+                let r = (px * px + py * py).sqrt();
+                let half_fov = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => {
+                        let s = r * (half_fov / 2.0).min(PI / 2.0).sin();
+                        if s > 1.0 {
+                            return None;
+                        }
+                        2.0 * s.asin()
+                    }
+                };
+                if theta > PI {
+                    return None;
+                }
+                let phi = py.atan2(px);
+                let dir = (u * phi.cos() + v * phi.sin()) * theta.sin() - w * theta.cos();
+                Ray::new(position, dir)
+            }
+            Projection::Equirectangular => {
+                // The image's height runs from straight up to straight
+                // down, along -v, and its width once around
+                let phi = 2.0 * PI * (x - 0.5);
+                let (sin_theta, cos_theta) = (PI * y).sin_cos();
+                let around = -w * phi.cos() + u * phi.sin();
+                Ray::new(position, around * sin_theta - v * cos_theta)
+            }
+        };
+        Some(ray.with_time(time))
+    }
+}
+
+/// Route of a camera flying through the scene over a sequence of frames. It
+/// passes through each waypoint in turn on a Catmull-Rom spline, reaching
+/// them at even intervals, with the first and last frames at the ends.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    pub waypoints: Vec<Waypoint>,
+    pub frames: usize,
+    /// Path each frame is written to, with `_<frame>.png` appended.
+    pub output: String,
+}
+
+#[derive(Deserialize)]
+struct CameraPathDesc {
+    waypoints: Vec<Waypoint>,
+    frames: usize,
+    output: String,
+}
+
+impl TryFrom<CameraPathDesc> for CameraPath {
+    type Error = String;
+
+    fn try_from(desc: CameraPathDesc) -> Result<Self, String> {
+        if desc.waypoints.is_empty() {
+            return Err("the camera path needs at least one waypoint".to_owned());
+        }
+        if desc.frames == 0 {
+            return Err("the camera path needs at least one frame".to_owned());
+        }
+        Ok(CameraPath {
+            waypoints: desc.waypoints,
+            frames: desc.frames,
+            output: desc.output,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for CameraPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = CameraPathDesc::deserialize(deserializer)?;
+        CameraPath::try_from(desc).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Waypoint {
+    pub position: Vec3,
+    pub looking_at: Vec3,
+}
+
+impl CameraPath {
+    /// How far through the sequence the given frame is, from 0 to 1.
+    pub fn progress(&self, frame: usize) -> f32 {
+        frame as f32 / self.frames.saturating_sub(1).max(1) as f32
+    }
+
+    /// Where the camera is and what it looks at in the given frame.
+    pub fn at_frame(&self, frame: usize) -> Waypoint {
+        let last = self.waypoints.len().saturating_sub(1);
+        let t = self.progress(frame) * last as f32;
+        let i = (t.floor() as usize).min(last.saturating_sub(1));
+        // The ends are repeated to give their segments a neighbor
+        let point = |j: isize| self.waypoints[j.max(0).min(last as isize) as usize];
+        let i = i as isize;
+        let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+        let t = t - i as f32;
+        Waypoint {
+            position: catmull_rom(p0.position, p1.position, p2.position, p3.position, t),
+            looking_at: catmull_rom(
+                p0.looking_at,
+                p1.looking_at,
+                p2.looking_at,
+                p3.looking_at,
+                t,
+            ),
+        }
+    }
+}
+
+/// Point `t` of the way from `p1` to `p2` on the uniform Catmull-Rom spline
+/// through the four points.
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let (t2, t3) = (t * t, t * t * t);
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undistort_inverts_distort() {
+        // Coefficients of the order of a wide-angle lens calibration
+        let lenses = [
+            Distortion {
+                k1: -0.28,
+                k2: 0.07,
+                k3: 0.0,
+                p1: 0.001,
+                p2: -0.0005,
+            },
+            Distortion {
+                k1: 0.12,
+                k2: -0.02,
+                k3: 0.004,
+                p1: 0.0,
+                p2: 0.0,
+            },
+        ];
+        for lens in &lenses {
+            for x in -4..=4 {
+                for y in -3..=3 {
+                    let p = glm::vec2(x as f32, y as f32) * 0.15;
+                    let back = lens.distort(lens.undistort(p));
+                    assert!(glm::distance(&back, &p) < 1e-5, "{:?} at {:?}", lens, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn undistort_without_distortion_is_identity() {
+        let p = glm::vec2(0.4, -0.3);
+        assert_eq!(Distortion::default().undistort(p), p);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_inner_points() {
+        let (p0, p1, p2, p3) = (
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(1.0, 2.0, 0.0),
+            glm::vec3(3.0, 1.0, -1.0),
+            glm::vec3(4.0, 4.0, 2.0),
+        );
+        assert!(glm::distance(&catmull_rom(p0, p1, p2, p3, 0.0), &p1) < 1e-6);
+        assert!(glm::distance(&catmull_rom(p0, p1, p2, p3, 1.0), &p2) < 1e-6);
+    }
+
+    #[test]
+    fn catmull_rom_is_linear_on_evenly_spaced_points() {
+        let point = |i: f32| glm::vec3(i, 2.0 * i, -i);
+        for &t in &[0.25, 0.5, 0.8] {
+            let p = catmull_rom(point(0.0), point(1.0), point(2.0), point(3.0), t);
+            assert!(glm::distance(&p, &point(1.0 + t)) < 1e-5, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn path_reaches_waypoints_at_even_intervals() {
+        let waypoint = |x: f32| Waypoint {
+            position: glm::vec3(x, 0.0, 0.0),
+            looking_at: glm::vec3(x, 0.0, -1.0),
+        };
+        let path = CameraPath {
+            waypoints: vec![waypoint(0.0), waypoint(2.0), waypoint(3.0)],
+            frames: 5,
+            output: String::new(),
+        };
+        for (frame, x) in [(0, 0.0), (2, 2.0), (4, 3.0)].iter() {
+            let at = path.at_frame(*frame);
+            assert!((at.position.x - x).abs() < 1e-5, "frame {}", frame);
+            assert!((at.looking_at.x - x).abs() < 1e-5, "frame {}", frame);
+        }
     }
 }
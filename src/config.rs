@@ -2,11 +2,18 @@ use std::error::Error;
 use std::fs;
 use std::path::Path;
 
-use nalgebra_glm::{zero, UVec2};
+use nalgebra_glm::{self as glm, zero, UVec2};
 use serde::Deserialize;
 
-use crate::geom::Scene;
-use crate::Vec3;
+use crate::camera::{
+    Camera, CameraPath, Distortion, FilmBack, FovAxis, LensEffects, Projection, Stereo,
+};
+use crate::geom::{BuildQuality, BuildSettings, GeomType, Mesh, Object, Scene, ToonParams};
+use crate::material::preset;
+use crate::obj;
+use crate::sampler::SamplerKind;
+use crate::texture::{ColorTexture, DEFAULT_CACHE_MB};
+use crate::{Vec2, Vec3};
 
 #[derive(Deserialize, Clone)]
 #[serde(default)]
@@ -16,9 +23,92 @@ pub struct RenderParams {
     pub max_light_bounces: usize,
     pub gamma: f32,
     pub exposure: f32,
+    /// Exposure value at ISO 100, for scenes lit in physical units. When
+    /// given it replaces `exposure`.
+    pub ev100: Option<f32>,
+    /// Sensitivity, aperture and shutter of a physical camera, which give
+    /// the exposure value in place of `ev100`.
+    pub physical_exposure: Option<PhysicalExposure>,
     pub camera_pos: Vec3,
     pub looking_at: Vec3,
+    /// Direction that is up in the image, unless looking along it.
+    pub up: Vec3,
+    /// Degrees across perspective views.
     pub fov: f32,
+    /// Whether `fov` spans the height or the width of the view, which is
+    /// that of the gate when there is a film back.
+    pub fov_axis: FovAxis,
+    /// Focal length of the lens in millimeters, which sets the field of
+    /// view of perspective views in place of `fov`.
+    pub focal_length: Option<f32>,
+    /// Width in millimeters of the sensor the focal length is taken with,
+    /// full frame by default. The gate's width when there is a film back.
+    pub sensor_width: f32,
+    /// Where the camera ends up by the close of the shutter, for motion
+    /// blur.
+    pub camera_motion: Option<CameraMotion>,
+    /// When given, renders a sequence of frames along this path in place
+    /// of a single image from `camera_pos`.
+    pub camera_path: Option<CameraPath>,
+    pub projection: Projection,
+    /// When given, renders a view for each eye into halves of the image.
+    pub stereo: Option<Stereo>,
+    /// Radius of the lens, where zero is a pinhole with everything in focus.
+    pub aperture: f32,
+    /// Offset of the lens across the image, in fractions of its width and
+    /// height to the right and up.
+    pub lens_shift: Vec2,
+    /// Degrees the lens is tilted up and swung to the right, turning the
+    /// plane of focus.
+    pub lens_tilt: Vec2,
+    /// Distortion of the lens of perspective views.
+    pub distortion: Option<Distortion>,
+    /// Distance at which things are in focus, that of the point looked at
+    /// when not given.
+    pub focus_distance: Option<f32>,
+    /// Gate, pixel aspect and overscan of the camera, when given.
+    pub film_back: Option<FilmBack>,
+    /// Vignetting and chromatic aberration of the lens, when given.
+    pub lens_effects: Option<LensEffects>,
+    /// How the numbers of each pixel sample are picked, for its position in
+    /// the pixel, on the lens and in the shutter interval and for the
+    /// directions it bounces in.
+    pub sampler: SamplerKind,
+    /// Trace primary rays in packets of several samples at once.
+    pub packets: bool,
+    /// When given, renders with toon shading and outlines instead of path
+    /// tracing.
+    pub toon: Option<ToonParams>,
+    /// Memory in megabytes that image textures may take up together. They
+    /// are loaded when first sampled, and the least recently used are
+    /// unloaded to stay within it.
+    pub texture_cache_mb: usize,
+    /// When given, also writes the light of each light group to its own
+    /// linear HDR image, named by appending `_<group>.hdr` to this path, so
+    /// the lighting can be rebalanced afterwards. Packets are not used.
+    pub light_passes: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct PhysicalExposure {
+    pub iso: f32,
+    /// Ratio of the focal length to the diameter of the aperture.
+    pub f_number: f32,
+    /// Seconds the shutter is open for.
+    pub shutter_speed: f32,
+}
+
+impl PhysicalExposure {
+    /// Exposure value at ISO 100 that these settings amount to.
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct CameraMotion {
+    pub position: Vec3,
+    pub looking_at: Vec3,
 }
 
 impl Default for RenderParams {
@@ -29,9 +119,122 @@ impl Default for RenderParams {
             max_light_bounces: 5,
             gamma: 2.2,
             exposure: 1.0,
-            camera_pos: Vec3::new(0.0, 0.0, -1.0),
+            ev100: None,
+            physical_exposure: None,
+            camera_pos: Vec3::new(0.0, 2.0, -5.0),
             looking_at: zero(),
+            up: Vec3::new(0.0, 1.0, 0.0),
             fov: 80.0,
+            fov_axis: FovAxis::default(),
+            focal_length: None,
+            sensor_width: 36.0,
+            camera_motion: None,
+            camera_path: None,
+            projection: Projection::default(),
+            stereo: None,
+            aperture: 0.0,
+            lens_shift: zero(),
+            lens_tilt: zero(),
+            distortion: None,
+            focus_distance: None,
+            film_back: None,
+            lens_effects: None,
+            sampler: SamplerKind::default(),
+            packets: false,
+            toon: None,
+            texture_cache_mb: DEFAULT_CACHE_MB,
+            light_passes: None,
+        }
+    }
+}
+
+impl RenderParams {
+    /// The camera described, seeing a view of the given aspect ratio in
+    /// pixels.
+    pub fn camera(&self, aspect: f32) -> Camera {
+        let aspect = match &self.film_back {
+            Some(film_back) => aspect * film_back.pixel_aspect,
+            None => aspect,
+        };
+        // Fields of view across the width are turned into ones across the
+        // height, of the gate when there is one
+        let fit_aspect = match &self.film_back {
+            Some(film_back) => film_back.sensor_size.x / film_back.sensor_size.y,
+            None => aspect,
+        };
+        let axis = self.fov_axis;
+        let (position, at, up) = (self.camera_pos, self.looking_at, self.up);
+        let camera = match self.projection {
+            Projection::Perspective => {
+                let fov = match self.focal_length {
+                    Some(focal_length) => {
+                        let sensor_width = match &self.film_back {
+                            Some(film_back) => film_back.sensor_size.x,
+                            None => self.sensor_width,
+                        };
+                        let fov = 2.0 * (sensor_width / (2.0 * focal_length)).atan();
+                        FovAxis::Horizontal.vertical_fov(fov.to_degrees(), fit_aspect)
+                    }
+                    None => axis.vertical_fov(self.fov, fit_aspect),
+                };
+                Camera::looking_at(position, at, up, fov, aspect)
+            }
+            Projection::Orthographic { height } => {
+                let height = axis.vertical_size(height, fit_aspect);
+                Camera::orthographic(position, at, up, height, aspect)
+            }
+            Projection::Fisheye { fov, mapping } => {
+                let fov = axis.vertical_fisheye_fov(fov, mapping, fit_aspect);
+                Camera::fisheye(position, at, up, fov, mapping, aspect)
+            }
+            Projection::Equirectangular => Camera::equirectangular(position, at, up),
+        };
+        let focus_distance = self
+            .focus_distance
+            .unwrap_or_else(|| glm::distance(&position, &at));
+        let camera = match &self.film_back {
+            Some(film_back) => {
+                let gate = film_back.sensor_size;
+                camera.with_film_gate(gate.x / gate.y, film_back.overscan)
+            }
+            None => camera,
+        };
+        let camera = camera
+            .with_lens(self.aperture, focus_distance)
+            .with_shift(self.lens_shift)
+            .with_tilt(self.lens_tilt.x, self.lens_tilt.y);
+        let camera = match self.distortion {
+            Some(distortion) => camera.with_distortion(distortion),
+            None => camera,
+        };
+        match &self.camera_motion {
+            Some(motion) => camera.with_motion(motion.position, motion.looking_at, up),
+            None => camera,
+        }
+    }
+
+    /// These parameters with the camera placed where the path has it in
+    /// the given frame.
+    pub fn at_frame(&self, path: &CameraPath, frame: usize) -> RenderParams {
+        let waypoint = path.at_frame(frame);
+        RenderParams {
+            camera_pos: waypoint.position,
+            looking_at: waypoint.looking_at,
+            ..self.clone()
+        }
+    }
+
+    /// Factor applied to radiance before tone mapping. With an exposure
+    /// value, luminance is scaled by that which saturates the sensor,
+    /// 1.2 * 2^ev100 nits.
+    pub fn exposure_scale(&self) -> f32 {
+        let ev100 = self
+            .physical_exposure
+            .map(|physical| physical.ev100())
+            .or(self.ev100);
+        match ev100 {
+            Some(ev100) => 1.0 / (1.2 * 2f32.powf(ev100)),
+            None => self.exposure,
         }
     }
 }
@@ -48,4 +251,30 @@ impl UserConfig {
         let cfg = toml::from_str(&contents)?;
         Ok(cfg)
     }
+
+    /// A scene holding just the given mesh, with the materials of its MTL
+    /// libraries, built-in presets named by its groups, or a default one.
+    pub fn from_obj(path: &Path) -> Result<Self, Box<dyn Error + '_>> {
+        let obj::Model { groups, materials } = obj::load_model(path)?;
+        let objects = groups
+            .into_iter()
+            .map(|(name, tris)| Object {
+                geometry: GeomType::Mesh(Mesh::from_triangles(tris)),
+                material: name
+                    .and_then(|name| materials.get(&name).cloned().or_else(|| preset(&name)))
+                    .unwrap_or_default(),
+                transform: None,
+                animation: None,
+            })
+            .collect();
+        let environment = ColorTexture::solid(Vec3::new(0.8, 0.8, 0.8));
+        let settings = BuildSettings {
+            quality: BuildQuality::High,
+            ..BuildSettings::default()
+        };
+        Ok(UserConfig {
+            params: RenderParams::default(),
+            scene: Scene::build(objects, environment, settings),
+        })
+    }
 }
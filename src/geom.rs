@@ -1,55 +1,188 @@
 mod aabb;
+mod accel;
+mod background;
+mod bvh;
+mod csg;
+mod cuboid;
+mod curve;
+mod cylinder;
+mod decimate;
+mod disk;
+mod displacement;
+mod distribution;
+#[cfg(feature = "embree")]
+mod embree;
+mod heightfield;
+mod ies;
+mod instance;
 mod kdtree;
+mod light;
+mod light_tree;
 mod mesh;
+mod packet;
 mod plane;
+mod polymesh;
+mod quad;
 mod scene;
+mod sdf;
 mod sphere;
+mod splat;
+mod subdivision;
+mod toon;
+mod torus;
 mod tracer;
+mod transform;
+
+use std::sync::Arc;
 
 use serde::Deserialize;
 
 pub use self::aabb::*;
+pub use self::accel::*;
+pub use self::background::*;
+pub use self::bvh::*;
+pub use self::csg::*;
+pub use self::cuboid::*;
+pub use self::curve::*;
+pub use self::cylinder::*;
+pub use self::disk::*;
+pub use self::displacement::*;
+pub use self::distribution::*;
+#[cfg(feature = "embree")]
+pub use self::embree::*;
+pub use self::heightfield::*;
+pub use self::ies::*;
+pub use self::instance::*;
 pub use self::kdtree::*;
+pub use self::light::*;
+pub use self::light_tree::*;
 pub use self::mesh::*;
+pub use self::packet::*;
 pub use self::plane::*;
+pub use self::polymesh::*;
+pub use self::quad::*;
 pub use self::scene::*;
+pub use self::sdf::*;
 pub use self::sphere::*;
+pub use self::splat::*;
+pub use self::toon::*;
+pub use self::torus::*;
 pub use self::tracer::*;
+pub use self::transform::*;
 
 use crate::material::Material;
 use crate::ray::Ray;
 
 use crate::{Vec2, Vec3};
 
-pub trait Geometry {
+/// Every geometry reports its world-space bounds, which accelerators and
+/// culling rely on.
+pub trait Geometry: Bounds {
     fn intersection(&self, ray: &Ray, min: f32, max: f32) -> Option<RayHit>;
 }
 
-pub trait Traceable {
-    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult>;
+pub trait Traceable: Bounds {
+    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>>;
+}
+
+/// Surfaces that can be sampled uniformly by area, e.g. to be used as lights.
+pub trait Sampleable: Geometry {
+    fn area(&self) -> f32;
+
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample;
+
+    /// Samples a point to be seen from `origin`. Shapes that can avoid
+    /// points hidden from it override this together with `pdf`.
+    fn sample_from(&self, _origin: &Vec3, rnd: Vec2) -> SurfaceSample {
+        self.sample_surface(rnd)
+    }
+
+    /// Density of sampling `hit` from `origin`, with respect to solid angle.
+    fn pdf(&self, origin: &Vec3, hit: &RayHit) -> f32 {
+        area_pdf(self.area(), origin, hit)
+    }
+}
+
+/// Density with respect to solid angle of sampling `hit` from `origin`
+/// uniformly over a surface of the given area.
+pub fn area_pdf(area: f32, origin: &Vec3, hit: &RayHit) -> f32 {
+    let d = hit.point - origin;
+    let dist_sq = d.dot(&d);
+    let cos = hit.geometric_normal.dot(&d).abs() / dist_sq.sqrt();
+    dist_sq / (cos * area)
+}
+
+pub struct SurfaceSample {
+    pub point: Vec3,
+    pub normal: Vec3,
 }
 
 pub struct RayHit {
     pub t: f32,
     pub point: Vec3,
     pub normal: Vec3,
+    /// Normal of the surface itself, where `normal` is interpolated or
+    /// otherwise smoothed. Densities of sampling surfaces by area use it.
+    pub geometric_normal: Vec3,
     pub uv: Vec2,
+    /// Direction of increasing `u` along the surface, where the geometry
+    /// defines one. Anisotropic materials orient their highlights along it.
+    pub tangent: Option<Vec3>,
+    /// Width of the ray's footprint on the surface in UV units, for picking
+    /// a texture level of detail. Zero samples textures at full resolution.
+    pub footprint: f32,
 }
 
 #[derive(Deserialize, Clone)]
 #[serde(untagged)]
 pub enum GeomType {
+    // Untagged variants are tried in order, so shapes whose fields are a
+    // superset of another's must come first.
+    Disk(Disk),
     Sphere(Sphere),
+    Csg(Csg),
     Plane(Plane),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Quad(Quad),
+    Torus(Torus),
+    Sdf(Sdf),
+    Heightfield(Heightfield),
+    Curves(Curves),
+    PointCloud(PointCloud),
     Mesh(Mesh),
+    #[serde(skip_deserializing)]
+    Instance(Instance),
 }
 
 impl Geometry for GeomType {
     fn intersection(&self, ray: &Ray, min: f32, max: f32) -> Option<RayHit> {
         match self {
+            GeomType::Disk(d) => d.intersection(ray, min, max),
             GeomType::Sphere(s) => s.intersection(ray, min, max),
+            GeomType::Csg(c) => c.intersection(ray, min, max),
             GeomType::Plane(p) => p.intersection(ray, min, max),
+            GeomType::Cuboid(c) => c.intersection(ray, min, max),
+            GeomType::Cylinder(c) => c.intersection(ray, min, max),
+            GeomType::Quad(q) => q.intersection(ray, min, max),
+            GeomType::Torus(t) => t.intersection(ray, min, max),
+            GeomType::Sdf(s) => s.intersection(ray, min, max),
+            GeomType::Heightfield(h) => h.intersection(ray, min, max),
+            GeomType::Curves(c) => c.intersection(ray, min, max),
+            GeomType::PointCloud(p) => p.intersection(ray, min, max),
             GeomType::Mesh(m) => m.intersection(ray, min, max),
+            GeomType::Instance(i) => i.intersection(ray, min, max),
+        }
+    }
+}
+
+impl GeomType {
+    pub fn build(self, settings: BuildSettings) -> Self {
+        match self {
+            GeomType::Mesh(m) => GeomType::Mesh(m.build(settings)),
+            GeomType::Curves(c) => GeomType::Curves(c.build(settings)),
+            GeomType::PointCloud(p) => GeomType::PointCloud(p.build(settings)),
+            other => other,
         }
     }
 }
@@ -57,9 +190,20 @@ impl Geometry for GeomType {
 impl Bounds for GeomType {
     fn bounds(&self) -> AABB {
         match self {
+            GeomType::Disk(d) => d.bounds(),
             GeomType::Sphere(s) => s.bounds(),
+            GeomType::Csg(c) => c.bounds(),
             GeomType::Plane(p) => p.bounds(),
+            GeomType::Cuboid(c) => c.bounds(),
+            GeomType::Cylinder(c) => c.bounds(),
+            GeomType::Quad(q) => q.bounds(),
+            GeomType::Torus(t) => t.bounds(),
+            GeomType::Sdf(s) => s.bounds(),
+            GeomType::Heightfield(h) => h.bounds(),
+            GeomType::Curves(c) => c.bounds(),
+            GeomType::PointCloud(p) => p.bounds(),
             GeomType::Mesh(m) => m.bounds(),
+            GeomType::Instance(i) => i.bounds(),
         }
     }
 }
@@ -68,6 +212,72 @@ impl Bounds for GeomType {
 pub struct Object {
     pub geometry: GeomType,
     pub material: Material,
+    /// Applied when the scene is built, by moving the geometry into an instance.
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    /// Moving transform whose motion spans a whole camera path sequence
+    /// rather than one shutter interval, placing the object anew each frame
+    /// in place of `transform`.
+    #[serde(default)]
+    pub animation: Option<Transform>,
+}
+
+impl Object {
+    /// Surface area of lights that can be sampled by area, including
+    /// untransformed static meshes.
+    pub fn area(&self) -> Option<f32> {
+        match (&self.geometry, &self.transform) {
+            (GeomType::Mesh(mesh), None) => {
+                Some(mesh.triangles()?.into_iter().map(Triangle::area).sum())
+            }
+            _ => self.sampleable().map(|s| s.area()),
+        }
+    }
+
+    /// The surface as one lights can be sampled on. Transformed objects and
+    /// ellipsoids cannot be.
+    pub fn sampleable(&self) -> Option<&dyn Sampleable> {
+        match (&self.geometry, &self.transform) {
+            (GeomType::Disk(d), None) => Some(d),
+            (GeomType::Quad(q), None) => Some(q),
+            (GeomType::Sphere(s), None) if s.scale.min() == s.scale.max() => Some(s),
+            (GeomType::Cuboid(c), None) => Some(c),
+            (GeomType::Cylinder(c), None) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn build(self, settings: BuildSettings) -> Self {
+        let geometry = self.geometry.build(settings);
+        let geometry = match self.transform {
+            Some(transform) => GeomType::Instance(Instance::new(Arc::new(geometry), transform)),
+            None => geometry,
+        };
+        Object {
+            geometry,
+            material: self.material,
+            transform: None,
+            animation: self.animation,
+        }
+    }
+}
+
+impl Bounds for Object {
+    fn bounds(&self) -> AABB {
+        self.geometry.bounds()
+    }
+}
+
+impl<T: Bounds> Bounds for Arc<T> {
+    fn bounds(&self) -> AABB {
+        (**self).bounds()
+    }
+}
+
+impl<T: Traceable> Traceable for Arc<T> {
+    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>> {
+        (**self).trace(ray, min, max)
+    }
 }
 
 pub struct TraceResult<'a> {
@@ -76,7 +286,7 @@ pub struct TraceResult<'a> {
 }
 
 impl Traceable for Object {
-    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult> {
+    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>> {
         self.geometry
             .intersection(ray, min, max)
             .map(|hit| TraceResult {
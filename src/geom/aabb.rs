@@ -12,6 +12,21 @@ pub trait Bounds {
 }
 
 impl AABB {
+    pub fn infinite() -> AABB {
+        let inf = f32::INFINITY;
+        AABB {
+            min: glm::vec3(-inf, -inf, -inf),
+            max: glm::vec3(inf, inf, inf),
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.min
+            .iter()
+            .chain(self.max.iter())
+            .all(|x| x.is_finite())
+    }
+
     pub fn intersects(&self, r: &Ray) -> bool {
         let tx1 = (self.min.x - r.origin.x) * r.inv_dir.x;
         let tx2 = (self.max.x - r.origin.x) * r.inv_dir.x;
@@ -57,6 +72,21 @@ impl AABB {
         };
         (left, right)
     }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn largest_axis(&self) -> usize {
+        let extent = self.max - self.min;
+        if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        }
+    }
 }
 
 impl Default for AABB {
@@ -84,6 +114,14 @@ where
     }
 }
 
+pub fn total_bounds<G: Bounds>(geoms: &[G]) -> AABB {
+    let bounds = geoms.first().map(|g| g.bounds()).unwrap_or_default();
+    geoms
+        .iter()
+        .map(Bounds::bounds)
+        .fold(bounds, |a, b| AABB::union(&a, &b))
+}
+
 fn component_minmax<'a, I: Iterator<Item = &'a Vec3>>(mut it: I) -> Option<(Vec3, Vec3)> {
     let a = *it.next()?;
     let minmax = (a, a);
@@ -0,0 +1,160 @@
+use serde::Deserialize;
+
+use crate::Ray;
+
+use super::aabb::*;
+use super::{BuildQuality, Bvh, Geometry, KdTree, RayHit, TraceResult, Traceable};
+
+/// Spatial index over a set of geometries.
+pub trait Accelerator<T>: Bounds {
+    fn build(geoms: Vec<T>, quality: BuildQuality) -> Self;
+}
+
+impl<T: Bounds + Send> Accelerator<T> for Bvh<T> {
+    fn build(geoms: Vec<T>, quality: BuildQuality) -> Self {
+        Bvh::with_quality(geoms, quality)
+    }
+}
+
+impl<T: Bounds + Clone + Sync> Accelerator<T> for KdTree<T> {
+    /// The kd-tree always uses its full SAH sweep.
+    fn build(geoms: Vec<T>, _quality: BuildQuality) -> Self {
+        KdTree::new(geoms)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AcceleratorKind {
+    #[default]
+    Bvh,
+    KdTree,
+    /// Meshes are handed to Embree, while everything else uses a BVH.
+    #[cfg(feature = "embree")]
+    Embree,
+}
+
+/// Which accelerator to build, and how carefully.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BuildSettings {
+    pub accelerator: AcceleratorKind,
+    pub quality: BuildQuality,
+}
+
+/// One of the available accelerators, or a plain list of geometries
+/// that has not been built yet.
+#[derive(Clone)]
+pub enum Accel<T> {
+    Linear { bounds: AABB, geoms: Vec<T> },
+    Bvh(Bvh<T>),
+    KdTree(KdTree<T>),
+}
+
+impl<T: Bounds + Clone + Send + Sync> Accel<T> {
+    pub fn new(geoms: Vec<T>, settings: BuildSettings) -> Self {
+        match settings.accelerator {
+            AcceleratorKind::Bvh => Accel::Bvh(Accelerator::build(geoms, settings.quality)),
+            #[cfg(feature = "embree")]
+            AcceleratorKind::Embree => Accel::Bvh(Accelerator::build(geoms, settings.quality)),
+            AcceleratorKind::KdTree => Accel::KdTree(Accelerator::build(geoms, settings.quality)),
+        }
+    }
+
+    pub fn linear(geoms: Vec<T>) -> Self {
+        let bounds = total_bounds(&geoms);
+        Accel::Linear { bounds, geoms }
+    }
+
+    /// Builds the configured accelerator if this is still a plain list.
+    pub fn build(self, settings: BuildSettings) -> Self {
+        match self {
+            Accel::Linear { geoms, .. } => Self::new(geoms, settings),
+            built => built,
+        }
+    }
+}
+
+impl<T> Accel<T> {
+    /// Every geometry once, where the accelerator keeps them so. Kd-trees
+    /// may reference a geometry from several leaves.
+    pub fn geoms(&self) -> Option<Vec<&T>> {
+        match self {
+            Accel::Linear { geoms, .. } => Some(geoms.iter().collect()),
+            Accel::Bvh(bvh) => {
+                let mut geoms = Vec::new();
+                bvh.collect_geoms(&mut geoms);
+                Some(geoms)
+            }
+            Accel::KdTree(_) => None,
+        }
+    }
+}
+
+impl<T: Bounds> Accel<T> {
+    /// Updates the geometries in place and refits the bounds around them,
+    /// returning false if this accelerator has to be rebuilt instead.
+    pub fn refit<F: FnMut(&mut T)>(&mut self, update: &mut F) -> bool {
+        match self {
+            Accel::Linear { bounds, geoms } => {
+                geoms.iter_mut().for_each(update);
+                *bounds = total_bounds(geoms);
+                true
+            }
+            Accel::Bvh(bvh) => {
+                bvh.refit(update);
+                true
+            }
+            Accel::KdTree(_) => false,
+        }
+    }
+}
+
+impl<T: Geometry> Geometry for Accel<T> {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        match self {
+            Accel::Linear { geoms, .. } => {
+                let mut max = max;
+                let mut result = None;
+                for geom in geoms {
+                    if let Some(hit) = geom.intersection(r, min, max) {
+                        max = hit.t;
+                        result = Some(hit);
+                    }
+                }
+                result
+            }
+            Accel::Bvh(bvh) => bvh.intersection(r, min, max),
+            Accel::KdTree(tree) => tree.intersection(r, min, max),
+        }
+    }
+}
+
+impl<T: Traceable> Traceable for Accel<T> {
+    fn trace(&self, r: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>> {
+        match self {
+            Accel::Linear { geoms, .. } => {
+                let mut max = max;
+                let mut result = None;
+                for geom in geoms {
+                    if let Some(traced) = geom.trace(r, min, max) {
+                        max = traced.hit.t;
+                        result = Some(traced);
+                    }
+                }
+                result
+            }
+            Accel::Bvh(bvh) => bvh.trace(r, min, max),
+            Accel::KdTree(tree) => tree.trace(r, min, max),
+        }
+    }
+}
+
+impl<T> Bounds for Accel<T> {
+    fn bounds(&self) -> AABB {
+        match self {
+            Accel::Linear { bounds, .. } => bounds.clone(),
+            Accel::Bvh(bvh) => bvh.bounds(),
+            Accel::KdTree(tree) => tree.bounds(),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
+
+use super::Sphere;
+use crate::texture::{ColorTexture, Texture as _};
+use crate::vec::{Vec3, Vec4};
+
+/// What camera rays escaping the scene show. Reflections and lighting see
+/// the environment whatever the background.
+#[derive(Clone, Default)]
+pub enum Background {
+    /// The environment itself.
+    #[default]
+    Environment,
+    /// A solid color, gradient or any other texture in latitude-longitude
+    /// layout.
+    Texture(ColorTexture),
+    /// Nothing, leaving the pixels transparent for compositing.
+    Transparent,
+}
+
+impl Background {
+    pub fn is_transparent(&self) -> bool {
+        matches!(self, Background::Transparent)
+    }
+
+    /// Color and alpha seen in `dir`.
+    pub fn color(&self, dir: &Vec3, environment: &ColorTexture) -> Vec4 {
+        let uv = Sphere::uv_at_dir(dir);
+        let color = match self {
+            Background::Environment => environment.sample(uv),
+            Background::Texture(texture) => texture.sample(uv),
+            Background::Transparent => return glm::zero(),
+        };
+        glm::vec4(color.x, color.y, color.z, 1.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Keyword {
+    Environment,
+    Transparent,
+}
+
+// Keywords are tried before textures, whose strings are file paths.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Keyword(Keyword),
+    Texture(ColorTexture),
+}
+
+impl<'de> Deserialize<'de> for Background {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match BackgroundDesc::deserialize(deserializer)? {
+            BackgroundDesc::Keyword(Keyword::Environment) => Background::Environment,
+            BackgroundDesc::Keyword(Keyword::Transparent) => Background::Transparent,
+            BackgroundDesc::Texture(texture) => Background::Texture(texture),
+        })
+    }
+}
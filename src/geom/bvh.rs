@@ -0,0 +1,313 @@
+use serde::Deserialize;
+
+use crate::vec::Vec3;
+use crate::Ray;
+
+use super::aabb::*;
+use super::{Geometry, RayHit, TraceResult, Traceable};
+
+/// Sets of at most this many geometries are stored in a single leaf,
+/// so tiny scenes degrade to a plain linear search.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Above this many geometries a node is always split, even when the
+/// SAH estimates a leaf to be cheaper.
+const MAX_SAH_LEAF_SIZE: usize = 16;
+
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECT_COST: f32 = 2.0;
+
+#[derive(Clone)]
+pub enum Bvh<T> {
+    Leaf {
+        bounds: AABB,
+        geoms: Vec<T>,
+    },
+    Node {
+        bounds: AABB,
+        left: Box<Bvh<T>>,
+        right: Box<Bvh<T>>,
+    },
+}
+
+/// How much effort goes into building a hierarchy.
+/// `Fast` splits at the object median, `Medium` uses a binned SAH along the
+/// widest axis, and `High` uses a finer binned SAH along all three axes.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildQuality {
+    Fast,
+    #[default]
+    Medium,
+    High,
+}
+
+impl BuildQuality {
+    fn bins(self) -> Option<usize> {
+        match self {
+            BuildQuality::Fast => None,
+            BuildQuality::Medium => Some(12),
+            BuildQuality::High => Some(32),
+        }
+    }
+}
+
+enum Split {
+    Leaf,
+    Median,
+    Plane { dim: usize, pos: f32 },
+}
+
+impl<T: Bounds + Send> Bvh<T> {
+    pub fn new(geoms: Vec<T>) -> Self {
+        Self::with_quality(geoms, BuildQuality::default())
+    }
+
+    pub fn with_quality(geoms: Vec<T>, quality: BuildQuality) -> Self {
+        let bounds = total_bounds(&geoms);
+        Self::build(bounds, geoms, quality)
+    }
+
+    fn build(bounds: AABB, geoms: Vec<T>, quality: BuildQuality) -> Self {
+        if geoms.len() <= MAX_LEAF_SIZE {
+            return Bvh::Leaf { bounds, geoms };
+        }
+        let boxes: Vec<AABB> = geoms.iter().map(Bounds::bounds).collect();
+        let centers: Vec<Vec3> = boxes.iter().map(AABB::center).collect();
+        let split = match quality.bins() {
+            Some(bins) => sah_split(
+                &boxes,
+                &centers,
+                &bounds,
+                bins,
+                quality == BuildQuality::High,
+            ),
+            None => Split::Median,
+        };
+        let (left_geoms, right_geoms) = match split {
+            Split::Leaf => return Bvh::Leaf { bounds, geoms },
+            Split::Median => median_partition(geoms),
+            Split::Plane { dim, pos } => {
+                let (left, right): (Vec<_>, Vec<_>) = geoms
+                    .into_iter()
+                    .zip(centers.iter())
+                    .partition(|(_, c)| c[dim] < pos);
+                let left: Vec<T> = left.into_iter().map(|(g, _)| g).collect();
+                let right: Vec<T> = right.into_iter().map(|(g, _)| g).collect();
+                if left.is_empty() || right.is_empty() {
+                    let mut geoms = left;
+                    geoms.extend(right);
+                    median_partition(geoms)
+                } else {
+                    (left, right)
+                }
+            }
+        };
+        let (left, right) = rayon::join(
+            || Bvh::with_quality(left_geoms, quality),
+            || Bvh::with_quality(right_geoms, quality),
+        );
+        Bvh::Node {
+            bounds,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+}
+
+impl<T> Bvh<T> {
+    /// Appends the geometries of all leaves, which hold each exactly once.
+    pub fn collect_geoms<'a>(&'a self, out: &mut Vec<&'a T>) {
+        match self {
+            Bvh::Leaf { geoms, .. } => out.extend(geoms.iter()),
+            Bvh::Node { left, right, .. } => {
+                left.collect_geoms(out);
+                right.collect_geoms(out);
+            }
+        }
+    }
+}
+
+impl<T: Bounds> Bvh<T> {
+    /// Passes every geometry to `update`, then recomputes all node bounds
+    /// while keeping the tree's structure. Much cheaper than a rebuild, but
+    /// the tree degrades if geometries move far from where they were.
+    pub fn refit<F: FnMut(&mut T)>(&mut self, update: &mut F) {
+        match self {
+            Bvh::Leaf { bounds, geoms } => {
+                geoms.iter_mut().for_each(update);
+                *bounds = total_bounds(geoms);
+            }
+            Bvh::Node {
+                bounds,
+                left,
+                right,
+            } => {
+                left.refit(update);
+                right.refit(update);
+                *bounds = left.bounds().union(&right.bounds());
+            }
+        }
+    }
+}
+
+fn median_partition<T: Bounds>(geoms: Vec<T>) -> (Vec<T>, Vec<T>) {
+    let centers: Vec<Vec3> = geoms.iter().map(|g| g.bounds().center()).collect();
+    let dim = AABB::from(centers.iter()).largest_axis();
+    let mut keyed: Vec<(f32, T)> = centers.iter().map(|c| c[dim]).zip(geoms).collect();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("Tried sorting NaNs"));
+    let right = keyed
+        .split_off(keyed.len() / 2)
+        .into_iter()
+        .map(|(_, g)| g)
+        .collect();
+    let left = keyed.into_iter().map(|(_, g)| g).collect();
+    (left, right)
+}
+
+fn sah_split(
+    boxes: &[AABB],
+    centers: &[Vec3],
+    bounds: &AABB,
+    bins: usize,
+    all_axes: bool,
+) -> Split {
+    let parent_area = bounds.surface_area();
+    if parent_area <= 0.0 {
+        return Split::Median;
+    }
+    let centroid_bounds = AABB::from(centers.iter());
+    let axes = if all_axes {
+        vec![0, 1, 2]
+    } else {
+        vec![centroid_bounds.largest_axis()]
+    };
+
+    let mut best: Option<(f32, usize, f32)> = None;
+    for dim in axes {
+        let lo = centroid_bounds.min[dim];
+        let extent = centroid_bounds.max[dim] - lo;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut counts = vec![0usize; bins];
+        let mut bin_bounds: Vec<Option<AABB>> = vec![None; bins];
+        for (b, c) in boxes.iter().zip(centers) {
+            let i = usize::min(((c[dim] - lo) / extent * bins as f32) as usize, bins - 1);
+            counts[i] += 1;
+            bin_bounds[i] = Some(merge(&bin_bounds[i], b));
+        }
+
+        let mut right_area = vec![0.0; bins];
+        let mut right_count = vec![0usize; bins];
+        let mut acc = None;
+        let mut count = 0;
+        for i in (1..bins).rev() {
+            if let Some(b) = &bin_bounds[i] {
+                acc = Some(merge(&acc, b));
+            }
+            count += counts[i];
+            right_area[i] = acc.as_ref().map_or(0.0, AABB::surface_area);
+            right_count[i] = count;
+        }
+
+        let mut acc = None;
+        let mut count = 0;
+        for i in 0..bins - 1 {
+            if let Some(b) = &bin_bounds[i] {
+                acc = Some(merge(&acc, b));
+            }
+            count += counts[i];
+            let left_area = acc.as_ref().map_or(0.0, AABB::surface_area);
+            let cost = TRAVERSAL_COST
+                + INTERSECT_COST
+                    * (left_area * count as f32 + right_area[i + 1] * right_count[i + 1] as f32)
+                    / parent_area;
+            if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                let pos = lo + extent * (i + 1) as f32 / bins as f32;
+                best = Some((cost, dim, pos));
+            }
+        }
+    }
+
+    let leaf_cost = INTERSECT_COST * boxes.len() as f32;
+    match best {
+        Some((cost, dim, pos)) if cost < leaf_cost || boxes.len() > MAX_SAH_LEAF_SIZE => {
+            Split::Plane { dim, pos }
+        }
+        Some(_) => Split::Leaf,
+        None => Split::Median,
+    }
+}
+
+fn merge(acc: &Option<AABB>, b: &AABB) -> AABB {
+    match acc {
+        Some(a) => a.union(b),
+        None => b.clone(),
+    }
+}
+
+impl<T: Geometry> Geometry for Bvh<T> {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        match self {
+            Bvh::Leaf { bounds, geoms } if bounds.intersects(r) => {
+                let mut max = max;
+                let mut result = None;
+                for geom in geoms {
+                    if let Some(hit) = geom.intersection(r, min, max) {
+                        max = hit.t;
+                        result = Some(hit);
+                    }
+                }
+                result
+            }
+            Bvh::Node {
+                bounds,
+                left,
+                right,
+            } if bounds.intersects(r) => {
+                let left = left.intersection(r, min, max);
+                let max = left.as_ref().map_or(max, |hit| hit.t);
+                right.intersection(r, min, max).or(left)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T: Traceable> Traceable for Bvh<T> {
+    fn trace(&self, r: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>> {
+        match self {
+            Bvh::Leaf { bounds, geoms } if bounds.intersects(r) => {
+                let mut max = max;
+                let mut result = None;
+                for geom in geoms {
+                    if let Some(traced) = geom.trace(r, min, max) {
+                        max = traced.hit.t;
+                        result = Some(traced);
+                    }
+                }
+                result
+            }
+            Bvh::Node {
+                bounds,
+                left,
+                right,
+            } if bounds.intersects(r) => {
+                let left = left.trace(r, min, max);
+                let max = left.as_ref().map_or(max, |traced| traced.hit.t);
+                right.trace(r, min, max).or(left)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T> Bounds for Bvh<T> {
+    fn bounds(&self) -> AABB {
+        match self {
+            Bvh::Leaf { bounds, .. } | Bvh::Node { bounds, .. } => bounds.clone(),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+
+/// Closed shapes that can report every span of a ray lying inside them.
+pub trait Solid {
+    /// Disjoint spans along the whole ray, in ascending order.
+    fn intervals(&self, r: &Ray) -> Vec<Interval>;
+}
+
+pub struct Interval {
+    pub enter: RayHit,
+    pub exit: RayHit,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            CsgOp::Union => a || b,
+            CsgOp::Intersection => a && b,
+            CsgOp::Difference => a && !b,
+        }
+    }
+}
+
+/// Boolean combination of two solids.
+#[derive(Deserialize, Clone)]
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Box<CsgShape>,
+    pub right: Box<CsgShape>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum CsgShape {
+    Csg(Csg),
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Torus(Torus),
+}
+
+impl Solid for CsgShape {
+    fn intervals(&self, r: &Ray) -> Vec<Interval> {
+        match self {
+            CsgShape::Csg(c) => c.intervals(r),
+            CsgShape::Sphere(s) => s.intervals(r),
+            CsgShape::Cuboid(c) => c.intervals(r),
+            CsgShape::Cylinder(c) => c.intervals(r),
+            CsgShape::Torus(t) => t.intervals(r),
+        }
+    }
+}
+
+impl Bounds for CsgShape {
+    fn bounds(&self) -> AABB {
+        match self {
+            CsgShape::Csg(c) => c.bounds(),
+            CsgShape::Sphere(s) => s.bounds(),
+            CsgShape::Cuboid(c) => c.bounds(),
+            CsgShape::Cylinder(c) => c.bounds(),
+            CsgShape::Torus(t) => t.bounds(),
+        }
+    }
+}
+
+struct Event {
+    hit: RayHit,
+    left: bool,
+    enter: bool,
+}
+
+impl Solid for Csg {
+    fn intervals(&self, r: &Ray) -> Vec<Interval> {
+        let events = |intervals: Vec<Interval>, left: bool| {
+            intervals
+                .into_iter()
+                .flat_map(move |Interval { enter, exit }| {
+                    vec![
+                        Event {
+                            hit: enter,
+                            left,
+                            enter: true,
+                        },
+                        Event {
+                            hit: exit,
+                            left,
+                            enter: false,
+                        },
+                    ]
+                })
+        };
+        let mut events: Vec<Event> = events(self.left.intervals(r), true)
+            .chain(events(self.right.intervals(r), false))
+            .collect();
+        events.sort_by(|a, b| {
+            a.hit
+                .t
+                .partial_cmp(&b.hit.t)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let (mut in_left, mut in_right) = (false, false);
+        let mut start: Option<RayHit> = None;
+        let mut result = Vec::new();
+        for Event {
+            mut hit,
+            left,
+            enter,
+        } in events
+        {
+            if left {
+                in_left = enter;
+            } else {
+                in_right = enter;
+            }
+            let inside = self.op.inside(in_left, in_right);
+            if inside == start.is_some() {
+                continue;
+            }
+            // Leaving an operand can enter the result and vice versa,
+            // in which case the surface faces the other way.
+            if inside != enter {
+                hit.normal = -hit.normal;
+            }
+            match start.take() {
+                Some(enter) => result.push(Interval { enter, exit: hit }),
+                None => start = Some(hit),
+            }
+        }
+        result
+    }
+}
+
+impl Geometry for Csg {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        self.intervals(r)
+            .into_iter()
+            .flat_map(|Interval { enter, exit }| vec![enter, exit])
+            .find(|hit| hit.t > min && hit.t < max)
+    }
+}
+
+impl Bounds for Csg {
+    fn bounds(&self) -> AABB {
+        let (left, right) = (self.left.bounds(), self.right.bounds());
+        match self.op {
+            CsgOp::Union => left.union(&right),
+            CsgOp::Intersection => AABB {
+                min: glm::max2(&left.min, &right.min),
+                max: glm::min2(&left.max, &right.max),
+            },
+            CsgOp::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(x: f32) -> Box<CsgShape> {
+        Box::new(CsgShape::Sphere(Sphere {
+            center: glm::vec3(x, 0.0, 0.0),
+            radius: 1.0,
+            scale: glm::vec3(1.0, 1.0, 1.0),
+        }))
+    }
+
+    /// Spans of a ray along the x axis through two unit spheres a unit
+    /// apart, with the x component of the normals at their ends.
+    fn spans(op: CsgOp) -> Vec<(f32, f32, f32, f32)> {
+        let csg = Csg {
+            op,
+            left: sphere(0.0),
+            right: sphere(1.0),
+        };
+        let r = Ray::new(glm::vec3(-5.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0));
+        csg.intervals(&r)
+            .into_iter()
+            .map(|i| (i.enter.t, i.exit.t, i.enter.normal.x, i.exit.normal.x))
+            .collect()
+    }
+
+    fn assert_spans(op: CsgOp, expected: &[(f32, f32, f32, f32)]) {
+        let spans = spans(op);
+        assert_eq!(spans.len(), expected.len(), "{:?}: {:?}", op, spans);
+        for (span, expected) in spans.iter().zip(expected) {
+            let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+            assert!(
+                close(span.0, expected.0)
+                    && close(span.1, expected.1)
+                    && close(span.2, expected.2)
+                    && close(span.3, expected.3),
+                "{:?}: {:?}",
+                op,
+                spans
+            );
+        }
+    }
+
+    #[test]
+    fn union_spans_both() {
+        assert_spans(CsgOp::Union, &[(4.0, 7.0, -1.0, 1.0)]);
+    }
+
+    #[test]
+    fn intersection_spans_overlap() {
+        assert_spans(CsgOp::Intersection, &[(5.0, 6.0, -1.0, 1.0)]);
+    }
+
+    #[test]
+    fn difference_faces_out_of_the_cut() {
+        // Leaving through the right sphere's surface, whose normal there
+        // is flipped to face out of the result
+        assert_spans(CsgOp::Difference, &[(4.0, 5.0, -1.0, 1.0)]);
+    }
+
+    #[test]
+    fn disjoint_union_keeps_both_spans() {
+        let csg = Csg {
+            op: CsgOp::Union,
+            left: sphere(0.0),
+            right: sphere(3.0),
+        };
+        let r = Ray::new(glm::vec3(-5.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0));
+        let spans: Vec<(f32, f32)> = csg
+            .intervals(&r)
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect();
+        assert_eq!(spans.len(), 2, "{:?}", spans);
+        assert!((spans[0].0 - 4.0).abs() < 1e-4 && (spans[0].1 - 6.0).abs() < 1e-4);
+        assert!((spans[1].0 - 7.0).abs() < 1e-4 && (spans[1].1 - 9.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,138 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::Vec3;
+
+/// Axis-aligned box spanning `min` to `max`.
+#[derive(Deserialize, Clone)]
+pub struct Cuboid {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Cuboid {
+    fn face_uv(&self, point: &Vec3, dim: usize) -> Vec2 {
+        let size = self.max - self.min;
+        let (a, b) = ((dim + 1) % 3, (dim + 2) % 3);
+        let u = (point[a] - self.min[a]) / size[a];
+        let v = (point[b] - self.min[b]) / size[b];
+        glm::vec2(u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+    }
+}
+
+impl Cuboid {
+    /// Entry and exit distances along `r`, each with the axis of the face hit.
+    fn slabs(&self, r: &Ray) -> Option<((f32, usize), (f32, usize))> {
+        let mut near = (f32::NEG_INFINITY, 0);
+        let mut far = (f32::INFINITY, 0);
+        for dim in 0..3 {
+            let t1 = (self.min[dim] - r.origin[dim]) * r.inv_dir[dim];
+            let t2 = (self.max[dim] - r.origin[dim]) * r.inv_dir[dim];
+            let (t1, t2) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+            if t1 > near.0 {
+                near = (t1, dim);
+            }
+            if t2 < far.0 {
+                far = (t2, dim);
+            }
+        }
+        if near.0 > far.0 {
+            None
+        } else {
+            Some((near, far))
+        }
+    }
+
+    fn hit_at(&self, r: &Ray, t: f32, dim: usize) -> RayHit {
+        let point = r.point_at(t);
+        let center = (self.min + self.max) * 0.5;
+        let mut normal: Vec3 = glm::zero();
+        normal[dim] = if point[dim] < center[dim] { -1.0 } else { 1.0 };
+        let uv = self.face_uv(&point, dim);
+        RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent: None,
+            footprint: 0.0,
+        }
+    }
+}
+
+impl Geometry for Cuboid {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let (near, far) = self.slabs(r)?;
+        let (t, dim) = if near.0 > min && near.0 < max {
+            near
+        } else if far.0 > min && far.0 < max {
+            far
+        } else {
+            return None;
+        };
+        Some(self.hit_at(r, t, dim))
+    }
+}
+
+impl Sampleable for Cuboid {
+    fn area(&self) -> f32 {
+        let s = self.max - self.min;
+        2.0 * (s.x * s.y + s.y * s.z + s.z * s.x)
+    }
+
+    /// Picks a face in proportion to its area, reusing what is left of
+    /// `rnd.x` for the point on it.
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        let s = self.max - self.min;
+        // Both faces across each axis together
+        let pairs = [s.y * s.z, s.z * s.x, s.x * s.y];
+        let mut pick = rnd.x * (pairs[0] + pairs[1] + pairs[2]);
+        let mut dim = 2;
+        for (i, &area) in pairs.iter().enumerate() {
+            if pick < area {
+                dim = i;
+                break;
+            }
+            pick -= area;
+        }
+        let t = (pick / pairs[dim]).clamp(0.0, 1.0);
+        let (side, t) = if t < 0.5 {
+            (0.0, t * 2.0)
+        } else {
+            (1.0, t * 2.0 - 1.0)
+        };
+        let (a, b) = ((dim + 1) % 3, (dim + 2) % 3);
+        let mut point = self.min;
+        point[dim] += s[dim] * side;
+        point[a] += s[a] * t;
+        point[b] += s[b] * rnd.y;
+        let mut normal: Vec3 = glm::zero();
+        normal[dim] = side * 2.0 - 1.0;
+        SurfaceSample { point, normal }
+    }
+}
+
+impl Solid for Cuboid {
+    fn intervals(&self, r: &Ray) -> Vec<Interval> {
+        match self.slabs(r) {
+            Some(((t0, dim0), (t1, dim1))) => vec![Interval {
+                enter: self.hit_at(r, t0, dim0),
+                exit: self.hit_at(r, t1, dim1),
+            }],
+            None => vec![],
+        }
+    }
+}
+
+impl Bounds for Cuboid {
+    fn bounds(&self) -> AABB {
+        AABB {
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
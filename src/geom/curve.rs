@@ -0,0 +1,185 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::Vec3;
+
+/// A piece of a curve, approximated by a cylinder with rounded ends.
+/// `v0` and `v1` are the curve parameters at either end.
+#[derive(Clone)]
+pub struct CurveSegment {
+    start: Vec3,
+    end: Vec3,
+    radius: f32,
+    v0: f32,
+    v1: f32,
+}
+
+/// Cubic Bézier curves with a width tapering from root to tip, suitable for
+/// hair and grass. Each curve is split into straight segments, which are kept
+/// in their own accelerator.
+///
+/// Shading normals are those of the swept tube, so thin strands shade like
+/// cylinders. The `v` texture coordinate runs from root to tip.
+#[derive(Clone)]
+pub struct Curves {
+    tree: Accel<CurveSegment>,
+}
+
+#[derive(Deserialize)]
+struct CurvesDesc {
+    curves: Vec<[Vec3; 4]>,
+    width: f32,
+    #[serde(default)]
+    tip_width: Option<f32>,
+    #[serde(default = "default_segments")]
+    segments: usize,
+}
+
+fn default_segments() -> usize {
+    8
+}
+
+fn bezier(p: &[Vec3; 4], t: f32) -> Vec3 {
+    let s = 1.0 - t;
+    p[0] * (s * s * s) + p[1] * (3.0 * s * s * t) + p[2] * (3.0 * s * t * t) + p[3] * (t * t * t)
+}
+
+impl Curves {
+    pub fn new(curves: &[[Vec3; 4]], width: f32, tip_width: f32, segments: usize) -> Self {
+        let segments = segments.max(1);
+        let mut pieces = Vec::with_capacity(curves.len() * segments);
+        for control in curves {
+            for i in 0..segments {
+                let v0 = i as f32 / segments as f32;
+                let v1 = (i + 1) as f32 / segments as f32;
+                let mid = (v0 + v1) * 0.5;
+                pieces.push(CurveSegment {
+                    start: bezier(control, v0),
+                    end: bezier(control, v1),
+                    radius: (width + (tip_width - width) * mid) * 0.5,
+                    v0,
+                    v1,
+                });
+            }
+        }
+        Curves {
+            tree: Accel::linear(pieces),
+        }
+    }
+
+    pub fn build(self, settings: BuildSettings) -> Self {
+        Curves {
+            tree: self.tree.build(settings),
+        }
+    }
+}
+
+impl CurveSegment {
+    fn hit_at(&self, r: &Ray, t: f32, center: Vec3, v: f32) -> RayHit {
+        let point = r.point_at(t);
+        RayHit {
+            t,
+            point,
+            normal: (point - center) / self.radius,
+            geometric_normal: (point - center) / self.radius,
+            uv: glm::vec2(0.5, v),
+            // Along the strand, so highlights stretch across it as on hair
+            tangent: Some(glm::normalize(&(self.end - self.start))),
+            footprint: 0.0,
+        }
+    }
+}
+
+impl Geometry for CurveSegment {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let ba = self.end - self.start;
+        let oa = r.origin - self.start;
+        let baba = glm::dot(&ba, &ba);
+        let bard = glm::dot(&ba, &r.direction);
+        let baoa = glm::dot(&ba, &oa);
+        let dd = glm::dot(&r.direction, &r.direction);
+        let rr = self.radius * self.radius;
+
+        let mut best: Option<RayHit> = None;
+        let mut max = max;
+
+        // Tube between the two ends
+        let a = baba * dd - bard * bard;
+        let b = baba * glm::dot(&r.direction, &oa) - baoa * bard;
+        let c = baba * glm::dot(&oa, &oa) - baoa * baoa - rr * baba;
+        let h = b * b - a * c;
+        if a.abs() > 1e-12 && h >= 0.0 {
+            for &t in &[(-b - h.sqrt()) / a, (-b + h.sqrt()) / a] {
+                let y = baoa + t * bard;
+                if t > min && t < max && y > 0.0 && y < baba {
+                    let s = y / baba;
+                    let hit =
+                        self.hit_at(r, t, self.start + ba * s, self.v0 + (self.v1 - self.v0) * s);
+                    max = t;
+                    best = Some(hit);
+                    break;
+                }
+            }
+        }
+
+        // Rounded ends, so consecutive segments join without gaps
+        for &(center, v) in &[(self.start, self.v0), (self.end, self.v1)] {
+            let oc = r.origin - center;
+            let b = glm::dot(&r.direction, &oc);
+            let c = glm::dot(&oc, &oc) - rr;
+            let h = b * b - dd * c;
+            if h < 0.0 {
+                continue;
+            }
+            for &t in &[(-b - h.sqrt()) / dd, (-b + h.sqrt()) / dd] {
+                if t > min && t < max {
+                    max = t;
+                    best = Some(self.hit_at(r, t, center, v));
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+impl Bounds for CurveSegment {
+    fn bounds(&self) -> AABB {
+        let extent = glm::vec3(self.radius, self.radius, self.radius);
+        let corners = [
+            self.start - extent,
+            self.start + extent,
+            self.end - extent,
+            self.end + extent,
+        ];
+        AABB::from(corners.iter())
+    }
+}
+
+impl Geometry for Curves {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        self.tree.intersection(r, min, max)
+    }
+}
+
+impl Bounds for Curves {
+    fn bounds(&self) -> AABB {
+        self.tree.bounds()
+    }
+}
+
+impl<'de> Deserialize<'de> for Curves {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = CurvesDesc::deserialize(deserializer)?;
+        let tip_width = desc.tip_width.unwrap_or(desc.width);
+        Ok(Curves::new(
+            &desc.curves,
+            desc.width,
+            tip_width,
+            desc.segments,
+        ))
+    }
+}
@@ -0,0 +1,251 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::vec::{self, Vec3};
+
+/// Cylinder of `radius` around the segment from `start` to `end`,
+/// closed off by disks at both ends unless `capped` is false.
+#[derive(Deserialize, Clone)]
+pub struct Cylinder {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: f32,
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+impl Cylinder {
+    fn axis(&self) -> (Vec3, f32) {
+        let d = self.end - self.start;
+        let height = glm::length(&d);
+        (d / height, height)
+    }
+
+    /// Distances along `r` where it crosses the infinite cylinder around the axis.
+    fn side_roots(&self, r: &Ray, axis: &Vec3) -> Option<(f32, f32)> {
+        let oc = r.origin - self.start;
+        let d_perp = r.direction - axis * glm::dot(&r.direction, axis);
+        let oc_perp = oc - axis * glm::dot(&oc, axis);
+        let a = glm::dot(&d_perp, &d_perp);
+        let b = glm::dot(&d_perp, &oc_perp);
+        let c = glm::dot(&oc_perp, &oc_perp) - self.radius * self.radius;
+        let delta = b * b - a * c;
+        if a > 1e-8 && delta > 0.0 {
+            let sqrt = delta.sqrt();
+            Some(((-b - sqrt) / a, (-b + sqrt) / a))
+        } else {
+            None
+        }
+    }
+
+    fn side_hit(&self, r: &Ray, t: f32, axis: &Vec3, height: f32) -> Option<RayHit> {
+        let y = glm::dot(&(r.point_at(t) - self.start), axis);
+        if y < 0.0 || y > height {
+            return None;
+        }
+        Some(self.side_hit_at(r, t, axis, height))
+    }
+
+    fn side_hit_at(&self, r: &Ray, t: f32, axis: &Vec3, height: f32) -> RayHit {
+        let point = r.point_at(t);
+        let rel = point - self.start;
+        let y = glm::dot(&rel, axis);
+        let normal = (rel - axis * y) / self.radius;
+        let (u, v) = vec::orthonormal_basis(axis);
+        let phi = f32::atan2(glm::dot(&normal, &v), glm::dot(&normal, &u));
+        let uv = glm::vec2(0.5 + phi / glm::two_pi::<f32>(), y / height);
+        RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent: None,
+            footprint: 0.0,
+        }
+    }
+
+    fn cap_hit(&self, r: &Ray, center: &Vec3, normal: Vec3, min: f32, max: f32) -> Option<RayHit> {
+        let denom = glm::dot(&r.direction, &normal);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = glm::dot(&(center - r.origin), &normal) / denom;
+        if t <= min || t >= max {
+            return None;
+        }
+        let rel = r.point_at(t) - center;
+        if glm::dot(&rel, &rel) > self.radius * self.radius {
+            return None;
+        }
+        Some(self.cap_hit_at(r, t, center, normal))
+    }
+
+    fn cap_hit_at(&self, r: &Ray, t: f32, center: &Vec3, normal: Vec3) -> RayHit {
+        let point = r.point_at(t);
+        let rel = point - center;
+        let (u, v) = vec::orthonormal_basis(&normal);
+        let uv = glm::vec2(
+            (glm::dot(&rel, &u) / self.radius + 1.0) * 0.5,
+            (glm::dot(&rel, &v) / self.radius + 1.0) * 0.5,
+        );
+        RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent: None,
+            footprint: 0.0,
+        }
+    }
+}
+
+impl Geometry for Cylinder {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let (axis, height) = self.axis();
+        let mut max = max;
+        let mut result = None;
+        if let Some((t0, t1)) = self.side_roots(r, &axis) {
+            for &t in &[t0, t1] {
+                if t > min && t < max {
+                    if let Some(hit) = self.side_hit(r, t, &axis, height) {
+                        max = hit.t;
+                        result = Some(hit);
+                        break;
+                    }
+                }
+            }
+        }
+        if self.capped {
+            for (center, normal) in &[(self.start, -axis), (self.end, axis)] {
+                if let Some(hit) = self.cap_hit(r, center, *normal, min, max) {
+                    max = hit.t;
+                    result = Some(hit);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Sampleable for Cylinder {
+    fn area(&self) -> f32 {
+        let (_, height) = self.axis();
+        let side = glm::two_pi::<f32>() * self.radius * height;
+        if self.capped {
+            side + glm::two_pi::<f32>() * self.radius * self.radius
+        } else {
+            side
+        }
+    }
+
+    /// Picks the side or a cap in proportion to their areas, reusing what
+    /// is left of `rnd.x` for the point on it.
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        let (axis, height) = self.axis();
+        let (u, v) = vec::orthonormal_basis(&axis);
+        let phi = glm::two_pi::<f32>() * rnd.y;
+        let radial = u * phi.cos() + v * phi.sin();
+        let area = self.area();
+        let side = glm::two_pi::<f32>() * self.radius * height;
+        let pick = rnd.x * area;
+        if pick < side {
+            SurfaceSample {
+                point: self.start + axis * (pick / side * height) + radial * self.radius,
+                normal: radial,
+            }
+        } else {
+            let t = ((pick - side) / (area - side)).min(1.0);
+            let (center, normal, t) = if t < 0.5 {
+                (self.start, -axis, t * 2.0)
+            } else {
+                (self.end, axis, t * 2.0 - 1.0)
+            };
+            SurfaceSample {
+                point: center + radial * (self.radius * t.sqrt()),
+                normal,
+            }
+        }
+    }
+}
+
+/// Cylinders always act as capped solids in boolean operations.
+impl Solid for Cylinder {
+    fn intervals(&self, r: &Ray) -> Vec<Interval> {
+        let (axis, height) = self.axis();
+        let (t0, t1) = match self.side_roots(r, &axis) {
+            Some(roots) => roots,
+            None => {
+                let d_perp = r.direction - axis * glm::dot(&r.direction, &axis);
+                if glm::dot(&d_perp, &d_perp) > 1e-8 {
+                    return vec![];
+                }
+                // Parallel to the axis: inside the tube everywhere or nowhere
+                let oc = r.origin - self.start;
+                let oc_perp = oc - axis * glm::dot(&oc, &axis);
+                if glm::dot(&oc_perp, &oc_perp) > self.radius * self.radius {
+                    return vec![];
+                }
+                (f32::NEG_INFINITY, f32::INFINITY)
+            }
+        };
+
+        // Span between the cap planes, with the cap each end lies on
+        let dd = glm::dot(&r.direction, &axis);
+        let od = glm::dot(&(r.origin - self.start), &axis);
+        let (near_cap, far_cap) = if dd.abs() < 1e-8 {
+            if od < 0.0 || od > height {
+                return vec![];
+            }
+            (None, None)
+        } else {
+            let start = (-od / dd, (self.start, -axis));
+            let end = ((height - od) / dd, (self.end, axis));
+            if start.0 < end.0 {
+                (Some(start), Some(end))
+            } else {
+                (Some(end), Some(start))
+            }
+        };
+
+        let enter = match near_cap {
+            Some((t, (center, normal))) if t >= t0 => self.cap_hit_at(r, t, &center, normal),
+            _ => self.side_hit_at(r, t0, &axis, height),
+        };
+        let exit = match far_cap {
+            Some((t, (center, normal))) if t <= t1 => self.cap_hit_at(r, t, &center, normal),
+            _ => self.side_hit_at(r, t1, &axis, height),
+        };
+        if enter.t >= exit.t {
+            vec![]
+        } else {
+            vec![Interval { enter, exit }]
+        }
+    }
+}
+
+impl Bounds for Cylinder {
+    fn bounds(&self) -> AABB {
+        let (a, _) = self.axis();
+        let extent = glm::vec3(
+            self.radius * f32::sqrt((1.0 - a.x * a.x).max(0.0)),
+            self.radius * f32::sqrt((1.0 - a.y * a.y).max(0.0)),
+            self.radius * f32::sqrt((1.0 - a.z * a.z).max(0.0)),
+        );
+        let corners = [
+            self.start - extent,
+            self.start + extent,
+            self.end - extent,
+            self.end + extent,
+        ];
+        AABB::from(corners.iter())
+    }
+}
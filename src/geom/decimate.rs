@@ -0,0 +1,301 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use nalgebra_glm as glm;
+
+use super::PolyMesh;
+use crate::Vec3;
+
+/// Symmetric 4x4 error quadric, storing the upper triangle in row order.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(n: &Vec3, p: &Vec3) -> Self {
+        let (a, b, c) = (f64::from(n.x), f64::from(n.y), f64::from(n.z));
+        let d = -(a * f64::from(p.x) + b * f64::from(p.y) + c * f64::from(p.z));
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut q = self.0;
+        for (a, b) in q.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+        Quadric(q)
+    }
+
+    fn scaled(&self, s: f64) -> Quadric {
+        let mut q = self.0;
+        for a in q.iter_mut() {
+            *a *= s;
+        }
+        Quadric(q)
+    }
+
+    fn error(&self, v: &Vec3) -> f64 {
+        let [a2, ab, ac, ad, b2, bc, bd, c2, cd, d2] = self.0;
+        let (x, y, z) = (f64::from(v.x), f64::from(v.y), f64::from(v.z));
+        a2 * x * x
+            + 2.0 * ab * x * y
+            + 2.0 * ac * x * z
+            + 2.0 * ad * x
+            + b2 * y * y
+            + 2.0 * bc * y * z
+            + 2.0 * bd * y
+            + c2 * z * z
+            + 2.0 * cd * z
+            + d2
+    }
+
+    /// The point minimizing the error, if the quadric is well conditioned.
+    fn optimum(&self) -> Option<Vec3> {
+        let [a2, ab, ac, ad, b2, bc, bd, c2, cd, _] = self.0;
+        let det = a2 * (b2 * c2 - bc * bc) - ab * (ab * c2 - bc * ac) + ac * (ab * bc - b2 * ac);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        // Cramer's rule on the 3x3 system Av = -(ad, bd, cd)
+        let (r0, r1, r2) = (-ad, -bd, -cd);
+        let x =
+            (r0 * (b2 * c2 - bc * bc) - ab * (r1 * c2 - bc * r2) + ac * (r1 * bc - b2 * r2)) / det;
+        let y =
+            (a2 * (r1 * c2 - bc * r2) - r0 * (ab * c2 - bc * ac) + ac * (ab * r2 - r1 * ac)) / det;
+        let z =
+            (a2 * (b2 * r2 - r1 * bc) - ab * (ab * r2 - r1 * ac) + r0 * (ab * bc - b2 * ac)) / det;
+        Some(glm::vec3(x as f32, y as f32, z as f32))
+    }
+}
+
+struct Collapse {
+    cost: f64,
+    a: usize,
+    b: usize,
+    target: Vec3,
+    stamps: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    /// Reversed, so the heap yields the cheapest collapse first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+struct Decimator {
+    mesh: PolyMesh,
+    quadrics: Vec<Quadric>,
+    stamps: Vec<u32>,
+    adjacent: Vec<Vec<usize>>,
+    alive: Vec<bool>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Decimator {
+    fn new(mesh: PolyMesh) -> Self {
+        let mut quadrics = vec![Quadric::default(); mesh.positions.len()];
+        let mut adjacent = vec![Vec::new(); mesh.positions.len()];
+        for (f, face) in mesh.faces.iter().enumerate() {
+            let n = mesh.face_normal(face);
+            let len = glm::length(&n);
+            if len > 0.0 {
+                // Weight each plane by the area of its face
+                let q = Quadric::plane(&(n / len), &mesh.positions[face[0].pos]);
+                let q = q.scaled(f64::from(len * 0.5));
+                for c in face {
+                    quadrics[c.pos] = quadrics[c.pos].add(&q);
+                }
+            }
+            for c in face {
+                adjacent[c.pos].push(f);
+            }
+        }
+        let alive = vec![true; mesh.faces.len()];
+        let stamps = vec![0; mesh.positions.len()];
+        let mut decimator = Decimator {
+            mesh,
+            quadrics,
+            stamps,
+            adjacent,
+            alive,
+            heap: BinaryHeap::new(),
+        };
+        for f in 0..decimator.mesh.faces.len() {
+            for i in 0..3 {
+                let a = decimator.mesh.faces[f][i].pos;
+                let b = decimator.mesh.faces[f][(i + 1) % 3].pos;
+                // Interior edges are queued twice, which is harmless
+                decimator.push(a, b);
+            }
+        }
+        decimator
+    }
+
+    fn push(&mut self, a: usize, b: usize) {
+        let q = self.quadrics[a].add(&self.quadrics[b]);
+        let (pa, pb) = (self.mesh.positions[a], self.mesh.positions[b]);
+        let candidates = [q.optimum(), Some((pa + pb) * 0.5), Some(pa), Some(pb)];
+        let (cost, target) = candidates
+            .iter()
+            .filter_map(|v| *v)
+            .map(|v| (q.error(&v), v))
+            .min_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(Ordering::Equal))
+            .unwrap();
+        self.heap.push(Collapse {
+            cost,
+            a,
+            b,
+            target,
+            stamps: (self.stamps[a], self.stamps[b]),
+        });
+    }
+
+    /// Whether moving `a` and `b` to `target` would flip any face that
+    /// survives the collapse.
+    fn flips(&self, a: usize, b: usize, target: &Vec3) -> bool {
+        self.adjacent[a]
+            .iter()
+            .chain(self.adjacent[b].iter())
+            .filter(|&&f| self.alive[f])
+            .any(|&f| {
+                let face = &self.mesh.faces[f];
+                if face.iter().any(|c| c.pos == a) && face.iter().any(|c| c.pos == b) {
+                    return false;
+                }
+                let moved: Vec<Vec3> = face
+                    .iter()
+                    .map(|c| {
+                        if c.pos == a || c.pos == b {
+                            *target
+                        } else {
+                            self.mesh.positions[c.pos]
+                        }
+                    })
+                    .collect();
+                let before = self.mesh.face_normal(face);
+                let after = (moved[1] - moved[0]).cross(&(moved[2] - moved[0]));
+                glm::dot(&before, &after) <= 0.0
+            })
+    }
+
+    /// Collapses the edge from `b` into `a`, returning the number of faces removed.
+    fn collapse(&mut self, a: usize, b: usize, target: Vec3) -> usize {
+        self.mesh.positions[a] = target;
+        self.quadrics[a] = self.quadrics[a].add(&self.quadrics[b]);
+        self.stamps[a] += 1;
+        self.stamps[b] += 1;
+
+        let mut removed = 0;
+        let moved = std::mem::take(&mut self.adjacent[b]);
+        for f in moved {
+            if !self.alive[f] {
+                continue;
+            }
+            for c in self.mesh.faces[f].iter_mut().filter(|c| c.pos == b) {
+                c.pos = a;
+            }
+            let face = &self.mesh.faces[f];
+            if face[0].pos == face[1].pos
+                || face[1].pos == face[2].pos
+                || face[2].pos == face[0].pos
+            {
+                self.alive[f] = false;
+                removed += 1;
+            } else {
+                self.adjacent[a].push(f);
+            }
+        }
+
+        let alive = &self.alive;
+        self.adjacent[a].retain(|&f| alive[f]);
+        self.adjacent[a].sort_unstable();
+        self.adjacent[a].dedup();
+
+        let mut neighbours: Vec<usize> = self.adjacent[a]
+            .iter()
+            .flat_map(|&f| self.mesh.faces[f].iter().map(|c| c.pos))
+            .filter(|&v| v != a)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for v in neighbours {
+            self.push(a, v);
+        }
+        removed
+    }
+
+    fn run(mut self, target_faces: usize) -> PolyMesh {
+        let mut faces = self.mesh.faces.len();
+        while faces > target_faces {
+            let Collapse {
+                a,
+                b,
+                target,
+                stamps,
+                ..
+            } = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            if stamps != (self.stamps[a], self.stamps[b]) || self.flips(a, b, &target) {
+                continue;
+            }
+            faces -= self.collapse(a, b, target);
+        }
+
+        let alive = self.alive;
+        let faces = self
+            .mesh
+            .faces
+            .into_iter()
+            .zip(alive)
+            .filter(|(_, alive)| *alive)
+            .map(|(face, _)| face)
+            .collect();
+        PolyMesh {
+            positions: self.mesh.positions,
+            faces,
+        }
+    }
+}
+
+impl PolyMesh {
+    /// Simplifies the mesh down to about `target` triangles by collapsing
+    /// the edges with the least quadric error first, following Garland and
+    /// Heckbert. Unused positions are left in place.
+    pub fn decimated(self, target: usize) -> PolyMesh {
+        let mesh = self.into_triangles();
+        if mesh.faces.len() <= target {
+            return mesh;
+        }
+        Decimator::new(mesh).run(target)
+    }
+}
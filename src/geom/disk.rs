@@ -0,0 +1,94 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::vec::{self, Vec3};
+
+/// Flat disk facing along `normal`, optionally with a hole of `inner_radius`.
+#[derive(Deserialize, Clone)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    #[serde(default)]
+    pub inner_radius: f32,
+}
+
+impl Disk {
+    fn frame(&self) -> (Vec3, Vec3, Vec3) {
+        let n = glm::normalize(&self.normal);
+        let (u, v) = vec::orthonormal_basis(&n);
+        (n, u, v)
+    }
+}
+
+impl Geometry for Disk {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let (normal, u, v) = self.frame();
+        let denom = glm::dot(&r.direction, &normal);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = glm::dot(&(self.center - r.origin), &normal) / denom;
+        if t <= min || t >= max {
+            return None;
+        }
+        let point = r.point_at(t);
+        let d = point - self.center;
+        let dist = glm::length(&d);
+        if dist > self.radius || dist < self.inner_radius {
+            return None;
+        }
+        let phi = f32::atan2(glm::dot(&d, &v), glm::dot(&d, &u));
+        let uv = glm::vec2(
+            0.5 + phi / glm::two_pi::<f32>(),
+            (dist - self.inner_radius) / (self.radius - self.inner_radius),
+        );
+        Some(RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent: None,
+            footprint: 0.0,
+        })
+    }
+}
+
+impl Sampleable for Disk {
+    fn area(&self) -> f32 {
+        glm::pi::<f32>() * (self.radius * self.radius - self.inner_radius * self.inner_radius)
+    }
+
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        let (normal, u, v) = self.frame();
+        let (r0, r1) = (
+            self.inner_radius * self.inner_radius,
+            self.radius * self.radius,
+        );
+        let r = f32::sqrt(r0 + (r1 - r0) * rnd.x);
+        let phi = glm::two_pi::<f32>() * rnd.y;
+        SurfaceSample {
+            point: self.center + (u * phi.cos() + v * phi.sin()) * r,
+            normal,
+        }
+    }
+}
+
+impl Bounds for Disk {
+    fn bounds(&self) -> AABB {
+        let n = glm::normalize(&self.normal);
+        let extent = glm::vec3(
+            self.radius * f32::sqrt((1.0 - n.x * n.x).max(0.0)),
+            self.radius * f32::sqrt((1.0 - n.y * n.y).max(0.0)),
+            self.radius * f32::sqrt((1.0 - n.z * n.z).max(0.0)),
+        );
+        AABB {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::{Corner, PolyMesh};
+use crate::texture::{GrayScaleTexture, Texture};
+use crate::Vec3;
+
+/// Upper bound on the number of splitting passes, so a tiny edge length
+/// cannot exhaust memory.
+const MAX_TESSELLATION_PASSES: usize = 12;
+
+/// Moves vertices along their normal by `scale` times the height texture,
+/// after splitting edges until none is longer than `edge_length`.
+#[derive(Deserialize)]
+pub struct Displacement {
+    pub texture: GrayScaleTexture,
+    pub scale: f32,
+    pub edge_length: f32,
+}
+
+fn midpoint(a: &Corner, b: &Corner, pos: usize) -> Corner {
+    let normal = match (a.normal, b.normal) {
+        (Some(na), Some(nb)) => Some(glm::normalize(&(na + nb))),
+        _ => None,
+    };
+    Corner {
+        pos,
+        uv: (a.uv + b.uv) * 0.5,
+        normal,
+    }
+}
+
+impl PolyMesh {
+    /// Splits edges longer than `max_edge` at their midpoint until none are
+    /// left. Splits are shared between neighbouring triangles, so the result
+    /// has no cracks.
+    pub fn tessellated(self, max_edge: f32) -> PolyMesh {
+        let mut mesh = self.into_triangles();
+        for _ in 0..MAX_TESSELLATION_PASSES {
+            let mut splits: HashMap<(usize, usize), usize> = HashMap::new();
+            for face in &mesh.faces {
+                for i in 0..3 {
+                    let (a, b) = (face[i].pos, face[(i + 1) % 3].pos);
+                    let (pa, pb) = (mesh.positions[a], mesh.positions[b]);
+                    let key = (a.min(b), a.max(b));
+                    if glm::distance(&pa, &pb) > max_edge && !splits.contains_key(&key) {
+                        splits.insert(key, mesh.positions.len());
+                        mesh.positions.push((pa + pb) * 0.5);
+                    }
+                }
+            }
+            if splits.is_empty() {
+                break;
+            }
+
+            let mut faces = Vec::with_capacity(mesh.faces.len() * 2);
+            for face in mesh.faces {
+                let mid: Vec<Option<Corner>> = (0..3)
+                    .map(|i| {
+                        let (a, b) = (&face[i], &face[(i + 1) % 3]);
+                        splits
+                            .get(&(a.pos.min(b.pos), a.pos.max(b.pos)))
+                            .map(|&pos| midpoint(a, b, pos))
+                    })
+                    .collect();
+                let count = mid.iter().filter(|m| m.is_some()).count();
+                match count {
+                    0 => faces.push(face),
+                    1 => {
+                        let i = mid.iter().position(Option::is_some).unwrap();
+                        let m = mid[i].clone().unwrap();
+                        let (c0, c1, c2) = (&face[i], &face[(i + 1) % 3], &face[(i + 2) % 3]);
+                        faces.push(vec![c0.clone(), m.clone(), c2.clone()]);
+                        faces.push(vec![m, c1.clone(), c2.clone()]);
+                    }
+                    2 => {
+                        // Rotate so the edge left whole runs from c0 to c1
+                        let k = mid.iter().position(Option::is_none).unwrap();
+                        let (c0, c1, c2) = (&face[k], &face[(k + 1) % 3], &face[(k + 2) % 3]);
+                        let m1 = mid[(k + 1) % 3].clone().unwrap();
+                        let m2 = mid[(k + 2) % 3].clone().unwrap();
+                        faces.push(vec![m1.clone(), c2.clone(), m2.clone()]);
+                        faces.push(vec![c0.clone(), c1.clone(), m1.clone()]);
+                        faces.push(vec![c0.clone(), m1, m2]);
+                    }
+                    _ => {
+                        let m: Vec<Corner> = mid.into_iter().map(Option::unwrap).collect();
+                        faces.push(vec![face[0].clone(), m[0].clone(), m[2].clone()]);
+                        faces.push(vec![m[0].clone(), face[1].clone(), m[1].clone()]);
+                        faces.push(vec![m[2].clone(), m[1].clone(), face[2].clone()]);
+                        faces.push(m);
+                    }
+                }
+            }
+            mesh.faces = faces;
+        }
+        mesh
+    }
+
+    /// Tessellates and displaces the mesh, then recomputes its normals.
+    /// Each position is moved once, using the first texture coordinate it
+    /// appears with, so texture seams stay closed.
+    pub fn displaced(self, displacement: &Displacement) -> PolyMesh {
+        let mut mesh = self.tessellated(displacement.edge_length);
+
+        let mut normals = vec![glm::zero::<Vec3>(); mesh.positions.len()];
+        let mut uvs = vec![None; mesh.positions.len()];
+        for face in &mesh.faces {
+            let flat = mesh.face_normal(face);
+            for c in face {
+                normals[c.pos] += c.normal.map_or(flat, |n| n * glm::length(&flat));
+                uvs[c.pos].get_or_insert(c.uv);
+            }
+        }
+        for (pos, (n, uv)) in mesh.positions.iter_mut().zip(normals.iter().zip(uvs)) {
+            if let Some(uv) = uv {
+                let height = displacement.texture.sample(glm::fract(&uv));
+                *pos += glm::normalize(n) * height * displacement.scale;
+            }
+        }
+        mesh.smooth_normals();
+        mesh
+    }
+}
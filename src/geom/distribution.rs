@@ -0,0 +1,112 @@
+use crate::Vec2;
+use nalgebra_glm as glm;
+
+/// Piecewise-constant density over [0, 1) proportional to `func`, sampled
+/// by inverting its cumulative distribution.
+#[derive(Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Falls back to a uniform density when `func` is zero everywhere.
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in &func {
+            let last = cdf[cdf.len() - 1];
+            cdf.push(last + f.abs() / n);
+        }
+        let integral = cdf[func.len()];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f32 / n
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Mean of `func` over the interval.
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Density of the piece at `index`.
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[index].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// Samples a value in [0, 1), returning it with the index of its piece.
+    pub fn sample(&self, u: f32) -> (f32, usize) {
+        // Last piece starting at or before `u`
+        let (mut lo, mut hi) = (0, self.len());
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let width = self.cdf[lo + 1] - self.cdf[lo];
+        let offset = if width > 0.0 {
+            (u - self.cdf[lo]) / width
+        } else {
+            0.0
+        };
+        let x = (lo as f32 + offset.clamp(0.0, 1.0)) / self.len() as f32;
+        (x.min(1.0 - f32::EPSILON), lo)
+    }
+}
+
+/// Piecewise-constant density over the unit square, proportional to a
+/// function given in rows, sampled row first and then along the row.
+#[derive(Clone)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<_> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::integral).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Samples a point, returning it with its density.
+    pub fn sample(&self, rnd: Vec2) -> (Vec2, f32) {
+        let (v, row) = self.marginal.sample(rnd.y);
+        let (u, col) = self.rows[row].sample(rnd.x);
+        let pdf = self.marginal.pdf(row) * self.rows[row].pdf(col);
+        (glm::vec2(u, v), pdf)
+    }
+
+    pub fn pdf(&self, uv: Vec2) -> f32 {
+        let row = ((uv.y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let cols = self.rows[row].len();
+        let col = ((uv.x * cols as f32) as usize).min(cols - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(col)
+    }
+}
@@ -0,0 +1,143 @@
+//! Triangle intersection through Intel's Embree kernels, enabled with the
+//! `embree` cargo feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::{Vector3, Vector4};
+use embree_rs::{CommittedScene, Device, IntersectContext, TriangleMesh};
+use lazy_static::lazy_static;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::Vec3;
+
+/// Embree devices may be used from any number of threads.
+struct SharedDevice(Device);
+
+unsafe impl Send for SharedDevice {}
+unsafe impl Sync for SharedDevice {}
+
+lazy_static! {
+    // One device for all meshes, including those of reloaded scenes
+    static ref DEVICE: SharedDevice = SharedDevice(Device::new());
+}
+
+/// An Embree scene along with its committed view, which borrows from it.
+struct OwnedScene {
+    // Declared first so that it is dropped before the scene it borrows
+    committed: CommittedScene<'static>,
+    _scene: Box<embree_rs::Scene<'static>>,
+}
+
+// Committed Embree scenes may be intersected from any number of threads.
+unsafe impl Send for OwnedScene {}
+unsafe impl Sync for OwnedScene {}
+
+impl OwnedScene {
+    fn new(scene: embree_rs::Scene<'static>) -> Self {
+        let scene = Box::new(scene);
+        // The box keeps the scene in place for as long as the view lives
+        let view = unsafe { &*(&*scene as *const embree_rs::Scene<'static>) };
+        OwnedScene {
+            committed: view.commit(),
+            _scene: scene,
+        }
+    }
+}
+
+/// A static mesh handed over to Embree. The original triangles are kept for
+/// interpolating normals and texture coordinates at the hit point.
+#[derive(Clone)]
+pub struct EmbreeMesh {
+    scene: Arc<OwnedScene>,
+    triangles: Arc<Vec<Triangle>>,
+    bounds: AABB,
+}
+
+impl EmbreeMesh {
+    /// Vertices shared between triangles are passed to Embree only once.
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let mut positions = Vec::new();
+        let mut index_of = HashMap::new();
+        let indices: Vec<_> = triangles
+            .iter()
+            .map(|tri| {
+                let (v0, v1, v2) = tri.positions();
+                let mut index = |v: Vec3| {
+                    let key = [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+                    *index_of.entry(key).or_insert_with(|| {
+                        positions.push(v);
+                        positions.len() as u32 - 1
+                    })
+                };
+                Vector3::new(index(v0), index(v1), index(v2))
+            })
+            .collect();
+        let device = &DEVICE.0;
+        let mut mesh = TriangleMesh::unanimated(device, indices.len(), positions.len());
+        {
+            let mut verts = mesh.vertex_buffer.map();
+            for (i, v) in positions.iter().enumerate() {
+                verts[i] = Vector4::new(v.x, v.y, v.z, 0.0);
+            }
+            let mut ids = mesh.index_buffer.map();
+            for (i, tri) in indices.into_iter().enumerate() {
+                ids[i] = tri;
+            }
+        }
+        let mut geometry = embree_rs::Geometry::Triangle(mesh);
+        geometry.commit();
+
+        let mut scene = embree_rs::Scene::new(device);
+        scene.attach_geometry(geometry);
+        let bounds = total_bounds(&triangles);
+        EmbreeMesh {
+            scene: Arc::new(OwnedScene::new(scene)),
+            triangles: Arc::new(triangles),
+            bounds,
+        }
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+}
+
+impl Geometry for EmbreeMesh {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let mut ctx = IntersectContext::incoherent();
+        let mut ray = embree_rs::Ray::segment(
+            Vector3::new(r.origin.x, r.origin.y, r.origin.z),
+            Vector3::new(r.direction.x, r.direction.y, r.direction.z),
+            min,
+            max,
+        );
+        ray.time = r.time;
+        let mut ray_hit = embree_rs::RayHit::new(ray);
+        self.scene.committed.intersect(&mut ctx, &mut ray_hit);
+        if !ray_hit.hit.hit() {
+            return None;
+        }
+        let tri = &self.triangles[ray_hit.hit.primID as usize];
+        let (u, v) = (ray_hit.hit.u, ray_hit.hit.v);
+        let t = ray_hit.ray.tfar;
+        let (uv, normal) = tri.interpolate([1.0 - u - v, u, v]);
+        Some(RayHit {
+            t,
+            point: r.point_at(t),
+            normal,
+            geometric_normal: tri.face_normal(),
+            uv,
+            tangent: tri.tangent(),
+            footprint: tri.footprint(r, t),
+        })
+    }
+}
+
+impl Bounds for EmbreeMesh {
+    fn bounds(&self) -> AABB {
+        self.bounds.clone()
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::{Vec2, Vec3};
+
+/// Terrain from a grayscale image, spanning `size` along x and z from
+/// `origin`, with white pixels raised by `height`.
+#[derive(Clone)]
+pub struct Heightfield {
+    origin: Vec3,
+    cell: Vec2,
+    resolution: (usize, usize),
+    heights: Arc<Vec<f32>>,
+    normals: Arc<Vec<Vec3>>,
+    min_height: f32,
+    max_height: f32,
+}
+
+#[derive(Deserialize)]
+struct HeightfieldDesc {
+    heightmap: String,
+    size: Vec2,
+    height: f32,
+    #[serde(default = "default_origin")]
+    origin: Vec3,
+}
+
+fn default_origin() -> Vec3 {
+    glm::zero()
+}
+
+impl Heightfield {
+    pub fn new(image: &image::GrayImage, origin: Vec3, size: Vec2, height: f32) -> Self {
+        let (w, h) = (image.width() as usize, image.height() as usize);
+        let heights: Vec<f32> = image
+            .pixels()
+            .map(|p| f32::from(p.0[0]) / 255.0 * height)
+            .collect();
+        let cell = glm::vec2(
+            size.x / (w.max(2) - 1) as f32,
+            size.y / (h.max(2) - 1) as f32,
+        );
+        let at = |x: usize, z: usize| heights[z.min(h - 1) * w + x.min(w - 1)];
+        let normals = (0..h)
+            .flat_map(|z| (0..w).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let dx = at(x + 1, z) - at(x.saturating_sub(1), z);
+                let dz = at(x, z + 1) - at(x, z.saturating_sub(1));
+                glm::normalize(&glm::vec3(-dx / (2.0 * cell.x), 1.0, -dz / (2.0 * cell.y)))
+            })
+            .collect();
+        let min_height = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max_height = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        Heightfield {
+            origin,
+            cell,
+            resolution: (w, h),
+            heights: Arc::new(heights),
+            normals: Arc::new(normals),
+            min_height,
+            max_height,
+        }
+    }
+
+    fn vertex(&self, x: usize, z: usize) -> (Vec3, Vec3) {
+        let idx = z * self.resolution.0 + x;
+        let pos = self.origin
+            + glm::vec3(
+                x as f32 * self.cell.x,
+                self.heights[idx],
+                z as f32 * self.cell.y,
+            );
+        (pos, self.normals[idx])
+    }
+
+    fn size(&self) -> Vec2 {
+        let (w, h) = self.resolution;
+        glm::vec2(self.cell.x * (w - 1) as f32, self.cell.y * (h - 1) as f32)
+    }
+
+    /// Intersects the two triangles making up the cell at `(x, z)`.
+    fn intersect_cell(&self, r: &Ray, x: usize, z: usize, min: f32, max: f32) -> Option<RayHit> {
+        let v00 = self.vertex(x, z);
+        let v10 = self.vertex(x + 1, z);
+        let v01 = self.vertex(x, z + 1);
+        let v11 = self.vertex(x + 1, z + 1);
+        let mut max = max;
+        let mut result = None;
+        for [a, b, c] in &[[&v00, &v10, &v11], [&v00, &v11, &v01]] {
+            if let Some((t, u, v)) = intersect_triangle(r, &a.0, &b.0, &c.0) {
+                if t > min && t < max {
+                    let point = r.point_at(t);
+                    let normal = glm::normalize(&(a.1 * (1.0 - u - v) + b.1 * u + c.1 * v));
+                    let face = glm::normalize(&(b.0 - a.0).cross(&(c.0 - a.0)));
+                    let local = point - self.origin;
+                    let size = self.size();
+                    let uv = glm::vec2(local.x / size.x, local.z / size.y);
+                    max = t;
+                    result = Some(RayHit {
+                        t,
+                        point,
+                        normal,
+                        geometric_normal: face,
+                        uv,
+                        tangent: None,
+                        footprint: 0.0,
+                    });
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Double sided Möller-Trumbore test, returning distance and barycentrics.
+fn intersect_triangle(r: &Ray, p0: &Vec3, p1: &Vec3, p2: &Vec3) -> Option<(f32, f32, f32)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pvec = r.direction.cross(&e2);
+    let det = e1.dot(&pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let idet = 1.0 / det;
+    let tvec = r.origin - p0;
+    let u = tvec.dot(&pvec) * idet;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let qvec = tvec.cross(&e1);
+    let v = r.direction.dot(&qvec) * idet;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some((e2.dot(&qvec) * idet, u, v))
+}
+
+impl Geometry for Heightfield {
+    /// Walks the grid cells under the ray in order, so only cells along
+    /// its path are tested.
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let bounds = self.bounds();
+        let mut t_enter = min;
+        let mut t_exit = max;
+        for dim in 0..3 {
+            let t1 = (bounds.min[dim] - r.origin[dim]) * r.inv_dir[dim];
+            let t2 = (bounds.max[dim] - r.origin[dim]) * r.inv_dir[dim];
+            t_enter = t_enter.max(t1.min(t2));
+            t_exit = t_exit.min(t1.max(t2));
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        let (cells_x, cells_z) = (self.resolution.0 - 1, self.resolution.1 - 1);
+        let start = r.point_at(t_enter) - self.origin;
+        let cell_of =
+            |p: f32, size: f32, count: usize| ((p / size).floor().max(0.0) as usize).min(count - 1);
+        let mut x = cell_of(start.x, self.cell.x, cells_x);
+        let mut z = cell_of(start.z, self.cell.y, cells_z);
+
+        // Distance along the ray to the next cell boundary on each axis
+        let axis = |p: f32, d: f32, cell: usize, size: f32| -> (f32, f32) {
+            if d > 0.0 {
+                (t_enter + ((cell + 1) as f32 * size - p) / d, size / d)
+            } else if d < 0.0 {
+                (t_enter + (cell as f32 * size - p) / d, -size / d)
+            } else {
+                (f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (mut next_x, delta_x) = axis(start.x, r.direction.x, x, self.cell.x);
+        let (mut next_z, delta_z) = axis(start.z, r.direction.z, z, self.cell.y);
+
+        loop {
+            if let Some(hit) = self.intersect_cell(r, x, z, min, max) {
+                return Some(hit);
+            }
+            if next_x < next_z {
+                if next_x > t_exit {
+                    return None;
+                }
+                x = match (r.direction.x > 0.0, x) {
+                    (true, x) if x + 1 < cells_x => x + 1,
+                    (false, x) if x > 0 => x - 1,
+                    _ => return None,
+                };
+                next_x += delta_x;
+            } else {
+                if next_z > t_exit {
+                    return None;
+                }
+                z = match (r.direction.z > 0.0, z) {
+                    (true, z) if z + 1 < cells_z => z + 1,
+                    (false, z) if z > 0 => z - 1,
+                    _ => return None,
+                };
+                next_z += delta_z;
+            }
+        }
+    }
+}
+
+impl Bounds for Heightfield {
+    fn bounds(&self) -> AABB {
+        let size = self.size();
+        AABB {
+            min: self.origin + glm::vec3(0.0, self.min_height, 0.0),
+            max: self.origin + glm::vec3(size.x, self.max_height, size.y),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Heightfield {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = HeightfieldDesc::deserialize(deserializer)?;
+        let image = image::open(&desc.heightmap)
+            .map_err(serde::de::Error::custom)?
+            .to_luma();
+        if image.width() < 2 || image.height() < 2 {
+            return Err(serde::de::Error::custom(
+                "heightmap must be at least 2x2 pixels",
+            ));
+        }
+        Ok(Heightfield::new(
+            &image,
+            desc.origin,
+            desc.size,
+            desc.height,
+        ))
+    }
+}
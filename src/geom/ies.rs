@@ -0,0 +1,216 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::vec::{glm, Vec3};
+
+/// Angular distribution of a luminaire's intensity, read from an IES LM-63
+/// photometric file and scaled to a peak of one. Vertical angles are
+/// measured from straight down, horizontal ones around the vertical axis
+/// from the x axis towards the z axis.
+#[derive(Clone, Debug)]
+pub struct IesProfile {
+    vertical: Vec<f32>,
+    horizontal: Vec<f32>,
+    /// Intensities for each horizontal angle in turn, over the vertical
+    /// angles.
+    intensity: Vec<f32>,
+}
+
+impl IesProfile {
+    pub fn from_file<'a, P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'a>> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .find(|line| line.trim_start().starts_with("TILT="))
+            .ok_or("IES file has no TILT line")?;
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f32>());
+        let mut next = || -> Result<f32, Box<dyn Error>> {
+            let number = numbers.next().ok_or("IES file ends early")?;
+            Ok(number?)
+        };
+        if tilt.trim() == "TILT=INCLUDE" {
+            // Lamp geometry, then pairs of angles and factors
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        if photometric_type as i32 != 1 {
+            return Err("only type C photometry is supported".into());
+        }
+        // Units, luminous opening, ballast factors and input power
+        for _ in 0..7 {
+            next()?;
+        }
+        let mut read = |count: usize| (0..count).map(|_| next()).collect::<Result<Vec<_>, _>>();
+        let vertical = read(vertical_count)?;
+        let horizontal = read(horizontal_count)?;
+        let mut intensity = read(vertical_count * horizontal_count)?;
+        if vertical.is_empty() || horizontal.is_empty() {
+            return Err("IES file has no angles".into());
+        }
+        let peak = intensity.iter().fold(0.0f32, |a, &b| a.max(b * multiplier));
+        if peak <= 0.0 {
+            return Err("IES file has no light".into());
+        }
+        for value in &mut intensity {
+            *value *= multiplier / peak;
+        }
+        Ok(IesProfile {
+            vertical,
+            horizontal,
+            intensity,
+        })
+    }
+
+    /// Relative intensity towards `dir`, in the frame where the luminaire
+    /// points along -y.
+    pub fn eval(&self, dir: &Vec3) -> f32 {
+        let dir = glm::normalize(dir);
+        let vertical = (-dir.y).clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = dir.z.atan2(dir.x).to_degrees();
+        self.eval_angles(vertical, self.unfold(horizontal))
+    }
+
+    /// Maps a horizontal angle into the range the file covers, relying on
+    /// the symmetry its last angle implies.
+    fn unfold(&self, angle: f32) -> f32 {
+        let mut angle = angle.rem_euclid(360.0);
+        let last = self.horizontal[self.horizontal.len() - 1];
+        if last <= 180.0 && angle > 180.0 {
+            angle = 360.0 - angle;
+        }
+        if last <= 90.0 && angle > 90.0 {
+            angle = 180.0 - angle;
+        }
+        angle
+    }
+
+    fn eval_angles(&self, vertical: f32, horizontal: f32) -> f32 {
+        let (v, tv) = match locate(&self.vertical, vertical) {
+            Some(found) => found,
+            None => return 0.0,
+        };
+        let (h, th) = locate(&self.horizontal, horizontal).unwrap_or((0, 0.0));
+        let rows = self.vertical.len();
+        let at = |h: usize, v: usize| {
+            let h = h.min(self.horizontal.len() - 1);
+            self.intensity[h * rows + v.min(rows - 1)]
+        };
+        let below = at(h, v) * (1.0 - tv) + at(h, v + 1) * tv;
+        let above = at(h + 1, v) * (1.0 - tv) + at(h + 1, v + 1) * tv;
+        below * (1.0 - th) + above * th
+    }
+}
+
+/// Index of the interval of the ascending `angles` holding `angle`, and how
+/// far along it lies. None outside of them, except for a single angle.
+fn locate(angles: &[f32], angle: f32) -> Option<(usize, f32)> {
+    if angles.len() == 1 {
+        return Some((0, 0.0));
+    }
+    if angle < angles[0] || angle > angles[angles.len() - 1] {
+        return None;
+    }
+    let i = angles
+        .windows(2)
+        .position(|w| angle <= w[1])
+        .unwrap_or(angles.len() - 2);
+    let width = angles[i + 1] - angles[i];
+    let t = if width > 0.0 {
+        (angle - angles[i]) / width
+    } else {
+        0.0
+    };
+    Some((i, t))
+}
+
+impl<'de> Deserialize<'de> for IesProfile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let path = String::deserialize(deserializer)?;
+        IesProfile::from_file(&path).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn parse(text: &str) -> Result<IesProfile, String> {
+        let mut file = tempfile::Builder::new().suffix(".ies").tempfile().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        IesProfile::from_file(file.path()).map_err(|e| e.to_string())
+    }
+
+    /// Quarter-symmetric profile twice as bright towards +z as towards +x.
+    const PROFILE: &str = "IESNA:LM-63-2002\n\
+                           [TEST] quarter\n\
+                           TILT=NONE\n\
+                           1 1000 1 3 2 1 1 0 0 0\n\
+                           1 1 100\n\
+                           0 45 90\n\
+                           0 90\n\
+                           100 50 0\n\
+                           200 100 0\n";
+
+    fn at_angles(vertical: f32, horizontal: f32) -> Vec3 {
+        let (v, h) = (vertical.to_radians(), horizontal.to_radians());
+        glm::vec3(v.sin() * h.cos(), -v.cos(), v.sin() * h.sin())
+    }
+
+    #[test]
+    fn interpolates_scaled_intensities() {
+        let profile = parse(PROFILE).unwrap();
+        let eval = |vertical, horizontal| profile.eval(&at_angles(vertical, horizontal));
+        assert!((eval(0.0, 0.0) - 0.5).abs() < 1e-4);
+        assert!((eval(45.0, 90.0) - 0.5).abs() < 1e-4);
+        assert!((eval(22.5, 0.0) - 0.375).abs() < 1e-4);
+        assert!((eval(45.0, 45.0) - 0.375).abs() < 1e-4);
+        // Nothing is given above the horizon
+        assert_eq!(profile.eval(&glm::vec3(0.0, 1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn mirrors_symmetric_profiles() {
+        let profile = parse(PROFILE).unwrap();
+        for &horizontal in &[30.0, 120.0, 210.0, 300.0] {
+            let mirrored = [180.0 - horizontal, 360.0 - horizontal];
+            for &other in &mirrored {
+                let a = profile.eval(&at_angles(60.0, horizontal));
+                let b = profile.eval(&at_angles(60.0, other));
+                assert!((a - b).abs() < 1e-4, "{} and {}", horizontal, other);
+            }
+        }
+    }
+
+    #[test]
+    fn skips_included_tilt() {
+        let text = PROFILE.replace("TILT=NONE", "TILT=INCLUDE\n1\n2\n0 90\n1 0.5");
+        let profile = parse(&text).unwrap();
+        assert!((profile.eval(&at_angles(0.0, 0.0)) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rejects_other_photometry() {
+        let text = PROFILE.replace("1 1000 1 3 2 1", "1 1000 1 3 2 2");
+        assert!(parse(&text).is_err());
+        assert!(parse("IESNA:LM-63-2002\n").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+
+use super::*;
+
+use crate::ray::Ray;
+
+/// Shared geometry placed in the scene with its own transform,
+/// so many copies can reference the same triangle data.
+#[derive(Clone)]
+pub struct Instance {
+    geometry: Arc<GeomType>,
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(geometry: Arc<GeomType>, transform: Transform) -> Self {
+        Instance {
+            geometry,
+            transform,
+        }
+    }
+
+    pub fn with_transform(&self, transform: Transform) -> Self {
+        Instance {
+            geometry: self.geometry.clone(),
+            transform,
+        }
+    }
+
+    pub fn geometry(&self) -> &GeomType {
+        &self.geometry
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+impl Geometry for Instance {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let transform = self.transform.at(r.time);
+        let local = transform.ray_to_local(r);
+        self.geometry
+            .intersection(&local, min, max)
+            .map(|hit| RayHit {
+                t: hit.t,
+                point: r.point_at(hit.t),
+                normal: transform.normal(&hit.normal),
+                geometric_normal: transform.normal(&hit.geometric_normal),
+                uv: hit.uv,
+                tangent: hit.tangent.map(|t| glm::normalize(&transform.vector(&t))),
+                footprint: hit.footprint,
+            })
+    }
+}
+
+impl Bounds for Instance {
+    fn bounds(&self) -> AABB {
+        self.transform.bounds(&self.geometry.bounds())
+    }
+}
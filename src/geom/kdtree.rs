@@ -3,7 +3,7 @@ use rayon::prelude::*;
 use crate::Ray;
 
 use super::aabb::*;
-use super::{Geometry, RayHit};
+use super::{Geometry, RayHit, TraceResult, Traceable};
 
 #[derive(Clone)]
 pub enum KdTree<T> {
@@ -68,14 +68,6 @@ fn cost(aabb: &AABB, num_geoms: usize) -> f32 {
     INTERSECT_COST * aabb.surface_area() * num_geoms as f32
 }
 
-fn total_bounds<G: Bounds>(geoms: &[G]) -> AABB {
-    let bounds = geoms.get(0).map(|g| g.bounds()).unwrap_or_default();
-    geoms
-        .iter()
-        .map(Bounds::bounds)
-        .fold(bounds, |a, b| AABB::union(&a, &b))
-}
-
 fn sorted_markers<'a, I, G>(geoms: I, dimension: usize) -> Vec<Marker>
 where
     I: Iterator<Item = &'a G>,
@@ -95,7 +87,7 @@ where
         markers.push(start);
         markers.push(end);
     }
-    markers.par_sort_by(|a, b| a.partial_cmp(&b).expect("Tried sorting NaNs"));
+    markers.par_sort_by(|a, b| a.partial_cmp(b).expect("Tried sorting NaNs"));
     markers
 }
 
@@ -117,7 +109,7 @@ where
                     State::Start => left += 1,
                     State::End => right -= 1,
                 }
-                assert!(left <= count, right <= count);
+                assert!(left <= count, "{}", right <= count);
                 let (l, r) = bounds.split_dimension(pos, dim);
                 let cost = TRAVERSAL_COST + cost(&l, left) + cost(&r, right);
                 Split { pos, dim, cost }
@@ -201,6 +193,34 @@ impl<T: Geometry> Geometry for KdTree<T> {
     }
 }
 
+impl<T: Traceable> Traceable for KdTree<T> {
+    fn trace(&self, r: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>> {
+        match self {
+            KdTree::Leaf { bounds, geoms } if bounds.intersects(r) => {
+                let mut max = max;
+                let mut result = None;
+                for geom in geoms {
+                    if let Some(traced) = geom.trace(r, min, max) {
+                        max = traced.hit.t;
+                        result = Some(traced);
+                    }
+                }
+                result
+            }
+            KdTree::Node {
+                bounds,
+                left,
+                right,
+            } if bounds.intersects(r) => {
+                let left = left.trace(r, min, max);
+                let max = left.as_ref().map_or(max, |traced| traced.hit.t);
+                right.trace(r, min, max).or(left)
+            }
+            _ => None,
+        }
+    }
+}
+
 impl<T> Bounds for KdTree<T> {
     fn bounds(&self) -> AABB {
         match self {
@@ -0,0 +1,496 @@
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+use crate::material::{Emitter, Gobo};
+use crate::solar::SunPosition;
+use crate::texture::{ColorTexture, Sky, Texture as _};
+use crate::vec::{self, luminance};
+
+/// How samples of the same direction from light and material sampling are
+/// weighted against each other. The power heuristic favours whichever
+/// strategy is much better more strongly, which helps glossy surfaces
+/// reflecting small lights.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Heuristic {
+    Balance,
+    #[default]
+    Power,
+}
+
+impl Heuristic {
+    /// Weight of a sample drawn with density `pdf` by one strategy, where
+    /// the others would have drawn it with densities `others`.
+    pub fn weight(self, pdf: f32, others: &[f32]) -> f32 {
+        let term = |pdf: f32| match self {
+            Heuristic::Balance => pdf,
+            Heuristic::Power => pdf * pdf,
+        };
+        let total = others
+            .iter()
+            .fold(term(pdf), |sum, &other| sum + term(other));
+        if total > 0.0 {
+            term(pdf) / total
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Source of light sampled directly from each shaded point, rather than
+/// waiting for paths to hit it by chance.
+#[derive(Clone)]
+pub enum Light {
+    /// Emissive object with a surface that can be sampled.
+    Object(Arc<Object>),
+    /// Emissive mesh, sampled through its triangles.
+    Mesh(Arc<Object>, Arc<MeshLight>),
+    Point(PointLight),
+    Spot(SpotLight),
+    Sun(SunLight),
+    Environment(EnvironmentLight),
+}
+
+/// What sampling a light from a point yields.
+pub enum LightSample {
+    /// Point on the surface of an emissive object, whose emission is found
+    /// by tracing a shadow ray to it.
+    Surface(SurfaceSample),
+    /// Light arriving along `dir` from `dist` away, from a light that rays
+    /// cannot hit, unless something is in the way.
+    Direct {
+        dir: Vec3,
+        dist: f32,
+        irradiance: Vec3,
+    },
+    /// Direction towards the environment, with its density with respect to
+    /// solid angle, whose light arrives unless something is in the way.
+    Environment { dir: Vec3, radiance: Vec3, pdf: f32 },
+}
+
+impl Light {
+    /// A light for `object` if it emits and its surface can be sampled.
+    /// Meshes are sampled through the transform of their instance, unless
+    /// it moves during the shutter interval.
+    pub fn from_object(object: &Arc<Object>) -> Option<Self> {
+        if !object.material.emits() {
+            return None;
+        }
+        if object.sampleable().is_some() {
+            return Some(Light::Object(object.clone()));
+        }
+        match (&object.geometry, &object.transform) {
+            (GeomType::Mesh(mesh), None) => {
+                Some(Light::Mesh(object.clone(), Arc::new(MeshLight::new(mesh)?)))
+            }
+            (GeomType::Instance(instance), None) => match instance.geometry() {
+                GeomType::Mesh(mesh) if !instance.transform().is_moving() => {
+                    let light = MeshLight::transformed(mesh, instance.transform())?;
+                    Some(Light::Mesh(object.clone(), Arc::new(light)))
+                }
+                GeomType::Mesh(_) => {
+                    eprintln!("Moving emissive meshes are only lit by paths hitting them");
+                    None
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn object(&self) -> Option<&Arc<Object>> {
+        match self {
+            Light::Object(object) | Light::Mesh(object, _) => Some(object),
+            _ => None,
+        }
+    }
+
+    /// Name of the group the light belongs to, if any. The environment
+    /// makes up a group of its own.
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            Light::Object(object) | Light::Mesh(object, _) => object.material.light_group(),
+            Light::Point(point) => point.light_group.as_deref(),
+            Light::Spot(spot) => spot.light_group.as_deref(),
+            Light::Sun(sun) => sun.light_group.as_deref(),
+            Light::Environment(_) => Some("environment"),
+        }
+    }
+
+    /// Whether a ray tracing to `material` hit this light.
+    pub fn is(&self, material: &Material) -> bool {
+        match self.object() {
+            Some(object) => std::ptr::eq(material, &object.material),
+            None => false,
+        }
+    }
+
+    pub fn sample(&self, origin: &Vec3, rnd: Vec2) -> LightSample {
+        match self {
+            Light::Object(object) => LightSample::Surface(shape(object).sample_from(origin, rnd)),
+            Light::Mesh(_, mesh) => LightSample::Surface(mesh.sample_surface(rnd)),
+            Light::Point(point) => point.sample(origin, rnd),
+            Light::Spot(spot) => spot.sample(origin, rnd),
+            Light::Sun(sun) => sun.sample(rnd),
+            Light::Environment(env) => env.sample(rnd),
+        }
+    }
+
+    /// Region the light shines from, or none for lights at infinity.
+    pub fn bounds(&self) -> Option<AABB> {
+        match self {
+            Light::Object(object) | Light::Mesh(object, _) => Some(object.bounds()),
+            Light::Point(point) => {
+                let r = glm::vec3(point.radius, point.radius, point.radius);
+                Some(AABB {
+                    min: point.position - r,
+                    max: point.position + r,
+                })
+            }
+            Light::Spot(spot) => {
+                let r = glm::vec3(spot.radius, spot.radius, spot.radius);
+                Some(AABB {
+                    min: spot.position - r,
+                    max: spot.position + r,
+                })
+            }
+            Light::Sun(_) | Light::Environment(_) => None,
+        }
+    }
+
+    /// Rough estimate of the total power given off, as a luminance, for
+    /// weighing lights with bounds against each other.
+    pub fn power(&self) -> f32 {
+        let pi = glm::pi::<f32>();
+        match self {
+            Light::Object(object) => {
+                let emission = luminance(&object.material.emission_estimate());
+                pi * emission * object.area().unwrap_or(0.0)
+            }
+            Light::Mesh(object, mesh) => {
+                pi * luminance(&object.material.emission_estimate()) * mesh.area()
+            }
+            Light::Point(point) => 4.0 * pi * point.intensity * luminance(&point.color),
+            Light::Spot(spot) => {
+                let cos_max = (spot.cone_angle.to_radians() * 0.5).cos();
+                2.0 * pi * (1.0 - cos_max) * spot.intensity * luminance(&spot.color)
+            }
+            Light::Sun(_) | Light::Environment(_) => 0.0,
+        }
+    }
+
+    /// Density of `sample` picking `hit` from `origin`, with respect to
+    /// solid angle. Zero for lights that rays cannot hit.
+    pub fn pdf(&self, origin: &Vec3, hit: &RayHit) -> f32 {
+        match self {
+            Light::Object(object) => shape(object).pdf(origin, hit),
+            Light::Mesh(_, mesh) => mesh.pdf(origin, hit),
+            _ => 0.0,
+        }
+    }
+}
+
+fn shape(object: &Object) -> &dyn Sampleable {
+    object
+        .sampleable()
+        .expect("lights are made of sampleable objects")
+}
+
+fn default_color() -> Vec3 {
+    glm::vec3(1.0, 1.0, 1.0)
+}
+
+/// Light radiating equally in all directions from a point, or from random
+/// points within `radius` of it for softer shadows. It has no surface for
+/// rays to hit.
+#[derive(Deserialize, Clone)]
+pub struct PointLight {
+    pub position: Vec3,
+    #[serde(default = "default_color")]
+    pub color: Vec3,
+    /// Radiant intensity, the power per unit solid angle.
+    pub intensity: f32,
+    #[serde(default)]
+    pub radius: f32,
+    /// Photometric file shaping the emission, with the luminaire pointing
+    /// down and `intensity` at its peak.
+    #[serde(default)]
+    pub ies: Option<IesProfile>,
+    /// Light group, see `Scene::light_groups`.
+    #[serde(default)]
+    pub light_group: Option<String>,
+}
+
+impl PointLight {
+    fn sample(&self, origin: &Vec3, rnd: Vec2) -> LightSample {
+        let d = self.position + sphere_point(rnd) * self.radius - origin;
+        let dist = glm::length(&d);
+        let profile = self.ies.as_ref().map_or(1.0, |ies| ies.eval(&-d));
+        LightSample::Direct {
+            dir: d / dist,
+            dist,
+            irradiance: self.color
+                * (profile * self.intensity / (dist * dist).max(self.radius * self.radius)),
+        }
+    }
+}
+
+/// Uniformly distributed point on the unit sphere.
+fn sphere_point(rnd: Vec2) -> Vec3 {
+    let z = 1.0 - 2.0 * rnd.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = glm::two_pi::<f32>() * rnd.y;
+    glm::vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Point light shining in a cone, as from a stage light or flashlight,
+/// optionally projecting an image. Like point lights it can shine from
+/// within `radius` of its position for softer shadows, keeping the cone
+/// and projection of its center.
+#[derive(Deserialize, Clone)]
+pub struct SpotLight {
+    pub position: Vec3,
+    /// Direction the light points in.
+    pub direction: Vec3,
+    #[serde(default = "default_color")]
+    pub color: Vec3,
+    /// Radiant intensity along `direction`.
+    pub intensity: f32,
+    #[serde(default)]
+    pub radius: f32,
+    /// Angle in degrees between opposite edges of the cone.
+    #[serde(default = "default_cone_angle")]
+    pub cone_angle: f32,
+    /// Exponent of the fall-off from the center to the edge of the cone,
+    /// where zero lights the whole cone evenly.
+    #[serde(default = "default_falloff")]
+    pub falloff: f32,
+    /// Image projected over the cone, fitting in it.
+    #[serde(default)]
+    pub texture: Option<ColorTexture>,
+    /// Photometric file shaping the emission within the cone, with the
+    /// luminaire pointing along `direction` and `intensity` at its peak.
+    #[serde(default)]
+    pub ies: Option<IesProfile>,
+    /// Light group, see `Scene::light_groups`.
+    #[serde(default)]
+    pub light_group: Option<String>,
+}
+
+fn default_cone_angle() -> f32 {
+    45.0
+}
+
+fn default_falloff() -> f32 {
+    1.0
+}
+
+impl SpotLight {
+    fn sample(&self, origin: &Vec3, rnd: Vec2) -> LightSample {
+        let d = self.position + sphere_point(rnd) * self.radius - origin;
+        let dist = glm::length(&d);
+        // The cone and projection are those of the center
+        let dir = glm::normalize(&(self.position - origin));
+        let axis = glm::normalize(&self.direction);
+        let cos = -glm::dot(&dir, &axis);
+        let cos_max = (self.cone_angle.to_radians() * 0.5).cos();
+        let mut irradiance = if cos > cos_max {
+            let t = (cos - cos_max) / (1.0 - cos_max);
+            let dist_sq = (dist * dist).max(self.radius * self.radius);
+            self.color * (self.intensity * t.powf(self.falloff) / dist_sq)
+        } else {
+            glm::zero()
+        };
+        // Upright where possible
+        let up = if axis.y.abs() < 0.999 {
+            glm::vec3(0.0, 1.0, 0.0)
+        } else {
+            glm::vec3(0.0, 0.0, 1.0)
+        };
+        let right = glm::normalize(&axis.cross(&up));
+        let up = right.cross(&axis);
+        if let Some(ies) = &self.ies {
+            // The profile's down is the light's axis
+            let away = -dir;
+            let local = glm::vec3(glm::dot(&away, &right), -cos, glm::dot(&away, &up));
+            irradiance *= ies.eval(&local);
+        }
+        if let Some(texture) = &self.texture {
+            // Projected onto a plane in front of the light, with the cone
+            // meeting the edges of the image
+            let scale = 0.5 / (self.cone_angle.to_radians() * 0.5).tan();
+            let uv = glm::vec2(
+                0.5 - glm::dot(&dir, &right) / cos * scale,
+                0.5 + glm::dot(&dir, &up) / cos * scale,
+            );
+            irradiance = irradiance.component_mul(&texture.sample(uv));
+        }
+        LightSample::Direct {
+            dir: d / dist,
+            dist,
+            irradiance,
+        }
+    }
+}
+
+/// Light arriving from infinitely far away in nearly parallel rays, from
+/// a disc `angular_diameter` degrees across, which softens its shadows.
+#[derive(Deserialize, Clone)]
+pub struct SunLight {
+    /// Direction towards the sun, or the place and time it shines on.
+    pub direction: SunDirection,
+    #[serde(default = "default_color")]
+    pub color: Vec3,
+    /// Power arriving per unit area facing the sun.
+    pub irradiance: f32,
+    #[serde(default = "default_angular_diameter")]
+    pub angular_diameter: f32,
+    /// Light group, see `Scene::light_groups`.
+    #[serde(default)]
+    pub light_group: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SunDirection {
+    Vector(Vec3),
+    Position(SunPosition),
+}
+
+impl SunDirection {
+    pub fn vector(&self) -> Vec3 {
+        match self {
+            SunDirection::Vector(v) => *v,
+            SunDirection::Position(position) => position.direction(),
+        }
+    }
+}
+
+/// As seen from the earth.
+fn default_angular_diameter() -> f32 {
+    0.53
+}
+
+impl SunLight {
+    /// The sun of an analytic sky, colored by the atmosphere in between.
+    pub fn from_sky(sky: &Sky) -> Self {
+        SunLight {
+            direction: SunDirection::Vector(sky.sun_direction()),
+            color: sky.sun_irradiance(),
+            irradiance: 1.0,
+            angular_diameter: default_angular_diameter(),
+            light_group: Some("sun".to_owned()),
+        }
+    }
+
+    fn sample(&self, rnd: Vec2) -> LightSample {
+        let w = glm::normalize(&self.direction.vector());
+        let cos_max = (self.angular_diameter.to_radians() * 0.5).cos();
+        let cos = 1.0 - rnd.x * (1.0 - cos_max);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = glm::two_pi::<f32>() * rnd.y;
+        let (u, v) = vec::orthonormal_basis(&w);
+        LightSample::Direct {
+            dir: u * (sin * phi.cos()) + v * (sin * phi.sin()) + w * cos,
+            dist: f32::MAX,
+            irradiance: self.color * self.irradiance,
+        }
+    }
+}
+
+/// The environment as a light, sampled in proportion to its brightness so
+/// that small, bright regions such as the sun in a photographed sky are
+/// found.
+#[derive(Clone)]
+pub struct EnvironmentLight {
+    environment: ColorTexture,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentLight {
+    /// Tabulates the brightness of `environment` at up to its own
+    /// resolution. Black environments give off no light and have none.
+    pub fn new(environment: &ColorTexture) -> Option<Self> {
+        if environment.is_black() {
+            return None;
+        }
+        let dim = environment.dimensions();
+        let width = (dim.x as usize).clamp(64, 1024);
+        let height = (dim.y as usize).clamp(32, 512);
+        let func: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let uv = glm::vec2(
+                    ((i % width) as f32 + 0.5) / width as f32,
+                    ((i / width) as f32 + 0.5) / height as f32,
+                );
+                // Rows nearer the poles cover less of the sphere
+                luminance(&environment.sample(uv)) * (glm::pi::<f32>() * uv.y).sin()
+            })
+            .collect();
+        Some(EnvironmentLight {
+            environment: environment.clone(),
+            distribution: Distribution2D::new(&func, width, height),
+        })
+    }
+
+    fn sample(&self, rnd: Vec2) -> LightSample {
+        let (uv, pdf) = self.distribution.sample(rnd);
+        LightSample::Environment {
+            dir: Sphere::dir_at_uv(&uv),
+            radiance: self.environment.sample(uv),
+            pdf: solid_angle_pdf(pdf, &uv),
+        }
+    }
+
+    /// Density of sampling `dir`, with respect to solid angle.
+    pub fn pdf(&self, dir: &Vec3) -> f32 {
+        let uv = Sphere::uv_at_dir(dir);
+        solid_angle_pdf(self.distribution.pdf(uv), &uv)
+    }
+}
+
+/// Converts a density over the latitude-longitude square to one over
+/// directions.
+fn solid_angle_pdf(pdf: f32, uv: &Vec2) -> f32 {
+    let sin = (glm::pi::<f32>() * uv.y).sin();
+    if sin > 0.0 {
+        pdf / (2.0 * glm::pi::<f32>() * glm::pi::<f32>() * sin)
+    } else {
+        0.0
+    }
+}
+
+/// Rectangular light, emitting from the face that `edge1 × edge2` points
+/// out of unless two-sided. Its emission texture varies it over the
+/// surface, while a gobo shapes it by direction.
+#[derive(Deserialize)]
+pub struct QuadLight {
+    #[serde(flatten)]
+    pub quad: Quad,
+    #[serde(flatten)]
+    pub emitter: Emitter,
+    #[serde(default)]
+    pub gobo: Option<Gobo>,
+}
+
+impl QuadLight {
+    /// The light's surface, which is visible to rays like any other.
+    pub fn into_object(self) -> Object {
+        let mut emitter = self.emitter;
+        let (edge1, edge2) = (self.quad.edge1, self.quad.edge2);
+        emitter.gobo = self.gobo.map(|gobo| Gobo {
+            edge1,
+            edge2,
+            ..gobo
+        });
+        Object {
+            geometry: GeomType::Quad(self.quad),
+            material: Material::Emitter(emitter),
+            transform: None,
+            animation: None,
+        }
+    }
+}
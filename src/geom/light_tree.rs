@@ -0,0 +1,222 @@
+use super::*;
+use crate::vec::glm;
+
+/// Binary hierarchy over the lights with bounds, for picking one in
+/// proportion to an estimate of its contribution at a point, its power over
+/// the squared distance to it. Picking then takes time logarithmic in the
+/// number of lights. Lights at infinity, such as the sun and environment,
+/// are picked uniformly alongside the hierarchy as a whole.
+#[derive(Clone, Default)]
+pub struct LightTree {
+    nodes: Vec<LightNode>,
+    /// Node of each light that is in the hierarchy.
+    leaves: Vec<Option<usize>>,
+    /// Lights without bounds.
+    infinite: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct LightNode {
+    bounds: AABB,
+    power: f32,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>,
+    /// The light of a leaf.
+    light: usize,
+}
+
+impl LightNode {
+    /// Estimated contribution at `origin`, which is not allowed to come
+    /// closer than the node's extent so that nearby nodes are not picked
+    /// almost exclusively.
+    fn importance(&self, origin: &Vec3) -> f32 {
+        let dist_sq = glm::distance2(&self.bounds.center(), origin);
+        let radius_sq = glm::distance2(&self.bounds.max, &self.bounds.min) * 0.25;
+        self.power / dist_sq.max(radius_sq).max(1e-6)
+    }
+}
+
+impl LightTree {
+    pub fn new(lights: &[Light]) -> Self {
+        let mut tree = LightTree {
+            nodes: Vec::new(),
+            leaves: vec![None; lights.len()],
+            infinite: Vec::new(),
+        };
+        let mut bounded = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) => bounded.push((i, bounds, light.power())),
+                None => tree.infinite.push(i),
+            }
+        }
+        if !bounded.is_empty() {
+            tree.build(&mut bounded, None);
+        }
+        tree
+    }
+
+    /// Adds the node for `lights`, split in two halves along the largest
+    /// axis of their centers, returning its index.
+    fn build(&mut self, lights: &mut [(usize, AABB, f32)], parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        let bounds = lights[1..]
+            .iter()
+            .fold(lights[0].1.clone(), |bounds, light| bounds.union(&light.1));
+        self.nodes.push(LightNode {
+            bounds,
+            power: lights.iter().map(|light| light.2).sum(),
+            parent,
+            children: None,
+            light: lights[0].0,
+        });
+        if lights.len() == 1 {
+            self.leaves[lights[0].0] = Some(index);
+            return index;
+        }
+        let centers: Vec<Vec3> = lights.iter().map(|light| light.1.center()).collect();
+        let axis = AABB::from(&centers).largest_axis();
+        lights.sort_by(|a, b| {
+            let (a, b) = (a.1.center()[axis], b.1.center()[axis]);
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let left = self.build(left, Some(index));
+        let right = self.build(right, Some(index));
+        self.nodes[index].children = Some((left, right));
+        index
+    }
+
+    /// Number of groups picked among uniformly: each light at infinity, and
+    /// the hierarchy.
+    fn groups(&self) -> usize {
+        self.infinite.len() + if self.nodes.is_empty() { 0 } else { 1 }
+    }
+
+    /// Chance of descending from a node to `child` rather than `sibling`.
+    fn branch_pdf(&self, origin: &Vec3, child: usize, sibling: usize) -> f32 {
+        let child = self.nodes[child].importance(origin);
+        let sibling = self.nodes[sibling].importance(origin);
+        if child + sibling > 0.0 {
+            child / (child + sibling)
+        } else {
+            0.5
+        }
+    }
+
+    /// Picks a light to be seen from `origin`, returning its index with the
+    /// chance of picking it.
+    pub fn pick(&self, origin: &Vec3, u: f32) -> Option<(usize, f32)> {
+        let groups = self.groups();
+        if groups == 0 {
+            return None;
+        }
+        let group = ((u * groups as f32) as usize).min(groups - 1);
+        if let Some(&light) = self.infinite.get(group) {
+            return Some((light, 1.0 / groups as f32));
+        }
+        // What is left of `u` picks the branches on the way down
+        let mut u = (u * groups as f32 - group as f32).clamp(0.0, 1.0);
+        let mut pdf = 1.0 / groups as f32;
+        let mut node = 0;
+        while let Some((left, right)) = self.nodes[node].children {
+            let p = self.branch_pdf(origin, left, right);
+            if u < p {
+                u /= p;
+                pdf *= p;
+                node = left;
+            } else {
+                u = (u - p) / (1.0 - p);
+                pdf *= 1.0 - p;
+                node = right;
+            }
+        }
+        Some((self.nodes[node].light, pdf))
+    }
+
+    /// Chance of `pick` picking the light at `index` from `origin`.
+    pub fn pdf(&self, origin: &Vec3, index: usize) -> f32 {
+        let groups = self.groups();
+        if self.infinite.contains(&index) {
+            return 1.0 / groups as f32;
+        }
+        let mut node = match self.leaves.get(index) {
+            Some(&Some(node)) => node,
+            _ => return 0.0,
+        };
+        let mut pdf = 1.0 / groups as f32;
+        while let Some(parent) = self.nodes[node].parent {
+            let (left, right) = self.nodes[parent].children.expect("parents have children");
+            let sibling = if node == left { right } else { left };
+            pdf *= self.branch_pdf(origin, node, sibling);
+            node = parent;
+        }
+        pdf
+    }
+
+    /// Indices of the lights without bounds.
+    pub fn infinite(&self) -> &[usize] {
+        &self.infinite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, z: f32, intensity: f32) -> Light {
+        Light::Point(PointLight {
+            position: glm::vec3(x, y, z),
+            color: glm::vec3(1.0, 1.0, 1.0),
+            intensity,
+            radius: 0.1,
+            ies: None,
+            light_group: None,
+        })
+    }
+
+    fn lights() -> Vec<Light> {
+        vec![
+            point(0.0, 2.0, 0.0, 1.0),
+            point(5.0, 0.0, 1.0, 10.0),
+            point(-3.0, 1.0, 4.0, 0.5),
+            Light::Sun(SunLight {
+                direction: SunDirection::Vector(glm::vec3(0.0, 1.0, 0.0)),
+                color: glm::vec3(1.0, 1.0, 1.0),
+                irradiance: 1.0,
+                angular_diameter: 0.5,
+                light_group: None,
+            }),
+            point(2.0, -1.0, -6.0, 3.0),
+        ]
+    }
+
+    #[test]
+    fn pick_probabilities_sum_to_one() {
+        let lights = lights();
+        let tree = LightTree::new(&lights);
+        for origin in &[glm::vec3(0.0, 0.0, 0.0), glm::vec3(4.0, 1.0, 1.0)] {
+            let total: f32 = (0..lights.len()).map(|i| tree.pdf(origin, i)).sum();
+            assert!((total - 1.0).abs() < 1e-5, "probabilities sum to {}", total);
+        }
+    }
+
+    #[test]
+    fn pdf_matches_pick() {
+        let lights = lights();
+        let tree = LightTree::new(&lights);
+        let origin = glm::vec3(1.0, 0.5, -1.0);
+        let n = 1000;
+        let mut picked = vec![0; lights.len()];
+        for k in 0..n {
+            let (i, pdf) = tree.pick(&origin, (k as f32 + 0.5) / n as f32).unwrap();
+            assert!((pdf - tree.pdf(&origin, i)).abs() < 1e-5);
+            picked[i] += 1;
+        }
+        // Evenly spread numbers pick each light about as often as its pdf says
+        for (i, &count) in picked.iter().enumerate() {
+            let expected = tree.pdf(&origin, i);
+            assert!((count as f32 / n as f32 - expected).abs() < 0.01);
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::path::Path;
 
 use nalgebra_glm as glm;
 use serde::{Deserialize, Deserializer};
+use wide::f32x4;
 
 use super::*;
 use crate::obj;
@@ -18,75 +19,269 @@ pub struct Vertex {
 #[derive(Clone)]
 pub struct Triangle {
     verts: [Vertex; 3],
+    /// Derived from the texture coordinates, if they are not degenerate.
+    tangent: Option<Vec3>,
+    /// Texture coordinates covered per unit of length on the surface.
+    uv_density: f32,
 }
 
+/// A triangle whose vertices move across the shutter interval, given as
+/// keyframes evenly spaced in time.
 #[derive(Clone)]
-pub struct Mesh {
-    tree: KdTree<Triangle>,
+pub struct MotionTriangle {
+    frames: Vec<Triangle>,
+}
+
+#[derive(Clone)]
+pub enum Mesh {
+    Static(Accel<Triangle>),
+    Deforming(Accel<MotionTriangle>),
+    #[cfg(feature = "embree")]
+    Embree(EmbreeMesh),
 }
 
 impl Triangle {
     pub fn new(v1: Vertex, v2: Vertex, v3: Vertex) -> Self {
+        let (e1, e2) = (v2.pos - v1.pos, v3.pos - v1.pos);
+        let (d1, d2) = (v2.uv - v1.uv, v3.uv - v1.uv);
+        let det = d1.x * d2.y - d1.y * d2.x;
+        let tangent = if det.abs() > 1e-12 {
+            let t = (e1 * d2.y - e2 * d1.y) / det;
+            Some(glm::normalize(&t))
+        } else {
+            None
+        };
+        let uv_density = (det.abs() / e1.cross(&e2).norm().max(1e-12)).sqrt();
         Triangle {
             verts: [v1, v2, v3],
+            tangent,
+            uv_density,
         }
     }
 
+    pub fn tangent(&self) -> Option<Vec3> {
+        self.tangent
+    }
+
+    /// Width in UV units of the footprint of a ray hitting at `t`.
+    pub fn footprint(&self, r: &Ray, t: f32) -> f32 {
+        r.spread * t * self.uv_density
+    }
+
     pub fn positions(&self) -> (Vec3, Vec3, Vec3) {
         (self.verts[0].pos, self.verts[1].pos, self.verts[2].pos)
     }
 
-    fn interpolate(&self, p: &Vec3) -> Vertex {
-        let triangle_area = |e0: Vec3, e1: Vec3| glm::length(&e0.cross(&e1));
+    pub fn area(&self) -> f32 {
+        let (v0, v1, v2) = self.positions();
+        0.5 * (v1 - v0).cross(&(v2 - v0)).norm()
+    }
+
+    /// Normal of the triangle's plane, following the winding of its vertices.
+    pub(crate) fn face_normal(&self) -> Vec3 {
+        let (v0, v1, v2) = self.positions();
+        glm::normalize(&(v1 - v0).cross(&(v2 - v0)))
+    }
+
+    /// Point picked uniformly over the triangle, facing the side rays hit.
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        let (v0, v1, v2) = self.positions();
+        let su = rnd.x.sqrt();
+        let (b1, b2) = (su * (1.0 - rnd.y), su * rnd.y);
+        SurfaceSample {
+            point: v0 + (v1 - v0) * b1 + (v2 - v0) * b2,
+            normal: self.face_normal(),
+        }
+    }
+
+    pub(crate) fn interpolate(&self, [b0, b1, b2]: [f32; 3]) -> (Vec2, Vec3) {
         let [v0, v1, v2] = &self.verts;
-        let (p0, p1, p2) = self.positions();
-        let f0 = p0 - p;
-        let f1 = p1 - p;
-        let f2 = p2 - p;
-        let a = triangle_area(p1 - p0, p0 - p2);
-        let a0 = triangle_area(f1, f2) / a;
-        let a1 = triangle_area(f2, f0) / a;
-        let a2 = triangle_area(f0, f1) / a;
-        let uv = v0.uv * a0 + v1.uv * a1 + v2.uv * a2;
-        let normal = v0.normal * a0 + v1.normal * a1 + v2.normal * a2;
-        Vertex {
-            pos: *p,
-            uv,
+        let uv = v0.uv * b0 + v1.uv * b1 + v2.uv * b2;
+        let normal = v0.normal * b0 + v1.normal * b1 + v2.normal * b2;
+        (uv, glm::normalize(&normal))
+    }
+
+    fn transformed(&self, transform: &Transform) -> Triangle {
+        let vertex = |v: &Vertex| Vertex {
+            pos: transform.point(&v.pos),
+            normal: transform.normal(&v.normal),
+            uv: v.uv,
+        };
+        let [v0, v1, v2] = &self.verts;
+        Triangle::new(vertex(v0), vertex(v1), vertex(v2))
+    }
+
+    fn lerp(&self, other: &Triangle, t: f32) -> Triangle {
+        let vertex = |i: usize| {
+            let (a, b) = (&self.verts[i], &other.verts[i]);
+            Vertex {
+                pos: glm::lerp(&a.pos, &b.pos, t),
+                normal: glm::lerp(&a.normal, &b.normal, t),
+                uv: a.uv,
+            }
+        };
+        Triangle::new(vertex(0), vertex(1), vertex(2))
+    }
+}
+
+/// Edge functions of the triangle projected onto the plane perpendicular to
+/// the ray, following Woop et al., "Watertight Ray/Triangle Intersection".
+fn edge_functions(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> [f32; 3] {
+    let u = c[0] * b[1] - c[1] * b[0];
+    let v = a[0] * c[1] - a[1] * c[0];
+    let w = b[0] * a[1] - b[1] * a[0];
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        // Fall back to double precision on edges so neighbouring triangles agree
+        let f = |p: [f32; 2], q: [f32; 2]| {
+            (f64::from(p[0]) * f64::from(q[1]) - f64::from(p[1]) * f64::from(q[0])) as f32
+        };
+        [f(c, b), f(a, c), f(b, a)]
+    } else {
+        [u, v, w]
+    }
+}
+
+/// Axes of a ray's own space for watertight triangle tests, the ray
+/// travelling along the last, and the factors shearing it to point
+/// straight along it.
+pub struct Shear {
+    pub axes: [usize; 3],
+    pub factors: [f32; 3],
+}
+
+impl Shear {
+    pub fn new(d: &Vec3) -> Self {
+        // Permute axes so the ray travels along the dominant dimension
+        let kz = if d.x.abs() > d.y.abs() && d.x.abs() > d.z.abs() {
+            0
+        } else if d.y.abs() > d.z.abs() {
+            1
+        } else {
+            2
+        };
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+        Shear {
+            axes: [kx, ky, kz],
+            factors: [d[kx] / d[kz], d[ky] / d[kz], 1.0 / d[kz]],
+        }
+    }
+}
+
+impl Triangle {
+    /// Distance and barycentric coordinates of where `r` crosses the
+    /// triangle, found in the ray's own space so that neighbouring
+    /// triangles leave no gaps. Both faces are hit, leaving back faces to
+    /// the material's sides.
+    fn crossing(&self, r: &Ray, min: f32, max: f32) -> Option<(f32, [f32; 3])> {
+        let (v0, v1, v2) = self.positions();
+        let Shear {
+            axes: [kx, ky, kz],
+            factors: [sx, sy, sz],
+        } = Shear::new(&r.direction);
+
+        let (a, b, c) = (v0 - r.origin, v1 - r.origin, v2 - r.origin);
+        let shear = |p: &Vec3| [p[kx] - sx * p[kz], p[ky] - sy * p[kz]];
+        let [u, v, w] = edge_functions(shear(&a), shear(&b), shear(&c));
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+        let t = (u * a[kz] + v * b[kz] + w * c[kz]) * sz / det;
+        if t <= min || t >= max {
+            return None;
+        }
+        Some((t, [u / det, v / det, w / det]))
+    }
+
+    fn hit(&self, r: &Ray, t: f32, barycentric: [f32; 3]) -> RayHit {
+        let (uv, normal) = self.interpolate(barycentric);
+        RayHit {
+            t,
+            point: r.point_at(t),
             normal,
+            geometric_normal: self.face_normal(),
+            uv,
+            tangent: self.tangent,
+            footprint: self.footprint(r, t),
         }
     }
 }
 
 impl Geometry for Triangle {
     fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let (t, barycentric) = self.crossing(r, min, max)?;
+        Some(self.hit(r, t, barycentric))
+    }
+}
+
+impl PacketGeometry for Triangle {
+    /// The watertight test on all four lanes at once, when the rays share
+    /// their axes, carrying out the same operations in the same order as
+    /// for single rays so that packets see exactly what their rays would
+    /// alone. Lanes landing on an edge are left to the test of single rays,
+    /// which resolves them in double precision, as are all lanes of packets
+    /// whose rays do not share their axes.
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        let shear = match &packet.shear {
+            Some(shear) => shear,
+            None => return intersect_each(self, packet, min, max, active, hits),
+        };
+        let [kx, ky, kz] = shear.axes;
+        let [sx, sy, sz] = shear.factors;
         let (v0, v1, v2) = self.positions();
-        let e1 = v1 - v0;
-        let e2 = v2 - v0;
-        let pvec = r.direction.cross(&e2);
-        let det = e1.dot(&pvec);
-
-        // Cull backfaces by ignoring negative det
-        if det.is_sign_positive() {
-            let idet = 1.0 / det;
-            let tvec = r.origin - v0;
-            let qvec = tvec.cross(&e1);
-            let t = e2.dot(&qvec) * idet;
-            let uv = glm::vec2(tvec.dot(&pvec), r.direction.dot(&qvec)) * idet;
-            if uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.x + uv.y <= 1.0 && t > min && t < max
-            {
-                let point = r.point_at(t);
-                let Vertex { uv, normal, .. } = self.interpolate(&point);
-                Some(RayHit {
-                    t,
-                    point,
-                    normal,
-                    uv,
-                })
+        let relative = |v: &Vec3| [0, 1, 2].map(|dim| f32x4::splat(v[dim]) - packet.origin[dim]);
+        let (a, b, c) = (relative(&v0), relative(&v1), relative(&v2));
+        let shear = |p: &[f32x4; 3]| [p[kx] - sx * p[kz], p[ky] - sy * p[kz]];
+        let (sa, sb, sc) = (shear(&a), shear(&b), shear(&c));
+        let u = sc[0] * sb[1] - sc[1] * sb[0];
+        let v = sa[0] * sc[1] - sa[1] * sc[0];
+        let w = sb[0] * sa[1] - sb[1] * sa[0];
+
+        let zero = f32x4::ZERO;
+        let edge = u.simd_eq(zero) | v.simd_eq(zero) | w.simd_eq(zero);
+        let negative = u.simd_lt(zero) | v.simd_lt(zero) | w.simd_lt(zero);
+        let positive = u.simd_gt(zero) | v.simd_gt(zero) | w.simd_gt(zero);
+        let det = u + v + w;
+        let t = (u * a[kz] + v * b[kz] + w * c[kz]) * sz / det;
+        let missed = (negative & positive)
+            | det.simd_eq(zero)
+            | t.simd_le(f32x4::splat(min))
+            | t.simd_ge(f32x4::new(*max));
+        let (edge, missed) = (edge.to_bitmask(), missed.to_bitmask());
+
+        let (t, u, v, w, det) = (
+            t.to_array(),
+            u.to_array(),
+            v.to_array(),
+            w.to_array(),
+            det.to_array(),
+        );
+        for i in (0..LANES).filter(|&i| active[i]) {
+            let r = &packet.rays[i];
+            let crossing = if edge & (1 << i) != 0 {
+                self.crossing(r, min, max[i])
+            } else if missed & (1 << i) == 0 {
+                Some((t[i], [u[i] / det[i], v[i] / det[i], w[i] / det[i]]))
             } else {
                 None
+            };
+            if let Some((t, barycentric)) = crossing {
+                max[i] = t;
+                hits[i] = Some(self.hit(r, t, barycentric));
             }
-        } else {
-            None
         }
     }
 }
@@ -97,30 +292,243 @@ impl Bounds for Triangle {
     }
 }
 
+impl Geometry for MotionTriangle {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let last = self.frames.len() - 1;
+        let s = r.time.clamp(0.0, 1.0) * last as f32;
+        let i = usize::min(s as usize, last.saturating_sub(1));
+        match self.frames.get(i + 1) {
+            Some(next) => self.frames[i]
+                .lerp(next, s - i as f32)
+                .intersection(r, min, max),
+            None => self.frames[i].intersection(r, min, max),
+        }
+    }
+}
+
+impl PacketGeometry for MotionTriangle {}
+
+impl Bounds for MotionTriangle {
+    /// Interpolated vertices never leave the bounds of the keyframes.
+    fn bounds(&self) -> AABB {
+        total_bounds(&self.frames)
+    }
+}
+
+/// Meshes are given either as a bare path, or as a table with processing
+/// options applied at load time.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MeshDesc {
+    Path(String),
+    Options(MeshOptions),
+}
+
+#[derive(Deserialize)]
+pub struct MeshOptions {
+    pub file: String,
+    /// Levels of Catmull-Clark subdivision applied to the loaded cage.
+    #[serde(default)]
+    pub subdivide: u32,
+    #[serde(default)]
+    pub displacement: Option<Displacement>,
+    /// Simplifies the loaded mesh down to about this many triangles.
+    #[serde(default)]
+    pub decimate: Option<usize>,
+    /// Largest angle in degrees between faces that are shaded smoothly,
+    /// used when the file has no normals.
+    #[serde(default = "default_crease_angle")]
+    pub crease_angle: f32,
+    /// Further OBJ files with the same topology, giving the vertex positions
+    /// at evenly spaced times across the shutter interval.
+    #[serde(default)]
+    pub motion: Vec<String>,
+}
+
+fn default_crease_angle() -> f32 {
+    obj::DEFAULT_CREASE_ANGLE
+}
+
 impl Mesh {
     pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
         let tris = obj::load(path)?;
-        let tree = KdTree::new(tris);
-        Ok(Mesh { tree })
+        Ok(Self::from_triangles(tris))
+    }
+
+    pub fn from_triangles(tris: Vec<Triangle>) -> Self {
+        Mesh::Static(Accel::linear(tris))
+    }
+
+    /// Loads a mesh and applies decimation, subdivision, then displacement.
+    /// Meshes with motion keyframes only support subdivision, as the other
+    /// steps would change the topology between frames.
+    pub fn load(options: &MeshOptions) -> std::io::Result<Self> {
+        let load_frame = |file: &String| -> std::io::Result<Vec<Triangle>> {
+            let mut mesh = obj::load_polygons(file)?;
+            if let Some(target) = options.decimate {
+                mesh = mesh.decimated(target);
+            }
+            mesh.generate_normals(options.crease_angle);
+            let mut mesh = mesh.subdivided(options.subdivide);
+            if let Some(displacement) = &options.displacement {
+                mesh = mesh.displaced(displacement);
+            }
+            Ok(mesh.triangulate())
+        };
+        let invalid =
+            |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_owned());
+        let has_motion = !options.motion.is_empty();
+        if has_motion && (options.decimate.is_some() || options.displacement.is_some()) {
+            return Err(invalid(
+                "Decimation and displacement are not supported on meshes with motion",
+            ));
+        }
+        let tris = load_frame(&options.file)?;
+        if !has_motion {
+            return Ok(Self::from_triangles(tris));
+        }
+        let mut frames = vec![tris];
+        for file in &options.motion {
+            let tris = load_frame(file)?;
+            if tris.len() != frames[0].len() {
+                return Err(invalid("Motion keyframes must share the same topology"));
+            }
+            frames.push(tris);
+        }
+        let tris = (0..frames[0].len())
+            .map(|i| MotionTriangle {
+                frames: frames.iter().map(|f| f[i].clone()).collect(),
+            })
+            .collect();
+        Ok(Mesh::Deforming(Accel::linear(tris)))
+    }
+
+    /// The triangles of a static mesh, unless its accelerator cannot list
+    /// each of them once.
+    pub fn triangles(&self) -> Option<Vec<&Triangle>> {
+        match self {
+            Mesh::Static(tree) => tree.geoms(),
+            Mesh::Deforming(_) => None,
+            #[cfg(feature = "embree")]
+            Mesh::Embree(mesh) => Some(mesh.triangles().iter().collect()),
+        }
+    }
+
+    /// Meshes are loaded flat; the triangle hierarchy is built once the
+    /// scene's build settings are known.
+    pub fn build(self, settings: BuildSettings) -> Self {
+        match self {
+            #[cfg(feature = "embree")]
+            Mesh::Static(Accel::Linear { geoms, .. })
+                if settings.accelerator == AcceleratorKind::Embree =>
+            {
+                Mesh::Embree(EmbreeMesh::new(geoms))
+            }
+            Mesh::Static(tree) => Mesh::Static(tree.build(settings)),
+            Mesh::Deforming(tree) => Mesh::Deforming(tree.build(settings)),
+            #[cfg(feature = "embree")]
+            embree => embree,
+        }
+    }
+}
+
+/// Surface of an emissive mesh, sampled as a light by picking triangles
+/// in proportion to their area.
+#[derive(Clone)]
+pub struct MeshLight {
+    triangles: Vec<Triangle>,
+    distribution: Distribution1D,
+    area: f32,
+}
+
+impl MeshLight {
+    /// None for meshes without a surface to sample.
+    pub fn new(mesh: &Mesh) -> Option<Self> {
+        Self::from_triangles(mesh.triangles()?.into_iter().cloned().collect())
+    }
+
+    /// The surface of an instance of `mesh`, placed by `transform`.
+    pub fn transformed(mesh: &Mesh, transform: &Transform) -> Option<Self> {
+        let triangles = mesh.triangles()?.into_iter();
+        Self::from_triangles(triangles.map(|t| t.transformed(transform)).collect())
+    }
+
+    fn from_triangles(triangles: Vec<Triangle>) -> Option<Self> {
+        let areas: Vec<f32> = triangles.iter().map(Triangle::area).collect();
+        let area: f32 = areas.iter().sum();
+        if area <= 0.0 {
+            return None;
+        }
+        Some(MeshLight {
+            triangles,
+            distribution: Distribution1D::new(areas),
+            area,
+        })
+    }
+
+    pub fn area(&self) -> f32 {
+        self.area
+    }
+
+    pub fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        let (x, i) = self.distribution.sample(rnd.x);
+        // What is left of the random number within the triangle's piece
+        let u = (x * self.triangles.len() as f32 - i as f32).clamp(0.0, 1.0);
+        self.triangles[i].sample_surface(glm::vec2(u, rnd.y))
+    }
+
+    /// Density of sampling `hit` from `origin`, with respect to solid angle.
+    pub fn pdf(&self, origin: &Vec3, hit: &RayHit) -> f32 {
+        area_pdf(self.area, origin, hit)
     }
 }
 
 impl Geometry for Mesh {
     fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
-        self.tree.intersection(r, min, max)
+        match self {
+            Mesh::Static(tree) => tree.intersection(r, min, max),
+            Mesh::Deforming(tree) => tree.intersection(r, min, max),
+            #[cfg(feature = "embree")]
+            Mesh::Embree(mesh) => mesh.intersection(r, min, max),
+        }
+    }
+}
+
+impl PacketGeometry for Mesh {
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        match self {
+            Mesh::Static(tree) => tree.intersect_packet(packet, min, max, active, hits),
+            Mesh::Deforming(tree) => tree.intersect_packet(packet, min, max, active, hits),
+            #[cfg(feature = "embree")]
+            Mesh::Embree(mesh) => intersect_each(mesh, packet, min, max, active, hits),
+        }
     }
 }
 
 impl Bounds for Mesh {
     fn bounds(&self) -> AABB {
-        self.tree.bounds()
+        match self {
+            Mesh::Static(tree) => tree.bounds(),
+            Mesh::Deforming(tree) => tree.bounds(),
+            #[cfg(feature = "embree")]
+            Mesh::Embree(mesh) => mesh.bounds(),
+        }
     }
 }
 
 impl<'de> Deserialize<'de> for Mesh {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        let s = String::deserialize(deserializer)?;
-        let mesh = Mesh::from_file(&s).map_err(serde::de::Error::custom)?;
-        Ok(mesh)
+        let mesh = match MeshDesc::deserialize(deserializer)? {
+            MeshDesc::Path(file) => Mesh::from_file(&file),
+            MeshDesc::Options(options) => Mesh::load(&options),
+        };
+        mesh.map_err(serde::de::Error::custom)
     }
 }
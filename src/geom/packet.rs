@@ -0,0 +1,343 @@
+use wide::f32x4;
+
+use super::*;
+
+use crate::ray::Ray;
+
+/// Number of rays traced together in a packet.
+pub const LANES: usize = 4;
+
+pub type PacketHits = [Option<RayHit>; LANES];
+
+/// Which rays of a packet are still being traced.
+pub type Mask = [bool; LANES];
+
+/// Every ray of a packet.
+pub const ALL: Mask = [true; LANES];
+
+pub fn no_hits() -> PacketHits {
+    [None, None, None, None]
+}
+
+/// A group of coherent rays, such as several samples of the same pixel,
+/// with their components held lane by lane in vector registers.
+pub struct RayPacket {
+    pub rays: [Ray; LANES],
+    pub origin: [f32x4; 3],
+    pub direction: [f32x4; 3],
+    pub inv_dir: [f32x4; 3],
+    /// How the rays are sheared for triangle tests, when they all travel
+    /// mostly along the same axis in the same direction.
+    pub shear: Option<PacketShear>,
+}
+
+/// `Shear` of all rays of a packet, which share its axes.
+pub struct PacketShear {
+    pub axes: [usize; 3],
+    pub factors: [f32x4; 3],
+}
+
+/// Vector of `f` of each lane.
+fn lanes<F: Fn(usize) -> f32>(f: F) -> f32x4 {
+    f32x4::new([f(0), f(1), f(2), f(3)])
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; LANES]) -> Self {
+        let per_dim = |v: fn(&Ray) -> Vec3| [0, 1, 2].map(|dim| lanes(|i| v(&rays[i])[dim]));
+        let origin = per_dim(|r| r.origin);
+        let direction = per_dim(|r| r.direction);
+        let inv_dir = per_dim(|r| r.inv_dir);
+
+        let shears = rays.each_ref().map(|r| Shear::new(&r.direction));
+        let axes = shears[0].axes;
+        let shear = if shears.iter().all(|s| s.axes == axes) {
+            Some(PacketShear {
+                axes,
+                factors: [0, 1, 2].map(|k| lanes(|i| shears[i].factors[k])),
+            })
+        } else {
+            None
+        };
+        RayPacket {
+            rays,
+            origin,
+            direction,
+            inv_dir,
+            shear,
+        }
+    }
+}
+
+impl AABB {
+    /// Which of the `active` rays in the packet enter the box before their
+    /// current `max`, and the distances at which they enter it.
+    pub fn intersects_packet(
+        &self,
+        p: &RayPacket,
+        max: &[f32; LANES],
+        active: Mask,
+    ) -> (Mask, f32x4) {
+        let mut tmin = f32x4::ZERO;
+        let mut tmax = f32x4::new(*max);
+        for dim in 0..3 {
+            let t1 = (f32x4::splat(self.min[dim]) - p.origin[dim]) * p.inv_dir[dim];
+            let t2 = (f32x4::splat(self.max[dim]) - p.origin[dim]) * p.inv_dir[dim];
+            tmin = tmin.max(t1.min(t2));
+            tmax = tmax.min(t1.max(t2));
+        }
+        let entered = tmin.simd_le(tmax).to_bitmask();
+        let mut mask = [false; LANES];
+        for (i, lane) in mask.iter_mut().enumerate() {
+            *lane = active[i] && entered & (1 << i) != 0;
+        }
+        (mask, tmin)
+    }
+}
+
+/// Geometry that can be intersected with a whole packet at once. Hits of
+/// the `active` rays only replace those in `hits` that are closer,
+/// shrinking `max` accordingly. The default intersects each ray on its own.
+pub trait PacketGeometry: Geometry {
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        intersect_each(self, packet, min, max, active, hits)
+    }
+}
+
+/// Intersects each active ray of the packet on its own.
+pub fn intersect_each<G: Geometry + ?Sized>(
+    geom: &G,
+    packet: &RayPacket,
+    min: f32,
+    max: &mut [f32; LANES],
+    active: Mask,
+    hits: &mut PacketHits,
+) {
+    for i in (0..LANES).filter(|&i| active[i]) {
+        if let Some(hit) = geom.intersection(&packet.rays[i], min, max[i]) {
+            max[i] = hit.t;
+            hits[i] = Some(hit);
+        }
+    }
+}
+
+impl<T> Bvh<T> {
+    fn node_bounds(&self) -> &AABB {
+        match self {
+            Bvh::Leaf { bounds, .. } | Bvh::Node { bounds, .. } => bounds,
+        }
+    }
+
+    /// Walks the hierarchy with all rays of the packet together, visiting
+    /// every leaf geometry that any ray may hit before its current `max`,
+    /// along with which rays may hit it.
+    pub fn visit_packet<'a, F: FnMut(&'a T, &mut [f32; LANES], Mask)>(
+        &'a self,
+        packet: &RayPacket,
+        max: &mut [f32; LANES],
+        visit: &mut F,
+    ) {
+        let (active, _) = self.node_bounds().intersects_packet(packet, max, ALL);
+        if active.iter().any(|&b| b) {
+            self.visit_entered(packet, max, active, visit);
+        }
+    }
+
+    /// Visits the leaves below this node, whose bounds the `active` rays
+    /// enter, the children nearer to the rays first. Rays leave the mask
+    /// for a child once hits nearer than it have been found.
+    fn visit_entered<'a, F: FnMut(&'a T, &mut [f32; LANES], Mask)>(
+        &'a self,
+        packet: &RayPacket,
+        max: &mut [f32; LANES],
+        active: Mask,
+        visit: &mut F,
+    ) {
+        match self {
+            Bvh::Leaf { geoms, .. } => {
+                for geom in geoms {
+                    visit(geom, max, active);
+                }
+            }
+            Bvh::Node { left, right, .. } => {
+                let (left_mask, left_entry) =
+                    left.node_bounds().intersects_packet(packet, max, active);
+                let (right_mask, right_entry) =
+                    right.node_bounds().intersects_packet(packet, max, active);
+                // Nearer by the rays entering both, or the one any enters
+                let closer = (left_entry - right_entry).to_array();
+                let lead: f32 = (0..LANES)
+                    .filter(|&i| left_mask[i] && right_mask[i])
+                    .map(|i| closer[i])
+                    .sum();
+                let (near, far) = if lead <= 0.0 {
+                    ((left, left_mask), (right, right_mask, right_entry))
+                } else {
+                    ((right, right_mask), (left, left_mask, left_entry))
+                };
+                if near.1.iter().any(|&b| b) {
+                    near.0.visit_entered(packet, max, near.1, visit);
+                }
+                let (node, mut mask, entry) = far;
+                let entry = entry.to_array();
+                for i in 0..LANES {
+                    mask[i] = mask[i] && entry[i] <= max[i];
+                }
+                if mask.iter().any(|&b| b) {
+                    node.visit_entered(packet, max, mask, visit);
+                }
+            }
+        }
+    }
+}
+
+impl<T: PacketGeometry> PacketGeometry for Bvh<T> {
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        let (active, _) = self.node_bounds().intersects_packet(packet, max, active);
+        if active.iter().any(|&b| b) {
+            self.visit_entered(packet, max, active, &mut |geom, max, mask| {
+                geom.intersect_packet(packet, min, max, mask, hits)
+            });
+        }
+    }
+}
+
+impl<T: PacketGeometry> PacketGeometry for Accel<T> {
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        match self {
+            Accel::Linear { bounds, geoms } => {
+                let (active, _) = bounds.intersects_packet(packet, max, active);
+                if active.iter().any(|&b| b) {
+                    for geom in geoms {
+                        geom.intersect_packet(packet, min, max, active, hits);
+                    }
+                }
+            }
+            Accel::Bvh(bvh) => bvh.intersect_packet(packet, min, max, active, hits),
+            Accel::KdTree(tree) => intersect_each(tree, packet, min, max, active, hits),
+        }
+    }
+}
+
+impl PacketGeometry for GeomType {
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        match self {
+            GeomType::Sphere(s) => s.intersect_packet(packet, min, max, active, hits),
+            GeomType::Mesh(m) => m.intersect_packet(packet, min, max, active, hits),
+            other => intersect_each(other, packet, min, max, active, hits),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+
+    /// Rays from `origin` towards the points of a grid half a unit apart,
+    /// four at a time.
+    fn packets(origin: Vec3, n: usize) -> Vec<RayPacket> {
+        let targets: Vec<Vec3> = (0..=2 * n)
+            .flat_map(|x| (0..=2 * n).map(move |y| glm::vec3(x as f32, y as f32, 0.0) * 0.5))
+            .collect();
+        let ray = |target: &Vec3| Ray::new(origin, target - origin);
+        targets
+            .chunks_exact(LANES)
+            .map(|t| RayPacket::new([ray(&t[0]), ray(&t[1]), ray(&t[2]), ray(&t[3])]))
+            .collect()
+    }
+
+    /// Checks that each ray of the packet hits `geom` exactly where it
+    /// does alone.
+    fn assert_same_hits<G: PacketGeometry>(geom: &G, packet: &RayPacket) {
+        let mut max = [f32::MAX; LANES];
+        let mut hits = no_hits();
+        geom.intersect_packet(packet, 0.001, &mut max, ALL, &mut hits);
+        for (r, hit) in packet.rays.iter().zip(&hits) {
+            let alone = geom.intersection(r, 0.001, f32::MAX);
+            assert_eq!(
+                hit.as_ref().map(|h| h.t.to_bits()),
+                alone.map(|h| h.t.to_bits()),
+                "ray {:?}",
+                r.direction
+            );
+        }
+    }
+
+    #[test]
+    fn triangles_match_single_rays() {
+        // Unit squares split in two, whose shared edges and corners some
+        // of the rays pass right through
+        let n = 4;
+        let vertex = |x: usize, y: usize| Vertex {
+            pos: glm::vec3(x as f32, y as f32, 0.0),
+            normal: glm::vec3(0.0, 0.0, 1.0),
+            uv: glm::zero(),
+        };
+        let mut tris = vec![];
+        for x in 0..n {
+            for y in 0..n {
+                tris.push(Triangle::new(
+                    vertex(x, y),
+                    vertex(x + 1, y),
+                    vertex(x + 1, y + 1),
+                ));
+                tris.push(Triangle::new(
+                    vertex(x, y),
+                    vertex(x + 1, y + 1),
+                    vertex(x, y + 1),
+                ));
+            }
+        }
+        let bvh = Bvh::new(tris);
+        // Steep rays share their axes, while those further out do not
+        let packets = packets(glm::vec3(2.3, 2.1, 1.5), n);
+        assert!(packets.iter().any(|p| p.shear.is_some()));
+        assert!(packets.iter().any(|p| p.shear.is_none()));
+        for packet in &packets {
+            assert_same_hits(&bvh, packet);
+        }
+    }
+
+    #[test]
+    fn spheres_match_single_rays() {
+        let sphere = Sphere {
+            center: glm::vec3(2.0, 2.0, 0.0),
+            radius: 1.5,
+            scale: glm::vec3(1.0, 0.5, 2.0),
+        };
+        for origin in &[glm::vec3(2.3, 2.1, 3.0), glm::vec3(2.0, 2.0, 0.0)] {
+            for packet in &packets(*origin, 4) {
+                assert_same_hits(&sphere, packet);
+            }
+        }
+    }
+}
@@ -1,54 +1,139 @@
 use nalgebra_glm as glm;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer};
 
 use super::*;
 
 use crate::ray::Ray;
-use crate::Vec3;
+use crate::vec::{self, Vec2, Vec3};
 
-#[derive(Serialize, Deserialize, Clone)]
+/// A plane through `origin`, either infinite or limited to a rectangle
+/// of `2 * extent` centered on `origin`.
+#[derive(Clone)]
 pub struct Plane {
-    pub points: [Vec3; 4],
+    origin: Vec3,
+    normal: Vec3,
+    u: Vec3,
+    v: Vec3,
+    extent: Option<Vec2>,
+    uv_scale: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PlaneDesc {
+    Points {
+        points: [Vec3; 4],
+    },
+    PointNormal {
+        point: Vec3,
+        normal: Vec3,
+        #[serde(default)]
+        size: Option<Vec2>,
+        #[serde(default = "default_uv_scale")]
+        uv_scale: f32,
+    },
+}
+
+fn default_uv_scale() -> f32 {
+    1.0
 }
 
 impl Plane {
+    /// Infinite plane; textures repeat every `uv_scale` world units.
+    pub fn infinite(point: Vec3, normal: Vec3, uv_scale: f32) -> Self {
+        let normal = glm::normalize(&normal);
+        let (u, v) = vec::orthonormal_basis(&normal);
+        Plane {
+            origin: point,
+            normal,
+            u,
+            v,
+            extent: None,
+            uv_scale,
+        }
+    }
+
+    /// Rectangle of the given width and height centered on `point`.
+    pub fn finite(point: Vec3, normal: Vec3, size: Vec2) -> Self {
+        Plane {
+            extent: Some(size * 0.5),
+            ..Self::infinite(point, normal, 1.0)
+        }
+    }
+
+    /// Rectangle spanned by four corners, with UVs running from `points[0]`
+    /// along `points[1]` and `points[3]`.
+    pub fn from_points(points: &[Vec3; 4]) -> Self {
+        let side1 = points[1] - points[0];
+        let side2 = points[3] - points[0];
+        Plane {
+            origin: points[0] + (side1 + side2) * 0.5,
+            normal: side1.cross(&side2).normalize(),
+            u: side1.normalize(),
+            v: side2.normalize(),
+            extent: Some(glm::vec2(glm::length(&side1), glm::length(&side2)) * 0.5),
+            uv_scale: 1.0,
+        }
+    }
+
     pub fn normal(&self) -> Vec3 {
-        let side1 = self.points[1] - self.points[0];
-        let side2 = self.points[3] - self.points[0];
-        side1.cross(&side2).normalize()
+        self.normal
+    }
+
+    fn local(&self, point: &Vec3) -> Vec2 {
+        let d = point - self.origin;
+        glm::vec2(glm::dot(&d, &self.u), glm::dot(&d, &self.v))
     }
 
     pub fn contains(&self, point: Vec3) -> bool {
-        let side1 = self.points[1] - self.points[0];
-        let side2 = self.points[3] - self.points[0];
-        let v = point - self.points[0];
-        let width = glm::length(&side1);
-        let height = glm::length(&side2);
-        let proj1 = glm::dot(&v, &side1) / width;
-        let proj2 = glm::dot(&v, &side2) / height;
-        proj1 < width && proj1 > 0.0 && proj2 < height && proj2 > 0.0
+        match self.extent {
+            Some(extent) => {
+                let p = self.local(&point);
+                p.x.abs() < extent.x && p.y.abs() < extent.y
+            }
+            None => true,
+        }
+    }
+
+    /// Texture coordinates covered per unit of length, along the denser of
+    /// the two directions.
+    fn uv_density(&self) -> f32 {
+        match self.extent {
+            Some(extent) => 0.5 / extent.min(),
+            None => 1.0 / self.uv_scale,
+        }
+    }
+
+    fn uv_at(&self, point: &Vec3) -> Vec2 {
+        let p = self.local(point);
+        match self.extent {
+            Some(extent) => glm::vec2((p.x / extent.x + 1.0) * 0.5, (p.y / extent.y + 1.0) * 0.5),
+            None => {
+                let p = p / self.uv_scale;
+                glm::vec2(p.x - p.x.floor(), p.y - p.y.floor())
+            }
+        }
     }
 }
 
 impl Geometry for Plane {
     fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
-        let normal = self.normal();
+        let normal = self.normal;
         let denom = glm::dot(&r.direction, &normal);
         if denom.abs() > 0.0001 {
-            let num = glm::dot(&(self.points[0] - r.origin), &normal);
+            let num = glm::dot(&(self.origin - r.origin), &normal);
             let t = num / denom;
             let point = r.point_at(t);
-            if self.contains(point) && t > min && t < max {
-                let x = self.points[1] - self.points[0];
-                let y = self.points[3] - self.points[0];
-                let u = glm::dot(&x.normalize(), &(point - self.points[0])) / glm::length(&x);
-                let v = glm::dot(&y.normalize(), &(point - self.points[0])) / glm::length(&y);
-                let uv = glm::vec2(u, v);
+            if t > min && t < max && self.contains(point) {
+                let uv = self.uv_at(&point);
                 Some(RayHit {
                     t,
                     point,
                     normal,
+                    geometric_normal: normal,
                     uv,
+                    tangent: None,
+                    footprint: r.spread * t * self.uv_density(),
                 })
             } else {
                 None
@@ -61,6 +146,39 @@ impl Geometry for Plane {
 
 impl Bounds for Plane {
     fn bounds(&self) -> AABB {
-        AABB::from(self.points.iter())
+        match self.extent {
+            Some(extent) => {
+                let (du, dv) = (self.u * extent.x, self.v * extent.y);
+                let corners = [
+                    self.origin - du - dv,
+                    self.origin + du - dv,
+                    self.origin + du + dv,
+                    self.origin - du + dv,
+                ];
+                AABB::from(corners.iter())
+            }
+            None => AABB::infinite(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Plane {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let plane = match PlaneDesc::deserialize(deserializer)? {
+            PlaneDesc::Points { points } => Plane::from_points(&points),
+            PlaneDesc::PointNormal {
+                point,
+                normal,
+                size: Some(size),
+                ..
+            } => Plane::finite(point, normal, size),
+            PlaneDesc::PointNormal {
+                point,
+                normal,
+                size: None,
+                uv_scale,
+            } => Plane::infinite(point, normal, uv_scale),
+        };
+        Ok(plane)
     }
 }
@@ -0,0 +1,133 @@
+use nalgebra_glm as glm;
+
+use super::{Triangle, Vertex};
+use crate::{Vec2, Vec3};
+
+/// One corner of a polygon. Texture coordinates and normals are stored per
+/// corner, so seams can be represented.
+#[derive(Clone)]
+pub struct Corner {
+    pub pos: usize,
+    pub uv: Vec2,
+    pub normal: Option<Vec3>,
+}
+
+/// Indexed polygon mesh, as loaded from disk and before triangulation.
+/// Processing such as subdivision happens on this representation.
+#[derive(Clone, Default)]
+pub struct PolyMesh {
+    pub positions: Vec<Vec3>,
+    pub faces: Vec<Vec<Corner>>,
+}
+
+impl PolyMesh {
+    pub fn face_normal(&self, face: &[Corner]) -> Vec3 {
+        // Newell's method, which also handles non-planar polygons
+        let mut n: Vec3 = glm::zero();
+        for (i, c) in face.iter().enumerate() {
+            let a = self.positions[c.pos];
+            let b = self.positions[face[(i + 1) % face.len()].pos];
+            n += glm::vec3(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            );
+        }
+        n
+    }
+
+    /// Replaces all corner normals with smooth ones, ignoring creases.
+    pub fn smooth_normals(&mut self) {
+        for c in self.faces.iter_mut().flatten() {
+            c.normal = None;
+        }
+        self.generate_normals(180.0);
+    }
+
+    /// Fills in missing corner normals by averaging the normals of the faces
+    /// sharing each position, weighted by their angle at that corner. Faces
+    /// meeting at more than `crease_angle` degrees are kept apart, so hard
+    /// edges stay sharp.
+    pub fn generate_normals(&mut self, crease_angle: f32) {
+        let cos_crease = crease_angle.to_radians().cos();
+        let face_normals: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let n = self.face_normal(face);
+                let len = glm::length(&n);
+                if len > 0.0 {
+                    n / len
+                } else {
+                    n
+                }
+            })
+            .collect();
+
+        let mut incident: Vec<Vec<(usize, f32)>> = vec![Vec::new(); self.positions.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            let len = face.len();
+            for (i, c) in face.iter().enumerate() {
+                let p = self.positions[c.pos];
+                let prev = self.positions[face[(i + len - 1) % len].pos] - p;
+                let next = self.positions[face[(i + 1) % len].pos] - p;
+                let angle = glm::angle(&prev, &next);
+                incident[c.pos].push((f, if angle.is_finite() { angle } else { 0.0 }));
+            }
+        }
+
+        for (f, face) in self.faces.iter_mut().enumerate() {
+            let n = face_normals[f];
+            for c in face.iter_mut().filter(|c| c.normal.is_none()) {
+                let sum = incident[c.pos]
+                    .iter()
+                    .filter(|&&(g, _)| glm::dot(&n, &face_normals[g]) >= cos_crease)
+                    .fold(glm::zero::<Vec3>(), |acc, &(g, angle)| {
+                        acc + face_normals[g] * angle
+                    });
+                let len = glm::length(&sum);
+                c.normal = Some(if len > 0.0 { sum / len } else { n });
+            }
+        }
+    }
+
+    /// Splits every polygon into a fan of triangles, keeping the corners.
+    pub fn into_triangles(self) -> PolyMesh {
+        let faces = self
+            .faces
+            .into_iter()
+            .filter(|f| f.len() >= 3)
+            .flat_map(|face| {
+                (1..face.len() - 1)
+                    .map(|i| vec![face[0].clone(), face[i].clone(), face[i + 1].clone()])
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        PolyMesh {
+            positions: self.positions,
+            faces,
+        }
+    }
+
+    /// Splits every polygon into a fan of triangles. Corners without a normal
+    /// use the flat normal of their face.
+    pub fn triangulate(&self) -> Vec<Triangle> {
+        let mut tris = Vec::new();
+        for face in self.faces.iter().filter(|f| f.len() >= 3) {
+            let flat = glm::normalize(&self.face_normal(face));
+            let vertex = |c: &Corner| Vertex {
+                pos: self.positions[c.pos],
+                normal: c.normal.unwrap_or(flat),
+                uv: c.uv,
+            };
+            for i in 1..face.len() - 1 {
+                tris.push(Triangle::new(
+                    vertex(&face[0]),
+                    vertex(&face[i]),
+                    vertex(&face[i + 1]),
+                ));
+            }
+        }
+        tris
+    }
+}
@@ -0,0 +1,200 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::Vec3;
+
+/// Parallelogram spanned by `edge1` and `edge2` from `origin`.
+#[derive(Deserialize, Clone)]
+pub struct Quad {
+    pub origin: Vec3,
+    pub edge1: Vec3,
+    pub edge2: Vec3,
+    /// How points are picked when the quad is sampled as a light or portal.
+    #[serde(default)]
+    pub sampling: QuadSampling,
+}
+
+/// Sampling by solid angle picks points as densely as the quad appears
+/// from the shaded point, which removes the noise of nearby, large lights.
+/// It only applies to rectangles, with other parallelograms sampled by area.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuadSampling {
+    Area,
+    #[default]
+    SolidAngle,
+}
+
+impl Quad {
+    pub fn normal(&self) -> Vec3 {
+        self.edge1.cross(&self.edge2).normalize()
+    }
+
+    /// The spherical rectangle the quad covers as seen from `origin`, if it
+    /// is sampled by solid angle from there.
+    fn spherical(&self, origin: &Vec3) -> Option<SphericalRect> {
+        let (len1, len2) = (glm::length(&self.edge1), glm::length(&self.edge2));
+        let rectangular = glm::dot(&self.edge1, &self.edge2).abs() < 1e-4 * len1 * len2;
+        if self.sampling != QuadSampling::SolidAngle || !rectangular {
+            return None;
+        }
+        SphericalRect::new(self, origin, len1, len2)
+    }
+}
+
+/// A rectangle projected onto the unit sphere around a point, following
+/// "An Area-Preserving Parametrization for Spherical Rectangles" by Ureña
+/// et al., in a frame along the rectangle's edges with it lying below.
+struct SphericalRect {
+    origin: Vec3,
+    axes: [Vec3; 3],
+    x: (f32, f32),
+    y: (f32, f32),
+    z0: f32,
+    b0: f32,
+    b1: f32,
+    k: f32,
+    solid_angle: f32,
+}
+
+impl SphericalRect {
+    fn new(quad: &Quad, origin: &Vec3, len1: f32, len2: f32) -> Option<Self> {
+        let x = quad.edge1 / len1;
+        let y = quad.edge2 / len2;
+        let mut z = x.cross(&y);
+        let d = quad.origin - origin;
+        let mut z0 = glm::dot(&d, &z);
+        if z0 > 0.0 {
+            z = -z;
+            z0 = -z0;
+        }
+        if z0.abs() < 1e-6 {
+            return None;
+        }
+        let (x0, y0) = (glm::dot(&d, &x), glm::dot(&d, &y));
+        let (x1, y1) = (x0 + len1, y0 + len2);
+        let v00 = glm::vec3(x0, y0, z0);
+        let v01 = glm::vec3(x0, y1, z0);
+        let v10 = glm::vec3(x1, y0, z0);
+        let v11 = glm::vec3(x1, y1, z0);
+        let n0 = glm::normalize(&v00.cross(&v10));
+        let n1 = glm::normalize(&v10.cross(&v11));
+        let n2 = glm::normalize(&v11.cross(&v01));
+        let n3 = glm::normalize(&v01.cross(&v00));
+        let angle = |a: &Vec3, b: &Vec3| (-glm::dot(a, b)).clamp(-1.0, 1.0).acos();
+        let (g0, g1) = (angle(&n0, &n1), angle(&n1, &n2));
+        let (g2, g3) = (angle(&n2, &n3), angle(&n3, &n0));
+        let k = glm::two_pi::<f32>() - g2 - g3;
+        let solid_angle = g0 + g1 - k;
+        if solid_angle <= 1e-6 {
+            return None;
+        }
+        Some(SphericalRect {
+            origin: *origin,
+            axes: [x, y, z],
+            x: (x0, x1),
+            y: (y0, y1),
+            z0,
+            b0: n0.z,
+            b1: n2.z,
+            k,
+            solid_angle,
+        })
+    }
+
+    fn sample(&self, rnd: Vec2) -> Vec3 {
+        let au = rnd.x * self.solid_angle + self.k;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = (1.0 / (fu * fu + self.b0 * self.b0).sqrt()).copysign(fu);
+        let cu = cu.clamp(-1.0, 1.0);
+        let xu = -(cu * self.z0) / (1.0 - cu * cu).max(1e-12).sqrt();
+        let xu = xu.max(self.x.0).min(self.x.1);
+        let d = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0 = self.y.0 / (d * d + self.y.0 * self.y.0).sqrt();
+        let h1 = self.y.1 / (d * d + self.y.1 * self.y.1).sqrt();
+        let hv = h0 + rnd.y * (h1 - h0);
+        let yv = if hv * hv < 1.0 - 1e-6 {
+            hv * d / (1.0 - hv * hv).sqrt()
+        } else {
+            self.y.1
+        };
+        let [x, y, z] = &self.axes;
+        self.origin + x * xu + y * yv + z * self.z0
+    }
+}
+
+impl Geometry for Quad {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let n = self.edge1.cross(&self.edge2);
+        let denom = glm::dot(&n, &r.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = glm::dot(&n, &(self.origin - r.origin)) / denom;
+        if t <= min || t >= max {
+            return None;
+        }
+        let point = r.point_at(t);
+        let w = n / glm::dot(&n, &n);
+        let p = point - self.origin;
+        let u = glm::dot(&w, &p.cross(&self.edge2));
+        let v = glm::dot(&w, &self.edge1.cross(&p));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(RayHit {
+            t,
+            point,
+            normal: n.normalize(),
+            geometric_normal: n.normalize(),
+            uv: glm::vec2(u, v),
+            tangent: None,
+            footprint: 0.0,
+        })
+    }
+}
+
+impl Sampleable for Quad {
+    fn area(&self) -> f32 {
+        glm::length(&self.edge1.cross(&self.edge2))
+    }
+
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        SurfaceSample {
+            point: self.origin + self.edge1 * rnd.x + self.edge2 * rnd.y,
+            normal: self.normal(),
+        }
+    }
+
+    fn sample_from(&self, origin: &Vec3, rnd: Vec2) -> SurfaceSample {
+        match self.spherical(origin) {
+            Some(rect) => SurfaceSample {
+                point: rect.sample(rnd),
+                normal: self.normal(),
+            },
+            None => self.sample_surface(rnd),
+        }
+    }
+
+    fn pdf(&self, origin: &Vec3, hit: &RayHit) -> f32 {
+        match self.spherical(origin) {
+            Some(rect) => 1.0 / rect.solid_angle,
+            None => area_pdf(self.area(), origin, hit),
+        }
+    }
+}
+
+impl Bounds for Quad {
+    fn bounds(&self) -> AABB {
+        let corners = [
+            self.origin,
+            self.origin + self.edge1,
+            self.origin + self.edge2,
+            self.origin + self.edge1 + self.edge2,
+        ];
+        AABB::from(corners.iter())
+    }
+}
@@ -1,20 +1,515 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
 
 use super::*;
+use crate::material::{Material, Registry, Sides};
+use crate::obj;
 use crate::ray::Ray;
-use crate::texture::ColorTexture;
+use crate::sampler;
+use crate::texture::{ColorTexture, Gradient};
 
-#[derive(Deserialize, Clone)]
+/// Objects are indexed by a top-level accelerator over their bounds, while
+/// meshes keep their own triangle hierarchies. Moving objects only requires
+/// rebuilding the top level.
+#[derive(Clone)]
 pub struct Scene {
-    objects: Vec<Object>,
+    objects: Vec<Arc<Object>>,
+    top_level: Accel<Arc<Object>>,
+    unbounded: Vec<Arc<Object>>,
+    /// Objects moved since the accelerator was last updated, keyed by the
+    /// address of the object they replace.
+    moved: HashMap<usize, Arc<Object>>,
+    settings: BuildSettings,
+    lights: Vec<Light>,
+    light_tree: LightTree,
+    /// Index of the light made of each emissive object, keyed by the
+    /// address of its material.
+    emitters: HashMap<usize, usize>,
+    /// Names of the light groups, starting with the default one.
+    light_groups: Vec<String>,
+    /// Group of each light.
+    group_of: Vec<usize>,
+    environment_group: usize,
+    /// Weighting of light and material samples against each other.
+    pub heuristic: Heuristic,
+    pub clamp: Option<RadianceClamp>,
     pub environment: ColorTexture,
+    pub background: Background,
+    /// Openings such as windows that the environment lights the scene
+    /// through, sampled directly instead of waiting for paths to escape.
+    pub portals: Vec<Quad>,
+}
+
+/// Group of lights not given one.
+const DEFAULT_LIGHT_GROUP: &str = "default";
+
+fn address(object: &Arc<Object>) -> usize {
+    &**object as *const Object as usize
+}
+
+#[derive(Deserialize)]
+struct SceneDesc {
+    #[serde(default)]
+    objects: Vec<Object>,
+    /// Named materials that objects can refer to in place of a material,
+    /// alongside the built-in presets.
+    #[serde(default)]
+    materials: HashMap<String, Material>,
+    /// Named geometry that instances refer to.
+    #[serde(default)]
+    shapes: HashMap<String, GeomType>,
+    #[serde(default)]
+    instances: Vec<InstanceDesc>,
+    /// OBJ files brought in with the materials of their MTL libraries.
+    #[serde(default)]
+    models: Vec<ModelDesc>,
+    /// Color of rays escaping the scene, from an image in latitude-longitude
+    /// layout or any other texture. A sky gradient when not given. Analytic
+    /// skies bring their sun along as a light.
+    #[serde(default = "default_environment")]
+    environment: ColorTexture,
+    /// What the camera sees where nothing is in the way: `"environment"`,
+    /// `"transparent"`, or a texture such as a solid color or gradient.
+    #[serde(default)]
+    background: Background,
+    /// Lights given by their shape and emission alone, which become
+    /// objects like any other.
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    #[serde(default)]
+    portals: Vec<Quad>,
+    #[serde(default)]
+    heuristic: Heuristic,
+    /// Limit on the light a single path adds to a pixel, against fireflies.
+    #[serde(default)]
+    clamp: Option<RadianceClamp>,
+    #[serde(default)]
+    build_quality: BuildQuality,
+    #[serde(default)]
+    accelerator: AcceleratorKind,
+}
+
+// Untagged variants are tried in order, so spot lights, which have all the
+// fields of point lights, come first.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LightDesc {
+    Quad(QuadLight),
+    Spot(SpotLight),
+    Point(PointLight),
+    Sun(SunLight),
+}
+
+fn default_environment() -> ColorTexture {
+    ColorTexture::Gradient(Gradient::sky())
+}
+
+#[derive(Deserialize)]
+struct InstanceDesc {
+    shape: String,
+    #[serde(default)]
+    transform: Transform,
+    #[serde(default)]
+    animation: Option<Transform>,
+    material: Material,
+}
+
+#[derive(Deserialize)]
+struct ModelDesc {
+    file: String,
+    #[serde(default)]
+    transform: Option<Transform>,
+    /// Used for faces without a material, or whose material is neither in
+    /// the MTL libraries nor in the scene's registry.
+    #[serde(default)]
+    material: Material,
+}
+
+impl ModelDesc {
+    fn load(self, registry: &Registry) -> std::io::Result<Vec<Object>> {
+        let obj::Model { groups, materials } = obj::load_model(&self.file)?;
+        let objects = groups
+            .into_iter()
+            .map(|(name, tris)| Object {
+                geometry: GeomType::Mesh(Mesh::from_triangles(tris)),
+                material: name
+                    .and_then(|name| {
+                        materials
+                            .get(&name)
+                            .cloned()
+                            .or_else(|| registry.get(&name).ok())
+                    })
+                    .unwrap_or_else(|| self.material.clone()),
+                transform: self.transform.clone(),
+                animation: None,
+            })
+            .collect();
+        Ok(objects)
+    }
+}
+
+impl Scene {
+    /// Builds the object hierarchy once, up front. Scenes with only a handful
+    /// of objects end up in a single leaf and are traced linearly, as are
+    /// objects without finite bounds such as infinite planes.
+    pub fn build(objects: Vec<Object>, environment: ColorTexture, settings: BuildSettings) -> Self {
+        let objects = objects
+            .into_iter()
+            .map(|o| Arc::new(o.build(settings)))
+            .collect();
+        let mut scene = Scene {
+            objects,
+            top_level: Accel::linear(Vec::new()),
+            unbounded: Vec::new(),
+            moved: HashMap::new(),
+            settings,
+            lights: Vec::new(),
+            light_tree: LightTree::default(),
+            emitters: HashMap::new(),
+            light_groups: vec![DEFAULT_LIGHT_GROUP.to_owned()],
+            group_of: Vec::new(),
+            environment_group: 0,
+            heuristic: Heuristic::default(),
+            clamp: None,
+            environment,
+            background: Background::default(),
+            portals: Vec::new(),
+        };
+        // The environment is sampled along with the other lights
+        let environment = EnvironmentLight::new(&scene.environment);
+        scene.lights.extend(environment.map(Light::Environment));
+        scene.rebuild();
+        scene
+    }
+
+    /// Samples a direction from `origin` towards a point on one of the
+    /// portals, picked uniformly, returning it with its density.
+    pub fn sample_portal(&self, origin: &Vec3, rnd: (f32, Vec2)) -> Option<(Vec3, f32)> {
+        if self.portals.is_empty() {
+            return None;
+        }
+        let i = ((rnd.0 * self.portals.len() as f32) as usize).min(self.portals.len() - 1);
+        let sample = self.portals[i].sample_from(origin, rnd.1);
+        let dir = glm::normalize(&(sample.point - origin));
+        Some((dir, self.portal_pdf(origin, &dir)))
+    }
+
+    /// Density with respect to solid angle of `sample_portal` choosing `dir`,
+    /// summed over the portals it passes through.
+    pub fn portal_pdf(&self, origin: &Vec3, dir: &Vec3) -> f32 {
+        let ray = Ray::new(*origin, *dir);
+        let pdf: f32 = self
+            .portals
+            .iter()
+            .filter_map(|portal| {
+                let hit = portal.intersection(&ray, 0.0, f32::MAX)?;
+                Some(portal.pdf(origin, &hit))
+            })
+            .sum();
+        pdf / self.portals.len().max(1) as f32
+    }
+
+    /// Lights sampled directly when shading: emissive objects, and lights
+    /// without geometry.
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Adds lights without geometry, indexing them all at once. Emissive
+    /// objects are found by the scene itself.
+    pub fn add_lights<I: IntoIterator<Item = Light>>(&mut self, lights: I) {
+        self.lights.extend(lights);
+        self.index_lights();
+    }
+
+    /// Picks one of the lights by its estimated contribution at `origin`
+    /// and samples it, returning its index with the chance of picking it.
+    pub fn sample_light(
+        &self,
+        origin: &Vec3,
+        rnd: (f32, Vec2),
+    ) -> Option<(usize, LightSample, f32)> {
+        let (i, pick_pdf) = self.light_tree.pick(origin, rnd.0)?;
+        Some((i, self.lights[i].sample(origin, rnd.1), pick_pdf))
+    }
+
+    /// Names of the groups lights are split into for rendering a pass per
+    /// group, in the order lights first name them with their `light_group`.
+    /// Emissive materials name the group of the objects made of them. The
+    /// first group is the one of lights not given a group, and each pass
+    /// holds the light reaching the camera from its group's lights alone.
+    pub fn light_groups(&self) -> &[String] {
+        &self.light_groups
+    }
+
+    /// Index into `light_groups` of the light at `index`.
+    pub fn light_group(&self, index: usize) -> usize {
+        self.group_of[index]
+    }
+
+    /// Index into `light_groups` of the light made of objects with
+    /// `material`, or of the default group for those that are not lights.
+    pub fn emission_group(&self, material: &Material) -> usize {
+        let address = material as *const Material as usize;
+        self.emitters.get(&address).map_or(0, |&i| self.group_of[i])
+    }
+
+    /// Index into `light_groups` of the environment.
+    pub fn environment_group(&self) -> usize {
+        self.environment_group
+    }
+
+    /// Density with respect to solid angle of `sample_light` picking `hit`
+    /// from `origin`, where `material` is that of the object hit. Zero for
+    /// objects that are not lights.
+    pub fn light_pdf(&self, origin: &Vec3, material: &Material, hit: &RayHit) -> f32 {
+        let address = material as *const Material as usize;
+        match self.emitters.get(&address) {
+            Some(&i) => self.lights[i].pdf(origin, hit) * self.light_tree.pdf(origin, i),
+            None => 0.0,
+        }
+    }
+
+    /// Density with respect to solid angle of `sample_light` picking the
+    /// environment in direction `dir` from `origin`.
+    pub fn environment_pdf(&self, origin: &Vec3, dir: &Vec3) -> f32 {
+        self.light_tree
+            .infinite()
+            .iter()
+            .find_map(|&i| match &self.lights[i] {
+                Light::Environment(env) => Some(env.pdf(dir) * self.light_tree.pdf(origin, i)),
+                _ => None,
+            })
+            .unwrap_or(0.0)
+    }
+
+    /// Finds the emissive objects among the current ones, keeping the
+    /// lights without geometry.
+    fn collect_lights(&mut self) {
+        let objects = self.objects.iter().filter_map(Light::from_object);
+        let others = self
+            .lights
+            .drain(..)
+            .filter(|light| light.object().is_none());
+        self.lights = objects.chain(others).collect();
+        self.index_lights();
+    }
+
+    fn index_lights(&mut self) {
+        self.light_tree = LightTree::new(&self.lights);
+        self.emitters = self
+            .lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| {
+                let object = light.object()?;
+                Some((&object.material as *const Material as usize, i))
+            })
+            .collect();
+        let mut names = vec![DEFAULT_LIGHT_GROUP.to_owned()];
+        self.group_of = self
+            .lights
+            .iter()
+            .map(|light| {
+                let name = light.group().unwrap_or(DEFAULT_LIGHT_GROUP);
+                names.iter().position(|n| n == name).unwrap_or_else(|| {
+                    names.push(name.to_owned());
+                    names.len() - 1
+                })
+            })
+            .collect();
+        self.light_groups = names;
+        self.environment_group = self
+            .lights
+            .iter()
+            .position(|light| matches!(light, Light::Environment(_)))
+            .map_or(0, |i| self.group_of[i]);
+    }
+
+    /// Rebuilds the top-level accelerator from the current object bounds.
+    pub fn rebuild(&mut self) {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = self
+            .objects
+            .iter()
+            .cloned()
+            .partition(|o| o.bounds().is_finite());
+        self.top_level = Accel::new(bounded, self.settings);
+        self.unbounded = unbounded;
+        self.moved.clear();
+        self.collect_lights();
+    }
+
+    /// Updates the top-level accelerator for objects moved with
+    /// `set_transform`, refitting bounds rather than rebuilding where possible.
+    /// Refitting keeps the old tree layout, so rebuild every so often when
+    /// objects travel far.
+    pub fn refit(&mut self) {
+        if self.moved.is_empty() {
+            return;
+        }
+        let moved = std::mem::take(&mut self.moved);
+        // Objects gaining or losing finite bounds change lists
+        let crosses_lists = moved.iter().any(|(&original, o)| {
+            let was_unbounded = self.unbounded.iter().any(|u| address(u) == original);
+            o.bounds().is_finite() == was_unbounded
+        });
+        let mut update = |o: &mut Arc<Object>| {
+            if let Some(replacement) = moved.get(&address(o)) {
+                *o = replacement.clone();
+            }
+        };
+        if crosses_lists || !self.top_level.refit(&mut update) {
+            self.rebuild();
+            return;
+        }
+        self.unbounded.iter_mut().for_each(update);
+        self.collect_lights();
+    }
+
+    pub fn objects(&self) -> &[Arc<Object>] {
+        &self.objects
+    }
+
+    /// Moves animated objects to where they are at `progress` through a
+    /// sequence, from 0 to 1, and refits the accelerator around them.
+    pub fn animate(&mut self, progress: f32) {
+        let placed: Vec<_> = self
+            .objects()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, o)| Some((i, o.animation.as_ref()?.at(progress).into_owned())))
+            .collect();
+        for (i, transform) in placed {
+            self.set_transform(i, transform);
+        }
+        self.refit();
+    }
+
+    /// Places the object at `index` with a new transform, reusing its geometry.
+    /// Call `refit` or `rebuild` once all objects have been moved.
+    pub fn set_transform(&mut self, index: usize, transform: Transform) {
+        let object = &self.objects[index];
+        let instance = match &object.geometry {
+            GeomType::Instance(instance) => instance.with_transform(transform),
+            geometry => Instance::new(Arc::new(geometry.clone()), transform),
+        };
+        let moved = Object {
+            geometry: GeomType::Instance(instance),
+            material: object.material.clone(),
+            transform: None,
+            animation: object.animation.clone(),
+        };
+        let moved = Arc::new(moved);
+        let original = address(&self.objects[index]);
+        // An object moved twice still replaces the one in the accelerator
+        let original = self
+            .moved
+            .iter()
+            .find(|(_, o)| Arc::ptr_eq(o, &self.objects[index]))
+            .map_or(original, |(&key, _)| key);
+        self.moved.insert(original, moved.clone());
+        self.objects[index] = moved;
+    }
+}
+
+impl Scene {
+    /// Traces all rays of a packet together, returning the closest hit of each.
+    pub fn trace_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: f32,
+    ) -> [Option<TraceResult<'_>>; LANES] {
+        let mut results = [None, None, None, None];
+        let mut max = [max; LANES];
+        match &self.top_level {
+            Accel::Bvh(bvh) => bvh.visit_packet(packet, &mut max, &mut |obj, max, active| {
+                trace_object_packet(obj, packet, min, max, active, &mut results)
+            }),
+            Accel::Linear { geoms, .. } => {
+                for obj in geoms {
+                    trace_object_packet(obj, packet, min, &mut max, ALL, &mut results);
+                }
+            }
+            Accel::KdTree(_) => {
+                // The kd-tree has no packet traversal
+                let [a, b, c, d] = &packet.rays;
+                return [
+                    self.trace(a, min, max[0]),
+                    self.trace(b, min, max[1]),
+                    self.trace(c, min, max[2]),
+                    self.trace(d, min, max[3]),
+                ];
+            }
+        }
+        for obj in &self.unbounded {
+            trace_object_packet(obj, packet, min, &mut max, ALL, &mut results);
+        }
+        results
+    }
+
+    /// Closest surface blocking light along `ray`. As for paths, culled back
+    /// faces are passed through, and so are holes with the probability of
+    /// the material there being one.
+    pub fn occluder(&self, ray: &Ray, mut min: f32, max: f32) -> Option<TraceResult<'_>> {
+        loop {
+            let traced = self.trace(ray, min, max)?;
+            let material = traced.material.select(&traced.hit);
+            let backface = glm::dot(&ray.direction, &traced.hit.normal) > 0.0;
+            let culled = backface && material.sides() == Sides::Cull;
+            if !culled && sampler::next_1d() < material.opacity(&traced.hit) {
+                return Some(traced);
+            }
+            min = traced.hit.t + 0.001;
+        }
+    }
+
+    /// Whether light travels unblocked along `ray` between `min` and `max`.
+    pub fn visible(&self, ray: &Ray, min: f32, max: f32) -> bool {
+        self.occluder(ray, min, max).is_none()
+    }
+}
+
+fn trace_object_packet<'a>(
+    obj: &'a Object,
+    packet: &RayPacket,
+    min: f32,
+    max: &mut [f32; LANES],
+    active: Mask,
+    results: &mut [Option<TraceResult<'a>>; LANES],
+) {
+    let mut hits = no_hits();
+    obj.geometry
+        .intersect_packet(packet, min, max, active, &mut hits);
+    for (result, hit) in results.iter_mut().zip(hits.iter_mut()) {
+        if let Some(hit) = hit.take() {
+            *result = Some(TraceResult {
+                hit,
+                material: &obj.material,
+            });
+        }
+    }
+}
+
+impl Bounds for Scene {
+    /// Infinite when the scene contains unbounded objects.
+    fn bounds(&self) -> AABB {
+        if self.unbounded.is_empty() {
+            self.top_level.bounds()
+        } else {
+            AABB::infinite()
+        }
+    }
 }
 
 impl Traceable for Scene {
-    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult> {
-        let mut max = max;
-        let mut result = None;
-        for obj in &self.objects {
+    fn trace(&self, ray: &Ray, min: f32, max: f32) -> Option<TraceResult<'_>> {
+        let mut result = self.top_level.trace(ray, min, max);
+        let mut max = result.as_ref().map_or(max, |traced| traced.hit.t);
+        for obj in &self.unbounded {
             let traced = obj.trace(ray, min, max);
             if let Some(TraceResult { hit, .. }) = &traced {
                 max = hit.t;
@@ -24,3 +519,85 @@ impl Traceable for Scene {
         result
     }
 }
+
+impl<'de> Deserialize<'de> for Scene {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let SceneDesc {
+            mut objects,
+            materials,
+            shapes,
+            instances,
+            models,
+            environment,
+            background,
+            lights,
+            portals,
+            heuristic,
+            clamp,
+            build_quality,
+            accelerator,
+        } = SceneDesc::deserialize(deserializer)?;
+        let settings = BuildSettings {
+            accelerator,
+            quality: build_quality,
+        };
+        let shapes: HashMap<_, _> = shapes
+            .into_iter()
+            .map(|(name, shape)| (name, Arc::new(shape.build(settings))))
+            .collect();
+        for InstanceDesc {
+            shape,
+            transform,
+            animation,
+            material,
+        } in instances
+        {
+            let geometry = shapes
+                .get(&shape)
+                .ok_or_else(|| D::Error::custom(format!("unknown shape `{}`", shape)))?;
+            objects.push(Object {
+                geometry: GeomType::Instance(Instance::new(geometry.clone(), transform)),
+                material,
+                transform: None,
+                animation,
+            });
+        }
+        let mut geometryless = Vec::new();
+        if let ColorTexture::Sky(sky) = &environment {
+            if sky.sun {
+                geometryless.push(Light::Sun(SunLight::from_sky(sky)));
+            }
+        }
+        for light in lights {
+            match light {
+                LightDesc::Quad(quad) => objects.push(quad.into_object()),
+                LightDesc::Spot(spot) => geometryless.push(Light::Spot(spot)),
+                LightDesc::Point(point) => geometryless.push(Light::Point(point)),
+                LightDesc::Sun(sun) => geometryless.push(Light::Sun(sun)),
+            }
+        }
+        let registry = Registry::new(materials);
+        for model in models {
+            objects.extend(model.load(&registry).map_err(D::Error::custom)?);
+        }
+        for object in &mut objects {
+            registry
+                .resolve(&mut object.material)
+                .map_err(D::Error::custom)?;
+            let area = object.area();
+            object
+                .material
+                .resolve_emission(area)
+                .map_err(D::Error::custom)?;
+        }
+        let mut scene = Scene::build(objects, environment, settings);
+        scene.background = background;
+        scene.portals = portals;
+        scene.heuristic = heuristic;
+        scene.clamp = clamp;
+        scene.add_lights(geometryless);
+        Ok(scene)
+    }
+}
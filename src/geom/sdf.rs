@@ -0,0 +1,195 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::Vec3;
+
+const MAX_STEPS: usize = 512;
+const HIT_EPSILON: f32 = 1e-4;
+const NORMAL_EPSILON: f32 = 1e-4;
+
+/// Built-in signed distance functions, in the shape's local coordinates.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SdfShape {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        half_extents: Vec3,
+    },
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    Mandelbulb {
+        #[serde(default = "default_power")]
+        power: f32,
+        #[serde(default = "default_iterations")]
+        iterations: usize,
+    },
+    Translate {
+        offset: Vec3,
+        shape: Box<SdfShape>,
+    },
+    SmoothUnion {
+        a: Box<SdfShape>,
+        b: Box<SdfShape>,
+        smoothness: f32,
+    },
+}
+
+fn default_power() -> f32 {
+    8.0
+}
+
+fn default_iterations() -> usize {
+    12
+}
+
+impl SdfShape {
+    pub fn distance(&self, p: &Vec3) -> f32 {
+        match self {
+            SdfShape::Sphere { radius } => glm::length(p) - radius,
+            SdfShape::Box { half_extents } => {
+                let q = glm::abs(p) - half_extents;
+                let outside = glm::vec3(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                glm::length(&outside) + q.x.max(q.y.max(q.z)).min(0.0)
+            }
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = f32::sqrt(p.x * p.x + p.z * p.z) - major_radius;
+                f32::sqrt(ring * ring + p.y * p.y) - minor_radius
+            }
+            SdfShape::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - a, b - a);
+                let h = (glm::dot(&pa, &ba) / glm::dot(&ba, &ba)).clamp(0.0, 1.0);
+                glm::length(&(pa - ba * h)) - radius
+            }
+            SdfShape::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            SdfShape::Translate { offset, shape } => shape.distance(&(p - offset)),
+            SdfShape::SmoothUnion { a, b, smoothness } => {
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (d2 - d1) / smoothness).clamp(0.0, 1.0);
+                d2 + (d1 - d2) * h - smoothness * h * (1.0 - h)
+            }
+        }
+    }
+}
+
+fn mandelbulb(p: &Vec3, power: f32, iterations: usize) -> f32 {
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = 0.0;
+    for _ in 0..iterations {
+        r = glm::length(&z);
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = f32::acos(z.z / r) * power;
+        let phi = f32::atan2(z.y, z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z = glm::vec3(
+            theta.sin() * phi.cos(),
+            phi.sin() * theta.sin(),
+            theta.cos(),
+        ) * zr
+            + p;
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// Shape given implicitly by a distance function and rendered by sphere
+/// tracing. It must fit within `bound` of `center`.
+#[derive(Deserialize, Clone)]
+pub struct Sdf {
+    pub sdf: SdfShape,
+    #[serde(default = "default_center")]
+    pub center: Vec3,
+    pub bound: f32,
+}
+
+fn default_center() -> Vec3 {
+    glm::zero()
+}
+
+impl Sdf {
+    fn distance(&self, p: &Vec3) -> f32 {
+        self.sdf.distance(&(p - self.center))
+    }
+
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        let h = NORMAL_EPSILON;
+        let dx = glm::vec3(h, 0.0, 0.0);
+        let dy = glm::vec3(0.0, h, 0.0);
+        let dz = glm::vec3(0.0, 0.0, h);
+        glm::normalize(&glm::vec3(
+            self.distance(&(p + dx)) - self.distance(&(p - dx)),
+            self.distance(&(p + dy)) - self.distance(&(p - dy)),
+            self.distance(&(p + dz)) - self.distance(&(p - dz)),
+        ))
+    }
+}
+
+impl Geometry for Sdf {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        // Only march within the bounding sphere
+        let oc = r.origin - self.center;
+        let a = glm::dot(&r.direction, &r.direction);
+        let b = glm::dot(&r.direction, &oc);
+        let c = glm::dot(&oc, &oc) - self.bound * self.bound;
+        let delta = b * b - a * c;
+        if delta <= 0.0 {
+            return None;
+        }
+        let len = a.sqrt();
+        let mut t = f32::max(min, (-b - delta.sqrt()) / a);
+        let exit = f32::min(max, (-b + delta.sqrt()) / a);
+
+        for _ in 0..MAX_STEPS {
+            if t >= exit {
+                return None;
+            }
+            let point = r.point_at(t);
+            let d = self.distance(&point).abs();
+            if d < HIT_EPSILON {
+                let normal = self.normal(&point);
+                let uv = Sphere::uv_at_dir(&glm::normalize(&(point - self.center)));
+                return Some(RayHit {
+                    t,
+                    point,
+                    normal,
+                    geometric_normal: normal,
+                    uv,
+                    tangent: None,
+                    footprint: 0.0,
+                });
+            }
+            t += d / len;
+        }
+        None
+    }
+}
+
+impl Bounds for Sdf {
+    fn bounds(&self) -> AABB {
+        let extent = glm::vec3(self.bound, self.bound, self.bound);
+        AABB {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
@@ -1,60 +1,205 @@
 use nalgebra_glm as glm;
 use serde::{Deserialize, Serialize};
+use wide::f32x4;
 
 use super::*;
 
 use crate::ray::Ray;
+use crate::vec;
 use crate::Vec3;
 
+/// Sphere of the given radius, stretched along each axis by `scale` to form
+/// an axis-aligned ellipsoid. Rotated ellipsoids can be made with a transform.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
 }
 
-impl Geometry for Sphere {
-    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
-        let oc = r.origin - self.center;
-        let a = glm::dot(&r.direction, &r.direction);
-        let b = glm::dot(&r.direction, &oc);
-        let c = glm::dot(&oc, &oc) - self.radius * self.radius;
+fn default_scale() -> Vec3 {
+    glm::vec3(1.0, 1.0, 1.0)
+}
+
+impl Sphere {
+    fn semi_axes(&self) -> Vec3 {
+        self.scale * self.radius
+    }
+
+    /// Distances along `r` where it crosses the surface, nearest first.
+    /// The ray is solved against a unit sphere after dividing out the semi-axes,
+    /// which leaves distances unchanged.
+    fn roots(&self, r: &Ray) -> Option<(f32, f32)> {
+        let axes = self.semi_axes();
+        let oc = (r.origin - self.center).component_div(&axes);
+        let dir = r.direction.component_div(&axes);
+        let a = glm::dot(&dir, &dir);
+        let b = glm::dot(&dir, &oc);
+        let c = glm::dot(&oc, &oc) - 1.0;
         let delta = b * b - a * c;
         if delta > 0.0 {
-            let t = (-b - f32::sqrt(b * b - a * c)) / a;
-            if t > min && t < max {
-                let point = r.point_at(t);
-                let normal = (point - self.center) / self.radius;
-                let uv = Self::uv_at_dir(&(point - self.center).normalize());
-                return Some(RayHit {
-                    t,
-                    point,
-                    normal,
-                    uv,
-                });
-            }
-            let t = (-b + f32::sqrt(b * b - a * c)) / a;
-            if t > min && t < max {
-                let point = r.point_at(t);
-                let normal = (point - self.center) / self.radius;
-                let uv = Self::uv_at_dir(&(point - self.center).normalize());
-                Some(RayHit {
-                    t,
-                    point,
-                    normal,
-                    uv,
-                })
-            } else {
-                None
-            }
+            let sqrt = f32::sqrt(delta);
+            Some(((-b - sqrt) / a, (-b + sqrt) / a))
+        } else {
+            None
+        }
+    }
+
+    fn hit_at(&self, r: &Ray, t: f32) -> RayHit {
+        let point = r.point_at(t);
+        let axes = self.semi_axes();
+        let local = (point - self.center).component_div(&axes);
+        // The gradient of the implicit surface, which unlike the radial
+        // direction stays perpendicular under non-uniform scaling
+        let normal = glm::normalize(&local.component_div(&axes));
+        let uv = Self::uv_at_dir(&local.normalize());
+        // Along lines of latitude; undefined at the poles
+        let tangent = glm::vec3(-local.z, 0.0, local.x).component_mul(&axes);
+        let tangent = if glm::length(&tangent) > 1e-6 {
+            Some(glm::normalize(&tangent))
+        } else {
+            None
+        };
+        RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent,
+            // Latitude runs over half a circumference
+            footprint: r.spread * t / (glm::pi::<f32>() * axes.min()),
+        }
+    }
+}
+
+impl Geometry for Sphere {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let (t0, t1) = self.roots(r)?;
+        if t0 > min && t0 < max {
+            Some(self.hit_at(r, t0))
+        } else if t1 > min && t1 < max {
+            Some(self.hit_at(r, t1))
         } else {
             None
         }
     }
 }
 
+/// Uniform spheres only; lights reject ellipsoids.
+impl Sampleable for Sphere {
+    fn area(&self) -> f32 {
+        let r = self.semi_axes().x;
+        4.0 * glm::pi::<f32>() * r * r
+    }
+
+    fn sample_surface(&self, rnd: Vec2) -> SurfaceSample {
+        let z = 1.0 - 2.0 * rnd.x;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = glm::two_pi::<f32>() * rnd.y;
+        let normal = glm::vec3(r * phi.cos(), r * phi.sin(), z);
+        SurfaceSample {
+            point: self.center + normal * self.semi_axes().x,
+            normal,
+        }
+    }
+
+    /// Samples the cone of directions from `origin` that the sphere
+    /// subtends, so that only its visible side is picked.
+    fn sample_from(&self, origin: &Vec3, rnd: Vec2) -> SurfaceSample {
+        let radius = self.semi_axes().x;
+        let d = self.center - origin;
+        let dist_sq = glm::dot(&d, &d);
+        if dist_sq <= radius * radius {
+            return self.sample_surface(rnd);
+        }
+        let dist = dist_sq.sqrt();
+        let cos_max = (1.0 - radius * radius / dist_sq).max(0.0).sqrt();
+        let cos = 1.0 - rnd.x * (1.0 - cos_max);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = glm::two_pi::<f32>() * rnd.y;
+        let w = d / dist;
+        let (u, v) = vec::orthonormal_basis(&w);
+        let dir = u * (sin * phi.cos()) + v * (sin * phi.sin()) + w * cos;
+        // Nearest crossing of the sphere along the sampled direction
+        let along = dist * cos - (radius * radius - dist_sq * sin * sin).max(0.0).sqrt();
+        let point = origin + dir * along;
+        SurfaceSample {
+            point,
+            normal: (point - self.center) / radius,
+        }
+    }
+
+    fn pdf(&self, origin: &Vec3, hit: &RayHit) -> f32 {
+        let radius = self.semi_axes().x;
+        let d = self.center - origin;
+        let dist_sq = glm::dot(&d, &d);
+        if dist_sq <= radius * radius {
+            return area_pdf(self.area(), origin, hit);
+        }
+        let cos_max = (1.0 - radius * radius / dist_sq).max(0.0).sqrt();
+        1.0 / (glm::two_pi::<f32>() * (1.0 - cos_max))
+    }
+}
+
+impl PacketGeometry for Sphere {
+    /// The roots of all four lanes at once, found as for single rays.
+    fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        min: f32,
+        max: &mut [f32; LANES],
+        active: Mask,
+        hits: &mut PacketHits,
+    ) {
+        let axes = self.semi_axes();
+        let oc = [0, 1, 2].map(|dim| {
+            (packet.origin[dim] - f32x4::splat(self.center[dim])) / f32x4::splat(axes[dim])
+        });
+        let dir = [0, 1, 2].map(|dim| packet.direction[dim] / f32x4::splat(axes[dim]));
+        let dot = |p: &[f32x4; 3], q: &[f32x4; 3]| p[0] * q[0] + p[1] * q[1] + p[2] * q[2];
+        let a = dot(&dir, &dir);
+        let b = dot(&dir, &oc);
+        let c = dot(&oc, &oc) - f32x4::ONE;
+        let delta = b * b - a * c;
+        let crossed = delta.simd_gt(f32x4::ZERO).to_bitmask();
+        let sqrt = delta.sqrt();
+        let (t0, t1) = ((-b - sqrt) / a, (-b + sqrt) / a);
+        let (lo, hi) = (f32x4::splat(min), f32x4::new(*max));
+        let first = (t0.simd_gt(lo) & t0.simd_lt(hi)).to_bitmask() & crossed;
+        let second = (t1.simd_gt(lo) & t1.simd_lt(hi)).to_bitmask() & crossed;
+
+        let (t0, t1) = (t0.to_array(), t1.to_array());
+        for i in (0..LANES).filter(|&i| active[i]) {
+            let t = if first & (1 << i) != 0 {
+                t0[i]
+            } else if second & (1 << i) != 0 {
+                t1[i]
+            } else {
+                continue;
+            };
+            max[i] = t;
+            hits[i] = Some(self.hit_at(&packet.rays[i], t));
+        }
+    }
+}
+
+impl Solid for Sphere {
+    fn intervals(&self, r: &Ray) -> Vec<Interval> {
+        match self.roots(r) {
+            Some((t0, t1)) => vec![Interval {
+                enter: self.hit_at(r, t0),
+                exit: self.hit_at(r, t1),
+            }],
+            None => vec![],
+        }
+    }
+}
+
 impl Bounds for Sphere {
     fn bounds(&self) -> AABB {
-        let r_vec = glm::vec3(self.radius, self.radius, self.radius);
+        let r_vec = glm::abs(&self.semi_axes());
         let min = self.center - r_vec;
         let max = self.center + r_vec;
         AABB { min, max }
@@ -67,4 +212,11 @@ impl Sphere {
         let v = 0.5 - f32::asin(dir.y) / glm::pi::<f32>();
         Vec2::new(u, v)
     }
+
+    /// The unit direction that `uv_at_dir` maps to `uv`.
+    pub fn dir_at_uv(uv: &Vec2) -> Vec3 {
+        let phi = glm::two_pi::<f32>() * (uv.x - 0.5);
+        let (sin_theta, cos_theta) = (glm::pi::<f32>() * uv.y).sin_cos();
+        glm::vec3(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+    }
 }
@@ -0,0 +1,160 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
+
+use super::*;
+
+use crate::points;
+use crate::ray::Ray;
+use crate::sampler;
+use crate::vec::{self, Vec3};
+
+/// How a splat's coverage falls off away from its center.
+/// `Gaussian` splats are hit stochastically, with a standard deviation of
+/// half the radius, so overlapping splats blend smoothly over many samples.
+/// Whether a ray hits one depends only on the ray and the splat, so the
+/// same ray traced again, such as a shadow ray, finds the same splats.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SplatKernel {
+    #[default]
+    Disc,
+    Gaussian,
+}
+
+/// A single disc in a point cloud. Points without a normal are drawn as
+/// discs facing the incoming ray.
+#[derive(Clone)]
+pub struct Splat {
+    center: Vec3,
+    normal: Option<Vec3>,
+    radius: f32,
+    kernel: SplatKernel,
+}
+
+/// Point cloud loaded from a PLY or XYZ file, with each point rendered as a
+/// splat of a fixed radius.
+#[derive(Clone)]
+pub struct PointCloud {
+    tree: Accel<Splat>,
+}
+
+#[derive(Deserialize)]
+struct PointCloudDesc {
+    point_cloud: String,
+    radius: f32,
+    #[serde(default)]
+    kernel: SplatKernel,
+}
+
+impl Splat {
+    /// Number in [0, 1) hashed from the ray and the splat, against which
+    /// Gaussian coverage is tested.
+    fn coverage(&self, r: &Ray) -> f32 {
+        let bits = [r.origin, r.direction, self.center]
+            .iter()
+            .flat_map(|v| v.iter())
+            .fold(0, |h, x| sampler::hash(h ^ x.to_bits()));
+        sampler::to_unit(bits)
+    }
+}
+
+impl Geometry for Splat {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let normal = match self.normal {
+            Some(n) => n,
+            None => -glm::normalize(&r.direction),
+        };
+        let denom = glm::dot(&r.direction, &normal);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = glm::dot(&(self.center - r.origin), &normal) / denom;
+        if t <= min || t >= max {
+            return None;
+        }
+        let point = r.point_at(t);
+        let d = point - self.center;
+        let dist2 = glm::dot(&d, &d) / (self.radius * self.radius);
+        if dist2 > 1.0 {
+            return None;
+        }
+        if self.kernel == SplatKernel::Gaussian && self.coverage(r) > f32::exp(-2.0 * dist2) {
+            return None;
+        }
+        // Splats are double-sided
+        let normal = if denom > 0.0 { -normal } else { normal };
+        let (u, v) = vec::orthonormal_basis(&normal);
+        let uv = glm::vec2(
+            0.5 + 0.5 * glm::dot(&d, &u) / self.radius,
+            0.5 + 0.5 * glm::dot(&d, &v) / self.radius,
+        );
+        Some(RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent: None,
+            footprint: 0.0,
+        })
+    }
+}
+
+impl Bounds for Splat {
+    fn bounds(&self) -> AABB {
+        // Camera-facing splats can be oriented any way
+        let extent = glm::vec3(self.radius, self.radius, self.radius);
+        AABB {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
+
+impl PointCloud {
+    pub fn from_file<P: AsRef<std::path::Path>>(
+        path: P,
+        radius: f32,
+        kernel: SplatKernel,
+    ) -> std::io::Result<Self> {
+        let splats = points::load(path)?
+            .into_iter()
+            .map(|p| Splat {
+                center: p.pos,
+                normal: p.normal.map(|n| glm::normalize(&n)),
+                radius,
+                kernel,
+            })
+            .collect();
+        Ok(PointCloud {
+            tree: Accel::linear(splats),
+        })
+    }
+
+    /// Point clouds are loaded flat, like meshes.
+    pub fn build(self, settings: BuildSettings) -> Self {
+        PointCloud {
+            tree: self.tree.build(settings),
+        }
+    }
+}
+
+impl Geometry for PointCloud {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        self.tree.intersection(r, min, max)
+    }
+}
+
+impl Bounds for PointCloud {
+    fn bounds(&self) -> AABB {
+        self.tree.bounds()
+    }
+}
+
+impl<'de> Deserialize<'de> for PointCloud {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = PointCloudDesc::deserialize(deserializer)?;
+        PointCloud::from_file(&desc.point_cloud, desc.radius, desc.kernel)
+            .map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use nalgebra_glm as glm;
+
+use super::{Corner, PolyMesh};
+use crate::{Vec2, Vec3};
+
+struct Edge {
+    index: usize,
+    faces: Vec<usize>,
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl PolyMesh {
+    /// Applies `levels` rounds of Catmull-Clark subdivision and smooths the
+    /// normals of the result.
+    pub fn subdivided(self, levels: u32) -> PolyMesh {
+        if levels == 0 {
+            return self;
+        }
+        let mut mesh = self;
+        for _ in 0..levels {
+            mesh = mesh.catmull_clark();
+        }
+        mesh.smooth_normals();
+        mesh
+    }
+
+    /// A single round of Catmull-Clark subdivision. Every polygon becomes one
+    /// quad per corner; boundaries follow the cubic B-spline boundary rules.
+    /// Texture coordinates are interpolated linearly within each face.
+    fn catmull_clark(&self) -> PolyMesh {
+        let num_verts = self.positions.len();
+
+        let mut edges: HashMap<(usize, usize), Edge> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for (i, c) in face.iter().enumerate() {
+                let next = face[(i + 1) % face.len()].pos;
+                let index = num_verts + edges.len();
+                edges
+                    .entry(edge_key(c.pos, next))
+                    .or_insert(Edge {
+                        index,
+                        faces: Vec::new(),
+                    })
+                    .faces
+                    .push(f);
+            }
+        }
+        let face_base = num_verts + edges.len();
+
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let sum = face
+                    .iter()
+                    .fold(glm::zero::<Vec3>(), |acc, c| acc + self.positions[c.pos]);
+                sum / face.len() as f32
+            })
+            .collect();
+
+        let mut positions = vec![glm::zero::<Vec3>(); face_base + self.faces.len()];
+        positions[face_base..].copy_from_slice(&face_points);
+
+        // Accumulated per original vertex: adjacent face points, edge
+        // midpoints, and boundary neighbours
+        let mut face_sum = vec![glm::zero::<Vec3>(); num_verts];
+        let mut face_count = vec![0usize; num_verts];
+        let mut mid_sum = vec![glm::zero::<Vec3>(); num_verts];
+        let mut valence = vec![0usize; num_verts];
+        let mut boundary_sum = vec![glm::zero::<Vec3>(); num_verts];
+        let mut boundary_count = vec![0usize; num_verts];
+
+        for (&(a, b), edge) in &edges {
+            let (pa, pb) = (self.positions[a], self.positions[b]);
+            let mid = (pa + pb) * 0.5;
+            positions[edge.index] = if edge.faces.len() == 2 {
+                (pa + pb + face_points[edge.faces[0]] + face_points[edge.faces[1]]) * 0.25
+            } else {
+                mid
+            };
+            for &(v, other) in &[(a, pb), (b, pa)] {
+                mid_sum[v] += mid;
+                valence[v] += 1;
+                if edge.faces.len() != 2 {
+                    boundary_sum[v] += other;
+                    boundary_count[v] += 1;
+                }
+            }
+        }
+        for (f, face) in self.faces.iter().enumerate() {
+            for c in face {
+                face_sum[c.pos] += face_points[f];
+                face_count[c.pos] += 1;
+            }
+        }
+
+        for v in 0..num_verts {
+            let p = self.positions[v];
+            positions[v] = if boundary_count[v] == 2 {
+                (p * 6.0 + boundary_sum[v]) / 8.0
+            } else if boundary_count[v] > 0 || valence[v] < 3 {
+                // Corners and non-manifold vertices stay put
+                p
+            } else {
+                let n = valence[v] as f32;
+                let f = face_sum[v] / face_count[v] as f32;
+                let r = mid_sum[v] / n;
+                (f + r * 2.0 + p * (n - 3.0)) / n
+            };
+        }
+
+        let mut faces = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let len = face.len();
+            let center_uv = face.iter().fold(glm::zero::<Vec2>(), |acc, c| acc + c.uv) / len as f32;
+            let center = Corner {
+                pos: face_base + f,
+                uv: center_uv,
+                normal: None,
+            };
+            let edge_corner = |i: usize, j: usize| Corner {
+                pos: edges[&edge_key(face[i].pos, face[j].pos)].index,
+                uv: (face[i].uv + face[j].uv) * 0.5,
+                normal: None,
+            };
+            for (i, corner) in face.iter().enumerate() {
+                let (prev, next) = ((i + len - 1) % len, (i + 1) % len);
+                faces.push(vec![
+                    Corner {
+                        normal: None,
+                        ..corner.clone()
+                    },
+                    edge_corner(i, next),
+                    center.clone(),
+                    edge_corner(prev, i),
+                ]);
+            }
+        }
+
+        PolyMesh { positions, faces }
+    }
+}
@@ -0,0 +1,147 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+use crate::material::Sides;
+use crate::ray::Ray;
+use crate::vec::{Vec3, Vec4};
+
+/// Non-photorealistic rendering with flat bands of diffuse shading from a
+/// single light, and outlines where depth or normals change abruptly
+/// between neighbouring pixels.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ToonParams {
+    /// Number of shading levels between unlit and fully lit.
+    pub bands: u32,
+    /// Direction towards the light.
+    pub light_dir: Vec3,
+    /// Brightness of the unlit band.
+    pub ambient: f32,
+    pub shadows: bool,
+    pub outline_color: Vec3,
+    /// Change in depth between neighbouring pixels, relative to the nearer
+    /// one, that draws an outline.
+    pub depth_threshold: f32,
+    /// Angle in degrees between neighbouring normals that draws an outline.
+    pub crease_angle: f32,
+}
+
+impl Default for ToonParams {
+    fn default() -> Self {
+        ToonParams {
+            bands: 3,
+            light_dir: glm::vec3(1.0, 1.0, -1.0),
+            ambient: 0.2,
+            shadows: true,
+            outline_color: glm::zero(),
+            depth_threshold: 0.1,
+            crease_angle: 45.0,
+        }
+    }
+}
+
+/// What a pixel sees, kept to find outlines once the image is complete.
+pub struct ToonSample {
+    color: Vec4,
+    /// Distance to the surface, infinite where the ray escaped.
+    depth: f32,
+    normal: Vec3,
+}
+
+impl ToonSample {
+    /// A pixel the camera sees nothing through.
+    pub fn empty() -> Self {
+        ToonSample {
+            color: glm::zero(),
+            depth: f32::INFINITY,
+            normal: glm::zero(),
+        }
+    }
+}
+
+impl ToonParams {
+    /// Flat shaded color along `r`.
+    pub fn shade(&self, r: &Ray, scene: &Scene) -> ToonSample {
+        let traced = scene.trace(r, 0.001, f32::MAX);
+        let TraceResult { material, mut hit } = match traced {
+            Some(traced) => traced,
+            None => {
+                let dir = r.direction.normalize();
+                return ToonSample {
+                    color: scene.background.color(&dir, &scene.environment),
+                    depth: f32::INFINITY,
+                    normal: glm::zero(),
+                };
+            }
+        };
+        let material = material.select(&hit);
+        if glm::dot(&r.direction, &hit.normal) > 0.0 && material.sides() == Sides::Two {
+            hit.normal = -hit.normal;
+        }
+        material.perturb_normal(&mut hit);
+
+        let light = glm::normalize(&self.light_dir);
+        let mut lit = glm::dot(&hit.normal, &light).max(0.0);
+        if self.shadows && lit > 0.0 {
+            let shadow = Ray::new(hit.point, light).with_time(r.time);
+            if !scene.visible(&shadow, 0.001, f32::MAX) {
+                lit = 0.0;
+            }
+        }
+        let level = if self.bands > 1 {
+            let top = (self.bands - 1) as f32;
+            (lit * self.bands as f32).floor().min(top) / top
+        } else {
+            lit.ceil()
+        };
+        let brightness = self.ambient + (1.0 - self.ambient) * level;
+        let color = material.base_color(&hit) * brightness + material.emitted(&hit);
+        ToonSample {
+            color: glm::vec4(color.x, color.y, color.z, 1.0),
+            depth: hit.t * glm::length(&r.direction),
+            normal: hit.normal,
+        }
+    }
+
+    fn is_edge(&self, a: &ToonSample, b: &ToonSample) -> bool {
+        let (near, far) = (a.depth.min(b.depth), a.depth.max(b.depth));
+        if far.is_infinite() {
+            return near.is_finite();
+        }
+        far - near > self.depth_threshold * near
+            || glm::dot(&a.normal, &b.normal) < self.crease_angle.to_radians().cos()
+    }
+
+    /// Colors of a `width` by `height` image of samples, with outlines
+    /// drawn on the pixels next to a discontinuity to their right or below.
+    /// Images holding several views side by side or stacked, each `view`
+    /// pixels in size, are only compared within each view.
+    pub fn outline(
+        &self,
+        samples: &[ToonSample],
+        width: u32,
+        height: u32,
+        view: (u32, u32),
+    ) -> Vec<Vec4> {
+        let (width, height) = (width as usize, height as usize);
+        let (view_w, view_h) = (view.0.max(1) as usize, view.1.max(1) as usize);
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let sample = &samples[i];
+                let right =
+                    x + 1 < width && (x + 1) % view_w != 0 && self.is_edge(sample, &samples[i + 1]);
+                let below = y + 1 < height
+                    && (y + 1) % view_h != 0
+                    && self.is_edge(sample, &samples[i + width]);
+                if right || below {
+                    let c = self.outline_color;
+                    glm::vec4(c.x, c.y, c.z, 1.0)
+                } else {
+                    sample.color
+                }
+            })
+            .collect()
+    }
+}
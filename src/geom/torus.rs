@@ -0,0 +1,296 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::vec::{self, Vec3};
+
+/// Torus around `axis` through `center`, with a tube of `minor_radius`
+/// swept along a circle of `major_radius`.
+#[derive(Deserialize, Clone)]
+pub struct Torus {
+    pub center: Vec3,
+    #[serde(default = "default_axis")]
+    pub axis: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+fn default_axis() -> Vec3 {
+    glm::vec3(0.0, 1.0, 0.0)
+}
+
+impl Torus {
+    fn frame(&self) -> (Vec3, Vec3, Vec3) {
+        let axis = glm::normalize(&self.axis);
+        let (u, v) = vec::orthonormal_basis(&axis);
+        (u, axis, v)
+    }
+
+    /// Coefficients of the quartic in `t`, highest power first, for a ray
+    /// with unit direction given in the torus' local frame.
+    fn quartic(&self, o: [f64; 3], d: [f64; 3]) -> [f64; 5] {
+        let r_maj = f64::from(self.major_radius);
+        let r_min = f64::from(self.minor_radius);
+        let (r2, big_r2) = (r_min * r_min, r_maj * r_maj);
+        let k = o[0] * o[0] + o[1] * o[1] + o[2] * o[2] + big_r2 - r2;
+        let m = o[0] * d[0] + o[1] * d[1] + o[2] * d[2];
+        let a = d[0] * d[0] + d[2] * d[2];
+        let b = o[0] * d[0] + o[2] * d[2];
+        let c = o[0] * o[0] + o[2] * o[2];
+        [
+            1.0,
+            4.0 * m,
+            4.0 * m * m + 2.0 * k - 4.0 * big_r2 * a,
+            4.0 * m * k - 8.0 * big_r2 * b,
+            k * k - 4.0 * big_r2 * c,
+        ]
+    }
+}
+
+impl Torus {
+    /// All distances along `r` where it crosses the surface, in ascending order.
+    fn roots(&self, r: &Ray) -> Vec<f32> {
+        let (u, w, v) = self.frame();
+        let len = glm::length(&r.direction);
+        let dir = r.direction / len;
+        let to_local = |p: &Vec3| {
+            [
+                f64::from(glm::dot(p, &u)),
+                f64::from(glm::dot(p, &w)),
+                f64::from(glm::dot(p, &v)),
+            ]
+        };
+        // Solve from the point closest to the center for better conditioning.
+        let oc = r.origin - self.center;
+        let shift = -glm::dot(&oc, &dir);
+        let d = to_local(&dir);
+        let o = to_local(&(oc + dir * shift));
+
+        let coeffs = self.quartic(o, d);
+        let mut roots: Vec<f32> = solve_quartic(&coeffs)
+            .into_iter()
+            .map(|t| polish_root(&coeffs, t))
+            .map(|t| (t as f32 + shift) / len)
+            .filter(|t| t.is_finite())
+            .collect();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        roots
+    }
+
+    fn hit_at(&self, r: &Ray, t: f32) -> RayHit {
+        let (u, w, v) = self.frame();
+        let point = r.point_at(t);
+        let p = point - self.center;
+        let (px, py, pz) = (glm::dot(&p, &u), glm::dot(&p, &w), glm::dot(&p, &v));
+        let ring = f32::sqrt(px * px + pz * pz).max(1e-8);
+        let q = glm::vec3(px, 0.0, pz) * (self.major_radius / ring);
+        let n = (glm::vec3(px, py, pz) - q) / self.minor_radius;
+        let normal = glm::normalize(&(u * n.x + w * n.y + v * n.z));
+        let uv = glm::vec2(
+            0.5 + f32::atan2(pz, px) / glm::two_pi::<f32>(),
+            0.5 + f32::atan2(py, ring - self.major_radius) / glm::two_pi::<f32>(),
+        );
+        RayHit {
+            t,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv,
+            tangent: None,
+            footprint: 0.0,
+        }
+    }
+}
+
+impl Geometry for Torus {
+    fn intersection(&self, r: &Ray, min: f32, max: f32) -> Option<RayHit> {
+        let t = self.roots(r).into_iter().find(|&t| t > min && t < max)?;
+        Some(self.hit_at(r, t))
+    }
+}
+
+impl Solid for Torus {
+    fn intervals(&self, r: &Ray) -> Vec<Interval> {
+        self.roots(r)
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| Interval {
+                enter: self.hit_at(r, pair[0]),
+                exit: self.hit_at(r, pair[1]),
+            })
+            .collect()
+    }
+}
+
+impl Bounds for Torus {
+    fn bounds(&self) -> AABB {
+        let r = self.major_radius + self.minor_radius;
+        let extent = glm::vec3(r, r, r);
+        AABB {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+fn eval(coeffs: &[f64; 5], t: f64) -> (f64, f64) {
+    let f = coeffs.iter().fold(0.0, |acc, c| acc * t + c);
+    let df = coeffs[..4]
+        .iter()
+        .enumerate()
+        .fold(0.0, |acc, (i, c)| acc * t + c * (4 - i) as f64);
+    (f, df)
+}
+
+/// A couple of Newton steps to recover precision lost in the closed form.
+fn polish_root(coeffs: &[f64; 5], mut t: f64) -> f64 {
+    for _ in 0..2 {
+        let (f, df) = eval(coeffs, t);
+        if df.abs() > EPSILON {
+            t -= f / df;
+        }
+    }
+    t
+}
+
+/// Real roots of `x^2 + p x + q`.
+fn solve_quadric(p: f64, q: f64) -> Vec<f64> {
+    let p = p / 2.0;
+    let d = p * p - q;
+    if is_zero(d) {
+        vec![-p]
+    } else if d < 0.0 {
+        vec![]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![sqrt_d - p, -sqrt_d - p]
+    }
+}
+
+/// Real roots of `x^3 + a x^2 + b x + c`.
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    let roots = if is_zero(d) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if d < 0.0 {
+        let phi = (-q / (-cb_p).sqrt()).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        let third = std::f64::consts::PI / 3.0;
+        vec![
+            t * phi.cos(),
+            -t * (phi + third).cos(),
+            -t * (phi - third).cos(),
+        ]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+    roots.into_iter().map(|x| x - a / 3.0).collect()
+}
+
+/// Real roots of a quartic with coefficients given highest power first.
+fn solve_quartic(coeffs: &[f64; 5]) -> Vec<f64> {
+    let a = coeffs[1] / coeffs[0];
+    let b = coeffs[2] / coeffs[0];
+    let c = coeffs[3] / coeffs[0];
+    let d = coeffs[4] / coeffs[0];
+
+    // Substitute x = y - a/4 to eliminate the cubic term
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + c;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * c / 4.0 + d;
+
+    let roots = if is_zero(r) {
+        let mut roots = solve_cubic(0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        let z = solve_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if q < 0.0 { -v } else { v };
+        let mut roots = solve_quadric(v, z - u);
+        roots.extend(solve_quadric(-v, z + u));
+        roots
+    };
+    roots.into_iter().map(|y| y - a / 4.0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_roots(coeffs: &[f64; 5]) -> Vec<f64> {
+        let mut roots: Vec<f64> = solve_quartic(coeffs)
+            .into_iter()
+            .map(|t| polish_root(coeffs, t))
+            .collect();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        roots.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+        roots
+    }
+
+    fn assert_roots(coeffs: &[f64; 5], expected: &[f64]) {
+        let roots = sorted_roots(coeffs);
+        assert_eq!(roots.len(), expected.len(), "roots {:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "roots {:?}", roots);
+        }
+    }
+
+    #[test]
+    fn four_real_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(&[1.0, -10.0, 35.0, -50.0, 24.0], &[1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn two_real_roots() {
+        // (x^2 + 1)(x - 2)(x + 3), with a leading coefficient to divide out
+        assert_roots(&[2.0, 2.0, -10.0, 2.0, -12.0], &[-3.0, 2.0]);
+    }
+
+    #[test]
+    fn no_real_roots() {
+        assert_roots(&[1.0, 0.0, 0.0, 0.0, 1.0], &[]);
+    }
+
+    #[test]
+    fn double_root() {
+        // (x - 1)^2 (x + 2)(x - 5)
+        assert_roots(&[1.0, -5.0, -3.0, 17.0, -10.0], &[-2.0, 1.0, 5.0]);
+    }
+}
@@ -1,29 +1,386 @@
 use crate::ray::Ray;
 use super::*;
 use crate::vec::*;
+use crate::material::{Bsdf, Material, Scatter, ShadowCatcher, Sides};
 use crate::texture::Texture as _;
+use crate::sampler;
+use serde::Deserialize;
 
-pub fn trace(r: &Ray, scene: &Scene, depth: usize) -> Vec3 {
+/// Color and alpha seen by a camera ray, which shows the scene's background
+/// where it escapes.
+pub fn trace(r: &Ray, scene: &Scene, depth: usize) -> Vec4 {
+    trace_passes(r, scene, depth, &mut [])
+}
+
+/// As `trace`, also adding the light from each of the scene's light groups
+/// that reaches the camera to `passes`, which holds a color per group.
+pub fn trace_passes(r: &Ray, scene: &Scene, depth: usize, passes: &mut [Vec3]) -> Vec4 {
+    trace_from(r, scene, depth, Path::camera(), passes)
+}
+
+/// Limit on the light each bounce of a path adds to its pixel, which removes
+/// fireflies, the rare paths that are much brighter than the rest, at the
+/// cost of some energy from bright light sources and caustics.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct RadianceClamp {
+    /// Largest value of any color channel added.
+    pub max: f32,
+    /// Leaves the light of the first bounce as it is, including lights seen
+    /// directly, clamping only light that has bounced.
+    #[serde(default)]
+    pub indirect_only: bool,
+}
+
+/// What a path carries from one bounce to the next.
+#[derive(Clone, Copy)]
+struct Path {
+    /// Origin and density of the bounce that sampled the ray, when that
+    /// bounce also sampled the lights and portals.
+    mis: Option<(Vec3, f32)>,
+    /// Whether the ray comes from the camera. Alpha only matters for these.
+    camera: bool,
+    /// Attenuation of light on its way back to the camera.
+    throughput: Vec3,
+}
+
+impl Path {
+    fn camera() -> Self {
+        Path {
+            mis: None,
+            camera: true,
+            throughput: glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Scales `light` arriving at the path's current bounce down to the
+    /// scene's clamp on what it adds to the pixel.
+    fn clamp(&self, scene: &Scene, light: Vec3) -> Vec3 {
+        match scene.clamp {
+            Some(clamp) if !(self.camera && clamp.indirect_only) => {
+                let added = glm::comp_max(&self.throughput.component_mul(&light));
+                if added > clamp.max {
+                    light * (clamp.max / added)
+                } else {
+                    light
+                }
+            }
+            _ => light,
+        }
+    }
+
+    /// Adds `light` arriving at the path's current bounce to the pass of
+    /// `group`, if passes are being rendered.
+    fn record(&self, passes: &mut [Vec3], group: usize, light: &Vec3) {
+        if let Some(pass) = passes.get_mut(group) {
+            *pass += self.throughput.component_mul(light);
+        }
+    }
+}
+
+fn trace_from(r: &Ray, scene: &Scene, depth: usize, path: Path, passes: &mut [Vec3]) -> Vec4 {
+    if depth == 0 {
+        return opaque(glm::zero());
+    }
+    let traced = scene.trace(r, 0.001, f32::MAX);
+    shade(r, traced, scene, depth, path, passes)
+}
+
+fn opaque(color: Vec3) -> Vec4 {
+    glm::vec4(color.x, color.y, color.z, 1.0)
+}
+
+/// Traces a packet of camera rays together, then follows each path on its
+/// own. `start_lane` is called before each path is followed, to start the
+/// sample that its bounces draw their numbers from.
+pub fn trace_packet<F: FnMut(usize)>(
+    packet: &RayPacket,
+    scene: &Scene,
+    depth: usize,
+    mut start_lane: F,
+) -> [Vec4; LANES] {
+    let mut colors = [opaque(glm::zero()); LANES];
     if depth == 0 {
+        return colors;
+    }
+    let mut traced = scene.trace_packet(packet, 0.001, f32::MAX);
+    for (i, traced) in traced.iter_mut().enumerate() {
+        start_lane(i);
+        let path = Path::camera();
+        colors[i] = shade(&packet.rays[i], traced.take(), scene, depth, path, &mut []);
+    }
+    colors
+}
+
+/// Environment light arriving through a portal picked by sampling the
+/// portals, weighted against the material sampling the same direction.
+fn sample_portals(r: &Ray, hit: &RayHit, material: &Material, scene: &Scene) -> Vec3 {
+    let bsdf = match material.bsdf() {
+        Some(bsdf) => bsdf,
+        None => return glm::zero(),
+    };
+    let rnd = (sampler::next_1d(), sampler::next_2d());
+    let (dir, portal_pdf) = match scene.sample_portal(&hit.point, rnd) {
+        Some(sample) => sample,
+        None => return glm::zero(),
+    };
+    let cos = glm::dot(&dir, &hit.normal);
+    let shadow = Ray::new(hit.point, dir).with_time(r.time);
+    if cos <= 0.0 || portal_pdf <= 0.0 || !scene.visible(&shadow, 0.001, f32::MAX) {
+        return glm::zero();
+    }
+    let wo = -r.direction.normalize();
+    let f = bsdf.eval(&wo, &dir, hit);
+    let bsdf_pdf = bsdf.pdf(&wo, &dir, hit);
+    let env = scene.environment.sample(Sphere::uv_at_dir(&dir));
+    let env_pdf = scene.environment_pdf(&hit.point, &dir);
+    let weight = scene.heuristic.weight(portal_pdf, &[bsdf_pdf, env_pdf]);
+    env.component_mul(&f) * (cos * weight / portal_pdf)
+}
+
+/// Light from one of the lights picked by sampling them, with the group of
+/// that light. Light from surfaces is weighted against the material
+/// sampling the same direction.
+fn sample_lights(r: &Ray, hit: &RayHit, material: &Material, scene: &Scene) -> (Vec3, usize) {
+    let bsdf = match material.bsdf() {
+        Some(bsdf) => bsdf,
+        None => return (glm::zero(), 0),
+    };
+    let rnd = (sampler::next_1d(), sampler::next_2d());
+    match scene.sample_light(&hit.point, rnd) {
+        Some((i, sample, pick_pdf)) => {
+            let light = &scene.lights()[i];
+            let color = sample_light(r, hit, bsdf, light, sample, pick_pdf, scene);
+            (color, scene.light_group(i))
+        }
+        None => (glm::zero(), 0),
+    }
+}
+
+fn sample_light(
+    r: &Ray,
+    hit: &RayHit,
+    bsdf: &dyn Bsdf,
+    light: &Light,
+    sample: LightSample,
+    pick_pdf: f32,
+    scene: &Scene,
+) -> Vec3 {
+    match sample {
+        LightSample::Surface(sample) => sample_surface_light(r, hit, bsdf, light, &sample, scene),
+        LightSample::Direct {
+            dir,
+            dist,
+            irradiance,
+        } => {
+            let cos = glm::dot(&dir, &hit.normal);
+            let shadow = Ray::new(hit.point, dir).with_time(r.time);
+            if cos <= 0.0 || !scene.visible(&shadow, 0.001, dist) {
+                return glm::zero();
+            }
+            // Material sampling never reaches these lights, so there is
+            // nothing to weigh against
+            let wo = -r.direction.normalize();
+            let f = bsdf.eval(&wo, &dir, hit);
+            irradiance.component_mul(&f) * (cos / pick_pdf)
+        }
+        LightSample::Environment { dir, radiance, pdf } => {
+            let cos = glm::dot(&dir, &hit.normal);
+            let shadow = Ray::new(hit.point, dir).with_time(r.time);
+            if cos <= 0.0 || pdf <= 0.0 || !scene.visible(&shadow, 0.001, f32::MAX) {
+                return glm::zero();
+            }
+            let wo = -r.direction.normalize();
+            let f = bsdf.eval(&wo, &dir, hit);
+            let pdf = pdf * pick_pdf;
+            let others = [bsdf.pdf(&wo, &dir, hit), scene.portal_pdf(&hit.point, &dir)];
+            let weight = scene.heuristic.weight(pdf, &others);
+            radiance.component_mul(&f) * (cos * weight / pdf)
+        }
+    }
+}
+
+fn sample_surface_light(
+    r: &Ray,
+    hit: &RayHit,
+    bsdf: &dyn Bsdf,
+    light: &Light,
+    sample: &SurfaceSample,
+    scene: &Scene,
+) -> Vec3 {
+    let dir = glm::normalize(&(sample.point - hit.point));
+    let cos = glm::dot(&dir, &hit.normal);
+    if cos <= 0.0 {
         return glm::zero();
     }
-    if let Some(TraceResult { material, hit }) = scene.trace(r, 0.001, std::f32::MAX) {
-        let RayHit { normal, uv, .. } = hit;
-        let w0 = -r.direction;
-        let (bounce, pdf) = material.bounce(&w0, &hit);
-        let incident = trace(&bounce, scene, depth - 1);
-        let (brdf, ks) = material.brdf(&w0, &bounce.direction, &normal, uv);
-        let specular = brdf / pdf;
-        let diffuse = {
-            let lambert = material.albedo.sample(uv) / glm::pi::<f32>();
-            let kd = (glm::vec3(1.0, 1.0, 1.0) - ks) * (1.0 - material.metalness.sample(uv));
-            let pdf = glm::one_over_two_pi::<f32>();
-            kd.component_mul(&lambert) / pdf
+    // The shadow ray must reach the sampled point itself, rather than
+    // another part of the light hiding it, and its hit there is shaded as a
+    // path reaching it would be
+    let shadow = Ray::new(hit.point, dir).with_time(r.time);
+    let dist = glm::distance(&sample.point, &hit.point);
+    let (light_material, light_hit) = match scene.occluder(&shadow, 0.001, f32::MAX) {
+        Some(TraceResult {
+            material,
+            hit: found,
+        }) if light.is(material) && (found.t - dist).abs() < 1e-3 * dist.max(1.0) => {
+            (material, found)
+        }
+        _ => return glm::zero(),
+    };
+    let light_pdf = scene.light_pdf(&hit.point, light_material, &light_hit);
+    if light_pdf <= 0.0 {
+        return glm::zero();
+    }
+    let light_material = light_material.select(&light_hit);
+    if glm::dot(&dir, &light_hit.normal) > 0.0 && !light_material.emits_back() {
+        return glm::zero();
+    }
+    let emitted = light_material
+        .emitted(&light_hit)
+        .component_mul(&light_material.emission_filter(&light_hit, &-dir));
+    let wo = -r.direction.normalize();
+    let f = bsdf.eval(&wo, &dir, hit);
+    let bsdf_pdf = bsdf.pdf(&wo, &dir, hit);
+    let weight = scene.heuristic.weight(light_pdf, &[bsdf_pdf]);
+    emitted.component_mul(&f) * (cos * weight / light_pdf)
+}
+
+/// What is behind a shadow catcher, darkened by the shadow on it and
+/// overlaid with the objects it reflects. Both are layers over the
+/// background with an alpha of their own, so that camera rays keep the
+/// background's alpha where there is neither.
+fn catch(
+    r: &Ray,
+    hit: &RayHit,
+    catcher: &ShadowCatcher,
+    scene: &Scene,
+    depth: usize,
+    path: Path,
+    passes: &mut [Vec3],
+) -> Vec4 {
+    let shadow = catcher.shadow(r, hit, scene);
+    // Reflected objects are shaded as any bounce, while the environment they
+    // would also reflect is left to the backplate
+    let reflection = catcher.mirror(r, hit).and_then(|mirrored| {
+        let traced = scene.trace(&mirrored, 0.001, f32::MAX)?;
+        let strength = catcher.reflection.min(1.0);
+        let next = Path {
+            mis: None,
+            camera: false,
+            throughput: path.throughput * strength,
         };
-        let costheta = f32::max(glm::dot(&normal, &bounce.direction), 0.0);
-        (diffuse + specular).component_mul(&incident) * costheta + material.emission.sample(uv)
+        let color = shade(&mirrored, Some(traced), scene, depth - 1, next, passes);
+        Some((glm::vec4_to_vec3(&color) * strength, strength))
+    });
+    let (reflected, strength) = reflection.unwrap_or((glm::zero(), 0.0));
+    let through = Ray::new(hit.point, r.direction).with_time(r.time);
+    let behind = Path {
+        throughput: path.throughput * ((1.0 - shadow) * (1.0 - strength)),
+        ..path
+    };
+    let color = trace_from(&through, scene, depth, behind, passes);
+    let alpha = shadow + color.w * (1.0 - shadow);
+    let color = glm::vec4_to_vec3(&color) * (1.0 - shadow);
+    let color = reflected + color * (1.0 - strength);
+    let alpha = strength + alpha * (1.0 - strength);
+    glm::vec4(color.x, color.y, color.z, alpha)
+}
+
+fn shade(
+    r: &Ray,
+    traced: Option<TraceResult>,
+    scene: &Scene,
+    depth: usize,
+    path: Path,
+    passes: &mut [Vec3],
+) -> Vec4 {
+    if let Some(TraceResult {
+        material: object_material,
+        mut hit,
+    }) = traced
+    {
+        let material = object_material.select(&hit);
+        let backface = glm::dot(&r.direction, &hit.normal) > 0.0;
+        let culled = backface && material.sides() == Sides::Cull;
+        // Holes and culled faces continue the ray without counting a bounce
+        if culled || sampler::next_1d() >= material.opacity(&hit) {
+            let through = Ray::new(hit.point, r.direction).with_time(r.time);
+            return trace_from(&through, scene, depth, path, passes);
+        }
+        if let Material::ShadowCatcher(catcher) = material {
+            return catch(r, &hit, catcher, scene, depth, path, passes);
+        }
+        // Emission the previous bounce also reached by sampling the lights
+        // is weighted against that
+        let emission_weight = match path.mis {
+            Some((origin, bsdf_pdf)) => {
+                let light_pdf = scene.light_pdf(&origin, object_material, &hit);
+                scene.heuristic.weight(bsdf_pdf, &[light_pdf])
+            }
+            None => 1.0,
+        };
+        if backface && material.sides() == Sides::Two {
+            hit.normal = -hit.normal;
+        }
+        material.perturb_normal(&mut hit);
+        let emitted = if backface && !material.emits_back() {
+            glm::zero()
+        } else {
+            let filter = material.emission_filter(&hit, &-r.direction.normalize());
+            let emitted = material.emitted(&hit).component_mul(&filter) * emission_weight;
+            path.clamp(scene, emitted)
+        };
+        let portals = path.clamp(scene, sample_portals(r, &hit, material, scene));
+        let (lights, group) = sample_lights(r, &hit, material, scene);
+        let lights = path.clamp(scene, lights);
+        path.record(passes, scene.emission_group(object_material), &emitted);
+        path.record(passes, scene.environment_group(), &portals);
+        path.record(passes, group, &lights);
+        let direct = portals + lights;
+        match material.scatter(r, &hit, scene) {
+            Some(Scatter { ray, attenuation }) => {
+                // Specular bounces, with no density, leave the lights and
+                // environment to the scattered ray alone
+                let sampled = !scene.portals.is_empty() || !scene.lights().is_empty();
+                let mis = match material.bsdf() {
+                    Some(bsdf) if sampled => {
+                        let wo = -r.direction.normalize();
+                        let pdf = bsdf.pdf(&wo, &ray.direction.normalize(), &hit);
+                        Some((hit.point, pdf)).filter(|_| pdf > 0.0)
+                    }
+                    _ => None,
+                };
+                let next = Path {
+                    mis,
+                    camera: false,
+                    throughput: path.throughput.component_mul(&attenuation),
+                };
+                let incident = trace_from(&ray.with_time(r.time), scene, depth - 1, next, passes);
+                let incident = glm::vec4_to_vec3(&incident);
+                opaque(attenuation.component_mul(&incident) + emitted + direct)
+            }
+            None => opaque(emitted + direct),
+        }
+    } else if path.camera {
+        let dir = r.direction.normalize();
+        let color = scene.background.color(&dir, &scene.environment);
+        let background = glm::vec4_to_vec3(&color);
+        path.record(passes, scene.environment_group(), &background);
+        color
     } else {
         let dir = r.direction.normalize();
-        scene.environment.sample(Sphere::uv_at_dir(&dir))
+        let env = scene.environment.sample(Sphere::uv_at_dir(&dir));
+        let env = match path.mis {
+            Some((origin, bsdf_pdf)) => {
+                let others = [
+                    scene.portal_pdf(&origin, &dir),
+                    scene.environment_pdf(&origin, &dir),
+                ];
+                env * scene.heuristic.weight(bsdf_pdf, &others)
+            }
+            None => env,
+        };
+        let env = path.clamp(scene, env);
+        path.record(passes, scene.environment_group(), &env);
+        opaque(env)
     }
 }
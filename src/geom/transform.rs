@@ -0,0 +1,218 @@
+use std::borrow::Cow;
+
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
+
+use super::*;
+
+use crate::ray::Ray;
+use crate::Vec3;
+
+/// Affine object-to-world transform, stored along with its inverse. Moving
+/// transforms also keep their parts at either end of the shutter interval,
+/// which are interpolated for the time of each ray.
+#[derive(Clone)]
+pub struct Transform {
+    matrix: glm::Mat4,
+    inverse: glm::Mat4,
+    motion: Option<Box<(Parts, Parts)>>,
+}
+
+/// Times across the shutter interval that the bounds of moving transforms
+/// are taken at.
+const MOTION_STEPS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Parts {
+    translate: Vec3,
+    rotate: Vec3,
+    scale: Vec3,
+}
+
+impl Parts {
+    fn matrix(&self) -> glm::Mat4 {
+        let rotate = &self.rotate;
+        let rotation = glm::rotation(rotate.z.to_radians(), &glm::vec3(0.0, 0.0, 1.0))
+            * glm::rotation(rotate.y.to_radians(), &glm::vec3(0.0, 1.0, 0.0))
+            * glm::rotation(rotate.x.to_radians(), &glm::vec3(1.0, 0.0, 0.0));
+        glm::translation(&self.translate) * rotation * glm::scaling(&self.scale)
+    }
+
+    fn lerp(&self, other: &Parts, t: f32) -> Parts {
+        Parts {
+            translate: glm::lerp(&self.translate, &other.translate, t),
+            rotate: glm::lerp(&self.rotate, &other.rotate, t),
+            scale: glm::lerp(&self.scale, &other.scale, t),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct TransformDesc {
+    translate: Vec3,
+    /// Degrees around the x, y and z axes, applied in that order.
+    rotate: Vec3,
+    scale: Scale,
+    /// Parts of the transform at the end of the shutter interval, which
+    /// moves the object for motion blur.
+    motion: Option<MotionDesc>,
+}
+
+/// Parts not given stay as they are at the start of the shutter interval.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MotionDesc {
+    translate: Option<Vec3>,
+    rotate: Option<Vec3>,
+    scale: Option<Scale>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scale {
+    Uniform(f32),
+    NonUniform(Vec3),
+}
+
+impl Scale {
+    fn vector(&self) -> Vec3 {
+        match *self {
+            Scale::Uniform(s) => glm::vec3(s, s, s),
+            Scale::NonUniform(s) => s,
+        }
+    }
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc {
+            translate: glm::zero(),
+            rotate: glm::zero(),
+            scale: Scale::Uniform(1.0),
+            motion: None,
+        }
+    }
+}
+
+impl Transform {
+    pub fn new(translate: &Vec3, rotate: &Vec3, scale: &Vec3) -> Self {
+        let parts = Parts {
+            translate: *translate,
+            rotate: *rotate,
+            scale: *scale,
+        };
+        Self::from_matrix(parts.matrix())
+    }
+
+    pub fn from_matrix(matrix: glm::Mat4) -> Self {
+        Transform {
+            matrix,
+            inverse: glm::inverse(&matrix),
+            motion: None,
+        }
+    }
+
+    fn moving(start: Parts, end: Parts) -> Self {
+        Transform {
+            motion: Some(Box::new((start, end))),
+            ..Self::from_matrix(start.matrix())
+        }
+    }
+
+    /// The transform at `time` in the shutter interval, from 0 to 1.
+    pub fn at(&self, time: f32) -> Cow<'_, Transform> {
+        match &self.motion {
+            Some(motion) => {
+                let (start, end) = &**motion;
+                let time = time.clamp(0.0, 1.0);
+                Cow::Owned(Self::from_matrix(start.lerp(end, time).matrix()))
+            }
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.motion.is_some()
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        let p = self.matrix * glm::vec4(p.x, p.y, p.z, 1.0);
+        glm::vec3(p.x, p.y, p.z)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let v = self.matrix * glm::vec4(v.x, v.y, v.z, 0.0);
+        glm::vec3(v.x, v.y, v.z)
+    }
+
+    /// Normals transform by the inverse transpose to stay perpendicular
+    /// to non-uniformly scaled surfaces.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        let n = glm::transpose(&self.inverse) * glm::vec4(n.x, n.y, n.z, 0.0);
+        glm::normalize(&glm::vec3(n.x, n.y, n.z))
+    }
+
+    /// Brings a world space ray into object space. The direction is left
+    /// unnormalized so hit distances carry over unchanged, while the spread
+    /// is scaled to object space units.
+    pub fn ray_to_local(&self, r: &Ray) -> Ray {
+        let o = self.inverse * glm::vec4(r.origin.x, r.origin.y, r.origin.z, 1.0);
+        let d = self.inverse * glm::vec4(r.direction.x, r.direction.y, r.direction.z, 0.0);
+        let d = glm::vec3(d.x, d.y, d.z);
+        let spread = r.spread * glm::length(&d) / glm::length(&r.direction);
+        Ray::new(glm::vec3(o.x, o.y, o.z), d)
+            .with_time(r.time)
+            .with_spread(spread)
+    }
+
+    /// Bounds of `local` once transformed, over the whole shutter interval
+    /// for moving transforms.
+    pub fn bounds(&self, local: &AABB) -> AABB {
+        if !local.is_finite() {
+            return AABB::infinite();
+        }
+        if self.motion.is_some() {
+            let at = |i: usize| self.at(i as f32 / MOTION_STEPS as f32).bounds(local);
+            return (1..=MOTION_STEPS).fold(at(0), |bounds, i| bounds.union(&at(i)));
+        }
+        let (a, b) = (local.min, local.max);
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                let x = if i & 1 == 0 { a.x } else { b.x };
+                let y = if i & 2 == 0 { a.y } else { b.y };
+                let z = if i & 4 == 0 { a.z } else { b.z };
+                self.point(&glm::vec3(x, y, z))
+            })
+            .collect();
+        AABB::from(corners.iter())
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::from_matrix(glm::identity())
+    }
+}
+
+impl<'de> Deserialize<'de> for Transform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = TransformDesc::deserialize(deserializer)?;
+        let start = Parts {
+            translate: desc.translate,
+            rotate: desc.rotate,
+            scale: desc.scale.vector(),
+        };
+        Ok(match desc.motion {
+            Some(motion) => {
+                let end = Parts {
+                    translate: motion.translate.unwrap_or(start.translate),
+                    rotate: motion.rotate.unwrap_or(start.rotate),
+                    scale: motion.scale.map_or(start.scale, |scale| scale.vector()),
+                };
+                Transform::moving(start, end)
+            }
+            None => Transform::from_matrix(start.matrix()),
+        })
+    }
+}
@@ -0,0 +1,20 @@
+//! The path tracer behind the `prayer` application, for building and
+//! rendering scenes from code, including with materials of your own through
+//! `material::Bsdf`.
+
+pub mod camera;
+pub mod config;
+pub mod geom;
+pub mod material;
+pub mod mtl;
+pub mod obj;
+pub mod points;
+pub mod ray;
+pub mod sampler;
+pub mod solar;
+pub mod spectrum;
+pub mod texture;
+pub mod vec;
+
+use ray::Ray;
+use vec::*;
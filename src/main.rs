@@ -1,18 +1,9 @@
 mod app;
-mod camera;
-mod config;
-mod geom;
-mod material;
-mod obj;
-mod ray;
 mod style;
-mod texture;
-mod vec;
 
 use app::AppModel;
 
-use ray::Ray;
-use vec::*;
+use prayer::{config, geom, sampler, texture, vec};
 
 use iced::{Application, Settings};
 
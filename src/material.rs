@@ -1,103 +1,336 @@
+mod bsdf;
+mod bump;
+mod carpaint;
+mod catcher;
+mod conductor;
+mod dielectric;
+mod emitter;
+mod layered;
+mod microfacet;
+mod mix;
+mod principled;
+mod registry;
+mod standard;
+mod subsurface;
+
+use std::sync::Arc;
+
 use nalgebra_glm as glm;
 use serde::Deserialize;
 
-use rand::prelude::*;
-
-use crate::geom::RayHit;
+use crate::geom::{RayHit, Scene};
 use crate::ray::Ray;
-use crate::texture::{ColorTexture, GrayScaleTexture, Texture as _};
-use crate::{Vec2, Vec3};
-
-fn transform_to_world(vec: &Vec3, norm: &Vec3) -> Vec3 {
-    // Find an axis that is not parallel to normal
-    let major_axis = if f32::abs(norm.x) < (1.0 / f32::sqrt(3.0)) {
-        glm::vec3(1.0, 0.0, 0.0)
-    } else if f32::abs(norm.y) < (1.0 / f32::sqrt(3.0)) {
-        glm::vec3(0.0, 1.0, 0.0)
-    } else {
-        glm::vec3(0.0, 0.0, 1.0)
-    };
-
-    // Create a coordinate system relative to world space
-    let u = glm::normalize(&norm.cross(&major_axis));
-    let v = norm.cross(&u);
-    let w = norm;
-
-    // Transform from local coordinates to world coordinates
-    v * vec.x + w * vec.y + u * vec.z
+use crate::texture::Texture as _;
+use crate::Vec3;
+
+pub use bsdf::*;
+pub use bump::*;
+pub use carpaint::*;
+pub use catcher::*;
+pub use conductor::*;
+pub use dielectric::*;
+pub use emitter::*;
+pub use layered::*;
+pub use mix::*;
+pub use principled::*;
+pub use registry::*;
+pub use standard::*;
+pub use subsurface::*;
+
+/// A ray continuing a path after hitting a surface, together with the factor
+/// its incoming light is scaled by, i.e. the BSDF times the cosine over the
+/// sampling density.
+pub struct Scatter {
+    pub ray: Ray,
+    pub attenuation: Vec3,
 }
 
-#[derive(Deserialize, Clone)]
-pub struct Material {
-    pub albedo: ColorTexture,
-    pub metalness: GrayScaleTexture,
-    pub roughness: GrayScaleTexture,
+/// How opaque surfaces treat rays hitting their back face, as happens with
+/// thin geometry such as leaves or single-walled OBJ models.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sides {
+    /// Shade both faces alike, flipping the normal towards the ray.
+    #[default]
+    Two,
+    /// Only the front face is shaded; back faces see the normal facing away.
+    One,
+    /// Back faces are invisible and rays pass straight through them.
+    Cull,
+}
 
-    #[serde(default)]
-    pub emission: ColorTexture,
+/// Units of a material's `emission_strength`. Photometric units become
+/// luminance in nits, meant to be rendered with the camera's `ev100`.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EmissionUnit {
+    /// A plain multiplier of the emission color.
+    #[default]
+    Scale,
+    /// Luminance, in candela per square meter.
+    Nits,
+    /// Total luminous flux leaving the surface.
+    Lumens,
+    /// Total radiant flux leaving the surface.
+    Watts,
 }
 
-impl Material {
-    fn importance_theta(&self, roughness: f32) -> f32 {
-        let mut rng = rand::thread_rng();
-        let a = roughness * roughness;
-        let eta: f32 = rng.gen();
-        let sqrt = f32::sqrt(eta / (1.0 - eta));
-        f32::atan(a * sqrt)
-    }
-
-    pub fn bounce(&self, w0: &Vec3, hit: &RayHit) -> (Ray, f32) {
-        let n = hit.normal;
-        let mut rng = rand::thread_rng();
-        let roughness = self.roughness.sample(hit.uv);
-        let theta = self.importance_theta(roughness);
-        let phi: f32 = rng.gen::<f32>() * 2.0 * std::f32::consts::PI;
-
-        let x = f32::sin(theta) * f32::sin(phi);
-        let y = f32::cos(theta);
-        let z = f32::sin(theta) * f32::cos(phi);
-
-        let direction = glm::normalize(&transform_to_world(&glm::vec3(x, y, z), &n));
-        let h = glm::normalize(&(w0 + direction));
-
-        let cost = f32::max(0.0, glm::dot(&n, &h));
-        let pdf = normal_distribution(&n, &h, roughness) * cost;
-        let p = pdf / (4.0 * f32::max(0.0, glm::dot(&w0, &h)));
-        (Ray::new(hit.point, direction), p)
-    }
-
-    /// Return type is (brdf, fresnel)
-    pub fn brdf(&self, w0: &Vec3, wi: &Vec3, n: &Vec3, uv: Vec2) -> (Vec3, Vec3) {
-        let h = glm::normalize(&(w0 + wi));
-        let d = normal_distribution(&n, &h, self.roughness.sample(uv));
-        let f0 = glm::vec3(0.04, 0.04, 0.04);
-        let f0 = glm::mix(&f0, &self.albedo.sample(uv), self.metalness.sample(uv));
-        let f = fresnel(&wi, &h, &f0);
-        let g = geometry(&n, &h, w0, wi);
-        let num = d * f * g;
-        let denom = 4.0 * glm::dot(&n, &wi) * glm::dot(&n, &w0);
-        (num / denom, f)
+/// Luminous efficacy assumed for watts, the maximum, at 555 nm.
+const LUMENS_PER_WATT: f32 = 683.0;
+
+impl EmissionUnit {
+    /// Converts a strength in these units to a scale of the emission color.
+    /// Units of total flux are spread over `area`, the area of the emitting
+    /// surface, which radiates as a Lambertian emitter.
+    pub fn to_scale(self, strength: f32, area: Option<f32>) -> Result<f32, String> {
+        let lumens = match self {
+            EmissionUnit::Scale | EmissionUnit::Nits => return Ok(strength),
+            EmissionUnit::Lumens => strength,
+            EmissionUnit::Watts => strength * LUMENS_PER_WATT,
+        };
+        match area {
+            Some(area) if area > 0.0 => Ok(lumens / (glm::pi::<f32>() * area)),
+            _ => Err(format!(
+                "emission in {:?} needs an untransformed disk, quad, sphere, box, \
+                 cylinder or static mesh to spread over",
+                self
+            )),
+        }
     }
 }
 
-fn normal_distribution(n: &Vec3, h: &Vec3, roughness: f32) -> f32 {
-    let a = roughness * roughness;
-    let ndoth = f32::max(glm::dot(n, h), 0.0);
-    let num = a * a;
-    let denom = (ndoth * ndoth) * (a * a - 1.0) + 1.0;
-    let denom = std::f32::consts::PI * denom * denom;
-    num / denom
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Material {
+    // Untagged variants are tried in order; mixes are told apart by their
+    // children, layered materials by their layers, car paint by its flakes,
+    // shadow catchers by their strength, subsurface materials by their mean
+    // free path, principled materials by their base color and dielectrics
+    // by their index of refraction.
+    Mix(Box<Mix>),
+    Layered(Box<Layered>),
+    CarPaint(Box<CarPaint>),
+    ShadowCatcher(ShadowCatcher),
+    Subsurface(Subsurface),
+    Principled(Principled),
+    Dielectric(Dielectric),
+    Standard(Standard),
+    /// A material of the scene's library or a built-in preset, replaced by
+    /// it when the scene is loaded.
+    Named(String),
+    /// The surface of a light declared among the scene's lights.
+    #[serde(skip_deserializing)]
+    Emitter(Emitter),
+    /// A scattering model defined outside this crate.
+    #[serde(skip_deserializing)]
+    Custom(Arc<dyn Bsdf>),
 }
 
-fn fresnel(wi: &Vec3, h: &Vec3, f0: &Vec3) -> Vec3 {
-    let widoth = f32::max(0.0, glm::dot(wi, h));
-    f0 + (glm::vec3(1.0, 1.0, 1.0) - f0) * f32::powi(1.0 - widoth, 5)
+impl Default for Material {
+    fn default() -> Self {
+        Material::Standard(Standard::default())
+    }
 }
 
-fn geometry(n: &Vec3, h: &Vec3, w0: &Vec3, wi: &Vec3) -> f32 {
-    let ndoth = f32::max(0.0, glm::dot(n, h));
-    let w0doth = f32::max(0.0, glm::dot(w0, h));
-    let term2 = 2.0 * ndoth * f32::max(0.0, glm::dot(n, w0)) / w0doth;
-    let term3 = 2.0 * ndoth * f32::max(0.0, glm::dot(n, wi)) / w0doth;
-    f32::min(1.0, f32::min(term2, term3))
+impl Material {
+    /// The material shading `hit`, after resolving mixes.
+    pub fn select(&self, hit: &RayHit) -> &Material {
+        match self {
+            Material::Mix(m) => m.pick(hit).select(hit),
+            other => other,
+        }
+    }
+
+    /// Materials that let light travel below the surface trace it through
+    /// `scene`.
+    pub fn scatter(&self, r: &Ray, hit: &RayHit, scene: &Scene) -> Option<Scatter> {
+        match self {
+            Material::Mix(m) => m.pick(hit).scatter(r, hit, scene),
+            Material::Layered(l) => l.scatter(r, hit, scene),
+            Material::CarPaint(c) => c.scatter(r, hit),
+            // Shadow catchers composite what is behind them, which the
+            // tracer does itself
+            Material::ShadowCatcher(_) => None,
+            Material::Subsurface(s) => s.scatter(r, hit, scene),
+            Material::Principled(p) => p.scatter(r, hit),
+            Material::Dielectric(d) => d.scatter(r, hit),
+            Material::Standard(s) => s.scatter(r, hit),
+            Material::Named(_) | Material::Emitter(_) => None,
+            Material::Custom(b) => b.sample(r, hit),
+        }
+    }
+
+    /// The scattering model as a `Bsdf`, for materials that can evaluate it
+    /// and so be sampled towards light sources. Mixes must be resolved with
+    /// `select` first.
+    pub fn bsdf(&self) -> Option<&dyn Bsdf> {
+        match self {
+            Material::Principled(p) => Some(p),
+            Material::Standard(s) => Some(s),
+            Material::Custom(b) => Some(b.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Back face handling. Refractive materials rely on the normal pointing
+    /// out of the object, so they always see it as it is. Mixes must be
+    /// resolved with `select` first.
+    pub fn sides(&self) -> Sides {
+        match self {
+            Material::Principled(p) if p.transmission <= 0.0 => p.sides,
+            Material::Standard(s) => s.sides,
+            _ => Sides::One,
+        }
+    }
+
+    /// Probability of `hit` being a surface rather than a hole. Mixes must
+    /// be resolved with `select` first.
+    pub fn opacity(&self, hit: &RayHit) -> f32 {
+        match self {
+            Material::Principled(p) => p.opacity.sample_at(hit),
+            Material::Standard(s) => s.opacity.sample_at(hit),
+            _ => 1.0,
+        }
+    }
+
+    /// Bends the shading normal of `hit` by the material's normal and bump
+    /// maps.
+    pub fn perturb_normal(&self, hit: &mut RayHit) {
+        match self {
+            Material::Mix(m) => m.pick(hit).perturb_normal(hit),
+            Material::Layered(l) => l.base.perturb_normal(hit),
+            Material::Principled(p) => p.bump.apply(hit),
+            Material::Dielectric(d) => d.bump.apply(hit),
+            Material::Standard(s) => s.bump.apply(hit),
+            Material::CarPaint(_)
+            | Material::ShadowCatcher(_)
+            | Material::Subsurface(_)
+            | Material::Named(_)
+            | Material::Emitter(_)
+            | Material::Custom(_) => {}
+        }
+    }
+
+    /// Converts emission given in physical units to a plain scale, for an
+    /// object whose surface has the given area.
+    pub fn resolve_emission(&mut self, area: Option<f32>) -> Result<(), String> {
+        match self {
+            Material::Mix(m) => {
+                for material in m.mix.iter_mut() {
+                    material.resolve_emission(area)?;
+                }
+            }
+            Material::Layered(l) => l.base.resolve_emission(area)?,
+            Material::Principled(p) => {
+                p.emission_strength = p.emission_unit.to_scale(p.emission_strength, area)?;
+                p.emission_unit = EmissionUnit::Scale;
+            }
+            Material::Standard(s) => {
+                s.emission_strength = s.emission_unit.to_scale(s.emission_strength, area)?;
+                s.emission_unit = EmissionUnit::Scale;
+            }
+            Material::Emitter(e) => e.resolve_emission(area)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Representative color of the surface at `hit`, for flat shading.
+    pub fn base_color(&self, hit: &RayHit) -> Vec3 {
+        match self {
+            Material::Mix(m) => m.pick(hit).base_color(hit),
+            Material::Layered(l) => l.base.base_color(hit),
+            Material::CarPaint(c) => c.paint_color(hit),
+            Material::Subsurface(s) => s.albedo.sample_at(hit),
+            Material::Principled(p) => p.base_color.sample_at(hit),
+            Material::Dielectric(d) => d.tint.sample_at(hit),
+            Material::Standard(s) => s.albedo.sample_at(hit),
+            Material::Emitter(e) => e.emission.sample_at(hit),
+            Material::ShadowCatcher(_) | Material::Named(_) | Material::Custom(_) => {
+                glm::vec3(0.8, 0.8, 0.8)
+            }
+        }
+    }
+
+    /// Whether the material may emit light anywhere, so that objects made
+    /// of it are sampled as lights.
+    pub fn emits(&self) -> bool {
+        match self {
+            Material::Mix(m) => m.mix.iter().any(Material::emits),
+            Material::Layered(l) => l.base.emits(),
+            Material::Principled(p) => p.emission_strength > 0.0 && !p.emission.is_black(),
+            Material::Standard(s) => s.emission_strength > 0.0 && !s.emission.is_black(),
+            Material::Emitter(e) => e.emission_strength > 0.0 && !e.emission.is_black(),
+            _ => false,
+        }
+    }
+
+    /// Rough emitted radiance of the surface as a whole, taken from the
+    /// middle of the emission texture, for weighing lights against each
+    /// other.
+    pub fn emission_estimate(&self) -> Vec3 {
+        let middle = glm::vec2(0.5, 0.5);
+        match self {
+            Material::Mix(m) => m
+                .mix
+                .iter()
+                .map(Material::emission_estimate)
+                .fold(glm::zero(), |a, b| glm::max2(&a, &b)),
+            Material::Layered(l) => l.base.emission_estimate(),
+            Material::Principled(p) => p.emission.sample(middle) * p.emission_strength,
+            Material::Standard(s) => s.emission.sample(middle) * s.emission_strength,
+            Material::Emitter(e) => e.emission.sample(middle) * e.emission_strength,
+            _ => glm::zero(),
+        }
+    }
+
+    /// Light group the emission belongs to, if named. Mixes take the first
+    /// one named among their materials.
+    pub fn light_group(&self) -> Option<&str> {
+        match self {
+            Material::Mix(m) => m.mix.iter().find_map(Material::light_group),
+            Material::Layered(l) => l.base.light_group(),
+            Material::Principled(p) => p.light_group.as_deref(),
+            Material::Standard(s) => s.light_group.as_deref(),
+            Material::Emitter(e) => e.light_group.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether back faces emit as well as front ones. Mixes must be
+    /// resolved with `select` first.
+    pub fn emits_back(&self) -> bool {
+        match self {
+            Material::Emitter(e) => e.two_sided,
+            _ => true,
+        }
+    }
+
+    /// Fraction of the emission at `hit` leaving along `dir`, which is less
+    /// than all of it for lights shining through a gobo. Mixes must be
+    /// resolved with `select` first.
+    pub fn emission_filter(&self, hit: &RayHit, dir: &Vec3) -> Vec3 {
+        match self {
+            Material::Emitter(Emitter {
+                gobo: Some(gobo), ..
+            }) => gobo.transmittance(hit, dir),
+            _ => glm::vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn emitted(&self, hit: &RayHit) -> Vec3 {
+        match self {
+            Material::Mix(m) => m.pick(hit).emitted(hit),
+            Material::Layered(l) => l.base.emitted(hit),
+            Material::CarPaint(_) => glm::zero(),
+            Material::ShadowCatcher(_) => glm::zero(),
+            Material::Subsurface(_) => glm::zero(),
+            Material::Principled(p) => p.emitted(hit),
+            Material::Dielectric(_) => glm::zero(),
+            Material::Standard(s) => s.emitted(hit),
+            Material::Named(_) => glm::zero(),
+            Material::Emitter(e) => e.emitted(hit),
+            Material::Custom(b) => b.emitted(hit),
+        }
+    }
 }
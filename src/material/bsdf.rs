@@ -0,0 +1,81 @@
+use nalgebra_glm as glm;
+
+use super::{Dielectric, Principled, Scatter, Standard};
+use crate::geom::RayHit;
+use crate::ray::Ray;
+use crate::Vec3;
+
+/// Scattering model of a surface. Directions are unit vectors pointing away
+/// from the hit: `wo` towards the viewer and `wi` towards incoming light.
+///
+/// Custom models can be used through `Material::Custom` without touching the
+/// built-in materials.
+pub trait Bsdf: Send + Sync {
+    /// Picks the direction a path continues in after arriving along `r`,
+    /// weighted by the BSDF times the cosine over its density.
+    fn sample(&self, r: &Ray, hit: &RayHit) -> Option<Scatter>;
+
+    /// Value of the BSDF, without the cosine term.
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> Vec3;
+
+    /// Density with respect to solid angle of `sample` choosing `wi`.
+    /// Perfectly specular models return 0, as their directions cannot be
+    /// hit by other sampling strategies.
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> f32;
+
+    fn emitted(&self, _hit: &RayHit) -> Vec3 {
+        glm::zero()
+    }
+}
+
+impl Bsdf for Standard {
+    fn sample(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        self.scatter(r, hit)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> Vec3 {
+        Standard::eval(self, wo, wi, hit).0
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> f32 {
+        Standard::eval(self, wo, wi, hit).1
+    }
+
+    fn emitted(&self, hit: &RayHit) -> Vec3 {
+        Standard::emitted(self, hit)
+    }
+}
+
+impl Bsdf for Principled {
+    fn sample(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        self.scatter(r, hit)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> Vec3 {
+        self.eval_reflection(wo, wi, hit).0
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> f32 {
+        self.eval_reflection(wo, wi, hit).1
+    }
+
+    fn emitted(&self, hit: &RayHit) -> Vec3 {
+        Principled::emitted(self, hit)
+    }
+}
+
+/// Smooth dielectrics are perfectly specular. Rough ones are only sampled,
+/// so they are likewise never evaluated.
+impl Bsdf for Dielectric {
+    fn sample(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        self.scatter(r, hit)
+    }
+
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _hit: &RayHit) -> Vec3 {
+        glm::zero()
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _hit: &RayHit) -> f32 {
+        0.0
+    }
+}
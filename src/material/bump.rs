@@ -0,0 +1,59 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::microfacet;
+use crate::geom::RayHit;
+use crate::texture::{GrayScaleTexture, NormalMap, Texture as _};
+
+/// Detail that bends the shading normal without changing the geometry,
+/// from a tangent-space normal map and/or a grayscale height map.
+#[derive(Deserialize, Clone)]
+pub struct Bump {
+    #[serde(default)]
+    pub normal_map: Option<NormalMap>,
+    #[serde(default)]
+    pub bump_map: Option<GrayScaleTexture>,
+    /// Tilt caused by the height map, where 1 tilts the normal by 45 degrees
+    /// across a black to white step between neighbouring texels.
+    #[serde(default = "default_bump_strength")]
+    pub bump_strength: f32,
+}
+
+fn default_bump_strength() -> f32 {
+    1.0
+}
+
+impl Default for Bump {
+    fn default() -> Self {
+        Bump {
+            normal_map: None,
+            bump_map: None,
+            bump_strength: default_bump_strength(),
+        }
+    }
+}
+
+impl Bump {
+    pub fn apply(&self, hit: &mut RayHit) {
+        if self.normal_map.is_none() && self.bump_map.is_none() {
+            return;
+        }
+        let (mut t, _) = microfacet::frame(&hit.normal, hit.tangent);
+        if let Some(map) = &self.normal_map {
+            hit.normal = map.perturb(&hit.normal, &t, hit.uv);
+            t = microfacet::frame(&hit.normal, Some(t)).0;
+        }
+        if let Some(map) = &self.bump_map {
+            // Forward differences of the height one texel apart
+            let texel = glm::vec2(1.0, 1.0).component_div(&map.dimensions());
+            let h = map.sample(hit.uv);
+            let du = map.sample(hit.uv + glm::vec2(texel.x, 0.0)) - h;
+            let dv = map.sample(hit.uv + glm::vec2(0.0, texel.y)) - h;
+            let b = hit.normal.cross(&t);
+            let n = hit.normal - (t * du + b * dv) * self.bump_strength;
+            hit.normal = glm::normalize(&n);
+            t = microfacet::frame(&hit.normal, Some(t)).0;
+        }
+        hit.tangent = Some(t);
+    }
+}
@@ -0,0 +1,141 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Deserializer};
+
+use super::{microfacet, Scatter, Standard};
+use crate::geom::RayHit;
+use crate::ray::Ray;
+use crate::texture::{lattice_hash, ColorTexture, GrayScaleTexture, Texture as _};
+use crate::Vec3;
+
+/// Automotive paint: a pigmented base sprinkled with tilted metallic flakes,
+/// under a clear lacquer. Flakes are scattered on a world-space grid, so
+/// they stay put under any UV layout.
+#[derive(Clone)]
+pub struct CarPaint {
+    /// Lacquer over the base; only its clearcoat lobe is used.
+    coat: Standard,
+    paint: Standard,
+    flake: Standard,
+    flake_density: f32,
+    flake_size: f32,
+    flake_tilt: f32,
+}
+
+#[derive(Deserialize)]
+struct CarPaintDesc {
+    paint: ColorTexture,
+    flake_color: Vec3,
+    /// Fraction of the surface covered by flakes.
+    #[serde(default = "default_flake_density")]
+    flake_density: f32,
+    /// Width of a flake in scene units.
+    #[serde(default = "default_flake_size")]
+    flake_size: f32,
+    /// Sine of the largest angle flakes are tilted by.
+    #[serde(default = "default_flake_tilt")]
+    flake_tilt: f32,
+    #[serde(default = "default_roughness")]
+    roughness: f32,
+    #[serde(default = "default_clearcoat")]
+    clearcoat: f32,
+    #[serde(default = "default_clearcoat_roughness")]
+    clearcoat_roughness: f32,
+}
+
+fn default_flake_density() -> f32 {
+    0.4
+}
+
+fn default_flake_size() -> f32 {
+    0.002
+}
+
+fn default_flake_tilt() -> f32 {
+    0.3
+}
+
+fn default_roughness() -> f32 {
+    0.4
+}
+
+fn default_clearcoat() -> f32 {
+    1.0
+}
+
+fn default_clearcoat_roughness() -> f32 {
+    0.03
+}
+
+impl<'de> Deserialize<'de> for CarPaint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = CarPaintDesc::deserialize(deserializer)?;
+        let coat = Standard {
+            clearcoat: desc.clearcoat,
+            clearcoat_roughness: desc.clearcoat_roughness,
+            ..Standard::default()
+        };
+        let paint = Standard {
+            albedo: desc.paint,
+            metalness: GrayScaleTexture::Solid(0.0),
+            roughness: GrayScaleTexture::Solid(desc.roughness),
+            ..Standard::default()
+        };
+        let flake = Standard {
+            albedo: ColorTexture::solid(desc.flake_color),
+            metalness: GrayScaleTexture::Solid(1.0),
+            roughness: GrayScaleTexture::Solid(0.15),
+            ..Standard::default()
+        };
+        Ok(CarPaint {
+            coat,
+            paint,
+            flake,
+            flake_density: desc.flake_density,
+            flake_size: desc.flake_size,
+            flake_tilt: desc.flake_tilt,
+        })
+    }
+}
+
+fn unit(hash: u32) -> f32 {
+    (hash >> 8) as f32 / (1 << 24) as f32
+}
+
+impl CarPaint {
+    /// Color of the paint below the flakes.
+    pub fn paint_color(&self, hit: &RayHit) -> Vec3 {
+        self.paint.albedo.sample_at(hit)
+    }
+
+    /// Normal of the flake covering `hit`, if any.
+    fn flake_normal(&self, hit: &RayHit) -> Option<Vec3> {
+        let cell = (hit.point / self.flake_size).map(f32::floor);
+        let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        if unit(lattice_hash(x, y, z, 0)) >= self.flake_density {
+            return None;
+        }
+        let tilt = self.flake_tilt * unit(lattice_hash(x, y, z, 1)).sqrt();
+        let phi = glm::two_pi::<f32>() * unit(lattice_hash(x, y, z, 2));
+        let (t, b) = microfacet::frame(&hit.normal, hit.tangent);
+        let n = hit.normal + (t * phi.cos() + b * phi.sin()) * tilt;
+        Some(glm::normalize(&n))
+    }
+
+    pub fn scatter(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        if let Some(coat) = self.coat.scatter_clearcoat(r, hit) {
+            return coat;
+        }
+        match self.flake_normal(hit) {
+            Some(normal) => {
+                let flake_hit = RayHit { normal, ..*hit };
+                let scatter = self.flake.scatter(r, &flake_hit)?;
+                // Flakes tilted away may send light below the real surface
+                if glm::dot(&scatter.ray.direction, &hit.normal) <= 0.0 {
+                    return None;
+                }
+                Some(scatter)
+            }
+            None => self.paint.scatter(r, hit),
+        }
+    }
+}
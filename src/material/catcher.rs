@@ -0,0 +1,128 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use crate::geom::{LightSample, RayHit, Scene, Sphere, Traceable as _};
+use crate::ray::Ray;
+use crate::sampler;
+use crate::texture::Texture as _;
+use crate::vec::{luminance, sample_cosine, Vec2, Vec3};
+
+/// Invisible surface that only shows the shadows and reflections objects
+/// cast onto it, for compositing renders over a photographic backplate.
+/// Camera rays keep the alpha of the background behind it, so over a
+/// transparent background only the shadows and reflections are opaque.
+#[derive(Deserialize, Clone)]
+pub struct ShadowCatcher {
+    /// How dark full shadows get, from 0 for none to 1 for black.
+    pub shadow_strength: f32,
+    /// How strongly objects are mirrored in the surface, from 0 for not at
+    /// all. The environment is never, as the backplate shows it already.
+    #[serde(default)]
+    pub reflection: f32,
+    /// Directions probed for occluders at each hit, towards the environment
+    /// and the lights each.
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+}
+
+fn default_samples() -> u32 {
+    4
+}
+
+impl ShadowCatcher {
+    /// Opacity of the shadow at `hit`, the fraction of the light reaching it
+    /// from the environment and the lights that objects block, scaled by
+    /// the shadow strength. Environment directions are cosine distributed
+    /// and weighted by its brightness in them.
+    pub fn shadow(&self, r: &Ray, hit: &RayHit, scene: &Scene) -> f32 {
+        let n = facing(r, hit);
+        let samples = self.samples.max(1);
+        let (mut visible, mut total) = (0.0, 0.0);
+        for _ in 0..samples {
+            let dir = sample_cosine(&n, sampler::next_2d());
+            // The cosine distribution estimates irradiance up to a factor of pi
+            let env = luminance(&scene.environment.sample(Sphere::uv_at_dir(&dir)));
+            let weight = env * glm::pi::<f32>();
+            total += weight;
+            let probe = Ray::new(hit.point, dir).with_time(r.time);
+            if scene.visible(&probe, 0.001, f32::MAX) {
+                visible += weight;
+            }
+
+            let rnd = (sampler::next_1d(), sampler::next_2d());
+            if let Some((dir, dist, weight)) = light_probe(r, hit, &n, scene, rnd) {
+                total += weight;
+                let probe = Ray::new(hit.point, dir).with_time(r.time);
+                if scene.visible(&probe, 0.001, dist) {
+                    visible += weight;
+                }
+            }
+        }
+        let lit = if total > 0.0 { visible / total } else { 1.0 };
+        self.shadow_strength * (1.0 - lit)
+    }
+
+    /// Ray mirroring `r` off the surface, when it reflects objects.
+    pub fn mirror(&self, r: &Ray, hit: &RayHit) -> Option<Ray> {
+        if self.reflection <= 0.0 {
+            return None;
+        }
+        let dir = glm::reflect_vec(&r.direction.normalize(), &facing(r, hit));
+        Some(Ray::new(hit.point, dir).with_time(r.time))
+    }
+}
+
+/// Normal of `hit` on the side `r` arrives from.
+fn facing(r: &Ray, hit: &RayHit) -> Vec3 {
+    if glm::dot(&r.direction, &hit.normal) > 0.0 {
+        -hit.normal
+    } else {
+        hit.normal
+    }
+}
+
+/// Direction and distance towards a light picked by sampling the lights,
+/// with its irradiance at `hit` over the density of picking it. The
+/// environment is left to the cosine distributed probes.
+fn light_probe(
+    r: &Ray,
+    hit: &RayHit,
+    n: &Vec3,
+    scene: &Scene,
+    rnd: (f32, Vec2),
+) -> Option<(Vec3, f32, f32)> {
+    let (i, sample, pick_pdf) = scene.sample_light(&hit.point, rnd)?;
+    match sample {
+        LightSample::Direct {
+            dir,
+            dist,
+            irradiance,
+        } => {
+            let cos = glm::dot(&dir, n);
+            Some((dir, dist, luminance(&irradiance) * cos.max(0.0) / pick_pdf))
+        }
+        LightSample::Surface(sample) => {
+            let dir = glm::normalize(&(sample.point - hit.point));
+            let cos = glm::dot(&dir, n);
+            if cos <= 0.0 {
+                return None;
+            }
+            // The light's emission is found on its own, whatever is in
+            // the way, and occluders are looked for before it
+            let ray = Ray::new(hit.point, dir).with_time(r.time);
+            let found = scene.lights()[i].object()?.trace(&ray, 0.001, f32::MAX)?;
+            let pdf = scene.light_pdf(&hit.point, found.material, &found.hit);
+            let material = found.material.select(&found.hit);
+            let back = glm::dot(&dir, &found.hit.normal) > 0.0;
+            if pdf <= 0.0 || (back && !material.emits_back()) {
+                return None;
+            }
+            let emitted = material
+                .emitted(&found.hit)
+                .component_mul(&material.emission_filter(&found.hit, &-dir));
+            let dist = found.hit.t * (1.0 - 1e-3);
+            Some((dir, dist, luminance(&emitted) * cos / pdf))
+        }
+        LightSample::Environment { .. } => None,
+    }
+}
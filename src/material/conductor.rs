@@ -0,0 +1,118 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use crate::Vec3;
+
+/// Metals with measured optical constants, sampled at the wavelengths of
+/// the red, green and blue channels.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminum,
+    Silver,
+}
+
+/// Complex index of refraction of a metal, given per color channel either
+/// directly or by naming a preset.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(untagged)]
+pub enum Conductor {
+    Preset(Metal),
+    Ior {
+        /// Real part, the index of refraction.
+        eta: Vec3,
+        /// Imaginary part, the extinction coefficient.
+        k: Vec3,
+    },
+}
+
+impl Conductor {
+    /// The real and imaginary parts of the index.
+    pub fn ior(&self) -> (Vec3, Vec3) {
+        match self {
+            Conductor::Preset(Metal::Gold) => (
+                glm::vec3(0.143, 0.374, 1.442),
+                glm::vec3(3.983, 2.386, 1.603),
+            ),
+            Conductor::Preset(Metal::Copper) => (
+                glm::vec3(0.200, 0.924, 1.102),
+                glm::vec3(3.912, 2.452, 2.142),
+            ),
+            Conductor::Preset(Metal::Aluminum) => (
+                glm::vec3(1.657, 0.880, 0.521),
+                glm::vec3(9.224, 6.270, 4.837),
+            ),
+            Conductor::Preset(Metal::Silver) => (
+                glm::vec3(0.155, 0.117, 0.138),
+                glm::vec3(4.828, 3.122, 2.147),
+            ),
+            Conductor::Ior { eta, k } => (*eta, *k),
+        }
+    }
+
+    /// Fraction of light reflected per channel, given the cosine of the
+    /// incident angle.
+    pub fn fresnel(&self, cos_i: f32) -> Vec3 {
+        let (eta, k) = self.ior();
+        glm::vec3(
+            fresnel_conductor(cos_i, eta.x, k.x),
+            fresnel_conductor(cos_i, eta.y, k.y),
+            fresnel_conductor(cos_i, eta.z, k.z),
+        )
+    }
+}
+
+/// The exact Fresnel equations for unpolarized light at the surface of a
+/// conductor with index `eta + ik`, coming from air.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i.max(0.0) * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    0.5 * (r_p + r_s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{fresnel_dielectric, FresnelMode};
+
+    #[test]
+    fn normal_incidence_matches_closed_form() {
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert!((fresnel_conductor(1.0, eta, k) - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reflects_everything_at_grazing_angles() {
+        assert!((fresnel_conductor(0.0, 1.657, 9.224) - 1.0).abs() < 1e-5);
+        assert!((fresnel_conductor(-0.5, 0.143, 3.983) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn matches_dielectric_without_extinction() {
+        for i in 0..=10 {
+            let cos = i as f32 / 10.0;
+            let conductor = fresnel_conductor(cos, 1.5, 0.0);
+            let dielectric = fresnel_dielectric(cos, 1.0 / 1.5, FresnelMode::Exact);
+            assert!((conductor - dielectric).abs() < 1e-5, "at {}", cos);
+        }
+    }
+
+    #[test]
+    fn gold_reflects_more_red_than_blue() {
+        let f = Conductor::Preset(Metal::Gold).fresnel(1.0);
+        assert!(f.x > 0.9 && f.x > f.y && f.y > f.z, "{:?}", f);
+    }
+}
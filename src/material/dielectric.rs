@@ -0,0 +1,188 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::Scatter;
+use super::{microfacet, Bump};
+use crate::geom::RayHit;
+use crate::ray::Ray;
+use crate::sampler;
+use crate::spectrum::{Spectrum, RGB_WAVELENGTHS};
+use crate::texture::{ColorTexture, GrayScaleTexture, Texture as _};
+use crate::Vec3;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FresnelMode {
+    /// Schlick's approximation, cheaper but too dark at grazing angles
+    /// for high indices.
+    Schlick,
+    /// The exact Fresnel equations for unpolarized light.
+    #[default]
+    Exact,
+}
+
+/// Index of refraction, either constant or varying with wavelength.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Ior {
+    Constant(f32),
+    /// Indices per wavelength, dispersing refracted light into colors.
+    Spectrum(Spectrum),
+}
+
+impl Ior {
+    /// Index for a path, along with the weight of its color channels.
+    /// Dispersive indices trace a single channel picked at random.
+    fn sample(&self) -> (f32, Vec3) {
+        match self {
+            Ior::Constant(ior) => (*ior, glm::vec3(1.0, 1.0, 1.0)),
+            Ior::Spectrum(spectrum) => {
+                let channel = ((sampler::next_1d() * 3.0) as usize).min(2);
+                let mut weight: Vec3 = glm::zero();
+                weight[channel] = 3.0;
+                (spectrum.eval(RGB_WAVELENGTHS[channel]), weight)
+            }
+        }
+    }
+}
+
+/// Glass-like material that reflects or refracts according to the Fresnel
+/// equations. Rough dielectrics scatter through GGX microfacets, following
+/// Walter et al., "Microfacet Models for Refraction through Rough Surfaces".
+/// Normals are taken to point out of the object.
+#[derive(Deserialize, Clone)]
+pub struct Dielectric {
+    pub ior: Ior,
+    #[serde(default = "default_roughness")]
+    pub roughness: GrayScaleTexture,
+    /// Color applied to every reflection and refraction.
+    #[serde(default = "default_tint")]
+    pub tint: ColorTexture,
+    /// Fraction of light absorbed per unit of distance traveled inside,
+    /// per color channel, so that thicker parts appear darker.
+    #[serde(default = "glm::zero")]
+    pub absorption: Vec3,
+    #[serde(default)]
+    pub fresnel: FresnelMode,
+    #[serde(flatten)]
+    pub bump: Bump,
+}
+
+fn default_tint() -> ColorTexture {
+    ColorTexture::solid(glm::vec3(1.0, 1.0, 1.0))
+}
+
+fn default_roughness() -> GrayScaleTexture {
+    GrayScaleTexture::Solid(0.0)
+}
+
+/// Below this GGX alpha surfaces are treated as perfectly smooth.
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+/// Fraction of light reflected at an interface, given the cosine of the
+/// incident angle and the ratio of indices `eta` = n_incident / n_transmitted.
+/// Total internal reflection is handled by returning 1.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32, mode: FresnelMode) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    match mode {
+        FresnelMode::Schlick => {
+            let r0 = ((1.0 - eta) / (1.0 + eta)).powi(2);
+            // Leaving a denser medium, the transmitted angle must be used
+            let cos = if eta > 1.0 { cos_t } else { cos_i };
+            r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+        }
+        FresnelMode::Exact => {
+            let r_par = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+            let r_perp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+            0.5 * (r_par * r_par + r_perp * r_perp)
+        }
+    }
+}
+
+/// Direction of `dir` refracted through a surface with normal `n` facing
+/// against it, if there is no total internal reflection.
+fn refract(dir: &Vec3, n: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = -glm::dot(dir, n);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    Some(dir * eta + n * (eta * cos_i - (1.0 - sin2_t).sqrt()))
+}
+
+/// Reflects or refracts `dir` at an interface with outward normal `n`,
+/// picking either with probability equal to its Fresnel weight. Returns the
+/// new direction and its weight, which is 1 on smooth surfaces.
+pub(crate) fn scatter_interface(
+    dir: &Vec3,
+    n: &Vec3,
+    ior: f32,
+    alpha: f32,
+    fresnel: FresnelMode,
+) -> Option<(Vec3, f32)> {
+    let dir = glm::normalize(dir);
+    let entering = glm::dot(&dir, n) < 0.0;
+    let (n, eta) = if entering { (*n, 1.0 / ior) } else { (-n, ior) };
+
+    // Smooth surfaces reflect and refract about the shading normal
+    let m = if alpha < SMOOTH_ALPHA {
+        n
+    } else {
+        microfacet::sample_normal(&n, alpha, sampler::next_2d())
+    };
+    let cos_i = -glm::dot(&dir, &m);
+    if cos_i <= 0.0 {
+        return None;
+    }
+    let reflectance = fresnel_dielectric(cos_i, eta, fresnel);
+    let reflect = sampler::next_1d() < reflectance;
+    let direction = if reflect {
+        glm::reflect_vec(&dir, &m)
+    } else {
+        refract(&dir, &m, eta)?
+    };
+
+    if alpha < SMOOTH_ALPHA {
+        return Some((direction, 1.0));
+    }
+    // Reflections must stay on the incident side, refractions cross over
+    if reflect != (glm::dot(&direction, &n) > 0.0) {
+        return None;
+    }
+    let wi = -dir;
+    let g =
+        microfacet::smith_g1(&wi, &m, &n, alpha) * microfacet::smith_g1(&direction, &m, &n, alpha);
+    let weight = glm::dot(&wi, &m).abs() * g / (glm::dot(&wi, &n).abs() * glm::dot(&m, &n).abs());
+    Some((direction, weight))
+}
+
+impl Dielectric {
+    /// Picks reflection or refraction with probability equal to their
+    /// Fresnel weights, so the attenuation of smooth surfaces is just the tint.
+    /// Rays reaching the surface from inside are first attenuated following
+    /// the Beer-Lambert law over the distance they traveled.
+    pub fn scatter(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        let roughness = self.roughness.sample_at(hit);
+        let (ior, channels) = self.ior.sample();
+        let (direction, weight) = scatter_interface(
+            &r.direction,
+            &hit.normal,
+            ior,
+            roughness * roughness,
+            self.fresnel,
+        )?;
+        let mut attenuation = self.tint.sample_at(hit).component_mul(&channels) * weight;
+        if glm::dot(&r.direction, &hit.normal) > 0.0 {
+            let distance = hit.t * glm::length(&r.direction);
+            attenuation = attenuation.component_mul(&(-self.absorption * distance).map(f32::exp));
+        }
+        Some(Scatter {
+            ray: Ray::new(hit.point, direction),
+            attenuation,
+        })
+    }
+}
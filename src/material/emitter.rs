@@ -0,0 +1,90 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::EmissionUnit;
+use crate::geom::RayHit;
+use crate::texture::{ColorTexture, Texture as _};
+use crate::Vec3;
+
+/// Surface of a light, which emits without reflecting anything. Emits from
+/// the front face alone unless `two_sided`.
+#[derive(Deserialize, Clone)]
+pub struct Emitter {
+    #[serde(default = "default_emission")]
+    pub emission: ColorTexture,
+    #[serde(default = "default_emission_strength")]
+    pub emission_strength: f32,
+    #[serde(default)]
+    pub emission_unit: EmissionUnit,
+    /// Light group of the emission, see `Scene::light_groups`.
+    #[serde(default)]
+    pub light_group: Option<String>,
+    #[serde(default)]
+    pub two_sided: bool,
+    /// Pattern the light shines through, set by quad lights.
+    #[serde(skip)]
+    pub gobo: Option<Gobo>,
+}
+
+/// Image a quad light shines through, as large as the light and hanging
+/// parallel to it `distance` in front, which casts patterns such as window
+/// frames. Dark parts of the image block the light.
+#[derive(Deserialize, Clone)]
+pub struct Gobo {
+    pub texture: ColorTexture,
+    pub distance: f32,
+    /// Edges of the light, as the image lines up with them.
+    #[serde(skip, default = "glm::zero")]
+    pub edge1: Vec3,
+    #[serde(skip, default = "glm::zero")]
+    pub edge2: Vec3,
+}
+
+impl Gobo {
+    /// Fraction of the light leaving `hit` along `dir` that gets through,
+    /// found where that direction meets the image.
+    pub fn transmittance(&self, hit: &RayHit, dir: &Vec3) -> Vec3 {
+        let n = self.edge1.cross(&self.edge2);
+        let cos = glm::dot(&n, dir).abs();
+        if cos <= 0.0 {
+            return glm::zero();
+        }
+        // Offset from the point below the hit on the image's plane, in the
+        // coordinates of the edges
+        let offset = dir * (self.distance * glm::length(&n) / cos);
+        let w = n / glm::dot(&n, &n);
+        let uv = hit.uv
+            + glm::vec2(
+                glm::dot(&w, &offset.cross(&self.edge2)),
+                glm::dot(&w, &self.edge1.cross(&offset)),
+            );
+        if uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 {
+            return glm::zero();
+        }
+        self.texture.sample(uv)
+    }
+}
+
+fn default_emission() -> ColorTexture {
+    ColorTexture::solid(Vec3::new(1.0, 1.0, 1.0))
+}
+
+fn default_emission_strength() -> f32 {
+    1.0
+}
+
+impl Emitter {
+    /// Converts emission in physical units to a plain scale, with flux
+    /// split between both faces when two-sided.
+    pub fn resolve_emission(&mut self, area: Option<f32>) -> Result<(), String> {
+        let faces = if self.two_sided { 2.0 } else { 1.0 };
+        let area = area.map(|area| area * faces);
+        self.emission_strength = self.emission_unit.to_scale(self.emission_strength, area)?;
+        self.emission_unit = EmissionUnit::Scale;
+        Ok(())
+    }
+
+    pub fn emitted(&self, hit: &RayHit) -> Vec3 {
+        self.emission.sample_at(hit) * self.emission_strength
+    }
+}
@@ -0,0 +1,128 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::{fresnel_dielectric, microfacet, FresnelMode, Material, Scatter};
+use crate::geom::{RayHit, Scene};
+use crate::ray::Ray;
+use crate::sampler;
+use crate::texture::{ColorTexture, GrayScaleTexture, Texture as _};
+use crate::vec::{sample_cosine, Vec3};
+
+/// Clear layer such as varnish or lacquer, reflecting by its Fresnel
+/// weight and tinting what passes through.
+#[derive(Deserialize, Clone)]
+pub struct Coating {
+    pub ior: f32,
+    #[serde(default)]
+    pub roughness: f32,
+    /// Color of light after passing down through the layer and back up.
+    #[serde(default = "default_white")]
+    pub tint: ColorTexture,
+}
+
+/// Partial layer of diffuse particles, such as dust or powder.
+#[derive(Deserialize, Clone)]
+pub struct Dust {
+    pub color: ColorTexture,
+    /// Fraction of the surface covered, as a constant or mask.
+    pub coverage: GrayScaleTexture,
+}
+
+fn default_white() -> ColorTexture {
+    ColorTexture::solid(glm::vec3(1.0, 1.0, 1.0))
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Layer {
+    Coating(Coating),
+    Dust(Dust),
+}
+
+/// A stack of layers over a base material, listed from the top down. Paths
+/// scatter off each layer in turn with the probability of it reflecting,
+/// and continue to the layers below with the remaining energy.
+#[derive(Deserialize, Clone)]
+pub struct Layered {
+    pub layers: Vec<Layer>,
+    pub base: Material,
+}
+
+impl Coating {
+    /// Reflects off the coating with probability equal to its Fresnel
+    /// weight, or returns `None` for light passing through.
+    fn scatter(&self, wo: &Vec3, hit: &RayHit) -> Option<Option<Scatter>> {
+        let n = hit.normal;
+        let eta = 1.0 / self.ior;
+        let fresnel = |cos: f32| fresnel_dielectric(cos.max(0.0), eta, FresnelMode::Exact);
+        let reflectance = fresnel(glm::dot(wo, &n));
+        if sampler::next_1d() >= reflectance {
+            return None;
+        }
+        let alpha = self.roughness * self.roughness;
+        if alpha < 1e-3 {
+            return Some(Some(Scatter {
+                ray: Ray::new(hit.point, glm::reflect_vec(&-wo, &n)),
+                attenuation: glm::vec3(1.0, 1.0, 1.0),
+            }));
+        }
+        let m = microfacet::sample_normal(&n, alpha, sampler::next_2d());
+        let wi = glm::reflect_vec(&-wo, &m);
+        if glm::dot(&wi, &n) <= 0.0 || glm::dot(wo, &m) <= 0.0 {
+            return Some(None);
+        }
+        let g = microfacet::smith_g1(wo, &m, &n, alpha) * microfacet::smith_g1(&wi, &m, &n, alpha);
+        let weight = fresnel(glm::dot(wo, &m)) / reflectance * glm::dot(wo, &m) * g
+            / (glm::dot(wo, &n) * glm::dot(&m, &n));
+        Some(Some(Scatter {
+            ray: Ray::new(hit.point, wi),
+            attenuation: glm::vec3(weight, weight, weight),
+        }))
+    }
+}
+
+impl Layered {
+    pub fn scatter(&self, r: &Ray, hit: &RayHit, scene: &Scene) -> Option<Scatter> {
+        let wo = -glm::normalize(&r.direction);
+        if glm::dot(&wo, &hit.normal) <= 0.0 {
+            return self.base.scatter(r, hit, scene);
+        }
+        let mut transmitted = glm::vec3(1.0, 1.0, 1.0);
+        for layer in &self.layers {
+            let scattered = match layer {
+                Layer::Coating(coating) => {
+                    let scattered = coating.scatter(&wo, hit);
+                    if scattered.is_none() {
+                        transmitted = transmitted.component_mul(&coating.tint.sample_at(hit));
+                    }
+                    scattered
+                }
+                Layer::Dust(dust) => {
+                    if sampler::next_1d() < dust.coverage.sample_at(hit) {
+                        // Cosine sampling cancels the Lambertian lobe
+                        Some(Some(Scatter {
+                            ray: Ray::new(
+                                hit.point,
+                                sample_cosine(&hit.normal, sampler::next_2d()),
+                            ),
+                            attenuation: dust.color.sample_at(hit),
+                        }))
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(scattered) = scattered {
+                return scattered.map(|s| Scatter {
+                    attenuation: s.attenuation.component_mul(&transmitted),
+                    ..s
+                });
+            }
+        }
+        let base = self.base.scatter(r, hit, scene)?;
+        Some(Scatter {
+            attenuation: base.attenuation.component_mul(&transmitted),
+            ..base
+        })
+    }
+}
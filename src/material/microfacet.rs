@@ -0,0 +1,92 @@
+use nalgebra_glm as glm;
+
+use crate::vec::{self, Vec2, Vec3};
+
+/// Samples a microfacet normal around `n` from the GGX distribution of
+/// normals weighted by their cosine to `n`.
+pub fn sample_normal(n: &Vec3, alpha: f32, rnd: Vec2) -> Vec3 {
+    let tan2 = alpha * alpha * rnd.x / (1.0 - rnd.x);
+    let cos = 1.0 / (1.0 + tan2).sqrt();
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = glm::two_pi::<f32>() * rnd.y;
+    let (u, v) = vec::orthonormal_basis(n);
+    glm::normalize(&(u * (sin * phi.cos()) + v * (sin * phi.sin()) + n * cos))
+}
+
+/// Smith's masking term for GGX, for direction `v` seen past microfacet `m`.
+pub fn smith_g1(v: &Vec3, m: &Vec3, n: &Vec3, alpha: f32) -> f32 {
+    let cos_n = glm::dot(v, n);
+    if glm::dot(v, m) * cos_n <= 0.0 {
+        return 0.0;
+    }
+    let cos2 = cos_n * cos_n;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+/// Shading frame of tangent, bitangent and normal. Without a tangent from
+/// the geometry an arbitrary one is picked.
+pub fn frame(n: &Vec3, tangent: Option<Vec3>) -> (Vec3, Vec3) {
+    let t = tangent
+        .map(|t| t - n * glm::dot(n, &t))
+        .filter(|t| glm::length(t) > 1e-6)
+        .map(|t| glm::normalize(&t))
+        .unwrap_or_else(|| vec::orthonormal_basis(n).0);
+    (t, n.cross(&t))
+}
+
+/// Anisotropic GGX, with roughness `alpha.x` along the tangent `t` and
+/// `alpha.y` along the bitangent `b`.
+pub struct Anisotropic<'a> {
+    pub n: &'a Vec3,
+    pub t: &'a Vec3,
+    pub b: &'a Vec3,
+    pub alpha: Vec2,
+}
+
+impl<'a> Anisotropic<'a> {
+    fn local(&self, v: &Vec3) -> Vec3 {
+        glm::vec3(
+            glm::dot(v, self.t),
+            glm::dot(v, self.b),
+            glm::dot(v, self.n),
+        )
+    }
+
+    pub fn distribution(&self, m: &Vec3) -> f32 {
+        let m = self.local(m);
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = (self.alpha.x, self.alpha.y);
+        let d = (m.x / ax).powi(2) + (m.y / ay).powi(2) + m.z * m.z;
+        1.0 / (glm::pi::<f32>() * ax * ay * d * d)
+    }
+
+    fn lambda(&self, v: &Vec3) -> f32 {
+        let v = self.local(v);
+        let tan2 = ((self.alpha.x * v.x).powi(2) + (self.alpha.y * v.y).powi(2)) / (v.z * v.z);
+        0.5 * (-1.0 + (1.0 + tan2).sqrt())
+    }
+
+    /// Height-correlated Smith masking and shadowing.
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal with density `distribution(m) * cos(m)`.
+    pub fn sample_normal(&self, rnd: Vec2) -> Vec3 {
+        let (ax, ay) = (self.alpha.x, self.alpha.y);
+        let mut phi =
+            (ay / ax * (glm::two_pi::<f32>() * rnd.y + glm::half_pi::<f32>()).tan()).atan();
+        if rnd.y > 0.5 {
+            phi += glm::pi::<f32>();
+        }
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let a2 = 1.0 / ((cos_phi / ax).powi(2) + (sin_phi / ay).powi(2));
+        let tan2 = a2 * rnd.x / (1.0 - rnd.x);
+        let cos = 1.0 / (1.0 + tan2).sqrt();
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        glm::normalize(&(self.t * (sin * cos_phi) + self.b * (sin * sin_phi) + self.n * cos))
+    }
+}
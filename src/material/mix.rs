@@ -0,0 +1,26 @@
+use serde::Deserialize;
+
+use super::Material;
+use crate::geom::RayHit;
+use crate::sampler;
+use crate::texture::{GrayScaleTexture, Texture as _};
+
+/// Blend of two materials, e.g. rust over metal or a decal over paint.
+/// Each hit picks one of them at random, with the second chosen with
+/// probability `factor`, which averages to their weighted sum.
+#[derive(Deserialize, Clone)]
+pub struct Mix {
+    pub mix: [Material; 2],
+    /// Constant or texture mask, 0 giving only the first material.
+    pub factor: GrayScaleTexture,
+}
+
+impl Mix {
+    pub fn pick(&self, hit: &RayHit) -> &Material {
+        if sampler::next_1d() < self.factor.sample_at(hit) {
+            &self.mix[1]
+        } else {
+            &self.mix[0]
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::{microfacet, scatter_interface, Bump, EmissionUnit, FresnelMode, Scatter, Sides};
+use crate::geom::RayHit;
+use crate::ray::Ray;
+use crate::sampler;
+use crate::texture::{ColorTexture, GrayScaleTexture, Texture as _};
+use crate::vec::{self, luminance, sample_cosine, Vec2, Vec3};
+
+/// Burley's principled BSDF as used by Disney and Blender, so parameters
+/// from the "Principled BSDF" node carry over as they are. Scalar parameters
+/// range over [0, 1], like the node's sliders.
+#[derive(Deserialize, Clone)]
+pub struct Principled {
+    pub base_color: ColorTexture,
+    #[serde(default = "default_zero")]
+    pub metallic: GrayScaleTexture,
+    #[serde(default = "default_roughness")]
+    pub roughness: GrayScaleTexture,
+    /// Blends the diffuse lobe towards a flatter one mimicking subsurface
+    /// scattering.
+    #[serde(default)]
+    pub subsurface: f32,
+    /// Dielectric reflectance, where the default 0.5 gives 4%.
+    #[serde(default = "default_specular")]
+    pub specular: f32,
+    /// Tints dielectric reflections towards the base color.
+    #[serde(default)]
+    pub specular_tint: f32,
+    /// Retroreflective grazing lobe for cloth.
+    #[serde(default)]
+    pub sheen: f32,
+    #[serde(default = "default_sheen_tint")]
+    pub sheen_tint: f32,
+    #[serde(default)]
+    pub clearcoat: f32,
+    #[serde(default = "default_clearcoat_roughness")]
+    pub clearcoat_roughness: f32,
+    /// Fraction of the dielectric base that refracts like glass.
+    #[serde(default)]
+    pub transmission: f32,
+    #[serde(default = "default_ior")]
+    pub ior: f32,
+    #[serde(default)]
+    pub emission: ColorTexture,
+    #[serde(default = "default_emission_strength")]
+    pub emission_strength: f32,
+    #[serde(default)]
+    pub emission_unit: EmissionUnit,
+    /// Light group of the emission, see `Scene::light_groups`.
+    #[serde(default)]
+    pub light_group: Option<String>,
+    #[serde(flatten)]
+    pub bump: Bump,
+    /// Back face handling.
+    #[serde(default)]
+    pub sides: Sides,
+    /// Chance of a ray hitting rather than passing straight through, for
+    /// leaves and fences modeled as alpha-mapped cards.
+    #[serde(default = "default_opacity")]
+    pub opacity: GrayScaleTexture,
+}
+
+fn default_zero() -> GrayScaleTexture {
+    GrayScaleTexture::Solid(0.0)
+}
+
+fn default_roughness() -> GrayScaleTexture {
+    GrayScaleTexture::Solid(0.5)
+}
+
+fn default_specular() -> f32 {
+    0.5
+}
+
+fn default_sheen_tint() -> f32 {
+    0.5
+}
+
+fn default_clearcoat_roughness() -> f32 {
+    0.03
+}
+
+fn default_ior() -> f32 {
+    1.45
+}
+
+fn default_opacity() -> GrayScaleTexture {
+    GrayScaleTexture::Solid(1.0)
+}
+
+fn default_emission_strength() -> f32 {
+    1.0
+}
+
+/// Schlick's Fresnel weight, (1 - cos)^5.
+fn schlick_weight(cos: f32) -> f32 {
+    (1.0 - cos).clamp(0.0, 1.0).powi(5)
+}
+
+/// GGX normal distribution for roughness `alpha`, given the cosine of the
+/// microfacet normal.
+fn ggx(cos_m: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = cos_m * cos_m * (a2 - 1.0) + 1.0;
+    a2 / (glm::pi::<f32>() * d * d)
+}
+
+/// Berry's distribution, used for the clearcoat for its longer tail.
+fn gtr1(cos_m: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (glm::pi::<f32>() * a2.ln() * (1.0 + (a2 - 1.0) * cos_m * cos_m))
+}
+
+fn sample_gtr1(n: &Vec3, alpha: f32, rnd: Vec2) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos = ((1.0 - a2.powf(1.0 - rnd.x)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = glm::two_pi::<f32>() * rnd.y;
+    let (u, v) = vec::orthonormal_basis(n);
+    glm::normalize(&(u * (sin * phi.cos()) + v * (sin * phi.sin()) + n * cos))
+}
+
+/// Parameters resolved at a hit point.
+struct Lobes {
+    base: Vec3,
+    tint: Vec3,
+    metallic: f32,
+    alpha: f32,
+    clearcoat_alpha: f32,
+}
+
+impl Principled {
+    fn lobes(&self, hit: &RayHit) -> Lobes {
+        let base = self.base_color.sample_at(hit);
+        let lum = luminance(&base);
+        let tint = if lum > 0.0 {
+            base / lum
+        } else {
+            glm::vec3(1.0, 1.0, 1.0)
+        };
+        let roughness = self.roughness.sample_at(hit);
+        Lobes {
+            base,
+            tint,
+            metallic: self.metallic.sample_at(hit),
+            alpha: f32::max(roughness * roughness, 1e-3),
+            clearcoat_alpha: f32::max(self.clearcoat_roughness * self.clearcoat_roughness, 1e-3),
+        }
+    }
+
+    /// Probabilities of sampling the diffuse, specular and clearcoat lobes.
+    fn lobe_weights(&self, l: &Lobes) -> [f32; 3] {
+        let diffuse = (1.0 - l.metallic) * luminance(&l.base);
+        let specular = 1.0;
+        let clearcoat = 0.25 * self.clearcoat;
+        let total = diffuse + specular + clearcoat;
+        [diffuse / total, specular / total, clearcoat / total]
+    }
+
+    /// Reflected part of the BSDF and its sampling density, for both
+    /// directions on the outside of the surface.
+    fn eval(&self, l: &Lobes, wo: &Vec3, wi: &Vec3, n: &Vec3) -> (Vec3, f32) {
+        let one = glm::vec3(1.0, 1.0, 1.0);
+        let (cos_o, cos_i) = (glm::dot(wo, n), glm::dot(wi, n));
+        let h = glm::normalize(&(wo + wi));
+        let (cos_h, cos_d) = (glm::dot(&h, n), glm::dot(wi, &h));
+        let (fo, fi, fd) = (
+            schlick_weight(cos_o),
+            schlick_weight(cos_i),
+            schlick_weight(cos_d),
+        );
+        let [p_diffuse, p_specular, p_clearcoat] = self.lobe_weights(l);
+
+        // Diffuse with retroreflection at grazing angles, blended towards
+        // the Hanrahan-Krueger subsurface approximation
+        let fd90 = 0.5 + 2.0 * l.alpha.sqrt() * cos_d * cos_d;
+        let diffuse = (1.0 + (fd90 - 1.0) * fi) * (1.0 + (fd90 - 1.0) * fo);
+        let fss90 = l.alpha.sqrt() * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fi) * (1.0 + (fss90 - 1.0) * fo);
+        let ss = 1.25 * (fss * (1.0 / (cos_i + cos_o) - 0.5) + 0.5);
+        let diffuse = l.base * (glm::lerp_scalar(diffuse, ss, self.subsurface) / glm::pi::<f32>());
+        let sheen = glm::lerp(&one, &l.tint, self.sheen_tint) * (self.sheen * fd);
+        let mut f = (diffuse + sheen) * (1.0 - l.metallic);
+        let mut pdf = p_diffuse * cos_i / glm::pi::<f32>();
+
+        // Specular, tinted towards the base color for metals
+        let spec0 = glm::lerp(&one, &l.tint, self.specular_tint) * (0.08 * self.specular);
+        let spec0 = glm::lerp(&spec0, &l.base, l.metallic);
+        let d = ggx(cos_h, l.alpha);
+        let g = microfacet::smith_g1(wo, &h, n, l.alpha) * microfacet::smith_g1(wi, &h, n, l.alpha);
+        let fresnel = glm::lerp(&spec0, &one, fd);
+        f += fresnel * (d * g / (4.0 * cos_o * cos_i));
+        pdf += p_specular * d * cos_h / (4.0 * cos_d);
+
+        // Clearcoat with a fixed 1.5 IOR
+        if self.clearcoat > 0.0 {
+            let d = gtr1(cos_h, l.clearcoat_alpha);
+            let g = microfacet::smith_g1(wo, &h, n, 0.25) * microfacet::smith_g1(wi, &h, n, 0.25);
+            let fresnel = glm::lerp_scalar(0.04, 1.0, fd);
+            let coat = 0.25 * self.clearcoat * d * g * fresnel / (4.0 * cos_o * cos_i);
+            f += glm::vec3(coat, coat, coat);
+            pdf += p_clearcoat * d * cos_h / (4.0 * cos_d);
+        }
+        (f, pdf)
+    }
+
+    /// Reflected part of the BSDF and its density, weighted by the chance
+    /// of not refracting.
+    pub(crate) fn eval_reflection(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> (Vec3, f32) {
+        let n = hit.normal;
+        if glm::dot(wo, &n) <= 0.0 || glm::dot(wi, &n) <= 0.0 {
+            return (glm::zero(), 0.0);
+        }
+        let l = self.lobes(hit);
+        let reflect = 1.0 - self.transmission * (1.0 - l.metallic);
+        let (f, pdf) = self.eval(&l, wo, wi, &n);
+        (f * reflect, pdf * reflect)
+    }
+
+    /// Refracts through the glassy part of the base with probability
+    /// `transmission`, otherwise picks one of the reflective lobes and
+    /// weights the sample by all of them.
+    pub fn scatter(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        let n = hit.normal;
+        let wo = -glm::normalize(&r.direction);
+        let l = self.lobes(hit);
+
+        let transmission = self.transmission * (1.0 - l.metallic);
+        if sampler::next_1d() < transmission || glm::dot(&wo, &n) <= 0.0 {
+            if self.transmission <= 0.0 {
+                return None;
+            }
+            let (direction, weight) =
+                scatter_interface(&r.direction, &n, self.ior, l.alpha, FresnelMode::Exact)?;
+            // Paths through a closed object cross the surface twice
+            let tint = glm::vec3(l.base.x.sqrt(), l.base.y.sqrt(), l.base.z.sqrt());
+            return Some(Scatter {
+                ray: Ray::new(hit.point, direction),
+                attenuation: tint * weight,
+            });
+        }
+
+        let [p_diffuse, p_specular, _] = self.lobe_weights(&l);
+        let rnd = sampler::next_2d();
+        let pick = sampler::next_1d();
+        let wi = if pick < p_diffuse {
+            sample_cosine(&n, rnd)
+        } else {
+            let m = if pick < p_diffuse + p_specular {
+                microfacet::sample_normal(&n, l.alpha, rnd)
+            } else {
+                sample_gtr1(&n, l.clearcoat_alpha, rnd)
+            };
+            glm::reflect_vec(&-wo, &m)
+        };
+        let cos_i = glm::dot(&wi, &n);
+        if cos_i <= 0.0 {
+            return None;
+        }
+        let (f, pdf) = self.eval(&l, &wo, &wi, &n);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Scatter {
+            ray: Ray::new(hit.point, wi),
+            attenuation: f * (cos_i / pdf),
+        })
+    }
+
+    pub fn emitted(&self, hit: &RayHit) -> Vec3 {
+        self.emission.sample_at(hit) * self.emission_strength
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use nalgebra_glm as glm;
+
+use super::{Bump, Conductor, Dielectric, FresnelMode, Ior, Material, Metal, Standard};
+use crate::texture::{ColorTexture, GrayScaleTexture};
+
+/// Named references are followed at most this deep, to catch cycles.
+const MAX_DEPTH: usize = 16;
+
+/// Materials by name, from a scene's library with the built-in presets
+/// as a fallback.
+pub struct Registry {
+    library: HashMap<String, Material>,
+}
+
+impl Registry {
+    pub fn new(library: HashMap<String, Material>) -> Self {
+        Registry { library }
+    }
+
+    /// The material of that name, with any references in it resolved.
+    pub fn get(&self, name: &str) -> Result<Material, String> {
+        self.lookup(name, 0)
+    }
+
+    /// Replaces the references in `material` by the materials they name.
+    pub fn resolve(&self, material: &mut Material) -> Result<(), String> {
+        self.resolve_at(material, 0)
+    }
+
+    fn lookup(&self, name: &str, depth: usize) -> Result<Material, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("material `{}` refers to itself", name));
+        }
+        let mut material = match self.library.get(name) {
+            Some(material) => material.clone(),
+            None => preset(name).ok_or_else(|| format!("unknown material `{}`", name))?,
+        };
+        self.resolve_at(&mut material, depth + 1)?;
+        Ok(material)
+    }
+
+    fn resolve_at(&self, material: &mut Material, depth: usize) -> Result<(), String> {
+        match material {
+            Material::Named(name) => *material = self.lookup(&name.clone(), depth)?,
+            Material::Mix(m) => {
+                for material in m.mix.iter_mut() {
+                    self.resolve_at(material, depth)?;
+                }
+            }
+            Material::Layered(l) => self.resolve_at(&mut l.base, depth)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Built-in materials that every scene can refer to by name.
+pub fn preset(name: &str) -> Option<Material> {
+    let glass = |ior| {
+        Material::Dielectric(Dielectric {
+            ior: Ior::Constant(ior),
+            roughness: GrayScaleTexture::Solid(0.0),
+            tint: ColorTexture::solid(glm::vec3(1.0, 1.0, 1.0)),
+            absorption: glm::zero(),
+            fresnel: FresnelMode::default(),
+            bump: Bump::default(),
+        })
+    };
+    let metal = |metal| {
+        Material::Standard(Standard {
+            roughness: GrayScaleTexture::Solid(0.2),
+            conductor: Some(Conductor::Preset(metal)),
+            ..Standard::default()
+        })
+    };
+    let plastic = |gray: f32, roughness| {
+        Material::Standard(Standard {
+            albedo: ColorTexture::solid(glm::vec3(gray, gray, gray)),
+            roughness: GrayScaleTexture::Solid(roughness),
+            ..Standard::default()
+        })
+    };
+    Some(match name {
+        "glass" => glass(1.5),
+        "water" => glass(1.33),
+        "diamond" => glass(2.42),
+        "gold" => metal(Metal::Gold),
+        "copper" => metal(Metal::Copper),
+        "aluminum" => metal(Metal::Aluminum),
+        "silver" => metal(Metal::Silver),
+        "plastic" => plastic(0.8, 0.3),
+        "rubber" => plastic(0.05, 0.8),
+        _ => return None,
+    })
+}
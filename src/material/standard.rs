@@ -0,0 +1,399 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::{
+    fresnel_dielectric, microfacet, Bump, Conductor, EmissionUnit, FresnelMode, Scatter, Sides,
+};
+use crate::geom::RayHit;
+use crate::ray::Ray;
+use crate::sampler;
+use crate::spectrum::RGB_WAVELENGTHS;
+use crate::texture::{ColorTexture, GrayScaleTexture, Texture as _};
+use crate::vec::{luminance, sample_cosine, Vec2, Vec3};
+
+/// Model used for the diffuse base. Oren-Nayar keeps rough surfaces such
+/// as clay and concrete from looking flat.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffuseModel {
+    #[default]
+    Lambert,
+    OrenNayar,
+}
+
+/// Metalness/roughness material with a GGX specular lobe over a diffuse
+/// base.
+#[derive(Deserialize, Clone)]
+pub struct Standard {
+    pub albedo: ColorTexture,
+    pub metalness: GrayScaleTexture,
+    pub roughness: GrayScaleTexture,
+    /// Roughness across the surface tangent. When given, `roughness` only
+    /// applies along the tangent and highlights stretch as on brushed metal.
+    #[serde(default)]
+    pub roughness_v: Option<GrayScaleTexture>,
+    /// Complex index of refraction of a metal, for its tint at grazing
+    /// angles. When given, `albedo` and `metalness` are ignored.
+    #[serde(default)]
+    pub conductor: Option<Conductor>,
+
+    #[serde(default)]
+    pub diffuse: DiffuseModel,
+
+    /// Light given off by the surface, e.g. from a texture of a screen or
+    /// sign, scaled by `emission_strength` in units of `emission_unit`.
+    #[serde(default)]
+    pub emission: ColorTexture,
+    #[serde(default = "default_emission_strength")]
+    pub emission_strength: f32,
+    #[serde(default)]
+    pub emission_unit: EmissionUnit,
+    /// Light group of the emission, see `Scene::light_groups`.
+    #[serde(default)]
+    pub light_group: Option<String>,
+    #[serde(flatten)]
+    pub bump: Bump,
+    /// Back face handling.
+    #[serde(default)]
+    pub sides: Sides,
+    /// Chance of a ray hitting rather than passing straight through, for
+    /// leaves and fences modeled as alpha-mapped cards.
+    #[serde(default = "default_opacity")]
+    pub opacity: GrayScaleTexture,
+
+    /// Weight of a clear lacquer layer on top, as on car paint.
+    #[serde(default)]
+    pub clearcoat: f32,
+    #[serde(default = "default_clearcoat_roughness")]
+    pub clearcoat_roughness: f32,
+
+    /// Weight of a retroreflective sheen, brightening fabrics at grazing
+    /// angles.
+    #[serde(default)]
+    pub sheen: f32,
+    #[serde(default = "default_sheen_color")]
+    pub sheen_color: ColorTexture,
+    #[serde(default = "default_sheen_roughness")]
+    pub sheen_roughness: f32,
+
+    /// Thickness in nanometers of a transparent film over the specular
+    /// lobe, as on soap bubbles and oil slicks. Zero disables it.
+    #[serde(default)]
+    pub thin_film_thickness: f32,
+    #[serde(default = "default_thin_film_ior")]
+    pub thin_film_ior: f32,
+}
+
+fn default_thin_film_ior() -> f32 {
+    1.33
+}
+
+fn default_opacity() -> GrayScaleTexture {
+    GrayScaleTexture::Solid(1.0)
+}
+
+fn default_emission_strength() -> f32 {
+    1.0
+}
+
+fn default_sheen_color() -> ColorTexture {
+    ColorTexture::solid(glm::vec3(1.0, 1.0, 1.0))
+}
+
+fn default_sheen_roughness() -> f32 {
+    0.5
+}
+
+fn default_clearcoat_roughness() -> f32 {
+    0.1
+}
+
+/// Reflectance of the clearcoat at normal incidence, that of a 1.5 IOR varnish.
+const CLEARCOAT_F0: f32 = 0.04;
+
+fn clearcoat_fresnel(cos: f32) -> f32 {
+    CLEARCOAT_F0 + (1.0 - CLEARCOAT_F0) * (1.0 - cos).max(0.0).powi(5)
+}
+
+/// Parameters resolved at a hit point, with the chances of sampling the
+/// clearcoat and, below it, the diffuse base rather than the specular lobe.
+struct Lobes {
+    metalness: f32,
+    f0: Vec3,
+    /// GGX roughness along the tangent and across it, alike unless
+    /// anisotropic.
+    alpha: Vec2,
+    clearcoat_alpha: f32,
+    /// Also the fraction of light the clearcoat reflects, leaving the rest
+    /// to the base.
+    p_clearcoat: f32,
+    p_diffuse: f32,
+}
+
+impl Default for Standard {
+    fn default() -> Self {
+        Standard {
+            albedo: ColorTexture::solid(glm::vec3(0.8, 0.8, 0.8)),
+            metalness: GrayScaleTexture::Solid(0.0),
+            roughness: GrayScaleTexture::Solid(0.5),
+            roughness_v: None,
+            conductor: None,
+            diffuse: DiffuseModel::default(),
+            emission: ColorTexture::default(),
+            emission_strength: default_emission_strength(),
+            emission_unit: EmissionUnit::default(),
+            light_group: None,
+            bump: Bump::default(),
+            sides: Sides::default(),
+            opacity: default_opacity(),
+            clearcoat: 0.0,
+            clearcoat_roughness: default_clearcoat_roughness(),
+            sheen: 0.0,
+            sheen_color: default_sheen_color(),
+            sheen_roughness: default_sheen_roughness(),
+            thin_film_thickness: 0.0,
+            thin_film_ior: default_thin_film_ior(),
+        }
+    }
+}
+
+impl Standard {
+    fn lobes(&self, wo: &Vec3, hit: &RayHit) -> Lobes {
+        let alpha = |roughness: f32| f32::max(roughness * roughness, 1e-3);
+        let alpha_u = alpha(self.roughness.sample_at(hit));
+        let alpha_v = match &self.roughness_v {
+            Some(roughness_v) => alpha(roughness_v.sample_at(hit)),
+            None => alpha_u,
+        };
+        let metalness = self.metalness_at(hit);
+        let cos_o = glm::dot(wo, &hit.normal).max(0.0);
+        // The diffuse base is picked by its rough share of the reflectance
+        let diffuse = (1.0 - metalness) * (luminance(&self.albedo.sample_at(hit)) + self.sheen);
+        Lobes {
+            metalness,
+            f0: self.f0_at(hit),
+            alpha: glm::vec2(alpha_u, alpha_v),
+            clearcoat_alpha: alpha(self.clearcoat_roughness),
+            p_clearcoat: (self.clearcoat * clearcoat_fresnel(cos_o)).min(1.0),
+            p_diffuse: diffuse / (diffuse + 1.0),
+        }
+    }
+
+    /// The BSDF, clearcoat included, and the density of `scatter` picking
+    /// `wi`, for both directions on the outside of the surface.
+    pub(crate) fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> (Vec3, f32) {
+        let l = self.lobes(wo, hit);
+        self.eval_lobes(&l, wo, wi, hit)
+    }
+
+    fn eval_lobes(&self, l: &Lobes, wo: &Vec3, wi: &Vec3, hit: &RayHit) -> (Vec3, f32) {
+        let n = hit.normal;
+        let (cos_o, cos_i) = (glm::dot(wo, &n), glm::dot(wi, &n));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return (glm::zero(), 0.0);
+        }
+        let h = glm::normalize(&(wo + wi));
+        let (cos_h, cos_d) = (glm::dot(&h, &n), glm::dot(wo, &h));
+        let (t, b) = microfacet::frame(&n, hit.tangent);
+        let ggx = |alpha: Vec2| microfacet::Anisotropic {
+            n: &n,
+            t: &t,
+            b: &b,
+            alpha,
+        };
+
+        // Specular lobe over a diffuse base, which gets what it leaves
+        let specular = ggx(l.alpha);
+        let d = specular.distribution(&h);
+        let fresnel = self.specular_fresnel(wi, &h, &l.f0);
+        let kd = (glm::vec3(1.0, 1.0, 1.0) - fresnel) * (1.0 - l.metalness);
+        let mut f = fresnel * (d * specular.g2(wo, wi) / (4.0 * cos_o * cos_i))
+            + kd.component_mul(&self.diffuse_brdf(wo, wi, &n, hit))
+            + self.sheen_brdf(wo, wi, &n, hit);
+        let mut pdf = l.p_diffuse * cos_i / glm::pi::<f32>()
+            + (1.0 - l.p_diffuse) * d * cos_h / (4.0 * cos_d);
+
+        // Clearcoat on top, with the base below getting what it leaves
+        if self.clearcoat > 0.0 {
+            let coat = ggx(glm::vec2(l.clearcoat_alpha, l.clearcoat_alpha));
+            let d = coat.distribution(&h);
+            let weight = self.clearcoat * clearcoat_fresnel(cos_d) * d * coat.g2(wo, wi)
+                / (4.0 * cos_o * cos_i);
+            f = f * (1.0 - l.p_clearcoat) + glm::vec3(weight, weight, weight);
+            pdf = l.p_clearcoat * d * cos_h / (4.0 * cos_d) + (1.0 - l.p_clearcoat) * pdf;
+        }
+        (f, pdf)
+    }
+
+    pub(crate) fn metalness_at(&self, hit: &RayHit) -> f32 {
+        match self.conductor {
+            Some(_) => 1.0,
+            None => self.metalness.sample_at(hit),
+        }
+    }
+
+    /// Specular reflectance at normal incidence.
+    fn f0_at(&self, hit: &RayHit) -> Vec3 {
+        match &self.conductor {
+            Some(conductor) => conductor.fresnel(1.0),
+            None => glm::mix(
+                &glm::vec3(0.04, 0.04, 0.04),
+                &self.albedo.sample_at(hit),
+                self.metalness.sample_at(hit),
+            ),
+        }
+    }
+
+    /// Fresnel term of the specular lobe, including the interference of the
+    /// thin film if there is one. The base below the film reflects `f0`,
+    /// or follows the exact equations for conductors.
+    fn specular_fresnel(&self, wi: &Vec3, h: &Vec3, f0: &Vec3) -> Vec3 {
+        if self.thin_film_thickness <= 0.0 {
+            return match &self.conductor {
+                Some(conductor) => conductor.fresnel(glm::dot(wi, h)),
+                None => fresnel(wi, h, f0),
+            };
+        }
+        let cos_i = f32::max(0.0, glm::dot(wi, h));
+        let eta = self.thin_film_ior;
+        let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+        let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+
+        // Amplitudes reflected at the top of the film and at the base, which
+        // is seen at the refracted angle
+        let r12 = fresnel_dielectric(cos_i, 1.0 / eta, FresnelMode::Exact).sqrt();
+        let r23 = (f0 + (glm::vec3(1.0, 1.0, 1.0) - f0) * (1.0 - cos_t).powi(5)).map(f32::sqrt);
+        let mut reflectance = glm::zero::<Vec3>();
+        for (i, lambda) in RGB_WAVELENGTHS.iter().enumerate() {
+            let phase = 4.0 * glm::pi::<f32>() * eta * self.thin_film_thickness * cos_t / lambda;
+            let cross = 2.0 * r12 * r23[i] * phase.cos();
+            let (a, b) = (r12 * r12, r23[i] * r23[i]);
+            reflectance[i] = (a + b + cross) / (1.0 + a * b + cross);
+        }
+        reflectance
+    }
+
+    /// Reflects off the clearcoat alone with probability equal to its
+    /// Fresnel weight; the base below then receives the remaining energy.
+    /// Returns `None` when the base is chosen instead, for layers such as
+    /// car paint that put a base of their own below.
+    pub(crate) fn scatter_clearcoat(&self, r: &Ray, hit: &RayHit) -> Option<Option<Scatter>> {
+        let n = hit.normal;
+        let wo = -glm::normalize(&r.direction);
+        let coat = self.clearcoat * clearcoat_fresnel(glm::dot(&wo, &n));
+        if sampler::next_1d() >= coat {
+            return None;
+        }
+
+        let alpha = self.clearcoat_roughness * self.clearcoat_roughness;
+        let m = microfacet::sample_normal(&n, alpha, sampler::next_2d());
+        let wi = glm::reflect_vec(&-wo, &m);
+        if glm::dot(&wi, &n) <= 0.0 || glm::dot(&wo, &m) <= 0.0 {
+            return Some(None);
+        }
+        let g = microfacet::smith_g1(&wo, &m, &n, alpha) * microfacet::smith_g1(&wi, &m, &n, alpha);
+        let fresnel = clearcoat_fresnel(glm::dot(&wo, &m));
+        let weight = self.clearcoat * fresnel / coat * glm::dot(&wo, &m) * g
+            / (glm::dot(&wo, &n) * glm::dot(&m, &n));
+        Some(Some(Scatter {
+            ray: Ray::new(hit.point, wi),
+            attenuation: glm::vec3(weight, weight, weight),
+        }))
+    }
+
+    /// The diffuse base, before weighting by what the specular lobe leaves.
+    pub(crate) fn diffuse_brdf(&self, wo: &Vec3, wi: &Vec3, n: &Vec3, hit: &RayHit) -> Vec3 {
+        let lambert = self.albedo.sample_at(hit) / glm::pi::<f32>();
+        match self.diffuse {
+            DiffuseModel::Lambert => lambert,
+            DiffuseModel::OrenNayar => {
+                // Qualitative model with the roughness as the slope deviation
+                // in radians
+                let sigma2 = self.roughness.sample_at(hit).powi(2);
+                let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+                let b = 0.45 * sigma2 / (sigma2 + 0.09);
+                let (cos_i, cos_o) = (glm::dot(wi, n).max(0.0), glm::dot(wo, n).max(0.0));
+                let (sin_i, sin_o) = (
+                    (1.0 - cos_i * cos_i).max(0.0).sqrt(),
+                    (1.0 - cos_o * cos_o).max(0.0).sqrt(),
+                );
+                // Cosine of the azimuth between the two directions
+                let (pi, po) = (wi - n * cos_i, wo - n * cos_o);
+                let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+                    (glm::dot(&pi, &po) / (sin_i * sin_o)).max(0.0)
+                } else {
+                    0.0
+                };
+                let (sin_alpha, tan_beta) = if cos_i > cos_o {
+                    (sin_o, sin_i / cos_i)
+                } else {
+                    (sin_i, sin_o / cos_o.max(1e-4))
+                };
+                lambert * (a + b * cos_phi * sin_alpha * tan_beta)
+            }
+        }
+    }
+
+    /// Estevez and Kulla's "Charlie" sheen, with Neubelt and Pettineo's
+    /// visibility term.
+    pub(crate) fn sheen_brdf(&self, wo: &Vec3, wi: &Vec3, n: &Vec3, hit: &RayHit) -> Vec3 {
+        if self.sheen <= 0.0 {
+            return glm::zero();
+        }
+        let (cos_o, cos_i) = (glm::dot(wo, n).max(0.0), glm::dot(wi, n).max(0.0));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return glm::zero();
+        }
+        let h = glm::normalize(&(wo + wi));
+        let cos_h = glm::dot(&h, n);
+        let sin2_h = (1.0 - cos_h * cos_h).max(0.0);
+        let inv_r = 1.0 / f32::max(self.sheen_roughness * self.sheen_roughness, 1e-3);
+        let d = (2.0 + inv_r) * sin2_h.powf(0.5 * inv_r) * glm::one_over_two_pi::<f32>();
+        let v = 1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o));
+        self.sheen_color.sample_at(hit) * (self.sheen * d * v)
+    }
+
+    /// Samples the clearcoat, the diffuse base or the specular lobe, with
+    /// the direction weighted by the density of any of them picking it.
+    pub fn scatter(&self, r: &Ray, hit: &RayHit) -> Option<Scatter> {
+        let n = hit.normal;
+        let wo = -glm::normalize(&r.direction);
+        let l = self.lobes(&wo, hit);
+        let (t, b) = microfacet::frame(&n, hit.tangent);
+        let reflect = |alpha: Vec2, rnd: Vec2| {
+            let ggx = microfacet::Anisotropic {
+                n: &n,
+                t: &t,
+                b: &b,
+                alpha,
+            };
+            glm::reflect_vec(&-wo, &ggx.sample_normal(rnd))
+        };
+        let pick = sampler::next_1d();
+        let rnd = sampler::next_2d();
+        let wi = if pick < l.p_clearcoat {
+            reflect(glm::vec2(l.clearcoat_alpha, l.clearcoat_alpha), rnd)
+        } else if pick - l.p_clearcoat < (1.0 - l.p_clearcoat) * l.p_diffuse {
+            sample_cosine(&n, rnd)
+        } else {
+            reflect(l.alpha, rnd)
+        };
+        let (f, pdf) = self.eval_lobes(&l, &wo, &wi, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Scatter {
+            ray: Ray::new(hit.point, wi),
+            attenuation: f * (glm::dot(&wi, &n) / pdf),
+        })
+    }
+
+    pub fn emitted(&self, hit: &RayHit) -> Vec3 {
+        self.emission.sample_at(hit) * self.emission_strength
+    }
+}
+
+fn fresnel(wi: &Vec3, h: &Vec3, f0: &Vec3) -> Vec3 {
+    let widoth = f32::max(0.0, glm::dot(wi, h));
+    f0 + (glm::vec3(1.0, 1.0, 1.0) - f0) * f32::powi(1.0 - widoth, 5)
+}
@@ -0,0 +1,133 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::{scatter_interface, FresnelMode, Scatter};
+use crate::geom::{RayHit, Scene, Traceable as _};
+use crate::ray::Ray;
+use crate::sampler;
+use crate::texture::{ColorTexture, Texture as _};
+use crate::vec::{self, Vec3};
+
+/// Translucent material whose light travels through the interior as a random
+/// walk in a homogeneous medium, for skin, wax and marble. The object must be
+/// closed, with normals pointing out of it, so that the walk finds where it
+/// leaves through the back of the surface.
+#[derive(Deserialize, Clone)]
+pub struct Subsurface {
+    /// Color the surface appears to have once light has diffused through it.
+    pub albedo: ColorTexture,
+    /// Average distance light travels between scattering events, per color
+    /// channel and in scene units. Red is usually the longest, as in skin.
+    pub mean_free_path: Vec3,
+    #[serde(default = "default_ior")]
+    pub ior: f32,
+    /// Henyey-Greenstein asymmetry of the interior scattering, from -1
+    /// (backward) through 0 (isotropic) to 1 (forward).
+    #[serde(default)]
+    pub anisotropy: f32,
+}
+
+fn default_ior() -> f32 {
+    1.4
+}
+
+/// Walks longer than this are assumed to have been absorbed.
+const MAX_STEPS: usize = 256;
+
+/// Single-scattering albedo of the medium that gives a surface of the given
+/// multiple-scattering albedo, inverting van de Hulst's relation.
+fn single_scattering_albedo(albedo: f32) -> f32 {
+    let a = albedo.clamp(0.0, 0.999);
+    let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    1.0 - s * s
+}
+
+/// Samples a direction around `dir` from the Henyey-Greenstein phase function,
+/// whose weight is then 1.
+fn sample_phase(dir: &Vec3, g: f32, rnd: (f32, f32)) -> Vec3 {
+    let cos = if g.abs() < 1e-3 {
+        1.0 - 2.0 * rnd.0
+    } else {
+        let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * rnd.0);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = glm::two_pi::<f32>() * rnd.1;
+    let (u, v) = vec::orthonormal_basis(dir);
+    glm::normalize(&(u * (sin * phi.cos()) + v * (sin * phi.sin()) + dir * cos))
+}
+
+fn average(v: &Vec3) -> f32 {
+    (v.x + v.y + v.z) / 3.0
+}
+
+impl Subsurface {
+    /// Either reflects off the smooth surface or refracts in and walks
+    /// through the interior until the path leaves it again. The walk picks
+    /// free-flight distances from all channels at once by averaging their
+    /// densities, so colors separate without per-channel paths.
+    pub fn scatter(&self, r: &Ray, hit: &RayHit, scene: &Scene) -> Option<Scatter> {
+        let (direction, _) =
+            scatter_interface(&r.direction, &hit.normal, self.ior, 0.0, FresnelMode::Exact)?;
+        if glm::dot(&direction, &hit.normal) > 0.0 {
+            return Some(Scatter {
+                ray: Ray::new(hit.point, direction),
+                attenuation: glm::vec3(1.0, 1.0, 1.0),
+            });
+        }
+
+        let albedo = self.albedo.sample_at(hit);
+        let sigma_t = glm::vec3(1.0, 1.0, 1.0).component_div(&self.mean_free_path);
+        let sigma_s = glm::vec3(
+            single_scattering_albedo(albedo.x),
+            single_scattering_albedo(albedo.y),
+            single_scattering_albedo(albedo.z),
+        )
+        .component_mul(&sigma_t);
+
+        let mut throughput = glm::vec3(1.0, 1.0, 1.0);
+        // The walk happens at the ray's time, where moving objects are
+        let mut ray = Ray::new(hit.point, direction).with_time(r.time);
+        for _ in 0..MAX_STEPS {
+            let rnd = sampler::next_2d();
+            let channel = ((rnd.x * 3.0) as usize).min(2);
+            let dist = -(1.0 - rnd.y).ln() / sigma_t[channel];
+            match scene.trace(&ray, 0.001, dist) {
+                Some(exit) => {
+                    // Reached the surface before scattering
+                    let d = exit.hit.t;
+                    let transmittance = (-sigma_t * d).map(f32::exp);
+                    throughput = throughput.component_mul(&transmittance) / average(&transmittance);
+                    let (direction, _) = scatter_interface(
+                        &ray.direction,
+                        &exit.hit.normal,
+                        self.ior,
+                        0.0,
+                        FresnelMode::Exact,
+                    )?;
+                    ray = Ray::new(exit.hit.point, direction).with_time(r.time);
+                    if glm::dot(&direction, &exit.hit.normal) > 0.0 {
+                        return Some(Scatter {
+                            ray,
+                            attenuation: throughput,
+                        });
+                    }
+                }
+                None => {
+                    let transmittance = (-sigma_t * dist).map(f32::exp);
+                    let pdf = average(&sigma_t.component_mul(&transmittance));
+                    throughput =
+                        throughput.component_mul(&sigma_s.component_mul(&transmittance)) / pdf;
+                    let direction = glm::normalize(&ray.direction);
+                    let rnd = sampler::next_2d();
+                    let direction = sample_phase(&direction, self.anisotropy, (rnd.x, rnd.y));
+                    ray = Ray::new(ray.point_at(dist), direction).with_time(r.time);
+                }
+            }
+            if throughput.max() <= 0.0 {
+                return None;
+            }
+        }
+        None
+    }
+}
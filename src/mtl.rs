@@ -0,0 +1,239 @@
+use crate::material::{Bump, Material, Standard};
+use crate::texture::{ColorTexture, GrayScaleTexture, NormalMap};
+use crate::Vec3;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nalgebra_glm as glm;
+
+/// Statements of a single `newmtl` block. Maps are resolved relative to the
+/// MTL file.
+#[derive(Default)]
+struct Entry {
+    kd: Option<Vec3>,
+    ks: Option<Vec3>,
+    ke: Option<Vec3>,
+    ns: Option<f32>,
+    dissolve: Option<f32>,
+    // Roughness and metalness from the PBR extension
+    pr: Option<f32>,
+    pm: Option<f32>,
+    map_kd: Option<PathBuf>,
+    map_ke: Option<PathBuf>,
+    map_pr: Option<PathBuf>,
+    map_pm: Option<PathBuf>,
+    bump: Option<(PathBuf, f32)>,
+    norm: Option<PathBuf>,
+}
+
+/// Loads the materials of an MTL file by name, translated to the closest
+/// built-in material: standard materials, with dissolve as their opacity and
+/// Phong exponents converted to roughness. Materials whose maps cannot be
+/// read are left out with a warning, so faces using them fall back to the
+/// default material.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<HashMap<String, Material>> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let text = fs::read_to_string(path)?;
+
+    let mut entries = Vec::new();
+    for line in text.lines().map(str::trim) {
+        let mut iter = line.split_whitespace();
+        let keyword = match iter.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        if keyword == "newmtl" {
+            let name = iter.collect::<Vec<_>>().join(" ");
+            entries.push((name, Entry::default()));
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => continue,
+        };
+        let args: Vec<&str> = iter.collect();
+        let scalar = || args.first().and_then(|s| s.parse::<f32>().ok());
+        match keyword {
+            "Kd" => entry.kd = parse_color(&args),
+            "Ks" => entry.ks = parse_color(&args),
+            "Ke" => entry.ke = parse_color(&args),
+            "Ns" => entry.ns = scalar(),
+            "d" => entry.dissolve = scalar(),
+            "Tr" => entry.dissolve = scalar().map(|tr| 1.0 - tr),
+            "Pr" => entry.pr = scalar(),
+            "Pm" => entry.pm = scalar(),
+            "map_Kd" => entry.map_kd = map_path(dir, &args),
+            "map_Ke" => entry.map_ke = map_path(dir, &args),
+            "map_Pr" => entry.map_pr = map_path(dir, &args),
+            "map_Pm" => entry.map_pm = map_path(dir, &args),
+            "norm" | "map_Kn" => entry.norm = map_path(dir, &args),
+            "bump" | "map_Bump" | "map_bump" => {
+                let strength = option_value(&args, "-bm").unwrap_or(1.0);
+                entry.bump = map_path(dir, &args).map(|p| (p, strength));
+            }
+            _ => (),
+        }
+    }
+
+    let materials = entries
+        .into_iter()
+        .filter_map(|(name, entry)| match entry.into_material() {
+            Ok(material) => Some((name, material)),
+            Err(e) => {
+                eprintln!("Could not load material `{}`: {}", name, e);
+                None
+            }
+        })
+        .collect();
+    Ok(materials)
+}
+
+fn parse_color(args: &[&str]) -> Option<Vec3> {
+    let mut iter = args.iter().filter_map(|s| s.parse::<f32>().ok());
+    let r = iter.next()?;
+    // A single value stands for a gray
+    let g = iter.next().unwrap_or(r);
+    let b = iter.next().unwrap_or(r);
+    Some(Vec3::new(r, g, b))
+}
+
+/// The file name is the last argument of a map statement, after any options.
+fn map_path(dir: &Path, args: &[&str]) -> Option<PathBuf> {
+    args.last().map(|file| dir.join(file))
+}
+
+fn option_value(args: &[&str], option: &str) -> Option<f32> {
+    let i = args.iter().position(|a| *a == option)?;
+    args.get(i + 1)?.parse().ok()
+}
+
+impl Entry {
+    fn into_material<'a>(self) -> Result<Material, Box<dyn Error + 'a>> {
+        let roughness = match (&self.map_pr, self.pr, self.ns) {
+            (Some(map), _, _) => GrayScaleTexture::from_file(map)?,
+            (None, Some(pr), _) => GrayScaleTexture::Solid(pr),
+            // Blinn-Phong exponent to GGX alpha, whose square root is the roughness
+            (None, None, Some(ns)) => GrayScaleTexture::Solid((2.0 / (ns + 2.0)).powf(0.25)),
+            (None, None, None) => GrayScaleTexture::Solid(0.5),
+        };
+        let bump = Bump {
+            normal_map: self.norm.as_ref().map(NormalMap::from_file).transpose()?,
+            bump_map: match &self.bump {
+                Some((map, _)) => Some(GrayScaleTexture::from_file(map)?),
+                None => None,
+            },
+            bump_strength: self.bump.as_ref().map_or(1.0, |(_, s)| *s),
+        };
+
+        let kd = self.kd.unwrap_or_else(|| glm::vec3(0.8, 0.8, 0.8));
+        let ks = self.ks.unwrap_or_else(glm::zero);
+        // Without the PBR extension, a black diffuse with a colored specular
+        // can only be a metal
+        let guessed_metal = self.pm.is_none() && kd.max() <= 0.0 && ks.max() > 0.0;
+        let albedo = match &self.map_kd {
+            Some(map) => ColorTexture::from_file(map)?,
+            None if guessed_metal => ColorTexture::solid(ks),
+            None => ColorTexture::solid(kd),
+        };
+        let metalness = match (&self.map_pm, self.pm) {
+            (Some(map), _) => GrayScaleTexture::from_file(map)?,
+            (None, Some(pm)) => GrayScaleTexture::Solid(pm),
+            (None, None) => GrayScaleTexture::Solid(if guessed_metal { 1.0 } else { 0.0 }),
+        };
+        let emission = match (&self.map_ke, self.ke) {
+            (Some(map), _) => ColorTexture::from_file(map)?,
+            (None, Some(ke)) => ColorTexture::solid(ke),
+            (None, None) => ColorTexture::default(),
+        };
+        Ok(Material::Standard(Standard {
+            albedo,
+            metalness,
+            roughness,
+            emission,
+            bump,
+            opacity: GrayScaleTexture::Solid(self.dissolve.unwrap_or(1.0)),
+            ..Standard::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn load_str(text: &str) -> HashMap<String, Material> {
+        let mut file = tempfile::Builder::new().suffix(".mtl").tempfile().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        load(file.path()).unwrap()
+    }
+
+    fn standard<'a>(materials: &'a HashMap<String, Material>, name: &str) -> &'a Standard {
+        match &materials[name] {
+            Material::Standard(s) => s,
+            _ => panic!("`{}` is not a standard material", name),
+        }
+    }
+
+    fn color(texture: &ColorTexture) -> Vec3 {
+        match texture {
+            ColorTexture::Image { buf, .. } => buf[0],
+            _ => panic!("not a solid color"),
+        }
+    }
+
+    fn value(texture: &GrayScaleTexture) -> f32 {
+        match texture {
+            GrayScaleTexture::Solid(v) => *v,
+            _ => panic!("not a solid value"),
+        }
+    }
+
+    #[test]
+    fn translates_phong_materials() {
+        let materials = load_str(
+            "# exported\n\
+             newmtl red paint\n\
+             Kd 0.8 0.1 0.1\n\
+             Ns 98\n\
+             d 0.25\n\
+             newmtl chrome\n\
+             Kd 0 0 0\n\
+             Ks 0.9\n\
+             Tr 0.0\n",
+        );
+        assert_eq!(materials.len(), 2);
+
+        let paint = standard(&materials, "red paint");
+        assert_eq!(color(&paint.albedo), Vec3::new(0.8, 0.1, 0.1));
+        assert!((value(&paint.roughness) - 0.02f32.powf(0.25)).abs() < 1e-6);
+        assert_eq!(value(&paint.metalness), 0.0);
+        assert_eq!(value(&paint.opacity), 0.25);
+
+        // A black diffuse with a specular color is taken for a metal
+        let chrome = standard(&materials, "chrome");
+        assert_eq!(color(&chrome.albedo), Vec3::new(0.9, 0.9, 0.9));
+        assert_eq!(value(&chrome.metalness), 1.0);
+        assert_eq!(value(&chrome.opacity), 1.0);
+    }
+
+    #[test]
+    fn skips_materials_with_missing_maps() {
+        let materials = load_str(
+            "newmtl textured\n\
+             map_Kd does_not_exist.png\n\
+             newmtl plain\n\
+             Kd 0.5\n",
+        );
+        assert!(!materials.contains_key("textured"));
+        assert_eq!(
+            color(&standard(&materials, "plain").albedo),
+            Vec3::new(0.5, 0.5, 0.5)
+        );
+    }
+}
@@ -1,16 +1,100 @@
-use crate::geom::{Triangle, Vertex};
+use crate::geom::{Corner, PolyMesh, Triangle};
+use crate::material::Material;
+use crate::mtl;
 use crate::{Vec2, Vec3};
 
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use nalgebra_glm as glm;
 
+/// Faces meeting at a sharper angle than this (in degrees) keep a hard edge
+/// when normals are generated for files without them.
+pub const DEFAULT_CREASE_ANGLE: f32 = 60.0;
+
 pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Triangle>> {
+    let mut mesh = load_polygons(path)?;
+    mesh.generate_normals(DEFAULT_CREASE_ANGLE);
+    Ok(mesh.triangulate())
+}
+
+/// Loads faces with all their corners, without triangulating them.
+pub fn load_polygons<P: AsRef<Path>>(path: P) -> std::io::Result<PolyMesh> {
+    parse(path.as_ref()).map(|parsed| parsed.mesh)
+}
+
+/// An OBJ file split into one triangle list per material its faces use, with
+/// the materials defined by its MTL libraries. Faces before any `usemtl`
+/// come first, with no material name.
+pub struct Model {
+    pub groups: Vec<(Option<String>, Vec<Triangle>)>,
+    pub materials: HashMap<String, Material>,
+}
+
+pub fn load_model<P: AsRef<Path>>(path: P) -> std::io::Result<Model> {
+    let path = path.as_ref();
+    let Parsed {
+        mut mesh,
+        face_materials,
+        material_names,
+        libraries,
+    } = parse(path)?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = HashMap::new();
+    for library in libraries {
+        // Faces keep their geometry even without their materials
+        match mtl::load(dir.join(&library)) {
+            Ok(library) => materials.extend(library),
+            Err(e) => eprintln!(
+                "Could not load material library {}: {}",
+                library.display(),
+                e
+            ),
+        }
+    }
+
+    // Normals are generated before splitting, so that smooth shading
+    // carries across material boundaries
+    mesh.generate_normals(DEFAULT_CREASE_ANGLE);
+    let mut faces = vec![Vec::new(); material_names.len() + 1];
+    for (face, material) in mesh.faces.into_iter().zip(face_materials) {
+        faces[material.map_or(0, |i| i + 1)].push(face);
+    }
+    let positions = mesh.positions;
+    let names = std::iter::once(None).chain(material_names.into_iter().map(Some));
+    let groups = names
+        .zip(faces)
+        .filter(|(_, faces)| !faces.is_empty())
+        .map(|(name, faces)| {
+            let group = PolyMesh {
+                positions: positions.clone(),
+                faces,
+            };
+            (name, group.triangulate())
+        })
+        .collect();
+    Ok(Model { groups, materials })
+}
+
+struct Parsed {
+    mesh: PolyMesh,
+    /// Index into `material_names` for each face.
+    face_materials: Vec<Option<usize>>,
+    material_names: Vec<String>,
+    libraries: Vec<PathBuf>,
+}
+
+fn parse(path: &Path) -> std::io::Result<Parsed> {
     let mut verts = Vec::new();
     let mut coords = Vec::new();
     let mut norms = Vec::new();
-    let mut tris = Vec::new();
+    let mut faces = Vec::new();
+    let mut face_materials = Vec::new();
+    let mut material_names: Vec<String> = Vec::new();
+    let mut libraries = Vec::new();
+    let mut current = None;
 
     let text = fs::read_to_string(path)?;
     for mut iter in text
@@ -29,14 +113,34 @@ pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Triangle>> {
                 norms.push(parse_vec3(iter).expect("Unable to parse vertex normal"));
             }
             Some("f") => {
-                tris.push(
-                    parse_triangle(iter, &verts, &coords, &norms).expect("Unable to parse face"),
-                );
+                faces.push(parse_face(iter, verts.len(), &coords, &norms));
+                face_materials.push(current);
+            }
+            Some("usemtl") => {
+                let name = iter.collect::<Vec<_>>().join(" ");
+                current = Some(match material_names.iter().position(|n| *n == name) {
+                    Some(i) => i,
+                    None => {
+                        material_names.push(name);
+                        material_names.len() - 1
+                    }
+                });
+            }
+            Some("mtllib") => {
+                libraries.extend(iter.map(PathBuf::from));
             }
             _ => (),
         }
     }
-    Ok(tris)
+    Ok(Parsed {
+        mesh: PolyMesh {
+            positions: verts,
+            faces,
+        },
+        face_materials,
+        material_names,
+        libraries,
+    })
 }
 
 fn parse_vec3<'a, I: Iterator<Item = &'a str>>(iter: I) -> Option<Vec3> {
@@ -54,49 +158,39 @@ fn parse_uv<'a, I: Iterator<Item = &'a str>>(iter: I) -> Option<Vec2> {
     Some(Vec2::new(x, y))
 }
 
-fn parse_triangle<'a, I: Iterator<Item = &'a str>>(
+fn parse_face<'a, I: Iterator<Item = &'a str>>(
     iter: I,
-    verts: &[Vec3],
+    num_verts: usize,
     coords: &[Vec2],
     norms: &[Vec3],
-) -> Option<Triangle> {
-    let mut iter = iter.map(|s| {
+) -> Vec<Corner> {
+    iter.map(|s| {
         let mut cmps = s.split('/');
         let pos = cmps
             .next()
             .and_then(|s| s.parse::<isize>().ok())
-            .map(|i| index_wrap(i, verts))
-            .expect("Position required for triangle definition");
-        let coord = cmps
+            .map(|i| index_wrap_pos(i, num_verts))
+            .expect("Position required for face definition");
+        let uv = cmps
             .next()
             .and_then(|s| s.parse::<isize>().ok())
             .map(|i| index_wrap(i, coords))
             .unwrap_or_else(glm::zero);
-        let norm = cmps
+        let normal = cmps
             .next()
             .and_then(|s| s.parse::<isize>().ok())
             .map(|i| index_wrap(i, norms));
-        (pos, coord, norm)
-    });
-    let (p1, uv1, n1) = iter.next()?;
-    let (p2, uv2, n2) = iter.next()?;
-    let (p3, uv3, n3) = iter.next()?;
-    let norm = triangle_normal(&p1, &p2, &p3);
-    let make_vertex = |(pos, uv, normal): (Vec3, Vec2, Option<Vec3>)| {
-        let normal = normal.unwrap_or(norm);
-        Vertex { pos, uv, normal }
-    };
-    Some(Triangle::new(
-        make_vertex((p1, uv1, n1)),
-        make_vertex((p2, uv2, n2)),
-        make_vertex((p3, uv3, n3)),
-    ))
+        Corner { pos, uv, normal }
+    })
+    .collect()
 }
 
-fn triangle_normal(p1: &Vec3, p2: &Vec3, p3: &Vec3) -> Vec3 {
-    let e1 = p2 - p1;
-    let e2 = p3 - p1;
-    e1.cross(&e2).normalize()
+fn index_wrap_pos(i: isize, len: usize) -> usize {
+    if i.is_negative() {
+        len - i.wrapping_abs() as usize
+    } else {
+        i as usize - 1
+    }
 }
 
 fn index_wrap<T: Clone>(i: isize, vec: &[T]) -> T {
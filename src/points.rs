@@ -0,0 +1,270 @@
+use crate::Vec3;
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+pub struct Point {
+    pub pos: Vec3,
+    pub normal: Option<Vec3>,
+}
+
+/// Loads a point cloud from either a PLY file (ASCII or binary little-endian)
+/// or a plain XYZ file with one point per line, optionally followed by a normal.
+pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<Point>> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(b"ply") {
+        load_ply(&bytes)
+    } else {
+        let text = String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(load_xyz(&text))
+    }
+}
+
+fn load_xyz(text: &str) -> Vec<Point> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let values: Vec<f32> = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter_map(|s| s.parse().ok())
+                .collect();
+            match values.as_slice() {
+                [x, y, z, nx, ny, nz, ..] => Some(Point {
+                    pos: Vec3::new(*x, *y, *z),
+                    normal: Some(Vec3::new(*nx, *ny, *nz)),
+                }),
+                [x, y, z, ..] => Some(Point {
+                    pos: Vec3::new(*x, *y, *z),
+                    normal: None,
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+/// Type of a PLY property value.
+#[derive(Clone, Copy)]
+enum Scalar {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+enum Property {
+    Scalar(Scalar),
+    /// Lists start with their length, followed by that many items.
+    List(Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_owned())
+}
+
+impl Scalar {
+    fn parse(ty: &str) -> Option<Self> {
+        match ty {
+            "char" | "int8" => Some(Scalar::Int8),
+            "uchar" | "uint8" => Some(Scalar::UInt8),
+            "short" | "int16" => Some(Scalar::Int16),
+            "ushort" | "uint16" => Some(Scalar::UInt16),
+            "int" | "int32" => Some(Scalar::Int32),
+            "uint" | "uint32" => Some(Scalar::UInt32),
+            "float" | "float32" => Some(Scalar::Float32),
+            "double" | "float64" => Some(Scalar::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::Int8 | Scalar::UInt8 => 1,
+            Scalar::Int16 | Scalar::UInt16 => 2,
+            Scalar::Int32 | Scalar::UInt32 | Scalar::Float32 => 4,
+            Scalar::Float64 => 8,
+        }
+    }
+
+    /// Decodes the little-endian value at the start of `bytes`, which
+    /// must hold at least `size` of them.
+    fn read(self, bytes: &[u8]) -> f32 {
+        let mut b = [0; 8];
+        b[..self.size()].copy_from_slice(&bytes[..self.size()]);
+        let [b0, b1, b2, b3, ..] = b;
+        match self {
+            Scalar::Int8 => f32::from(b0 as i8),
+            Scalar::UInt8 => f32::from(b0),
+            Scalar::Int16 => f32::from(i16::from_le_bytes([b0, b1])),
+            Scalar::UInt16 => f32::from(u16::from_le_bytes([b0, b1])),
+            Scalar::Int32 => i32::from_le_bytes([b0, b1, b2, b3]) as f32,
+            Scalar::UInt32 => u32::from_le_bytes([b0, b1, b2, b3]) as f32,
+            Scalar::Float32 => f32::from_le_bytes([b0, b1, b2, b3]),
+            Scalar::Float64 => f64::from_le_bytes(b) as f32,
+        }
+    }
+}
+
+/// Reads the binary record starting at `offset`, moving past it. Lists are
+/// skipped, leaving the values of the scalar properties.
+fn read_record(body: &[u8], offset: &mut usize, element: &Element) -> std::io::Result<Vec<f32>> {
+    let mut take = |size: usize| {
+        let bytes = body
+            .get(*offset..*offset + size)
+            .ok_or_else(|| invalid("Unexpected end of PLY data"))?;
+        *offset += size;
+        Ok::<_, Error>(bytes)
+    };
+    let mut values = Vec::new();
+    for (_, property) in &element.properties {
+        match *property {
+            Property::Scalar(ty) => values.push(ty.read(take(ty.size())?)),
+            Property::List(count, item) => {
+                let len = count.read(take(count.size())?) as usize;
+                take(len * item.size())?;
+            }
+        }
+    }
+    Ok(values)
+}
+
+fn load_ply(bytes: &[u8]) -> std::io::Result<Vec<Point>> {
+    let header_end = bytes
+        .windows(b"end_header".len())
+        .position(|w| w == b"end_header")
+        .ok_or_else(|| invalid("PLY header is not terminated"))?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|i| header_end + i + 1)
+        .ok_or_else(|| invalid("PLY header is not terminated"))?;
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for mut iter in header.lines().map(str::split_whitespace) {
+        match iter.next() {
+            Some("format") => {
+                format = match iter.next() {
+                    Some("ascii") => Some(Format::Ascii),
+                    Some("binary_little_endian") => Some(Format::BinaryLittleEndian),
+                    _ => return Err(invalid("Unsupported PLY format")),
+                }
+            }
+            Some("element") => {
+                let name = iter.next().unwrap_or_default().to_owned();
+                let count = iter
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| invalid("Invalid PLY element count"))?;
+                elements.push(Element {
+                    name,
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            Some("property") => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("PLY property outside of element"))?;
+                let scalar = |ty: Option<&str>| {
+                    Scalar::parse(ty.unwrap_or_default())
+                        .ok_or_else(|| invalid("Unknown PLY property type"))
+                };
+                let ty = iter.next();
+                let property = if ty == Some("list") {
+                    if element.name == "vertex" {
+                        return Err(invalid("List properties on PLY vertices are not supported"));
+                    }
+                    Property::List(scalar(iter.next())?, scalar(iter.next())?)
+                } else {
+                    Property::Scalar(scalar(ty)?)
+                };
+                let name = iter.next().unwrap_or_default().to_owned();
+                element.properties.push((name, property));
+            }
+            _ => (),
+        }
+    }
+    let format = format.ok_or_else(|| invalid("Missing PLY format"))?;
+
+    let body = &bytes[body_start..];
+    let text = String::from_utf8_lossy(body);
+    let mut lines = text.lines();
+    let mut offset = 0;
+    // Elements are stored one after another, so those before the vertices
+    // are read past
+    for element in &elements {
+        if element.name == "vertex" {
+            return read_vertices(element, format, &mut lines, body, &mut offset);
+        }
+        for _ in 0..element.count {
+            match format {
+                Format::Ascii => {
+                    lines
+                        .next()
+                        .ok_or_else(|| invalid("Unexpected end of PLY data"))?;
+                }
+                Format::BinaryLittleEndian => {
+                    read_record(body, &mut offset, element)?;
+                }
+            }
+        }
+    }
+    Err(invalid("PLY file has no vertex element"))
+}
+
+fn read_vertices<'a>(
+    element: &Element,
+    format: Format,
+    lines: &mut impl Iterator<Item = &'a str>,
+    body: &[u8],
+    offset: &mut usize,
+) -> std::io::Result<Vec<Point>> {
+    let index = |name: &str| element.properties.iter().position(|(n, _)| n == name);
+    let pos = [index("x"), index("y"), index("z")];
+    let normal = [index("nx"), index("ny"), index("nz")];
+    let mut points = Vec::with_capacity(element.count);
+    for _ in 0..element.count {
+        let values: Vec<f32> = match format {
+            Format::Ascii => lines
+                .next()
+                .ok_or_else(|| invalid("Unexpected end of PLY data"))?
+                .split_whitespace()
+                .map(|s| s.parse().map_err(|_| invalid("Invalid PLY vertex value")))
+                .collect::<Result<_, _>>()?,
+            Format::BinaryLittleEndian => read_record(body, offset, element)?,
+        };
+        let get = |i: Option<usize>| i.and_then(|i| values.get(i).copied());
+        let (x, y, z) = match (get(pos[0]), get(pos[1]), get(pos[2])) {
+            (Some(x), Some(y), Some(z)) => (x, y, z),
+            _ => return Err(invalid("PLY vertices require x, y and z")),
+        };
+        let normal = match (get(normal[0]), get(normal[1]), get(normal[2])) {
+            (Some(nx), Some(ny), Some(nz)) => Some(Vec3::new(nx, ny, nz)),
+            _ => None,
+        };
+        points.push(Point {
+            pos: Vec3::new(x, y, z),
+            normal,
+        });
+    }
+    Ok(points)
+}
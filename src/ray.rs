@@ -6,6 +6,11 @@ pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub inv_dir: Vec3,
+    /// Point in the shutter interval the ray is sampled at, from 0 to 1.
+    pub time: f32,
+    /// Growth of the ray's footprint per unit of `t`, for picking texture
+    /// levels of detail. Zero for rays that don't track their footprint.
+    pub spread: f32,
 }
 
 impl Ray {
@@ -15,9 +20,19 @@ impl Ray {
             origin,
             direction,
             inv_dir,
+            time: 0.0,
+            spread: 0.0,
         }
     }
 
+    pub fn with_time(self, time: f32) -> Self {
+        Ray { time, ..self }
+    }
+
+    pub fn with_spread(self, spread: f32) -> Self {
+        Ray { spread, ..self }
+    }
+
     pub fn point_at(&self, t: f32) -> Vec3 {
         self.origin + t * self.direction
     }
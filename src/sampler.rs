@@ -0,0 +1,364 @@
+use std::cell::RefCell;
+use std::str::FromStr;
+
+use rand::prelude::*;
+use serde::Deserialize;
+
+use crate::Vec2;
+
+/// How the numbers that pixel samples are traced with are picked.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerKind {
+    /// Independently at random.
+    Random,
+    /// Correlated multi-jittered, spreading the samples of a pixel evenly
+    /// in each pair of dimensions.
+    #[default]
+    Stratified,
+    /// Owen-scrambled Sobol points, which also spread them evenly across
+    /// dimensions and usually need the fewest samples for the same noise.
+    Sobol,
+}
+
+/// Parses the names used in configuration files, for the command line.
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "random" => Ok(SamplerKind::Random),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!(
+                "unknown sampler `{}`, expected random, stratified or sobol",
+                name
+            )),
+        }
+    }
+}
+
+impl SamplerKind {
+    fn sampler(self) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::default()),
+            SamplerKind::Sobol => Box::new(SobolSampler::default()),
+        }
+    }
+}
+
+/// Source of the numbers in [0, 1) a pixel sample is traced with. Each
+/// sample draws them in the same order, one dimension after another: its
+/// position in the pixel, on the lens and in the shutter interval, then
+/// those of each bounce.
+pub trait Sampler {
+    fn kind(&self) -> SamplerKind;
+    /// Starts sample `s` of the `n` taken of pixel `pixel`.
+    fn start_sample(&mut self, pixel: u32, s: u32, n: u32);
+    fn next_1d(&mut self) -> f32;
+    fn next_2d(&mut self) -> Vec2;
+}
+
+thread_local! {
+    // Samples are traced from start to end on one thread, so each thread
+    // keeps the sampler of the sample it is tracing
+    static SAMPLER: RefCell<Box<dyn Sampler>> = RefCell::new(Box::new(RandomSampler));
+}
+
+/// Starts sample `s` of the `n` taken of pixel `pixel` on this thread, with
+/// a sampler of the given kind. The numbers drawn until the next sample is
+/// started belong to it.
+pub fn start_sample(kind: SamplerKind, pixel: u32, s: u32, n: u32) {
+    SAMPLER.with(|sampler| {
+        let mut sampler = sampler.borrow_mut();
+        if sampler.kind() != kind {
+            *sampler = kind.sampler();
+        }
+        sampler.start_sample(pixel, s, n);
+    })
+}
+
+/// The next dimension of the sample traced on this thread, random when
+/// none was started.
+pub fn next_1d() -> f32 {
+    SAMPLER.with(|sampler| sampler.borrow_mut().next_1d())
+}
+
+/// The next two dimensions of the sample traced on this thread.
+pub fn next_2d() -> Vec2 {
+    SAMPLER.with(|sampler| sampler.borrow_mut().next_2d())
+}
+
+pub struct RandomSampler;
+
+impl Sampler for RandomSampler {
+    fn kind(&self) -> SamplerKind {
+        SamplerKind::Random
+    }
+
+    fn start_sample(&mut self, _pixel: u32, _s: u32, _n: u32) {}
+
+    fn next_1d(&mut self) -> f32 {
+        rand::thread_rng().gen()
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        let mut rng = rand::thread_rng();
+        Vec2::new(rng.gen(), rng.gen())
+    }
+}
+
+/// Stratifies each dimension, and each pair drawn together, over the
+/// samples of a pixel, in patterns unrelated between pixels and dimensions.
+#[derive(Default)]
+pub struct StratifiedSampler {
+    pixel: u32,
+    s: u32,
+    n: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    fn next_pattern(&mut self) -> u32 {
+        self.dimension += 1;
+        hash(hash(self.pixel) ^ self.dimension)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn kind(&self) -> SamplerKind {
+        SamplerKind::Stratified
+    }
+
+    fn start_sample(&mut self, pixel: u32, s: u32, n: u32) {
+        *self = StratifiedSampler {
+            pixel,
+            s,
+            n,
+            dimension: 0,
+        };
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        if self.s >= self.n {
+            return rand::thread_rng().gen();
+        }
+        let pattern = self.next_pattern();
+        let stratum = permute(self.s, self.n, pattern);
+        (stratum as f32 + unit(self.s, hash(pattern))) / self.n as f32
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        if self.s >= self.n {
+            return RandomSampler.next_2d();
+        }
+        let pattern = self.next_pattern();
+        cmj(self.s, self.n, pattern)
+    }
+}
+
+/// The first two dimensions of the Sobol sequence, with each pair of
+/// dimensions drawn getting its own shuffle of the points and its own
+/// nested uniform scrambling, after Burley's "Practical Hash-based Owen
+/// Scrambling". Shuffling keeps the dimensions from being correlated, while
+/// scrambling keeps the points' even spread without their regular structure.
+#[derive(Default)]
+pub struct SobolSampler {
+    seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl Sampler for SobolSampler {
+    fn kind(&self) -> SamplerKind {
+        SamplerKind::Sobol
+    }
+
+    fn start_sample(&mut self, pixel: u32, s: u32, _n: u32) {
+        *self = SobolSampler {
+            seed: hash(pixel),
+            index: s,
+            dimension: 0,
+        };
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.next_2d().x
+    }
+
+    fn next_2d(&mut self) -> Vec2 {
+        self.dimension += 1;
+        let seed = hash(self.seed ^ hash(self.dimension));
+        let index = scramble(self.index, seed);
+        let x = scramble(index.reverse_bits(), hash(seed ^ 1));
+        let y = scramble(sobol_second(index), hash(seed ^ 2));
+        Vec2::new(to_unit(x), to_unit(y))
+    }
+}
+
+/// The second dimension of the Sobol sequence at `i`, as a fraction of 2^32.
+fn sobol_second(mut i: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut x = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            x ^= direction;
+        }
+        i >>= 1;
+        direction ^= direction >> 1;
+    }
+    x
+}
+
+/// Nested uniform scrambling of the bits of `x`, which flips each bit based
+/// on those above it, with the Laine-Karras hash.
+fn scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// Mixes the bits of `x` so that nearby inputs give unrelated outputs.
+pub(crate) fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+/// A fraction of 2^32 as a float, rounded down to keep it below one.
+pub(crate) fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Sample `s` of `n` spread over the unit square, after Kensler's
+/// "Correlated Multi-Jittered Sampling". The `n` samples fall one in each
+/// cell of a grid as well as one in each of `n` rows and columns, so they
+/// cover the square far more evenly than random ones. Each `pattern` gives
+/// an unrelated arrangement.
+fn cmj(s: u32, n: u32, pattern: u32) -> Vec2 {
+    let m = (n as f32).sqrt() as u32;
+    let rows = n.div_ceil(m);
+    let s = permute(s, n, pattern.wrapping_mul(0x51633e2d));
+    let sx = permute(s % m, m, pattern.wrapping_mul(0x68bc21eb));
+    let sy = permute(s / m, rows, pattern.wrapping_mul(0x02e5be93));
+    let jx = unit(s, pattern.wrapping_mul(0x967a889b));
+    let jy = unit(s, pattern.wrapping_mul(0x368cc8b7));
+    Vec2::new(
+        (sx as f32 + (sy as f32 + jx) / rows as f32) / m as f32,
+        (s as f32 + jy) / n as f32,
+    )
+}
+
+/// Position of `i` in a random permutation of `0..len` picked by `pattern`.
+fn permute(mut i: u32, len: u32, pattern: u32) -> u32 {
+    let p = pattern;
+    // Hashes within the next power of two, until landing below `len`
+    let mut mask = len - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < len {
+            return i.wrapping_add(p) % len;
+        }
+    }
+}
+
+/// Random number in [0, 1) hashed from `i` and `pattern`.
+fn unit(mut i: u32, pattern: u32) -> f32 {
+    i ^= pattern;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb36534e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc4795);
+    i ^= 0xdf6e307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | pattern >> 18);
+    to_unit(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: u32 = 16;
+
+    /// The first 1D and 2D draws of each of the `N` samples of `pixel`.
+    fn draws(sampler: &mut dyn Sampler, pixel: u32) -> Vec<(f32, Vec2)> {
+        (0..N)
+            .map(|s| {
+                sampler.start_sample(pixel, s, N);
+                (sampler.next_1d(), sampler.next_2d())
+            })
+            .collect()
+    }
+
+    /// Whether the points fall one in each cell of a `cols` by `rows` grid.
+    fn one_per_cell(points: &[Vec2], cols: u32, rows: u32) -> bool {
+        let mut cells: Vec<_> = points
+            .iter()
+            .map(|p| ((p.x * cols as f32) as u32, (p.y * rows as f32) as u32))
+            .collect();
+        cells.sort();
+        cells.dedup();
+        cells.len() == points.len() && cells.iter().all(|&(x, y)| x < cols && y < rows)
+    }
+
+    #[test]
+    fn stratified_covers_each_stratum() {
+        let mut sampler = StratifiedSampler::default();
+        for pixel in 0..32 {
+            let (xs, points): (Vec<f32>, Vec<Vec2>) =
+                draws(&mut sampler, pixel).into_iter().unzip();
+            let mut strata: Vec<_> = xs.iter().map(|x| (x * N as f32) as u32).collect();
+            strata.sort();
+            assert_eq!(strata, (0..N).collect::<Vec<_>>());
+            assert!(one_per_cell(&points, N, 1));
+            assert!(one_per_cell(&points, 1, N));
+            assert!(one_per_cell(&points, 4, 4));
+        }
+    }
+
+    #[test]
+    fn sobol_points_form_nets() {
+        let mut sampler = SobolSampler::default();
+        for pixel in 0..32 {
+            let points: Vec<Vec2> = draws(&mut sampler, pixel)
+                .into_iter()
+                .map(|d| d.1)
+                .collect();
+            for &(cols, rows) in &[(16, 1), (8, 2), (4, 4), (2, 8), (1, 16)] {
+                assert!(one_per_cell(&points, cols, rows), "{} by {}", cols, rows);
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::Vec3;
+use nalgebra_glm as glm;
+
+/// Where the sun stands in the sky at a place and time on earth, after the
+/// low precision formulas of the Astronomical Almanac, good to about a
+/// hundredth of a degree. Scenes have north along -z and east along +x.
+#[derive(Clone, Debug)]
+pub struct SunPosition {
+    /// Degrees above the horizon.
+    pub elevation: f32,
+    /// Degrees clockwise from north.
+    pub azimuth: f32,
+}
+
+#[derive(Deserialize)]
+struct SunPositionDesc {
+    /// Degrees north of the equator.
+    latitude: f64,
+    /// Degrees east of Greenwich.
+    longitude: f64,
+    /// Local date and time, as `YYYY-MM-DDTHH:MM` with optional seconds.
+    time: String,
+    /// Hours the local time is ahead of UTC.
+    #[serde(default)]
+    utc_offset: f64,
+}
+
+impl TryFrom<SunPositionDesc> for SunPosition {
+    type Error = String;
+
+    fn try_from(desc: SunPositionDesc) -> Result<Self, String> {
+        let (year, month, day, hours) = parse_time(&desc.time)
+            .ok_or_else(|| format!("`{}` is not a time as YYYY-MM-DDTHH:MM", desc.time))?;
+        Ok(SunPosition::new(
+            desc.latitude,
+            desc.longitude,
+            year,
+            month,
+            day,
+            hours - desc.utc_offset,
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for SunPosition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let desc = SunPositionDesc::deserialize(deserializer)?;
+        SunPosition::try_from(desc).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Splits `YYYY-MM-DDTHH:MM[:SS]`, also with a space for the `T`, into
+/// the date and the hour of the day.
+fn parse_time(time: &str) -> Option<(i64, u32, u32, f64)> {
+    let mut parts = time.trim().splitn(2, ['T', ' ']);
+    let mut date = parts.next()?.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let mut clock = parts.next()?.split(':');
+    let hour: f64 = clock.next()?.parse().ok()?;
+    let minute: f64 = clock.next()?.parse().ok()?;
+    let second: f64 = match clock.next() {
+        Some(second) => second.parse().ok()?,
+        None => 0.0,
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some((year, month, day, hour + minute / 60.0 + second / 3600.0))
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian
+/// calendar, after Howard Hinnant's algorithm.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+impl SunPosition {
+    /// Position seen from `latitude` and `longitude` in degrees on the
+    /// given date, at `hours` past midnight UTC.
+    pub fn new(latitude: f64, longitude: f64, year: i64, month: u32, day: u32, hours: f64) -> Self {
+        // Days since noon UTC on 2000-01-01
+        let n = (days_from_civil(year, month, day) - days_from_civil(2000, 1, 1)) as f64
+            + (hours - 12.0) / 24.0;
+        let mean_longitude = 280.460 + 0.985_647_4 * n;
+        let mean_anomaly = (357.528 + 0.985_600_3 * n).to_radians();
+        let ecliptic_longitude =
+            (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+                .to_radians();
+        let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+        let right_ascension = (obliquity.cos() * ecliptic_longitude.sin())
+            .atan2(ecliptic_longitude.cos())
+            .to_degrees();
+        let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+        let sidereal = 280.460_618_37 + 360.985_647_366_29 * n + longitude;
+        let hour_angle = (sidereal - right_ascension).to_radians();
+        let latitude = latitude.to_radians();
+
+        let elevation = (latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin();
+        let azimuth = (-declination.cos() * hour_angle.sin()).atan2(
+            declination.sin() * latitude.cos()
+                - declination.cos() * hour_angle.cos() * latitude.sin(),
+        );
+        SunPosition {
+            elevation: elevation.to_degrees() as f32,
+            azimuth: azimuth.to_degrees().rem_euclid(360.0) as f32,
+        }
+    }
+
+    /// Unit direction towards the sun.
+    pub fn direction(&self) -> Vec3 {
+        let (sin_el, cos_el) = self.elevation.to_radians().sin_cos();
+        let (sin_az, cos_az) = self.azimuth.to_radians().sin_cos();
+        glm::vec3(cos_el * sin_az, sin_el, -cos_el * cos_az)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_position(sun: &SunPosition, elevation: f32, azimuth: f32) {
+        // Azimuths close to north may lie on either side of 0
+        let azimuth_error = (sun.azimuth - azimuth + 180.0).rem_euclid(360.0) - 180.0;
+        assert!(
+            (sun.elevation - elevation).abs() < 0.1 && azimuth_error.abs() < 1.0,
+            "{:?}",
+            sun
+        );
+    }
+
+    #[test]
+    fn noon_at_the_solstices() {
+        // Greenwich at solar noon, when the sun stands due south at the
+        // colatitude plus or minus the tilt of the earth's axis
+        let summer = SunPosition::new(51.48, 0.0, 2021, 6, 21, 12.0 + 2.0 / 60.0);
+        assert_position(&summer, 90.0 - 51.48 + 23.44, 180.0);
+        let winter = SunPosition::new(51.48, 0.0, 2021, 12, 21, 12.0 - 2.0 / 60.0);
+        assert_position(&winter, 90.0 - 51.48 - 23.44, 180.0);
+        // Sydney in its winter, with the sun due north
+        let sydney = SunPosition::new(-33.87, 151.21, 2021, 6, 21, 2.0);
+        assert_position(&sydney, 90.0 - 33.87 - 23.44, 0.0);
+    }
+
+    #[test]
+    fn rises_in_the_east_at_the_equinox() {
+        let sun = SunPosition::new(0.0, 0.0, 2021, 3, 20, 6.5);
+        assert_position(&sun, 5.6, 90.0);
+        let direction = sun.direction();
+        assert!(
+            direction.x > 0.99 && direction.z.abs() < 0.02,
+            "{:?}",
+            direction
+        );
+    }
+
+    #[test]
+    fn below_the_horizon_at_midnight() {
+        let sun = SunPosition::new(51.48, 0.0, 2021, 6, 21, 0.0);
+        assert!(sun.elevation < 0.0 && sun.direction().y < 0.0, "{:?}", sun);
+    }
+
+    #[test]
+    fn parses_local_times() {
+        assert_eq!(parse_time("2021-06-21T12:30"), Some((2021, 6, 21, 12.5)));
+        assert_eq!(parse_time("2021-06-21 06:00:36"), Some((2021, 6, 21, 6.01)));
+        assert_eq!(parse_time("2021-13-01T12:00"), None);
+        assert_eq!(parse_time("2021-06-21"), None);
+    }
+
+    #[test]
+    fn counts_days_from_the_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 1, 1), 10_957);
+        assert_eq!(
+            days_from_civil(2000, 3, 1) - days_from_civil(2000, 2, 28),
+            2
+        );
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde::{de::Visitor, Deserialize, Deserializer};
+
+use crate::Vec3;
+use nalgebra_glm as glm;
+
+/// Wavelengths in nanometers taken to represent the red, green and blue
+/// channels, as for interference and dispersion.
+pub const RGB_WAVELENGTHS: [f32; 3] = [650.0, 510.0, 475.0];
+
+/// Visible range integrated over when converting to RGB.
+const VISIBLE: (f32, f32) = (380.0, 780.0);
+const STEP: f32 = 5.0;
+
+/// A quantity such as reflectance or index of refraction sampled at
+/// wavelengths in nanometers, linearly interpolated between samples and
+/// held constant past either end.
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    samples: Vec<(f32, f32)>,
+}
+
+impl Spectrum {
+    pub fn new(mut samples: Vec<(f32, f32)>) -> Self {
+        samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Spectrum { samples }
+    }
+
+    /// Reads measured data as lines of `wavelength value`, separated by
+    /// whitespace or commas. Lines that don't start with a number, such as
+    /// headers and comments, are skipped.
+    pub fn from_file<'a, P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'a>> {
+        let text = fs::read_to_string(path)?;
+        let samples: Vec<_> = text
+            .lines()
+            .filter_map(|line| {
+                let mut iter = line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty());
+                let wavelength = iter.next()?.parse().ok()?;
+                let value = iter.next()?.parse().ok()?;
+                Some((wavelength, value))
+            })
+            .collect();
+        if samples.is_empty() {
+            return Err("spectrum file has no samples".into());
+        }
+        Ok(Spectrum::new(samples))
+    }
+
+    pub fn eval(&self, wavelength: f32) -> f32 {
+        let i = self.samples.iter().position(|&(w, _)| w >= wavelength);
+        match i {
+            None => self.samples.last().map_or(0.0, |s| s.1),
+            Some(0) => self.samples[0].1,
+            Some(i) => {
+                let (w0, v0) = self.samples[i - 1];
+                let (w1, v1) = self.samples[i];
+                v0 + (v1 - v0) * (wavelength - w0) / (w1 - w0)
+            }
+        }
+    }
+
+    /// Linear sRGB color of the spectrum as a reflectance, balanced so
+    /// that a constant spectrum of 1 is white.
+    pub fn to_rgb(&self) -> Vec3 {
+        let rgb = integrate(|w| self.eval(w));
+        let white = integrate(|_| 1.0);
+        rgb.component_div(&white)
+    }
+}
+
+/// Piecewise Gaussian used to fit the color matching functions.
+fn lobe(wavelength: f32, mean: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let sigma = if wavelength < mean {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (wavelength - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 standard observer, from the multi-lobe fit of Wyman et al.,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+fn cie_xyz(w: f32) -> Vec3 {
+    glm::vec3(
+        1.056 * lobe(w, 599.8, 37.9, 31.0) + 0.362 * lobe(w, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(w, 501.1, 20.4, 26.2),
+        0.821 * lobe(w, 568.8, 46.9, 40.5) + 0.286 * lobe(w, 530.9, 16.3, 31.1),
+        1.217 * lobe(w, 437.0, 11.8, 36.0) + 0.681 * lobe(w, 459.0, 26.0, 13.8),
+    )
+}
+
+fn integrate(f: impl Fn(f32) -> f32) -> Vec3 {
+    let mut xyz: Vec3 = glm::zero();
+    let mut w = VISIBLE.0;
+    while w <= VISIBLE.1 {
+        xyz += cie_xyz(w) * f(w);
+        w += STEP;
+    }
+    xyz_to_rgb(&xyz)
+}
+
+/// Converts CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(xyz: &Vec3) -> Vec3 {
+    glm::vec3(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+impl<'de> Deserialize<'de> for Spectrum {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{value::SeqAccessDeserializer, Error, SeqAccess};
+        use std::fmt;
+
+        struct SpectrumVisitor;
+
+        impl<'de> Visitor<'de> for SpectrumVisitor {
+            type Value = Spectrum;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("path to spectrum data or array of [wavelength, value] pairs")
+            }
+
+            // Load measured data
+            fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+                Spectrum::from_file(value).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, value: A) -> Result<Self::Value, A::Error> {
+                let samples: Vec<(f32, f32)> =
+                    Deserialize::deserialize(SeqAccessDeserializer::new(value))?;
+                if samples.is_empty() {
+                    return Err(A::Error::custom("spectrum has no samples"));
+                }
+                Ok(Spectrum::new(samples))
+            }
+        }
+        deserializer.deserialize_any(SpectrumVisitor)
+    }
+}
@@ -1,13 +1,32 @@
+mod cache;
+mod checker;
 mod color;
+mod gradient;
 mod grayscale;
+mod mipmap;
+mod noise;
+mod normal;
+mod sky;
+mod transform;
+mod triplanar;
 
 use std::ops::*;
 
+use crate::geom::RayHit;
 use crate::Vec2;
 use nalgebra_glm as glm;
 
+pub use cache::*;
+pub use checker::*;
 pub use color::*;
+pub use gradient::*;
 pub use grayscale::*;
+pub use mipmap::*;
+pub use noise::*;
+pub use normal::*;
+pub use sky::*;
+pub use transform::*;
+pub use triplanar::*;
 
 pub trait Texture {
     type Pixel: Mul<f32, Output = Self::Pixel> + Add<Self::Pixel, Output = Self::Pixel>;
@@ -17,16 +36,37 @@ pub trait Texture {
     fn pixel_at(&self, x: u32, y: u32) -> Self::Pixel;
 
     fn sample(&self, uv: Vec2) -> Self::Pixel {
-        let dim = self.dimensions();
-        let point = uv.component_mul(&(dim - glm::vec2(1.0, 1.0)));
-        let (p1, p2) = (glm::floor(&point), glm::ceil(&point));
-        let t = point - p1;
-        let f11 = self.pixel_at(p1.x as u32, p1.y as u32);
-        let f21 = self.pixel_at(p2.x as u32, p1.y as u32);
-        let f12 = self.pixel_at(p1.x as u32, p2.y as u32);
-        let f22 = self.pixel_at(p2.x as u32, p2.y as u32);
-        let a = f11 * (1.0 - t.x) + f21 * t.x;
-        let b = f12 * (1.0 - t.x) + f22 * t.x;
-        a * (1.0 - t.y) + b * t.y
+        bilinear(self, uv)
+    }
+
+    /// Lookup at a surface hit. Procedural textures may use its position
+    /// instead of its UVs.
+    fn sample_at(&self, hit: &RayHit) -> Self::Pixel {
+        self.sample(hit.uv)
+    }
+}
+
+/// Bilinearly filtered lookup. Coordinates outside [0, 1] repeat the
+/// texture, as tiled UVs from OBJ files expect.
+pub fn bilinear<T: Texture + ?Sized>(tex: &T, uv: Vec2) -> T::Pixel {
+    let uv = uv.map(wrap);
+    let dim = tex.dimensions();
+    let point = uv.component_mul(&(dim - glm::vec2(1.0, 1.0)));
+    let (p1, p2) = (glm::floor(&point), glm::ceil(&point));
+    let t = point - p1;
+    let f11 = tex.pixel_at(p1.x as u32, p1.y as u32);
+    let f21 = tex.pixel_at(p2.x as u32, p1.y as u32);
+    let f12 = tex.pixel_at(p1.x as u32, p2.y as u32);
+    let f22 = tex.pixel_at(p2.x as u32, p2.y as u32);
+    let a = f11 * (1.0 - t.x) + f21 * t.x;
+    let b = f12 * (1.0 - t.x) + f22 * t.x;
+    a * (1.0 - t.y) + b * t.y
+}
+
+fn wrap(x: f32) -> f32 {
+    if (0.0..=1.0).contains(&x) {
+        x
+    } else {
+        x - x.floor()
     }
 }
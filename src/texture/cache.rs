@@ -0,0 +1,212 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use lazy_static::lazy_static;
+
+use crate::Vec2;
+use nalgebra_glm as glm;
+
+/// Memory textures may take up together, unless configured otherwise.
+pub const DEFAULT_CACHE_MB: usize = 512;
+
+/// Textures that can be kept in the cache.
+pub trait Cached: Send + Sync + 'static {
+    /// Approximate memory taken up, in bytes.
+    fn size(&self) -> usize;
+}
+
+type Shared = Arc<dyn Any + Send + Sync>;
+
+/// Id of a texture, and the lock held while loading it.
+type Registered = (usize, Arc<Mutex<()>>);
+
+struct Entry {
+    texture: Shared,
+    size: usize,
+    last_used: AtomicU64,
+}
+
+/// Number of separately locked parts of the cache, so that threads looking
+/// up different textures don't wait on each other.
+const SHARDS: usize = 16;
+
+/// Textures loaded from files, evicted least recently used first whenever
+/// together they exceed the budget. Textures still being sampled when
+/// evicted are freed once sampling finishes.
+struct TextureCache {
+    shards: Vec<RwLock<HashMap<usize, Entry>>>,
+    budget: AtomicUsize,
+    used: AtomicUsize,
+    clock: AtomicU64,
+    /// Counts evictions, telling threads to let go of the textures they
+    /// resolved before.
+    epoch: AtomicU64,
+    evicting: Mutex<()>,
+}
+
+lazy_static! {
+    static ref CACHE: TextureCache = TextureCache {
+        shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        budget: AtomicUsize::new(DEFAULT_CACHE_MB << 20),
+        used: AtomicUsize::new(0),
+        clock: AtomicU64::new(0),
+        epoch: AtomicU64::new(0),
+        evicting: Mutex::new(()),
+    };
+    /// Ids of textures by their type and key, with the lock held while
+    /// loading each, so that it is read once however many threads want it.
+    static ref IDS: Mutex<HashMap<(TypeId, String), Registered>> = Mutex::default();
+}
+
+thread_local! {
+    // Textures this thread has resolved since the last eviction, by id,
+    // which it then samples without locking anything
+    static RESOLVED: RefCell<(u64, HashMap<usize, Shared>)> = RefCell::default();
+}
+
+impl TextureCache {
+    fn shard(&self, id: usize) -> &RwLock<HashMap<usize, Entry>> {
+        &self.shards[id % SHARDS]
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn get(&self, id: usize) -> Option<Shared> {
+        let shard = self.shard(id).read().unwrap();
+        let entry = shard.get(&id)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(entry.texture.clone())
+    }
+
+    fn insert(&self, id: usize, texture: Shared, size: usize) {
+        let entry = Entry {
+            texture,
+            size,
+            last_used: AtomicU64::new(self.tick()),
+        };
+        self.shard(id).write().unwrap().insert(id, entry);
+        self.used.fetch_add(size, Ordering::Relaxed);
+        self.evict(Some(id));
+    }
+
+    /// Evicts until within budget, sparing `keep`.
+    fn evict(&self, keep: Option<usize>) {
+        let _evicting = self.evicting.lock().unwrap();
+        while self.used.load(Ordering::Relaxed) > self.budget.load(Ordering::Relaxed) {
+            let oldest = self
+                .shards
+                .iter()
+                .filter_map(|shard| {
+                    let shard = shard.read().unwrap();
+                    shard
+                        .iter()
+                        .filter(|(id, _)| Some(**id) != keep)
+                        .map(|(id, entry)| (entry.last_used.load(Ordering::Relaxed), *id))
+                        .min()
+                })
+                .min();
+            match oldest.and_then(|(_, id)| self.shard(id).write().unwrap().remove(&id)) {
+                Some(entry) => {
+                    self.used.fetch_sub(entry.size, Ordering::Relaxed);
+                    self.epoch.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Sets the memory textures may take up together, evicting as needed.
+pub fn set_cache_budget(megabytes: usize) {
+    CACHE.budget.store(megabytes << 20, Ordering::Relaxed);
+    CACHE.evict(None);
+}
+
+type Loader<T> = Arc<dyn Fn() -> Result<T, String> + Send + Sync>;
+
+/// Texture read from a file the first time it is sampled rather than when
+/// the scene is loaded. Only its size is known up front.
+#[derive(Clone)]
+pub struct Lazy<T> {
+    key: String,
+    id: usize,
+    loading: Arc<Mutex<()>>,
+    width: u32,
+    height: u32,
+    load: Loader<T>,
+}
+
+impl<T: Cached + Default> Lazy<T> {
+    /// `key` identifies the texture in the cache among those of its type,
+    /// and `load` reads it in full.
+    pub fn new<F>(key: String, width: u32, height: u32, load: F) -> Self
+    where
+        F: Fn() -> Result<T, String> + Send + Sync + 'static,
+    {
+        let (id, loading) = {
+            let mut ids = IDS.lock().unwrap();
+            let next = ids.len();
+            ids.entry((TypeId::of::<T>(), key.clone()))
+                .or_insert_with(|| (next, Arc::default()))
+                .clone()
+        };
+        Lazy {
+            key,
+            id,
+            loading,
+            width,
+            height,
+            load: Arc::new(load),
+        }
+    }
+
+    pub fn dimensions(&self) -> Vec2 {
+        glm::vec2(self.width as f32, self.height as f32)
+    }
+
+    /// Calls `f` with the texture, which is resolved through the cache once
+    /// per thread until something is evicted.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        RESOLVED.with(|resolved| {
+            {
+                let mut resolved = resolved.borrow_mut();
+                let epoch = CACHE.epoch.load(Ordering::Relaxed);
+                if resolved.0 != epoch {
+                    *resolved = (epoch, HashMap::new());
+                }
+                resolved.1.entry(self.id).or_insert_with(|| self.resolve());
+            }
+            let resolved = resolved.borrow();
+            let texture = resolved.1[&self.id].downcast_ref::<T>();
+            f(texture.expect("texture ids are unique to their type"))
+        })
+    }
+
+    /// The texture, from the cache or loaded into it. Should the file have
+    /// become unreadable since the scene was loaded, the default texture
+    /// stands in for it.
+    fn resolve(&self) -> Shared {
+        if let Some(texture) = CACHE.get(self.id) {
+            return texture;
+        }
+        // Loading holds this texture's lock rather than the cache's, and
+        // whoever waited on it finds the texture loaded
+        let _loading = self.loading.lock().unwrap();
+        if let Some(texture) = CACHE.get(self.id) {
+            return texture;
+        }
+        let texture = (self.load)().unwrap_or_else(|err| {
+            eprintln!("Could not load texture {}: {}", self.key, err);
+            T::default()
+        });
+        let size = texture.size();
+        let texture: Shared = Arc::new(texture);
+        CACHE.insert(self.id, texture.clone(), size);
+        texture
+    }
+}
@@ -0,0 +1,49 @@
+use serde::Deserialize;
+
+use crate::geom::RayHit;
+use crate::{Vec2, Vec3};
+
+/// Space a procedural texture is evaluated in.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Projection {
+    #[default]
+    Uv,
+    World,
+}
+
+/// Checkerboard alternating between two colors, with `scale` squares per
+/// unit of UV or world space.
+#[derive(Deserialize, Clone)]
+pub struct Checker {
+    pub checker: [Vec3; 2],
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub projection: Projection,
+}
+
+fn default_scale() -> f32 {
+    10.0
+}
+
+impl Checker {
+    pub fn at_uv(&self, uv: Vec2) -> Vec3 {
+        let cell = (uv * self.scale).map(f32::floor);
+        self.color(cell.x + cell.y)
+    }
+
+    pub fn at_hit(&self, hit: &RayHit) -> Vec3 {
+        match self.projection {
+            Projection::Uv => self.at_uv(hit.uv),
+            Projection::World => {
+                let cell = (hit.point * self.scale).map(f32::floor);
+                self.color(cell.x + cell.y + cell.z)
+            }
+        }
+    }
+
+    fn color(&self, sum: f32) -> Vec3 {
+        self.checker[sum.rem_euclid(2.0) as usize % 2]
+    }
+}
@@ -7,26 +7,62 @@ use image::{self, hdr::HDRDecoder};
 
 use serde::{de::Visitor, Deserialize, Deserializer};
 
-use super::Texture;
+use super::{
+    bilinear, Cached, Checker, Gradient, Lazy, Mipmap, Noise, Sky, Texture, Triplanar, UvTransform,
+};
+
+use crate::geom::RayHit;
+use crate::spectrum::Spectrum;
 
 use crate::{Vec2, Vec3};
 use nalgebra_glm as glm;
 
 #[derive(Clone)]
-pub struct ColorTexture {
-    buf: Vec<Vec3>,
-    width: u32,
-    height: u32,
+pub enum ColorTexture {
+    Image {
+        buf: Vec<Vec3>,
+        width: u32,
+        height: u32,
+        mips: Mipmap<Vec3>,
+    },
+    /// Image loaded from a file on first use.
+    File(Lazy<ColorTexture>),
+    Checker(Checker),
+    Gradient(Gradient),
+    Sky(Sky),
+    Noise(Noise),
+    Transformed(Box<ColorTexture>, UvTransform),
+    Triplanar(Box<ColorTexture>, Triplanar),
 }
 
 impl ColorTexture {
     pub fn solid(color: Vec3) -> Self {
-        ColorTexture {
-            buf: vec![color],
-            width: 1,
-            height: 1,
+        Self::image(vec![color], 1, 1)
+    }
+
+    /// Image texture from its pixels in rows, building its mipmaps.
+    pub fn image(buf: Vec<Vec3>, width: u32, height: u32) -> Self {
+        let mips = Mipmap::new(width, height, |x, y| buf[(y * width + x) as usize]);
+        ColorTexture::Image {
+            buf,
+            width,
+            height,
+            mips,
         }
     }
+
+    /// Whether the texture is known to be black everywhere, as for solid
+    /// black colors.
+    pub fn is_black(&self) -> bool {
+        match self {
+            ColorTexture::Image { buf, .. } => buf.iter().all(|c| *c == Vec3::zeros()),
+            _ => false,
+        }
+    }
+
+    pub fn from_file<'a, P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'a>> {
+        open(path)
+    }
 }
 
 impl Default for ColorTexture {
@@ -38,25 +74,95 @@ impl Default for ColorTexture {
 impl Texture for ColorTexture {
     type Pixel = Vec3;
 
+    /// Procedural textures have no pixels and report a single one.
     fn dimensions(&self) -> Vec2 {
-        glm::vec2(self.width as f32, self.height as f32)
+        match self {
+            ColorTexture::Image { width, height, .. } => glm::vec2(*width as f32, *height as f32),
+            ColorTexture::File(lazy) => lazy.dimensions(),
+            ColorTexture::Transformed(tex, _) | ColorTexture::Triplanar(tex, _) => tex.dimensions(),
+            _ => glm::vec2(1.0, 1.0),
+        }
     }
 
     fn pixel_at(&self, x: u32, y: u32) -> Self::Pixel {
-        let idx = (y * self.width + x) as usize;
-        self.buf[idx]
+        match self {
+            ColorTexture::Image { buf, width, .. } => buf[(y * width + x) as usize],
+            ColorTexture::File(lazy) => lazy.with(|tex| tex.pixel_at(x, y)),
+            ColorTexture::Transformed(tex, _) | ColorTexture::Triplanar(tex, _) => {
+                tex.pixel_at(x, y)
+            }
+            _ => self.sample(glm::vec2(x as f32, y as f32)),
+        }
+    }
+
+    fn sample(&self, uv: Vec2) -> Self::Pixel {
+        match self {
+            ColorTexture::Image { .. } => bilinear(self, uv),
+            ColorTexture::File(lazy) => lazy.with(|tex| tex.sample(uv)),
+            ColorTexture::Checker(c) => c.at_uv(uv),
+            ColorTexture::Gradient(g) => g.at_uv(uv),
+            ColorTexture::Sky(s) => s.at_uv(uv),
+            ColorTexture::Noise(n) => n.color(n.value_at_uv(uv)),
+            ColorTexture::Transformed(tex, transform) => tex.sample(transform.apply(uv)),
+            ColorTexture::Triplanar(tex, _) => tex.sample(uv),
+        }
+    }
+
+    fn sample_at(&self, hit: &RayHit) -> Self::Pixel {
+        match self {
+            ColorTexture::Image { mips, .. } if hit.footprint > 0.0 => {
+                mips.trilinear(self, hit.uv, hit.footprint)
+            }
+            ColorTexture::File(lazy) => lazy.with(|tex| tex.sample_at(hit)),
+            ColorTexture::Checker(c) => c.at_hit(hit),
+            ColorTexture::Noise(n) => n.color(n.value_at_hit(hit)),
+            ColorTexture::Transformed(tex, transform) => tex.sample_at(&transform.apply_hit(hit)),
+            ColorTexture::Triplanar(tex, triplanar) => triplanar.sample(tex.as_ref(), hit),
+            _ => self.sample(hit.uv),
+        }
     }
 }
 
+impl Cached for ColorTexture {
+    fn size(&self) -> usize {
+        match self {
+            // A third more for the mipmaps
+            ColorTexture::Image { buf, .. } => buf.len() * std::mem::size_of::<Vec3>() * 4 / 3,
+            _ => std::mem::size_of::<Self>(),
+        }
+    }
+}
+
+/// Reads only the size of the image, leaving its pixels to be loaded when
+/// first sampled.
 fn open<'a, P: AsRef<Path>>(path: P) -> Result<ColorTexture, Box<dyn Error + 'a>> {
-    use std::ffi::OsStr;
-    if let Some("hdr") = path.as_ref().extension().and_then(OsStr::to_str) {
-        open_hdr(path)
-    } else {
-        let img = image::open(path)?.to_rgb();
-        let (width, height) = img.dimensions();
-        let buf = img.pixels().map(|p| rgb_to_float(*p)).collect();
-        Ok(ColorTexture { buf, width, height })
+    let path = path.as_ref().to_owned();
+    let (width, height) = match extension(&path).as_deref() {
+        Some("exr") => exr_dimensions(&path)?,
+        _ => image::image_dimensions(&path)?,
+    };
+    let key = path.to_string_lossy().into_owned();
+    let lazy = Lazy::new(key, width, height, move || {
+        load(&path).map_err(|err| err.to_string())
+    });
+    Ok(ColorTexture::File(lazy))
+}
+
+/// Lowercase extension of `path`, which picks the decoder for it.
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_lowercase())
+}
+
+fn load<'a, P: AsRef<Path>>(path: P) -> Result<ColorTexture, Box<dyn Error + 'a>> {
+    match extension(path.as_ref()).as_deref() {
+        Some("hdr") => open_hdr(path),
+        Some("exr") => open_exr(path),
+        _ => {
+            let img = image::open(path)?.to_rgb();
+            let (width, height) = img.dimensions();
+            let buf = img.pixels().map(|p| rgb_to_float(*p)).collect();
+            Ok(ColorTexture::image(buf, width, height))
+        }
     }
 }
 
@@ -72,7 +178,33 @@ fn open_hdr<'a, P: AsRef<Path>>(path: P) -> Result<ColorTexture, Box<dyn Error +
         .into_iter()
         .map(|pix| glm::make_vec3(&pix.0))
         .collect();
-    Ok(ColorTexture { width, height, buf })
+    Ok(ColorTexture::image(buf, width, height))
+}
+
+fn exr_dimensions(path: &Path) -> Result<(u32, u32), Box<dyn Error>> {
+    let meta = exr::meta::MetaData::read_from_file(path, false)?;
+    let size = meta.headers[0].layer_size;
+    Ok((size.width() as u32, size.height() as u32))
+}
+
+/// Reads the first layer of an OpenEXR image, which holds linear radiance
+/// like HDR files do.
+fn open_exr<'a, P: AsRef<Path>>(path: P) -> Result<ColorTexture, Box<dyn Error + 'a>> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |size, _| {
+            (
+                size.width(),
+                vec![Vec3::zeros(); size.width() * size.height()],
+            )
+        },
+        |(width, buf), pos, (r, g, b, _): (f32, f32, f32, f32)| {
+            buf[pos.y() * *width + pos.x()] = glm::vec3(r, g, b);
+        },
+    )?;
+    let (width, buf) = image.layer_data.channel_data.pixels;
+    let height = buf.len() / width.max(1);
+    Ok(ColorTexture::image(buf, width as u32, height as u32))
 }
 
 fn rgb_to_float(pix: image::Rgb<u8>) -> Vec3 {
@@ -80,14 +212,38 @@ fn rgb_to_float(pix: image::Rgb<u8>) -> Vec3 {
     let vec = Vec3::new(
         f32::from(r) / 255.0,
         f32::from(g) / 255.0,
-        f32::from(b) as f32 / 255.0,
+        f32::from(b) / 255.0,
     );
     glm::pow(&vec, &glm::vec3(2.2, 2.2, 2.2))
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProceduralDesc {
+    Checker(Checker),
+    Gradient(Gradient),
+    Sky(Sky),
+    Noise(Noise),
+    Spectrum {
+        spectrum: Spectrum,
+    },
+    Triplanar {
+        texture: ColorTexture,
+        triplanar: Triplanar,
+    },
+    Transformed {
+        texture: ColorTexture,
+        #[serde(flatten)]
+        transform: UvTransform,
+    },
+}
+
 impl<'de> Deserialize<'de> for ColorTexture {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        use serde::de::{value::SeqAccessDeserializer, Error, SeqAccess};
+        use serde::de::{
+            value::{MapAccessDeserializer, SeqAccessDeserializer},
+            Error, MapAccess, SeqAccess,
+        };
         use std::fmt;
 
         struct TexVisitor;
@@ -96,7 +252,8 @@ impl<'de> Deserialize<'de> for ColorTexture {
             type Value = ColorTexture;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("path to color image file or array")
+                formatter
+                    .write_str("path to color image file, array, spectrum or procedural texture")
             }
 
             // Load from texture file
@@ -109,6 +266,25 @@ impl<'de> Deserialize<'de> for ColorTexture {
                 let color: Vec3 = Deserialize::deserialize(SeqAccessDeserializer::new(value))?;
                 Ok(ColorTexture::solid(color))
             }
+
+            // Procedural texture, solid color from a spectrum, or another
+            // texture projected triplanarly or with a UV transform
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let desc = Deserialize::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(match desc {
+                    ProceduralDesc::Checker(c) => ColorTexture::Checker(c),
+                    ProceduralDesc::Gradient(g) => ColorTexture::Gradient(g),
+                    ProceduralDesc::Sky(s) => ColorTexture::Sky(s),
+                    ProceduralDesc::Noise(n) => ColorTexture::Noise(n),
+                    ProceduralDesc::Spectrum { spectrum } => ColorTexture::solid(spectrum.to_rgb()),
+                    ProceduralDesc::Triplanar { texture, triplanar } => {
+                        ColorTexture::Triplanar(Box::new(texture), triplanar)
+                    }
+                    ProceduralDesc::Transformed { texture, transform } => {
+                        ColorTexture::Transformed(Box::new(texture), transform)
+                    }
+                })
+            }
         }
         deserializer.deserialize_any(TexVisitor)
     }
@@ -0,0 +1,30 @@
+use serde::Deserialize;
+
+use crate::{Vec2, Vec3};
+use nalgebra_glm as glm;
+
+/// Vertical blend between two colors, from the bottom of the texture to
+/// its top. As an environment it shades by the height of the direction,
+/// like a simple sky.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Gradient {
+    /// Bottom and top colors.
+    pub gradient: [Vec3; 2],
+}
+
+impl Gradient {
+    /// White at the horizon below, turning azure overhead.
+    pub fn sky() -> Self {
+        Gradient {
+            gradient: [glm::vec3(1.0, 1.0, 1.0), glm::vec3(0.5, 0.7, 1.0)],
+        }
+    }
+
+    /// Blends by the height of the direction that `uv` maps to on a
+    /// sphere, matching latitude-longitude environment maps.
+    pub fn at_uv(&self, uv: Vec2) -> Vec3 {
+        let height = (glm::pi::<f32>() * uv.y).cos();
+        let t = 0.5 * (height + 1.0);
+        self.gradient[0] * (1.0 - t) + self.gradient[1] * t
+    }
+}
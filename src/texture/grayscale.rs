@@ -3,16 +3,22 @@ use std::path::Path;
 
 use serde::{de::Visitor, Deserialize, Deserializer};
 
-use super::Texture;
+use super::{bilinear, Cached, Lazy, Mipmap, Noise, Texture, Triplanar, UvTransform};
 use image::{self, GrayImage};
 
+use crate::geom::RayHit;
 use crate::Vec2;
 use nalgebra_glm as glm;
 
 #[derive(Clone)]
 pub enum GrayScaleTexture {
-    Tex(GrayImage),
+    Tex(GrayImage, Mipmap<f32>),
+    /// Image, or channel of one, loaded from a file on first use.
+    File(Lazy<GrayScaleTexture>),
     Solid(f32),
+    Noise(Noise),
+    Transformed(Box<GrayScaleTexture>, UvTransform),
+    Triplanar(Box<GrayScaleTexture>, Triplanar),
 }
 
 impl Texture for GrayScaleTexture {
@@ -20,27 +26,156 @@ impl Texture for GrayScaleTexture {
 
     fn dimensions(&self) -> Vec2 {
         match self {
-            GrayScaleTexture::Tex(img) => glm::vec2(img.width() as f32, img.height() as f32),
+            GrayScaleTexture::Tex(img, _) => glm::vec2(img.width() as f32, img.height() as f32),
+            GrayScaleTexture::File(lazy) => lazy.dimensions(),
             GrayScaleTexture::Solid(_color) => glm::vec2(100.0, 100.0),
+            GrayScaleTexture::Noise(_) => glm::vec2(1024.0, 1024.0),
+            GrayScaleTexture::Transformed(tex, _) | GrayScaleTexture::Triplanar(tex, _) => {
+                tex.dimensions()
+            }
         }
     }
 
     fn pixel_at(&self, x: u32, y: u32) -> Self::Pixel {
         match self {
-            GrayScaleTexture::Tex(img) => f32::from(img.get_pixel(x, y).0[0]) / 255.0,
+            GrayScaleTexture::Tex(img, _) => f32::from(img.get_pixel(x, y).0[0]) / 255.0,
+            GrayScaleTexture::File(lazy) => lazy.with(|tex| tex.pixel_at(x, y)),
             GrayScaleTexture::Solid(color) => *color,
+            GrayScaleTexture::Noise(_) => self.sample(glm::vec2(x as f32, y as f32) / 1024.0),
+            GrayScaleTexture::Transformed(tex, _) | GrayScaleTexture::Triplanar(tex, _) => {
+                tex.pixel_at(x, y)
+            }
+        }
+    }
+
+    fn sample(&self, uv: Vec2) -> Self::Pixel {
+        match self {
+            GrayScaleTexture::File(lazy) => lazy.with(|tex| tex.sample(uv)),
+            GrayScaleTexture::Noise(n) => n.value_at_uv(uv),
+            GrayScaleTexture::Transformed(tex, transform) => tex.sample(transform.apply(uv)),
+            GrayScaleTexture::Triplanar(tex, _) => tex.sample(uv),
+            _ => bilinear(self, uv),
+        }
+    }
+
+    fn sample_at(&self, hit: &RayHit) -> Self::Pixel {
+        match self {
+            GrayScaleTexture::Tex(_, mips) if hit.footprint > 0.0 => {
+                mips.trilinear(self, hit.uv, hit.footprint)
+            }
+            GrayScaleTexture::File(lazy) => lazy.with(|tex| tex.sample_at(hit)),
+            GrayScaleTexture::Noise(n) => n.value_at_hit(hit),
+            GrayScaleTexture::Transformed(tex, transform) => {
+                tex.sample_at(&transform.apply_hit(hit))
+            }
+            GrayScaleTexture::Triplanar(tex, triplanar) => triplanar.sample(tex.as_ref(), hit),
+            _ => self.sample(hit.uv),
         }
     }
 }
 
+impl GrayScaleTexture {
+    /// Image texture, building its mipmaps.
+    pub fn image(img: GrayImage) -> Self {
+        let mips = Mipmap::new(img.width(), img.height(), |x, y| {
+            f32::from(img.get_pixel(x, y).0[0]) / 255.0
+        });
+        GrayScaleTexture::Tex(img, mips)
+    }
+
+    pub fn from_file<'a, P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'a>> {
+        open(path)
+    }
+}
+
+impl Default for GrayScaleTexture {
+    fn default() -> Self {
+        GrayScaleTexture::Solid(0.0)
+    }
+}
+
+impl Cached for GrayScaleTexture {
+    fn size(&self) -> usize {
+        match self {
+            // Mipmaps hold a third as many pixels again, as floats
+            GrayScaleTexture::Tex(img, _) => img.len() + img.len() * 4 / 3,
+            _ => std::mem::size_of::<Self>(),
+        }
+    }
+}
+
+/// Texture loaded from `path` on first use by `load`.
+fn lazy<'a, F>(path: &str, key: String, load: F) -> Result<GrayScaleTexture, Box<dyn Error + 'a>>
+where
+    F: Fn() -> Result<GrayScaleTexture, Box<dyn Error>> + Send + Sync + 'static,
+{
+    let (width, height) = image::image_dimensions(path)?;
+    let lazy = Lazy::new(key, width, height, move || {
+        load().map_err(|err| err.to_string())
+    });
+    Ok(GrayScaleTexture::File(lazy))
+}
+
 fn open<'a, P: AsRef<Path>>(path: P) -> Result<GrayScaleTexture, Box<dyn Error + 'a>> {
-    let img = image::open(path)?;
-    Ok(GrayScaleTexture::Tex(img.to_luma()))
+    let path = path.as_ref().to_string_lossy().into_owned();
+    let file = path.clone();
+    lazy(&path, path.clone(), move || {
+        let img = image::open(&file)?;
+        Ok(GrayScaleTexture::image(img.to_luma()))
+    })
+}
+
+/// Color channel of an image, for maps packing several textures into one
+/// such as occlusion/roughness/metalness (ORM) maps.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Channel {
+    R,
+    G,
+    B,
+    A,
+}
+
+#[derive(Deserialize, Clone)]
+struct ChannelDesc {
+    file: String,
+    channel: Channel,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MapDesc {
+    Channel(ChannelDesc),
+    Noise(Noise),
+    Triplanar {
+        texture: GrayScaleTexture,
+        triplanar: Triplanar,
+    },
+    Transformed {
+        texture: GrayScaleTexture,
+        #[serde(flatten)]
+        transform: UvTransform,
+    },
+}
+
+fn open_channel<'a>(desc: &ChannelDesc) -> Result<GrayScaleTexture, Box<dyn Error + 'a>> {
+    let key = format!("{}:{:?}", desc.file, desc.channel);
+    let owned = desc.clone();
+    lazy(&desc.file, key, move || load_channel(&owned))
+}
+
+fn load_channel(desc: &ChannelDesc) -> Result<GrayScaleTexture, Box<dyn Error>> {
+    let img = image::open(&desc.file)?.to_rgba();
+    let idx = desc.channel as usize;
+    let gray = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        image::Luma([img.get_pixel(x, y).0[idx]])
+    });
+    Ok(GrayScaleTexture::image(gray))
 }
 
 impl<'de> Deserialize<'de> for GrayScaleTexture {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        use serde::de::Error;
+        use serde::de::{value::MapAccessDeserializer, Error, MapAccess};
         use std::fmt;
 
         struct TexVisitor;
@@ -49,7 +184,8 @@ impl<'de> Deserialize<'de> for GrayScaleTexture {
             type Value = GrayScaleTexture;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("path to grayscale image file or solid value")
+                formatter
+                    .write_str("path to grayscale image file, image channel, noise or solid value")
             }
 
             // Load from texture file
@@ -57,6 +193,21 @@ impl<'de> Deserialize<'de> for GrayScaleTexture {
                 open(value).map_err(E::custom)
             }
 
+            // Single channel of a packed texture, procedural noise, or another
+            // texture projected triplanarly or with a UV transform
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                match MapDesc::deserialize(MapAccessDeserializer::new(map))? {
+                    MapDesc::Channel(desc) => open_channel(&desc).map_err(A::Error::custom),
+                    MapDesc::Noise(noise) => Ok(GrayScaleTexture::Noise(noise)),
+                    MapDesc::Triplanar { texture, triplanar } => {
+                        Ok(GrayScaleTexture::Triplanar(Box::new(texture), triplanar))
+                    }
+                    MapDesc::Transformed { texture, transform } => {
+                        Ok(GrayScaleTexture::Transformed(Box::new(texture), transform))
+                    }
+                }
+            }
+
             fn visit_u64<E: Error>(self, val: u64) -> Result<Self::Value, E> {
                 self.visit_f32(val as f32)
             }
@@ -20,5 +20,5 @@ pub fn open<'a, P: AsRef<Path>>(path: P) -> Result<ColorTexture, Box<dyn Error +
         .into_iter()
         .map(|pix| glm::make_vec3(&pix.data))
         .collect();
-    Ok(ColorTexture { width, height, buf })
+    Ok(ColorTexture::Image { width, height, buf })
 }
@@ -0,0 +1,95 @@
+use std::ops::{Add, Mul};
+
+use super::{bilinear, Texture};
+use crate::Vec2;
+use nalgebra_glm as glm;
+
+#[derive(Clone)]
+pub struct MipLevel<P> {
+    buf: Vec<P>,
+    width: u32,
+    height: u32,
+}
+
+impl<P> Texture for MipLevel<P>
+where
+    P: Copy + Mul<f32, Output = P> + Add<P, Output = P>,
+{
+    type Pixel = P;
+
+    fn dimensions(&self) -> Vec2 {
+        glm::vec2(self.width as f32, self.height as f32)
+    }
+
+    fn pixel_at(&self, x: u32, y: u32) -> Self::Pixel {
+        self.buf[(y * self.width + x) as usize]
+    }
+}
+
+/// Successively halved copies of an image down to a single pixel, each
+/// box filtered from the one before. The full resolution image is kept by
+/// the texture itself and is not part of the chain.
+#[derive(Clone)]
+pub struct Mipmap<P> {
+    levels: Vec<MipLevel<P>>,
+}
+
+impl<P> Mipmap<P>
+where
+    P: Copy + Mul<f32, Output = P> + Add<P, Output = P>,
+{
+    /// Builds the chain for an image of the given size from its pixels.
+    pub fn new(width: u32, height: u32, pixel_at: impl Fn(u32, u32) -> P) -> Self {
+        let mut levels: Vec<MipLevel<P>> = Vec::new();
+        let (mut width, mut height) = (width, height);
+        while width > 1 || height > 1 {
+            let (w, h) = ((width / 2).max(1), (height / 2).max(1));
+            let fetch = |x: u32, y: u32| {
+                let (x, y) = (x.min(width - 1), y.min(height - 1));
+                match levels.last() {
+                    Some(level) => level.pixel_at(x, y),
+                    None => pixel_at(x, y),
+                }
+            };
+            let buf = (0..w * h)
+                .map(|i| {
+                    let (x, y) = (2 * (i % w), 2 * (i / w));
+                    (fetch(x, y) + fetch(x + 1, y) + fetch(x, y + 1) + fetch(x + 1, y + 1)) * 0.25
+                })
+                .collect();
+            levels.push(MipLevel {
+                buf,
+                width: w,
+                height: h,
+            });
+            width = w;
+            height = h;
+        }
+        Mipmap { levels }
+    }
+
+    /// Trilinear lookup for a footprint `footprint` wide in UV units,
+    /// blending bilinear lookups in the two levels whose texels are closest
+    /// to it in size. `base` is the full resolution texture.
+    pub fn trilinear<T>(&self, base: &T, uv: Vec2, footprint: f32) -> P
+    where
+        T: Texture<Pixel = P> + ?Sized,
+    {
+        let dim = base.dimensions();
+        let lod = (footprint * dim.x.max(dim.y))
+            .max(1.0)
+            .log2()
+            .min(self.levels.len() as f32);
+        let level = lod.floor() as usize;
+        let sample = |i: usize| match i {
+            0 => bilinear(base, uv),
+            i => bilinear(&self.levels[i - 1], uv),
+        };
+        let t = lod - level as f32;
+        if t <= 0.0 {
+            sample(level)
+        } else {
+            sample(level) * (1.0 - t) + sample(level + 1) * t
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use nalgebra_glm as glm;
+use serde::Deserialize;
+
+use super::Projection;
+use crate::geom::RayHit;
+use crate::{Vec2, Vec3};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseKind {
+    /// Fractal Brownian motion, a sum of octaves of gradient noise.
+    Fbm,
+    /// Like fBm but summing absolute values, giving sharp creases.
+    Turbulence,
+    /// Veins from a sine wave distorted by turbulence.
+    Marble,
+    /// Rings around the vertical axis, perturbed by fBm.
+    Wood,
+}
+
+/// Procedural texture from Perlin gradient noise, blending between two
+/// colors. Grayscale textures use the blend factor itself.
+#[derive(Deserialize, Clone)]
+pub struct Noise {
+    pub noise: NoiseKind,
+    /// Frequency of the first octave, in features per unit.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default = "default_colors")]
+    pub colors: [Vec3; 2],
+    #[serde(default = "default_projection")]
+    pub projection: Projection,
+}
+
+fn default_scale() -> f32 {
+    4.0
+}
+
+fn default_octaves() -> u32 {
+    5
+}
+
+fn default_colors() -> [Vec3; 2] {
+    [glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 1.0)]
+}
+
+fn default_projection() -> Projection {
+    Projection::World
+}
+
+/// Pseudo-random hash of a lattice point.
+pub fn lattice_hash(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed.wrapping_mul(0x9e37_79b9)
+        ^ (x as u32).wrapping_mul(0x85eb_ca6b)
+        ^ (y as u32).wrapping_mul(0xc2b2_ae35)
+        ^ (z as u32).wrapping_mul(0x27d4_eb2f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+/// Dot product of `d` with one of the 12 edge directions of a cube,
+/// as in Perlin's improved noise.
+fn gradient(hash: u32, d: &Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { d.x } else { d.y };
+    let v = if h < 4 {
+        d.y
+    } else if h == 12 || h == 14 {
+        d.x
+    } else {
+        d.z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Gradient noise, roughly in [-1, 1].
+pub fn perlin(p: &Vec3, seed: u32) -> f32 {
+    let cell = p.map(f32::floor);
+    let d = p - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let (u, v, w) = (fade(d.x), fade(d.y), fade(d.z));
+    let corner = |i: i32, j: i32, k: i32| {
+        let offset = glm::vec3(i as f32, j as f32, k as f32);
+        gradient(lattice_hash(x + i, y + j, z + k, seed), &(d - offset))
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+impl Noise {
+    fn octaves(&self, p: &Vec3, f: impl Fn(f32) -> f32) -> f32 {
+        let (mut sum, mut amplitude, mut frequency) = (0.0, 0.5, 1.0);
+        for octave in 0..self.octaves.max(1) {
+            sum += amplitude * f(perlin(&(p * frequency), self.seed.wrapping_add(octave)));
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum
+    }
+
+    /// Blend factor between the two colors at `p`, in [0, 1].
+    pub fn value(&self, p: &Vec3) -> f32 {
+        let p = p * self.scale;
+        let value = match self.noise {
+            NoiseKind::Fbm => 0.5 + self.octaves(&p, |n| n),
+            NoiseKind::Turbulence => 2.0 * self.octaves(&p, f32::abs),
+            NoiseKind::Marble => {
+                let turbulence = self.octaves(&p, f32::abs);
+                0.5 + 0.5 * (p.x + 10.0 * turbulence).sin()
+            }
+            NoiseKind::Wood => {
+                let rings = (p.x * p.x + p.z * p.z).sqrt() + 2.0 * self.octaves(&p, |n| n);
+                rings - rings.floor()
+            }
+        };
+        value.clamp(0.0, 1.0)
+    }
+
+    pub fn value_at_uv(&self, uv: Vec2) -> f32 {
+        self.value(&glm::vec3(uv.x, uv.y, 0.0))
+    }
+
+    pub fn value_at_hit(&self, hit: &RayHit) -> f32 {
+        match self.projection {
+            Projection::Uv => self.value_at_uv(hit.uv),
+            Projection::World => self.value(&hit.point),
+        }
+    }
+
+    pub fn color(&self, value: f32) -> Vec3 {
+        glm::lerp(&self.colors[0], &self.colors[1], value)
+    }
+}
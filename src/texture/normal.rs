@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde::{de::Visitor, Deserialize, Deserializer};
+
+use super::{bilinear, Texture, UvTransform};
+
+use crate::{Vec2, Vec3};
+use nalgebra_glm as glm;
+
+/// Tangent-space normal map. Unlike color textures its texels are stored
+/// linearly, decoded from [0, 1] to [-1, 1].
+#[derive(Clone)]
+pub struct NormalMap {
+    buf: Vec<Vec3>,
+    width: u32,
+    height: u32,
+    transform: UvTransform,
+}
+
+impl Texture for NormalMap {
+    type Pixel = Vec3;
+
+    fn dimensions(&self) -> Vec2 {
+        glm::vec2(self.width as f32, self.height as f32)
+    }
+
+    fn pixel_at(&self, x: u32, y: u32) -> Self::Pixel {
+        let idx = (y * self.width + x) as usize;
+        self.buf[idx]
+    }
+
+    fn sample(&self, uv: Vec2) -> Self::Pixel {
+        bilinear(self, self.transform.apply(uv))
+    }
+}
+
+impl NormalMap {
+    pub fn from_file<'a, P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + 'a>> {
+        open(path)
+    }
+
+    /// Shading normal for surface normal `n` and tangent `t` at `uv`.
+    pub fn perturb(&self, n: &Vec3, t: &Vec3, uv: Vec2) -> Vec3 {
+        let m = self.sample(uv);
+        let b = n.cross(t);
+        let perturbed = t * m.x + b * m.y + n * m.z;
+        if glm::length(&perturbed) > 1e-6 {
+            glm::normalize(&perturbed)
+        } else {
+            *n
+        }
+    }
+}
+
+fn open<'a, P: AsRef<Path>>(path: P) -> Result<NormalMap, Box<dyn Error + 'a>> {
+    let img = image::open(path)?.to_rgb();
+    let (width, height) = img.dimensions();
+    let buf = img
+        .pixels()
+        .map(|p| {
+            let [r, g, b] = p.0;
+            glm::vec3(f32::from(r), f32::from(g), f32::from(b)) * (2.0 / 255.0)
+                - glm::vec3(1.0, 1.0, 1.0)
+        })
+        .collect();
+    Ok(NormalMap {
+        buf,
+        width,
+        height,
+        transform: UvTransform::default(),
+    })
+}
+
+#[derive(Deserialize)]
+struct NormalMapDesc {
+    texture: String,
+    #[serde(flatten)]
+    transform: UvTransform,
+}
+
+impl<'de> Deserialize<'de> for NormalMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{value::MapAccessDeserializer, Error, MapAccess};
+        use std::fmt;
+
+        struct TexVisitor;
+
+        impl<'de> Visitor<'de> for TexVisitor {
+            type Value = NormalMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("path to normal map image file, optionally with a UV transform")
+            }
+
+            fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+                open(value).map_err(E::custom)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let desc = NormalMapDesc::deserialize(MapAccessDeserializer::new(map))?;
+                let map = open(&desc.texture).map_err(A::Error::custom)?;
+                Ok(NormalMap {
+                    transform: desc.transform,
+                    ..map
+                })
+            }
+        }
+
+        deserializer.deserialize_any(TexVisitor)
+    }
+}
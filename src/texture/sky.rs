@@ -0,0 +1,223 @@
+use serde::Deserialize;
+
+use crate::geom::Sphere;
+use crate::solar::SunPosition;
+use crate::spectrum::xyz_to_rgb;
+use crate::{Vec2, Vec3};
+use nalgebra_glm as glm;
+
+/// Analytic daylight sky after Preetham, Shirley and Smits, "A Practical
+/// Analytic Model for Daylight", given by the position of the sun and the
+/// haziness of the air. As an environment it comes with a sun light of the
+/// matching color. Luminance is in units of 30 kcd/m², roughly that of the
+/// zenith on a clear day with the sun overhead.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Sky {
+    #[serde(flatten)]
+    pub sun_placement: SunPlacement,
+    /// Haziness, from 2 for a clear sky to about 10 for a hazy one.
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f32,
+    #[serde(default = "default_strength")]
+    pub strength: f32,
+    /// Fraction of the light at the horizon reflected back up, shading the
+    /// lower half of the environment.
+    #[serde(default = "default_ground")]
+    pub ground: Vec3,
+    /// Whether to add the sun as a light.
+    #[serde(default = "default_sun")]
+    pub sun: bool,
+}
+
+/// Where the sun of a sky stands, given directly or by a place and time.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SunPlacement {
+    Angles {
+        /// Angle of the sun above the horizon, in degrees.
+        sun_elevation: f32,
+        /// Angle of the sun around the vertical axis, in degrees from the
+        /// x axis towards the z axis.
+        #[serde(default)]
+        sun_azimuth: f32,
+    },
+    Position {
+        sun_position: SunPosition,
+    },
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+fn default_ground() -> Vec3 {
+    glm::vec3(0.3, 0.3, 0.3)
+}
+
+fn default_sun() -> bool {
+    true
+}
+
+/// Luminance in kcd/m² that the sky's unit stands for.
+const UNIT_LUMINANCE: f32 = 30.0;
+
+/// Illuminance in klx of the sun outside the atmosphere.
+const SOLAR_ILLUMINANCE: f32 = 128.0;
+
+/// Coefficients of the Perez distribution, as a function of turbidity.
+fn perez(turbidity: f32, coefficients: [(f32, f32); 5]) -> [f32; 5] {
+    let mut perez = [0.0; 5];
+    for (p, (a, b)) in perez.iter_mut().zip(coefficients.iter()) {
+        *p = a * turbidity + b;
+    }
+    perez
+}
+
+/// Relative brightness in the direction at `theta` from the zenith and
+/// `gamma` from the sun.
+fn perez_eval(p: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let cos_gamma = gamma.cos();
+    (1.0 + p[0] * (p[1] / theta.cos().max(1e-3)).exp())
+        * (1.0 + p[2] * (p[3] * gamma).exp() + p[4] * cos_gamma * cos_gamma)
+}
+
+/// Polynomial in the sun's zenith angle for each power of turbidity.
+fn zenith_chromaticity(turbidity: f32, theta_s: f32, rows: [[f32; 4]; 3]) -> f32 {
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    let s = [theta_s.powi(3), theta_s * theta_s, theta_s, 1.0];
+    let mut sum = 0.0;
+    for (t, row) in t.iter().zip(rows.iter()) {
+        for (s, c) in s.iter().zip(row.iter()) {
+            sum += t * s * c;
+        }
+    }
+    sum
+}
+
+impl SunPlacement {
+    pub fn position(&self) -> SunPosition {
+        match self {
+            SunPlacement::Angles {
+                sun_elevation,
+                sun_azimuth,
+            } => SunPosition {
+                elevation: *sun_elevation,
+                // Compass bearings start from north, along -z
+                azimuth: sun_azimuth + 90.0,
+            },
+            SunPlacement::Position { sun_position } => sun_position.clone(),
+        }
+    }
+}
+
+impl Sky {
+    /// Unit direction towards the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_placement.position().direction()
+    }
+
+    /// Light from the sun arriving per unit area facing it, after passing
+    /// through the atmosphere. Zero once the sun has set.
+    pub fn sun_irradiance(&self) -> Vec3 {
+        let elevation = self.sun_placement.position().elevation;
+        if elevation <= 0.0 {
+            return glm::zero();
+        }
+        // Relative optical air mass, after Kasten and Young
+        let zenith = 90.0 - elevation;
+        let cos = zenith.to_radians().cos();
+        let air_mass = 1.0 / (cos + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+        // Rayleigh and aerosol optical depths, at wavelengths in µm
+        // standing for red, green and blue
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = glm::vec3(0.68f32, 0.55, 0.44).map(|lambda| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        });
+        transmittance * (self.strength * SOLAR_ILLUMINANCE / UNIT_LUMINANCE)
+    }
+
+    /// Sky radiance in `dir`, mirrored below the horizon and darkened by
+    /// the ground.
+    pub fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let sun = self.sun_direction();
+        let up = glm::vec3(dir.x, dir.y.abs(), dir.z);
+        let theta = up.y.min(1.0).acos();
+        let gamma = glm::dot(&up, &sun).clamp(-1.0, 1.0).acos();
+        let theta_s = sun.y.clamp(0.0, 1.0).acos();
+        let t = self.turbidity;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (glm::pi::<f32>() - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = zenith_chromaticity(
+            t,
+            theta_s,
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+        );
+        let zenith_yc = zenith_chromaticity(
+            t,
+            theta_s,
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+        );
+        let perez_y = perez(
+            t,
+            [
+                (0.1787, -1.4630),
+                (-0.3554, 0.4275),
+                (-0.0227, 5.3251),
+                (0.1206, -2.5771),
+                (-0.0670, 0.3703),
+            ],
+        );
+        let perez_x = perez(
+            t,
+            [
+                (-0.0193, -0.2592),
+                (-0.0665, 0.0008),
+                (-0.0004, 0.2125),
+                (-0.0641, -0.8989),
+                (-0.0033, 0.0452),
+            ],
+        );
+        let perez_yc = perez(
+            t,
+            [
+                (-0.0167, -0.2608),
+                (-0.0950, 0.0092),
+                (-0.0079, 0.2102),
+                (-0.0441, -1.6537),
+                (-0.0109, 0.0529),
+            ],
+        );
+        let relative = |p: &[f32; 5]| perez_eval(p, theta, gamma) / perez_eval(p, 0.0, theta_s);
+        let luminance = (zenith_y * relative(&perez_y)).max(0.0);
+        let x = zenith_x * relative(&perez_x);
+        let y = (zenith_yc * relative(&perez_yc)).max(1e-4);
+        let xyz = glm::vec3(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+        let rgb = xyz_to_rgb(&xyz).map(|c| c.max(0.0)) * (self.strength / UNIT_LUMINANCE);
+        if dir.y < 0.0 {
+            rgb.component_mul(&self.ground)
+        } else {
+            rgb
+        }
+    }
+
+    /// Radiance in the direction that `uv` maps to on a sphere, matching
+    /// latitude-longitude environment maps.
+    pub fn at_uv(&self, uv: Vec2) -> Vec3 {
+        self.radiance(&Sphere::dir_at_uv(&uv))
+    }
+}
@@ -0,0 +1,55 @@
+use serde::Deserialize;
+
+use crate::geom::RayHit;
+use crate::Vec2;
+use nalgebra_glm as glm;
+
+/// Placement of a texture in UV space, so that one tileable texture can
+/// cover a large surface. Applied as scaling, then rotation about the
+/// origin, then offset.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UvTransform {
+    /// Repetitions of the texture per unit of UV.
+    #[serde(default = "default_tiling")]
+    pub tiling: Vec2,
+    #[serde(default = "glm::zero")]
+    pub offset: Vec2,
+    /// Counterclockwise, in degrees.
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+fn default_tiling() -> Vec2 {
+    glm::vec2(1.0, 1.0)
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        UvTransform {
+            tiling: default_tiling(),
+            offset: glm::zero(),
+            rotation: 0.0,
+        }
+    }
+}
+
+impl UvTransform {
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        let scaled = uv.component_mul(&self.tiling);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let rotated = glm::vec2(
+            cos * scaled.x - sin * scaled.y,
+            sin * scaled.x + cos * scaled.y,
+        );
+        rotated + self.offset
+    }
+
+    /// `hit` with its UVs transformed, and its footprint scaled to match.
+    pub fn apply_hit(&self, hit: &RayHit) -> RayHit {
+        RayHit {
+            uv: self.apply(hit.uv),
+            footprint: hit.footprint * self.tiling.abs().max(),
+            ..*hit
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use serde::Deserialize;
+
+use super::Texture;
+use crate::geom::RayHit;
+use nalgebra_glm as glm;
+
+/// Projection of a texture along the three world axes, blended by how much
+/// the surface faces each of them, so that textures apply to geometry
+/// without usable UVs such as primitives and scans.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Triplanar {
+    /// Repetitions of the texture per unit of world space.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Exponent narrowing the blend between projections, where higher
+    /// values give sharper transitions.
+    #[serde(default = "default_sharpness")]
+    pub sharpness: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_sharpness() -> f32 {
+    4.0
+}
+
+impl Triplanar {
+    pub fn sample<T: Texture + ?Sized>(&self, tex: &T, hit: &RayHit) -> T::Pixel {
+        let weights = hit.normal.map(|c| c.abs().powf(self.sharpness));
+        let weights = weights / (weights.x + weights.y + weights.z);
+        let p = hit.point * self.scale;
+        tex.sample(glm::vec2(p.y, p.z)) * weights.x
+            + tex.sample(glm::vec2(p.x, p.z)) * weights.y
+            + tex.sample(glm::vec2(p.x, p.y)) * weights.z
+    }
+}
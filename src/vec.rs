@@ -1,4 +1,4 @@
-pub use glm::{Vec2, Vec3};
+pub use glm::{Vec2, Vec3, Vec4};
 pub use nalgebra_glm as glm;
 
 /*
@@ -27,3 +27,29 @@ pub fn component_minmax((mut min, mut max): (Vec3, Vec3), a: &Vec3) -> (Vec3, Ve
     }
     (min, max)
 }
+
+/// Returns two unit vectors that together with `n` form an orthonormal basis.
+pub fn orthonormal_basis(n: &Vec3) -> (Vec3, Vec3) {
+    let axis = if n.x.abs() > 0.9 {
+        glm::vec3(0.0, 1.0, 0.0)
+    } else {
+        glm::vec3(1.0, 0.0, 0.0)
+    };
+    let u = glm::normalize(&axis.cross(n));
+    let v = n.cross(&u);
+    (u, v)
+}
+
+/// Relative luminance of a linear RGB color.
+pub fn luminance(c: &Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Direction about `n` with a density proportional to its cosine with it,
+/// from a point `rnd` in the unit square.
+pub fn sample_cosine(n: &Vec3, rnd: Vec2) -> Vec3 {
+    let r = rnd.x.sqrt();
+    let phi = glm::two_pi::<f32>() * rnd.y;
+    let (u, v) = orthonormal_basis(n);
+    glm::normalize(&(u * (r * phi.cos()) + v * (r * phi.sin()) + n * (1.0 - rnd.x).sqrt()))
+}